@@ -1,4 +1,8 @@
 use crate::draw_spectator_page;
+use crate::leaderboard::LeaderboardView;
+use crate::replay::HandReplay;
+use protocol::{PlayerStats, ServerEvent, TableState};
+use crate::tls::TlsOptions;
 use crate::egui::TextureHandle;
 use crate::screens::*;
 use crate::App;
@@ -9,6 +13,7 @@ use crate::Mode;
 use crate::Mutex;
 use crate::Receiver;
 use crate::Sender;
+use std::collections::VecDeque;
 use eframe::egui; // or `use crate::egui;` depending on where egui is defineduse eframe::CreationContext;
 use eframe::Frame; // or `use crate::Frame;` or `use crate::egui::Frame;` if Frame is re-exported there
 
@@ -16,22 +21,46 @@ pub struct PlayerApp {
     pub username: String,
     pub password: String,
     pub dealer_ip: String,
+    /// Whether to connect with TLS, and the certificate to expect.
+    pub tls: TlsOptions,
     pub mode: Mode,
     pub stats_search_query: String,
-    pub user_stats: String,
+    /// Start of the date range for the stats search, as `YYYY-MM-DD`. Blank for all time.
+    pub stats_from: String,
+    /// End of the date range for the stats search, as `YYYY-MM-DD`. Blank for up to now.
+    pub stats_to: String,
+    /// The leaderboard on the stats screen.
+    pub leaderboard: LeaderboardView,
+    /// Stats of the player searched for, or why the search failed.
+    pub user_stats: Result<Option<PlayerStats>, String>,
     pub current_bet: String,
     pub current_swap: String,
     pub state: AppState,
+    /// The latest event from the game in progress.
+    pub game_event: Option<ServerEvent>,
     /// Stores the server response from the one-shot auth command.
     pub output: Arc<Mutex<String>>,
-    /// Stores the server response for game state requests for spectator mode.
-    pub game_state: Arc<Mutex<String>>,
+    /// Latest table seen in spectator mode.
+    pub spectate_table: Option<TableState>,
     pub last_spectate_request_time: Instant,
+    /// Session token from the last successful login. The persistent connection is
+    /// bound to it.
+    pub session_token: String,
     /// Channel to send messages from the UI to the network thread.
     pub ui_to_net_tx: Option<Sender<String>>,
     /// Channel to receive messages from the network thread.
     pub net_to_ui_rx: Option<Receiver<String>>,
+    /// Messages read by a screen that had no use for them, kept for the next one.
+    pub held_messages: VecDeque<String>,
     pub logo_texture: Option<TextureHandle>,
+    /// ID of the hand to replay. Filled in with the last hand played.
+    pub replay_hand_id: String,
+    /// The hand being replayed, once the dealer has sent it.
+    pub replay: Option<HandReplay>,
+    /// Index of the replay step being shown.
+    pub replay_step: usize,
+    /// Loading or error message for the replay screen.
+    pub replay_status: String,
 }
 
 impl Default for PlayerApp {
@@ -40,22 +69,44 @@ impl Default for PlayerApp {
             username: String::new(),
             password: String::new(),
             dealer_ip: "127.0.0.1".to_string(),
+            tls: TlsOptions::default(),
             mode: Mode::Login,
             stats_search_query: String::new(),
-            user_stats: String::new(),
+            stats_from: String::new(),
+            stats_to: String::new(),
+            leaderboard: LeaderboardView::default(),
+            user_stats: Ok(None),
             current_bet: "0".to_string(),
             current_swap: "".to_string(),
             state: AppState::Auth,
+            game_event: None,
             output: Arc::new(Mutex::new(String::new())),
             last_spectate_request_time: Instant::now(),
-            game_state: Arc::new(Mutex::new(String::new())),
+            spectate_table: None,
+            session_token: String::new(),
             ui_to_net_tx: None,
             net_to_ui_rx: None,
+            held_messages: VecDeque::new(),
             logo_texture: None,
+            replay_hand_id: String::new(),
+            replay: None,
+            replay_step: 0,
+            replay_status: String::new(),
         }
     }
 }
 
+impl PlayerApp {
+    /// Takes the next message from the dealer: one held back by an earlier screen
+    /// first, then whatever the network thread has received.
+    pub fn next_message(&mut self) -> Option<String> {
+        if let Some(msg) = self.held_messages.pop_front() {
+            return Some(msg);
+        }
+        self.net_to_ui_rx.as_ref()?.try_recv().ok()
+    }
+}
+
 impl App for PlayerApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut Frame) {
         match self.state {
@@ -64,6 +115,7 @@ impl App for PlayerApp {
             AppState::Ready => draw_ready(self, ctx),
             AppState::Stats => draw_stats_page(self, ctx),
             AppState::Spectator => draw_spectator_page(self, ctx),
+            AppState::Replay => draw_replay(self, ctx),
         }
         ctx.request_repaint();
     }
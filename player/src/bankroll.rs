@@ -0,0 +1,99 @@
+//! # Bankroll
+//!
+//! The date range typed in on the stats screen, and where the points of the
+//! bankroll chart go.
+//!
+//! Dates are `YYYY-MM-DD` in UTC, the same clock the dealer timestamps results
+//! with. The dealer takes times in seconds since the Unix epoch.
+
+use eframe::egui::{pos2, Pos2, Rect};
+use protocol::BankrollPoint;
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The date `days` after 1970-01-01, as `(year, month, day)`.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = if days >= 0 { days } else { days - 146096 } / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Parses a `YYYY-MM-DD` date.
+///
+/// # Returns
+/// Midnight at the start of the date, in seconds since the Unix epoch, or `None`
+/// if it is not a real date.
+pub fn parse_date(text: &str) -> Option<i64> {
+    let mut parts = text.trim().splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (parts.next()??, parts.next()??, parts.next()??);
+    let days = days_from_civil(year, month, day);
+    // Out-of-range months and days roll over into another date
+    (civil_from_days(days) == (year, month, day)).then_some(days * SECONDS_PER_DAY)
+}
+
+/// Formats seconds since the Unix epoch as a `YYYY-MM-DD` date.
+pub fn format_date(timestamp: i64) -> String {
+    let (year, month, day) = civil_from_days(timestamp.div_euclid(SECONDS_PER_DAY));
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Reads the date range typed into the stats screen. Either end may be left
+/// blank; the end date counts up to its last second.
+///
+/// # Returns
+/// The range in seconds since the Unix epoch, or the message to show if a date
+/// cannot be read or the range is backwards.
+pub fn date_range(from: &str, to: &str) -> Result<(Option<i64>, Option<i64>), String> {
+    let parse = |text: &str| match text.trim() {
+        "" => Ok(None),
+        date => parse_date(date).map(Some).ok_or_else(|| format!("Dates look like 2025-01-31, not \"{}\"", date)),
+    };
+    let from = parse(from)?;
+    let to = parse(to)?.map(|to| to + SECONDS_PER_DAY - 1);
+    if let (Some(from), Some(to)) = (from, to) {
+        if from > to {
+            return Err("The start date is after the end date".to_string());
+        }
+    }
+    Ok((from, to))
+}
+
+/// Places the bankroll points in a chart: time runs left to right, and the
+/// bankroll bottom to top, with zero always in view.
+///
+/// # Returns
+/// Where each point goes in `rect`, in the same order.
+pub fn chart_points(points: &[BankrollPoint], rect: Rect) -> Vec<Pos2> {
+    let (Some(first), Some(last)) = (points.first(), points.last()) else {
+        return Vec::new();
+    };
+    let low = points.iter().map(|p| p.bankroll).min().unwrap_or(0).min(0) as f32;
+    let high = points.iter().map(|p| p.bankroll).max().unwrap_or(0).max(0) as f32;
+    let span = (last.timestamp - first.timestamp) as f32;
+
+    points
+        .iter()
+        .map(|point| {
+            let x = if span > 0.0 { (point.timestamp - first.timestamp) as f32 / span } else { 0.5 };
+            let y = if high > low { (point.bankroll as f32 - low) / (high - low) } else { 0.5 };
+            pos2(rect.left() + x * rect.width(), rect.bottom() - y * rect.height())
+        })
+        .collect()
+}
@@ -0,0 +1,87 @@
+//! # Leaderboard
+//!
+//! The leaderboard on the stats screen: how the player has chosen to rank and
+//! filter it, and the page the dealer last sent (the `"leaderboard"` command).
+
+use protocol::{ClientCommand, LeaderboardPage, LeaderboardPeriod, LeaderboardSort};
+
+/// Variants the leaderboard can be filtered to, with the names shown for them.
+pub const VARIANTS: [(&str, &str); 3] = [
+    ("5card", "5 Card Draw"),
+    ("7card", "7 Card Stud"),
+    ("texas", "Texas Hold'em"),
+];
+
+/// The leaderboard being shown.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LeaderboardView {
+    pub sort: LeaderboardSort,
+    /// Variant to count hands of, or `None` for every variant.
+    pub variant: Option<String>,
+    pub period: LeaderboardPeriod,
+    /// Page number, starting at 1.
+    pub page: usize,
+    /// The page the dealer last sent.
+    pub shown: Option<LeaderboardPage>,
+}
+
+impl Default for LeaderboardView {
+    fn default() -> Self {
+        Self {
+            sort: LeaderboardSort::default(),
+            variant: None,
+            period: LeaderboardPeriod::default(),
+            page: 1,
+            shown: None,
+        }
+    }
+}
+
+impl LeaderboardView {
+    /// The command asking the dealer for the chosen page.
+    pub fn request(&self) -> ClientCommand {
+        ClientCommand::Leaderboard {
+            sort: self.sort,
+            variant: self.variant.clone(),
+            period: self.period,
+            page: self.page,
+            page_size: None,
+        }
+    }
+
+    /// Moves to the next page, if the last page sent was not the last.
+    ///
+    /// # Returns
+    /// Whether the page changed, so a new one should be requested.
+    pub fn next_page(&mut self) -> bool {
+        let pages = self.shown.as_ref().map_or(1, LeaderboardPage::page_count);
+        if self.page < pages {
+            self.page += 1;
+            return true;
+        }
+        false
+    }
+
+    /// Moves to the previous page, if this is not the first.
+    ///
+    /// # Returns
+    /// Whether the page changed, so a new one should be requested.
+    pub fn previous_page(&mut self) -> bool {
+        if self.page > 1 {
+            self.page -= 1;
+            return true;
+        }
+        false
+    }
+
+    /// Name shown for the variant filter.
+    pub fn variant_label(&self) -> &str {
+        match &self.variant {
+            Some(variant) => VARIANTS
+                .iter()
+                .find(|(id, _)| id == variant)
+                .map_or(variant.as_str(), |(_, label)| label),
+            None => "All variants",
+        }
+    }
+}
@@ -5,10 +5,11 @@
 //! Built using [`eframe`] and [`egui`] for the user interface, and TCP networking for communication with the dealer (server).
 //!
 //! ## Features
-//! - TCP client-server communication
+//! - TCP client-server communication, optionally over TLS with certificate pinning
 //! - Player registration & login
 //! - Live in-game updates (bets, swaps, cards, pot, etc.)
-//! - View player stats from the server
+//! - View player stats and ranked leaderboards from the server, over any date range, with a bankroll chart
+//! - Replay stored hands step by step
 //! - User-friendly GUI using egui
 //!
 //! ## Usage
@@ -24,30 +25,31 @@
 //!
 //! ## Dependencies
 //! - eframe (egui framework)
+//! - protocol (the messages shared with the dealer)
 //! - serde_json (for JSON encoding/decoding)
 //! - tokio
 //! - std::net (for TCP streams)
 
 mod app;
+mod bankroll;
+mod leaderboard;
+mod replay;
 mod screens;
 mod tests;
+mod tls;
 mod ui;
 
 use app::PlayerApp;
-use eframe::{egui, App, Frame, NativeOptions};
-use serde_json::json;
-use serde_json::Value;
+use eframe::{egui, App, NativeOptions};
+use protocol::{ClientCommand, ServerEvent};
 use std::{
-    io::{ErrorKind, Read, Write},
-    net::TcpStream,
-    sync::mpsc::{self, Receiver, Sender, TryRecvError},
+    sync::mpsc::{Receiver, Sender},
     sync::{Arc, Mutex},
-    thread,
-    time::{Duration, Instant},
+    time::Instant,
 };
 
-///maximum size of a network message buffer.
-const MSG_SIZE: usize = 2048;
+/// Port the dealer listens on.
+const DEALER_PORT: u16 = 8080;
 
 /// Modes for player authentication.
 #[derive(Debug, PartialEq, Clone)]
@@ -68,6 +70,8 @@ pub enum AppState {
     /// Game ready screen (preparing to start).
     Ready,
     Spectator,
+    /// Replay of a stored hand.
+    Replay,
 }
 
 fn draw_spectator_page(app: &mut PlayerApp, ctx: &egui::Context) {
@@ -82,102 +86,65 @@ fn draw_spectator_page(app: &mut PlayerApp, ctx: &egui::Context) {
             > 2.0
         {
             if let Some(tx) = &app.ui_to_net_tx {
-                let msg = json!({ "command": "spectate" }).to_string();
-                let _ = tx.send(msg);
+                let _ = tx.send(ClientCommand::Spectate.to_json());
             }
             app.last_spectate_request_time = now;
         }
 
         // Receive and store the most recent game state
-        if let Some(rx) = &app.net_to_ui_rx {
-            while let Ok(msg) = rx.try_recv() {
-                *app.game_state.lock().unwrap() = msg;
+        while let Some(msg) = app.next_message() {
+            if let Ok(ServerEvent::Table(table)) = ServerEvent::from_json(&msg) {
+                app.spectate_table = Some(table);
             }
         }
 
-        // Read and parse the current game state
-        let state_str = app.game_state.lock().unwrap().clone();
-
-        if let Ok(parsed) = serde_json::from_str::<Value>(&state_str) {
-            if let Some(community) = parsed.get("community").and_then(|v| v.as_array()) {
+        if let Some(table) = &app.spectate_table {
+            if !table.community.is_empty() {
                 ui.separator();
                 ui.heading("Community Cards:");
                 ui.horizontal(|ui| {
-                    for card in community {
-                        if let Some(card_str) = card.as_str() {
-                            ui.label(card_str);
-                        }
+                    for card in &table.community {
+                        ui.label(card);
                     }
                 });
             }
 
-            if let Some(cards_obj) = parsed.get("cards").and_then(|v| v.as_object()) {
+            if !table.hands.is_empty() {
                 ui.separator();
-                ui.heading("Player Hands:");
-                for (username, cards_val) in cards_obj {
+                if table.variant == "7card" {
+                    ui.heading("7 Card Stud Hands:");
+                } else {
+                    ui.heading("Player Hands:");
+                }
+                for (username, cards) in &table.hands {
                     ui.horizontal(|ui| {
                         ui.label(format!("{}:", username));
-                        if let Some(card_array) = cards_val.as_array() {
-                            for card in card_array {
-                                if let Some(card_str) = card.as_str() {
-                                    ui.label(card_str);
-                                }
-                            }
+                        for card in cards {
+                            ui.label(card);
                         }
                     });
                 }
             }
 
-            if let Some(cards_obj) = parsed.get("7 card hands") {
-                if let Some(cards_map) = cards_obj.as_object() {
-                    ui.separator();
-                    ui.heading("7 Card Stud Hands:");
-                    for (username, cards_val) in cards_map {
-                        ui.horizontal(|ui| {
-                            ui.label(format!("{}:", username));
-                            if let Some(card_array) = cards_val.as_array() {
-                                for (_i, card) in card_array.iter().enumerate() {
-                                    if let Some(card_str) = card.as_str() {
-                                        ui.label(card_str);
-                                    }
-                                }
-                            }
-                        });
-                    }
-                }
-            }
-
-            if let Some(round_bet) = parsed.get("round current bet").and_then(|v| v.as_i64()) {
-                ui.separator();
-                ui.label(format!("Round Current Bet: {}", round_bet));
-            }
+            ui.separator();
+            ui.label(format!("Round Current Bet: {}", table.current_bet));
 
-            if let Some(bets_map) = parsed
-                .get("player current bets")
-                .and_then(|v| v.as_object())
-            {
+            if !table.bets.is_empty() {
                 ui.separator();
                 ui.heading("Player Bets This Round:");
-                for (username, value) in bets_map {
-                    if let Some(amount) = value.as_i64() {
-                        ui.label(format!("{}: {}", username, amount));
-                    }
+                for (username, amount) in &table.bets {
+                    ui.label(format!("{}: {}", username, amount));
                 }
             }
 
-            if let Some(info) = parsed.get("info").and_then(|v| v.as_str()) {
+            if let Some(info) = &table.info {
                 ui.separator();
                 ui.label(info);
             }
 
-            if let Some(mut pot) = parsed.get("pot").and_then(|v| v.as_i64()) {
-                if pot < 0 {
-                    pot = 0;
-                }
-                ui.label(format!("Pot: {}", pot));
-            }
+            ui.label(format!("Pot: {}", table.pot.max(0)));
 
-            if let Some(winner) = parsed.get("winner").and_then(|v| v.as_str()) {
+            if let Some(winner) = &table.winner {
                 ui.separator();
                 ui.label(format!("Winner is: {}", winner));
             }
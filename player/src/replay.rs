@@ -0,0 +1,222 @@
+//! # Hand Replay
+//!
+//! Turns a hand record fetched from the dealer's history (the `"hand_history"`
+//! command) into a list of steps that the replay screen can move through.
+//!
+//! Each step is a snapshot of the table right after one action: the pot, every
+//! player's bet on the current street, the cards each player held and the board.
+
+use protocol::hand::{ActionKind, HandRecord};
+use protocol::ServerEvent;
+
+/// The table as it looked at one point in a hand.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayStep {
+    /// Street being played, e.g. `"preflop"` or `"draw"`.
+    pub street: String,
+    /// What just happened, e.g. `"alice raises 20 to 40"`.
+    pub description: String,
+    /// Chips in the pot.
+    pub pot: i64,
+    /// Each player's bet on the current street, in seat order.
+    pub bets: Vec<(String, i64)>,
+    /// Cards each player held at this point, in seat order.
+    pub hands: Vec<(String, Vec<String>)>,
+    /// Community cards dealt so far.
+    pub board: Vec<String>,
+    /// Players who have folded.
+    pub folded: Vec<String>,
+}
+
+/// A stored hand broken into steps.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HandReplay {
+    /// ID of the hand.
+    pub hand_id: String,
+    /// Game variant (`"5card"`, `"7card"` or `"texas"`).
+    pub variant: String,
+    /// Players whose cards were shown at showdown.
+    pub shown: Vec<String>,
+    /// Snapshots of the table, from the deal to the end of the hand.
+    pub steps: Vec<ReplayStep>,
+}
+
+impl HandReplay {
+    /// Builds a replay from the dealer's reply to a `hand_history` command.
+    ///
+    /// # Arguments
+    /// * `json` - The dealer's reply.
+    ///
+    /// # Returns
+    /// The replay, or the error message to show if the reply is not a hand.
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        match ServerEvent::from_json(json) {
+            Ok(ServerEvent::HandHistory(record)) => Ok(Self::from_record(&record)),
+            Ok(ServerEvent::Error { message }) => Err(message),
+            Ok(other) => Err(format!("Unexpected reply: {:?}", other)),
+            Err(e) => Err(format!("Invalid hand record: {}", e)),
+        }
+    }
+
+    /// Builds a replay from a stored hand.
+    pub fn from_record(record: &HandRecord) -> Self {
+        let variant = record.variant.clone();
+
+        // Cards each player ended the hand with, in seat order.
+        let seats: Vec<(String, Vec<String>)> = record
+            .seats
+            .iter()
+            .map(|seat| (seat.player.clone(), seat.cards.clone()))
+            .collect();
+
+        // Undo the draws to get the hands as they were first dealt.
+        let mut hands = seats.clone();
+        let draws = record
+            .streets
+            .iter()
+            .flat_map(|street| &street.actions)
+            .filter(|action| action.action == ActionKind::Draw);
+        for action in draws {
+            if let Some((_, cards)) = hands.iter_mut().find(|(name, _)| *name == action.player) {
+                for card in cards.iter_mut() {
+                    if let Some(i) = action.drawn.iter().position(|c| c == card) {
+                        if let Some(old) = action.discarded.get(i) {
+                            *card = old.clone();
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut state = ReplayStep {
+            street: String::new(),
+            description: format!("Hand #{} ({})", record.hand_id, variant_name(&variant)),
+            pot: 0,
+            bets: seats.iter().map(|(name, _)| (name.clone(), 0)).collect(),
+            hands: Vec::new(),
+            board: Vec::new(),
+            folded: Vec::new(),
+        };
+        state.hands = dealt_cards(&variant, "", &hands);
+        let mut steps = vec![state.clone()];
+
+        for street in &record.streets {
+            if !state.street.is_empty() {
+                for (_, bet) in &mut state.bets {
+                    *bet = 0;
+                }
+                state.board = board_for_street(&variant, &street.name, &record.board);
+                state.hands = dealt_cards(&variant, &street.name, &hands);
+                state.description = format!("*** {} ***", street.name.to_uppercase());
+                steps.push(state.clone());
+            }
+            state.street = street.name.clone();
+
+            for action in &street.actions {
+                let player = &action.player;
+                let amount = action.amount as i64;
+                let total = action.total as i64;
+
+                match action.action {
+                    ActionKind::Fold => state.folded.push(player.clone()),
+                    ActionKind::Draw => {
+                        if let Some((_, cards)) = hands.iter_mut().find(|(name, _)| name == player) {
+                            if let Some((_, end)) = seats.iter().find(|(name, _)| name == player) {
+                                *cards = end.clone();
+                            }
+                        }
+                        state.hands = dealt_cards(&variant, &state.street, &hands);
+                    }
+                    _ => {
+                        state.pot += amount;
+                        if let Some((_, bet)) = state.bets.iter_mut().find(|(name, _)| name == player) {
+                            *bet = total;
+                        }
+                    }
+                }
+                state.description = describe(player, action.action, amount, total);
+                steps.push(state.clone());
+            }
+        }
+
+        // Final step: the showdown and who was paid.
+        let winners: Vec<String> = record
+            .pots
+            .iter()
+            .map(|pot| format!("{} wins {}", pot.player, pot.amount))
+            .collect();
+        state.board = record.board.clone();
+        state.hands = seats;
+        state.pot = record.total_pot as i64;
+        state.description = if winners.is_empty() {
+            "Hand over".to_string()
+        } else {
+            winners.join(", ")
+        };
+        steps.push(state);
+
+        Self {
+            hand_id: record.hand_id.clone(),
+            variant,
+            shown: record.shown_hands.keys().cloned().collect(),
+            steps,
+        }
+    }
+}
+
+/// Display name of a game variant.
+pub fn variant_name(variant: &str) -> &str {
+    match variant {
+        "5card" => "Five Card Draw",
+        "7card" => "Seven Card Stud",
+        "texas" => "Texas Hold'em",
+        other => other,
+    }
+}
+
+/// Describes an action for the replay screen.
+fn describe(player: &str, kind: ActionKind, amount: i64, total: i64) -> String {
+    match kind {
+        ActionKind::Ante => format!("{} posts the ante of {}", player, amount),
+        ActionKind::SmallBlind => format!("{} posts the small blind of {}", player, amount),
+        ActionKind::BigBlind => format!("{} posts the big blind of {}", player, amount),
+        ActionKind::Fold => format!("{} folds", player),
+        ActionKind::Check => format!("{} checks", player),
+        ActionKind::Call => format!("{} calls {}", player, amount),
+        ActionKind::Bet => format!("{} bets {}", player, amount),
+        ActionKind::Raise => format!("{} raises {} to {}", player, amount, total),
+        ActionKind::Draw if amount == 0 => format!("{} stands pat", player),
+        ActionKind::Draw => format!("{} draws {}", player, amount),
+    }
+}
+
+/// Community cards on the table during a Texas Hold'em street.
+fn board_for_street(variant: &str, street: &str, board: &[String]) -> Vec<String> {
+    if variant != "texas" {
+        return Vec::new();
+    }
+    let count = match street {
+        "flop" => 3,
+        "turn" => 4,
+        "river" => 5,
+        _ => 0,
+    };
+    board.iter().take(count).cloned().collect()
+}
+
+/// Cards each player holds during a street. Seven card stud players receive a card
+/// on every street; in the other variants every card is dealt at the start.
+fn dealt_cards(variant: &str, street: &str, hands: &[(String, Vec<String>)]) -> Vec<(String, Vec<String>)> {
+    let count = match (variant, street) {
+        ("7card", "fourth") => 4,
+        ("7card", "fifth") => 5,
+        ("7card", "sixth") => 6,
+        ("7card", "seventh") => 7,
+        ("7card", _) => 3,
+        _ => usize::MAX,
+    };
+    hands
+        .iter()
+        .map(|(name, cards)| (name.clone(), cards.iter().take(count).cloned().collect()))
+        .collect()
+}
@@ -1,25 +1,31 @@
 use crate::egui::ScrollArea;
 use crate::ui::auth::color::*;
 use crate::ui::auth::images::*;
+use crate::tls::{connect, Connection, TlsOptions};
 use crate::ui::cards::load_card_texture;
 use crate::AppState;
 use crate::Mode;
 use crate::PlayerApp;
-use crate::MSG_SIZE;
+use crate::DEALER_PORT;
 use eframe::egui;
 use eframe::egui::Frame;
 use eframe::egui::RichText;
-use serde_json::json;
-use std::io::ErrorKind;
-use std::io::Read;
-use std::io::Write;
-use std::net::TcpStream;
+use protocol::framing::{encode_frame, FrameReader, FrameWriter};
+use protocol::{ClientCommand, ServerEvent};
+use std::io::{Read, Write};
 use std::sync::mpsc;
 use std::sync::mpsc::TryRecvError;
 use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
+/// How many times in a row to try reaching the dealer again after the
+/// persistent connection drops. Together with `RECONNECT_DELAY` this stays
+/// inside the dealer's 30 second seat grace period.
+const RECONNECT_ATTEMPTS: u32 = 5;
+/// Pause between reconnect attempts.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
 /// Draws the authentication screen with login/register options.
 ///
 /// - Allows players to enter username, password, and server IP.
@@ -92,6 +98,17 @@ pub fn draw_auth_screen(app: &mut PlayerApp, ctx: &egui::Context) {
                 ui.vertical_centered(|ui| {
                     ui.label(RichText::new("Dealer IP:").italics());
                     ui.text_edit_singleline(&mut app.dealer_ip);
+                    ui.checkbox(
+                        &mut app.tls.enabled,
+                        RichText::new("Use TLS").color(TEXT_COLOR),
+                    );
+                    if app.tls.enabled {
+                        ui.label(
+                            RichText::new("Pinned certificate fingerprint (blank = trust on first use):")
+                                .italics(),
+                        );
+                        ui.text_edit_singleline(&mut app.tls.pinned_fingerprint);
+                    }
                     ui.add_space(20.0);
                 });
 
@@ -101,44 +118,34 @@ pub fn draw_auth_screen(app: &mut PlayerApp, ctx: &egui::Context) {
                         let password = app.password.clone();
                         let mode = app.mode.clone();
                         let dealer_ip = app.dealer_ip.clone();
+                        let tls_options = app.tls.clone();
                         let output_ref = Arc::clone(&app.output);
 
                         // Spawn a thread to send the one-shot auth command.
                         thread::spawn(move || {
-                            match TcpStream::connect(format!("{}:8080", dealer_ip)) {
+                            match connect(&dealer_ip, DEALER_PORT, &tls_options) {
                                 Ok(mut stream) => {
-                                    let command = match mode {
-                                        Mode::Register => "register",
-                                        Mode::Login => "login",
-                                    };
-
-                                    println!("[Client] Selected command: {}", command);
-                                    println!(
-                                        "[Client] Sending credentials - username: {}, password: {}",
-                                        username, password
-                                    );
-
-                                    let message = json!({
-                                        "command": command,
-                                        "username": username,
-                                        "password": password,
-                                    })
-                                    .to_string();
-
-                                    println!("[Client] Sending JSON: {}", message);
-
-                                    if stream.write_all(message.as_bytes()).is_ok() {
+                                    if let Err(message) = handshake(&mut stream) {
+                                        println!("[Client] Handshake failed: {}", message);
+                                        *output_ref.lock().unwrap() = ServerEvent::error(message).to_json();
+                                        return;
+                                    }
+                                    println!("[Client] Selected command: {:?} for {}", mode, username);
+
+                                    let message = match mode {
+                                        Mode::Register => ClientCommand::Register { username, password },
+                                        Mode::Login => ClientCommand::Login { username, password },
+                                    }
+                                    .to_json();
+
+                                    if stream.write_all(&encode_frame(&message)).is_ok() {
                                         println!("[Client] Message sent successfully.");
 
-                                        let mut buffer = [0; 512];
-                                        match stream.read(&mut buffer) {
-                                            Ok(size) => {
-                                                let reply =
-                                                    String::from_utf8_lossy(&buffer[..size])
-                                                        .to_string();
+                                        match FrameReader::default().read_message(&mut stream) {
+                                            Ok(reply) => {
                                                 println!(
-                                                    "[Client] Received response ({} bytes): {}",
-                                                    size, reply
+                                                    "[Client] Received response ({} bytes)",
+                                                    reply.len()
                                                 );
                                                 *output_ref.lock().unwrap() = reply;
                                             }
@@ -169,9 +176,18 @@ pub fn draw_auth_screen(app: &mut PlayerApp, ctx: &egui::Context) {
                 ui.separator();
 
                 let reply = app.output.lock().unwrap().clone();
+                // Connection problems are reported as plain text rather than events.
+                let event = ServerEvent::from_json(&reply).ok();
+                let message = match &event {
+                    Some(ServerEvent::Info { message })
+                    | Some(ServerEvent::Error { message })
+                    | Some(ServerEvent::UpgradeRequired { message, .. })
+                    | Some(ServerEvent::LoggedIn { message, .. }) => message.as_str(),
+                    _ => reply.as_str(),
+                };
 
                 ui.vertical_centered(|ui| {
-                    ui.label(format!("Server response: {}", reply));
+                    ui.label(format!("Server response: {}", message));
                 });
 
                 ui.horizontal(|ui| {
@@ -183,56 +199,17 @@ pub fn draw_auth_screen(app: &mut PlayerApp, ctx: &egui::Context) {
                     }
                 });
 
-                // If the server response indicates a successful login, spawn the persistent thread.
-                if reply.contains("game") || reply.contains("Welcome") {
+                // After a successful login, spawn the persistent thread.
+                if let Some(token) = login_token(&reply) {
                     // Create channels for communication.
                     let (ui_to_net_tx, ui_to_net_rx) = mpsc::channel::<String>();
                     let (net_to_ui_tx, net_to_ui_rx) = mpsc::channel::<String>();
                     let dealer_ip = app.dealer_ip.clone();
-                    let _username = app.username.clone();
+                    app.session_token = token.clone();
+                    let tls_options = app.tls.clone();
                     // Spawn one background thread for persistent communication.
                     thread::spawn(move || {
-                        // Connect persistently.
-                        let mut client = TcpStream::connect(format!("{}:8080", dealer_ip))
-                            .expect("Failed to connect persistently");
-                        client
-                            .set_nonblocking(true)
-                            .expect("Failed to set non-blocking");
-                        loop {
-                            // Read from the server.
-                            let mut buff = vec![0; MSG_SIZE];
-                            match client.read(&mut buff) {
-                                Ok(_) => {
-                                    let msg_bytes = buff
-                                        .into_iter()
-                                        .take_while(|&x| x != 0)
-                                        .collect::<Vec<u8>>();
-                                    if let Ok(msg_str) = String::from_utf8(msg_bytes) {
-                                        if net_to_ui_tx.send(msg_str).is_err() {
-                                            break;
-                                        }
-                                    }
-                                }
-                                Err(ref err) if err.kind() == ErrorKind::WouldBlock => (),
-                                Err(_) => {
-                                    println!("Persistent connection severed");
-                                    break;
-                                }
-                            }
-                            // Check if UI sent a message.
-                            match ui_to_net_rx.try_recv() {
-                                Ok(msg) => {
-                                    let mut buff = msg.into_bytes();
-                                    buff.resize(MSG_SIZE, 0);
-                                    if let Err(e) = client.write_all(&buff) {
-                                        println!("Failed to send message: {}", e);
-                                    }
-                                }
-                                Err(TryRecvError::Empty) => (),
-                                Err(TryRecvError::Disconnected) => break,
-                            }
-                            thread::sleep(Duration::from_millis(100));
-                        }
+                        run_session(&dealer_ip, &tls_options, &token, ui_to_net_rx, net_to_ui_tx);
                     });
 
                     // Save the channel handles in the app.
@@ -245,3 +222,141 @@ pub fn draw_auth_screen(app: &mut PlayerApp, ctx: &egui::Context) {
             });
         });
 }
+
+/// Picks the session token out of the dealer's reply to a login.
+///
+/// # Arguments
+/// * `reply` - The reply to the one-shot auth command, or a connection error.
+///
+/// # Returns
+/// The token to bind the persistent connection with, or `None` if the login
+/// did not succeed.
+pub fn login_token(reply: &str) -> Option<String> {
+    match ServerEvent::from_json(reply) {
+        Ok(ServerEvent::LoggedIn { token, .. }) => Some(token),
+        _ => None,
+    }
+}
+
+/// Keeps the persistent connection to the dealer for a logged in player.
+///
+/// Passes every message from the dealer to the UI and sends whatever the UI
+/// queues. When the connection drops, reconnects and binds the same session
+/// token again, so a seated player gets back to their seat within the dealer's
+/// grace period. Returns when the UI goes away or the dealer cannot be reached.
+///
+/// # Arguments
+/// * `dealer_ip` - Address of the dealer.
+/// * `tls_options` - How to secure the connection.
+/// * `token` - Session token from the login reply.
+/// * `ui_to_net_rx` - Messages from the UI to send to the dealer.
+/// * `net_to_ui_tx` - Messages from the dealer for the UI.
+fn run_session(
+    dealer_ip: &str,
+    tls_options: &TlsOptions,
+    token: &str,
+    ui_to_net_rx: mpsc::Receiver<String>,
+    net_to_ui_tx: mpsc::Sender<String>,
+) {
+    let mut attempts = 0;
+    loop {
+        let mut client = match open_session(dealer_ip, tls_options) {
+            Ok(client) => client,
+            Err(message) if attempts < RECONNECT_ATTEMPTS => {
+                println!("[Client] Reconnect failed: {}", message);
+                attempts += 1;
+                thread::sleep(RECONNECT_DELAY);
+                continue;
+            }
+            Err(message) => {
+                println!("[Client] Giving up on the dealer: {}", message);
+                let _ = net_to_ui_tx.send(ServerEvent::error(format!("Lost connection to the dealer: {}", message)).to_json());
+                return;
+            }
+        };
+        attempts = 0;
+        let mut reader = FrameReader::default();
+        let mut writer = FrameWriter::default();
+        // Bind this connection to our session before anything else.
+        writer.queue(&ClientCommand::Bind { token: token.to_string() }.to_json());
+        loop {
+            // Read from the server, passing on each complete message.
+            // A read error means the connection is gone.
+            let open = reader.fill_from(&mut client).unwrap_or(false);
+            loop {
+                match reader.next_message() {
+                    Ok(Some(msg)) => {
+                        if net_to_ui_tx.send(msg).is_err() {
+                            return;
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        println!("Invalid message from server: {}", e);
+                        return;
+                    }
+                }
+            }
+            if !open {
+                println!("Persistent connection severed");
+                break;
+            }
+            // Send whatever the UI queued.
+            loop {
+                match ui_to_net_rx.try_recv() {
+                    Ok(msg) => writer.queue(&msg),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return,
+                }
+            }
+            if let Err(e) = writer.flush_to(&mut client) {
+                println!("Failed to send message: {}", e);
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        if net_to_ui_tx.send(ServerEvent::info("Connection lost. Reconnecting...").to_json()).is_err() {
+            return;
+        }
+    }
+}
+
+/// Connects to the dealer, handshakes, and switches the connection to
+/// non-blocking for the persistent thread.
+fn open_session(dealer_ip: &str, tls_options: &TlsOptions) -> Result<Connection, String> {
+    let mut client = connect(dealer_ip, DEALER_PORT, tls_options).map_err(|e| format!("Connection failed: {}", e))?;
+    handshake(&mut client)?;
+    client
+        .set_nonblocking(true)
+        .map_err(|e| format!("Failed to set non-blocking: {}", e))?;
+    Ok(client)
+}
+
+/// Opens a connection with the protocol handshake.
+///
+/// Sends `hello` with this build's protocol version and waits for the dealer's
+/// answer. Must be called before anything else is sent, while the stream is
+/// still blocking.
+///
+/// # Arguments
+/// * `stream` - A freshly opened connection to the dealer.
+///
+/// # Returns
+/// The features both sides support, or the message to show the player if the
+/// dealer refused this version.
+pub fn handshake<S: Read + Write>(stream: &mut S) -> Result<Vec<String>, String> {
+    stream
+        .write_all(&encode_frame(&ClientCommand::hello().to_json()))
+        .map_err(|e| format!("Failed to send handshake: {}", e))?;
+    let reply = FrameReader::default()
+        .read_message(stream)
+        .map_err(|e| format!("Error reading handshake reply: {}", e))?;
+
+    match ServerEvent::from_json(&reply) {
+        Ok(ServerEvent::Welcome { capabilities, .. }) => Ok(capabilities),
+        Ok(ServerEvent::UpgradeRequired { message, .. }) => Err(message),
+        // Dealers from before the handshake reject `hello` as an unknown command.
+        Ok(ServerEvent::Error { message }) => Err(format!("The dealer is too old for this client: {}", message)),
+        _ => Err(format!("The dealer is too old for this client: {}", reply)),
+    }
+}
@@ -6,9 +6,9 @@ use eframe::egui;
 use eframe::egui::Align;
 use eframe::egui::Frame;
 use eframe::egui::Layout;
+use eframe::egui::RichText;
 use eframe::egui::ScrollArea;
-use serde_json::json;
-use serde_json::Value;
+use protocol::{ClientCommand, ServerEvent};
 
 /// Draws the main in-game screen.
 ///
@@ -20,114 +20,64 @@ pub fn draw_in_game(app: &mut PlayerApp, ctx: &egui::Context) {
     .frame(Frame::default().fill(BACKGROUND_COLOR))
     .show(ctx, |ui| {
         ScrollArea::vertical().show(ui, |ui| {
+            // Drain the channel, keeping the most recent event.
+            while let Some(msg) = app.next_message() {
+                match ServerEvent::from_json(&msg) {
+                    Ok(event) => app.game_event = Some(event),
+                    Err(e) => println!("[Client] Unexpected message from dealer: {}", e),
+                }
+            }
+
             let current_bet = &mut app.current_bet;
 
-            ui.heading("In-Game");
+            ui.colored_label(HEADING_COLOR, RichText::new("In-Game").heading().strong());
             ui.label("Game is running...");
 
-            if let Some(rx) = &app.net_to_ui_rx {
-                // Drain the channel, updating app.output with the most recent message.
-                while let Ok(msg) = rx.try_recv() {
-                    *app.output.lock().unwrap() = msg;
-                }
-            }
-
-            let output = app.output.lock().unwrap().clone();
-            if let Ok(parsed) = serde_json::from_str::<Value>(&output) {
-                // Print community cards
-                if let Some(community) = parsed.get("community").and_then(|v| v.as_array()) {
-                    ui.separator();
-                    ui.heading("Community Cards:");
-                    ui.horizontal(|ui| {
-                        for card in community {
-                            if let Some(card_str) = card.as_str() {
-                                if let Some(texture) = load_card_texture(ctx, card_str) {
+            match app.game_event.clone() {
+                Some(ServerEvent::Table(table)) | Some(ServerEvent::Snapshot(table)) => {
+                    // Print community cards
+                    if !table.community.is_empty() {
+                        ui.separator();
+                        ui.heading("Community Cards:");
+                        ui.horizontal(|ui| {
+                            for card in &table.community {
+                                if let Some(texture) = load_card_texture(ctx, card) {
                                     ui.image((texture.id(), egui::vec2(60.0, 100.0)));
                                 }
                             }
-                        }
-                    });
-                }
+                        });
+                    }
 
-                // Print cards
-                if let Some(cards_obj) = parsed.get("cards") {
-                    if let Some(cards_map) = cards_obj.as_object() {
+                    // Print hands. Other players' cards are face down, except the
+                    // third to sixth cards in seven card stud, which are dealt face up.
+                    if !table.hands.is_empty() {
                         ui.separator();
                         ui.label("Table Hands:");
-                        // {"cards":{"q":["5 of Diamonds","Q of Spades"],"w":["J of Spades","6 of Hearts"]}
 
-                        for (username, cards_val) in cards_map {
+                        for (username, cards) in &table.hands {
                             ui.horizontal(|ui| {
-                                if username == &app.username {
+                                let is_you = *username == app.username;
+                                if is_you {
                                     ui.label(format!("{} (You):", username));
-                                    if let Some(card_array) = cards_val.as_array() {
-                                        for card in card_array {
-                                            if let Some(card_str) = card.as_str() {
-                                                if let Some(texture) = load_card_texture(ctx, card_str) {
-                                                    ui.image((texture.id(), egui::vec2(60.0, 100.0)));
-                                                }
-                                            }
-                                        }
-                                    }
                                 } else {
-                                    // For other players, just show blanks or hidden cards
                                     ui.label(format!("{}:", username));
-                                    if let Some(card_array) = cards_val.as_array() {
-                                        for _ in card_array {
-                                            ui.label("X");
-                                        }
-                                    }
                                 }
-                            });
-                        }
-                    }
-                }
-
-                // Print 7 card hands
-                if let Some(cards_obj) = parsed.get("7 card hands") {
-                    if let Some(cards_map) = cards_obj.as_object() {
-                        ui.separator();
-                        ui.label("Hands:");
-
-                        for (username, cards_val) in cards_map {
-                            ui.horizontal(|ui| {
-                                if username == &app.username {
-                                    // Show all 3 cards to the player
-                                    ui.label(format!("{} (You):", username));
-                                    if let Some(card_array) = cards_val.as_array() {
-                                        for card in card_array {
-                                            if let Some(card_str) = card.as_str() {
-                                                if let Some(texture) = load_card_texture(ctx, card_str) {
-                                                    ui.image((texture.id(), egui::vec2(60.0, 100.0)));
-                                                }
-                                            }
-                                        }
-                                    }
-                                } else {
-                                    // Show only the third card (index 2) to others
-                                    ui.label(format!("{}:", username));
-                                    if let Some(card_array) = cards_val.as_array() {
-                                        for (i, card) in card_array.iter().enumerate() {
-                                            if i == 2 || i == 3 || i == 4 || i == 5 {
-                                                if let Some(card_str) = card.as_str() {
-                                                    if let Some(texture) = load_card_texture(ctx, card_str) {
-                                                    ui.image((texture.id(), egui::vec2(60.0, 100.0)));
-                                                }// show the face-up card
-                                                }
-                                            } else {
-                                                ui.label("X"); // hidden cards
-                                            }
+                                for (i, card) in cards.iter().enumerate() {
+                                    let stud_up_card = table.variant == "7card" && (2..6).contains(&i);
+                                    if is_you || stud_up_card {
+                                        if let Some(texture) = load_card_texture(ctx, card) {
+                                            ui.image((texture.id(), egui::vec2(60.0, 100.0)));
                                         }
+                                    } else {
+                                        ui.label("X"); // hidden cards
                                     }
                                 }
                             });
                         }
                     }
-                }
 
-                // Prompt user to bet
-                if let Some(bet_turn) = parsed.get("bet").and_then(|v| v.as_str()) {
-                    if bet_turn == app.username {
+                    // Prompt user to bet
+                    if table.to_act.as_deref() == Some(app.username.as_str()) {
                         ui.separator();
                         ui.label("It's your turn to bet!");
 
@@ -137,28 +87,24 @@ pub fn draw_in_game(app: &mut PlayerApp, ctx: &egui::Context) {
                             ui.text_edit_singleline(current_bet);
                             if ui.button("Place Bet").clicked() {
                                 if let Some(tx) = &app.ui_to_net_tx {
-                                    let bet_msg = json!({
-                                        "command": "bet",
-                                        "username": app.username,
-                                        "amount": current_bet.trim().parse::<i32>().unwrap_or(0)
-                                    })
-                                    .to_string();
-                                    let _ = tx.send(bet_msg);
+                                    let bet = ClientCommand::Bet {
+                                        username: None,
+                                        amount: current_bet.trim().parse::<i32>().unwrap_or(0),
+                                    };
+                                    let _ = tx.send(bet.to_json());
                                 }
                             }
                         });
                     }
-                }
 
-                // Print extra info
-                if let Some(info) = parsed.get("info").and_then(|v| v.as_str()) {
-                    ui.separator();
-                    ui.label(info);
-                }
+                    // Print extra info
+                    if let Some(info) = &table.info {
+                        ui.separator();
+                        ui.label(info);
+                    }
 
-                // Promp user to swap cards
-                if let Some(swap_turn) = parsed.get("swap").and_then(|v| v.as_str()) {
-                    if swap_turn == app.username {
+                    // Promp user to swap cards
+                    if table.to_swap.as_deref() == Some(app.username.as_str()) {
                         ui.separator();
                         ui.label("It's your turn to swap!");
                         ui.label("Enter the indices of the cards you want to swap (comma-separated, starting from 0).");
@@ -166,42 +112,29 @@ pub fn draw_in_game(app: &mut PlayerApp, ctx: &egui::Context) {
                         // Input field for swap indices
                         ui.horizontal(|ui| {
                             ui.label("Swap indices:");
-                            ui.text_edit_singleline(&mut app.current_swap); // reuse current_bet for simplicity
+                            ui.text_edit_singleline(&mut app.current_swap);
                             if ui.button("Submit Swap").clicked() {
                                 if let Some(tx) = &app.ui_to_net_tx {
-                                    let swap_msg = json!({
-                                        "command": "swap",
-                                        "username": app.username,
-                                        "indices": app.current_swap.trim() // send string, server will parse
-                                    })
-                                    .to_string();
-                                    let _ = tx.send(swap_msg);
+                                    let swap = ClientCommand::Swap {
+                                        username: None,
+                                        indices: app.current_swap.trim().to_string(), // server will parse
+                                    };
+                                    let _ = tx.send(swap.to_json());
                                 }
                             }
                         });
                     }
-                }
 
-                // showing the current pot amount
-                if let Some(pot) = parsed.get("pot").and_then(|v| v.as_i64()) {
-                    ui.label(format!("Pot: {}", pot));
-                }
+                    // showing the current pot amount and round bet
+                    ui.label(format!("Pot: {}", table.pot));
+                    ui.label(format!("Round current bet: {}", table.current_bet.max(0)));
 
-                // showing the current round bet
-                if let Some(mut curr_bet) = parsed.get("round current bet").and_then(|v| v.as_i64()) {
-                    if curr_bet < 0 {
-                        curr_bet = 0;
-                    }
-                    ui.label(format!("Round current bet: {}", curr_bet));
-                }
-
-                // showing player bet amount
-                if let Some(bet_amounts_map) = parsed.get("player bet amount").and_then(|v| v.as_object()) {
-                    ui.separator();
-                    ui.label("Player Bet Amounts:");
-                    for (username, value) in bet_amounts_map {
-                        if let Some(amount) = value.as_i64() {
-                            if username == &app.username {
+                    // showing player bet amount
+                    if !table.bets.is_empty() {
+                        ui.separator();
+                        ui.label("Player Bet Amounts:");
+                        for (username, amount) in &table.bets {
+                            if *username == app.username {
                                 ui.label(format!("{} (You): {}", username, amount));
                             } else {
                                 ui.label(format!("{}: {}", username, amount));
@@ -209,27 +142,44 @@ pub fn draw_in_game(app: &mut PlayerApp, ctx: &egui::Context) {
                         }
                     }
                 }
+                Some(ServerEvent::Showdown { winner, hand_id, hands, pot, community }) => {
+                    if !community.is_empty() {
+                        ui.separator();
+                        ui.label(format!("Community Cards: {}", community.join(", ")));
+                    }
 
-                // showing all hands for all players
-                if let Some(hands_obj) = parsed.get("showdown").and_then(|v| v.as_object()) {
+                    // showing all hands for all players
                     ui.label("Player Hands:");
-                    for (username, hand_value) in hands_obj.iter() {
-                        let hand_str = if username == &app.username {
-                            // For yourself, show the actual cards with a clear label
-                            format!("> You ({}) | Hand: {}", username, hand_value)
+                    for (username, cards) in &hands {
+                        let hand_str = if *username == app.username {
+                            format!("> You ({}) | Hand: {}", username, cards.join(", "))
                         } else {
-                            // For other players, display their name and hand neatly
-                            format!("  {} | Hand: {}", username, hand_value)
+                            format!("  {} | Hand: {}", username, cards.join(", "))
                         };
                         ui.label(hand_str);
                     }
-                }
-                // Showing winner
-                if let Some(winner) = parsed.get("winner").and_then(|v| v.as_str()) {
+                    ui.label(format!("Pot: {}", pot));
                     ui.label(format!("Winner is {}", winner));
+
+                    // Remember the hand so it can be replayed later
+                    ui.label(format!("Hand #{}", hand_id));
+                    app.replay_hand_id = hand_id;
+                }
+                Some(ServerEvent::HandOver { winner, hand_id }) => {
+                    ui.label(format!("Game is over, winner is {}", winner));
+                    ui.label(format!("Hand #{}", hand_id));
+                    app.replay_hand_id = hand_id;
+                }
+                Some(ServerEvent::Folded { .. }) => {
+                    ui.label("You folded");
+                }
+                Some(ServerEvent::GameStarted { players, .. }) => {
+                    ui.label(format!("Started the game: {}", players.join(", ")));
+                }
+                Some(ServerEvent::Info { message }) | Some(ServerEvent::Error { message }) => {
+                    ui.label(format!("Server: {}", message));
                 }
-            } else {
-                ui.label(format!("Server: {}", output));
+                Some(_) | None => {}
             }
 
             ui.with_layout(Layout::bottom_up(Align::Center), |ui| {
@@ -1,9 +1,11 @@
 pub mod auth;
 pub mod in_game;
+pub mod replay;
 pub mod stats;
 pub mod waiting;
 
 pub use auth::draw_auth_screen;
 pub use in_game::draw_in_game;
+pub use replay::draw_replay;
 pub use stats::draw_stats_page;
 pub use waiting::draw_ready;
@@ -0,0 +1,151 @@
+use crate::egui::RichText;
+use crate::egui::ScrollArea;
+use crate::replay::variant_name;
+use crate::replay::HandReplay;
+use crate::ui::cards::images::load_card_texture;
+use crate::ui::replay::color::*;
+use crate::AppState;
+use crate::PlayerApp;
+use eframe::egui;
+use eframe::egui::Align;
+use eframe::egui::Frame;
+use eframe::egui::Layout;
+use protocol::ClientCommand;
+
+/// Draws the hand replay screen.
+///
+/// - Fetches a stored hand from the dealer by its ID.
+/// - Steps through the hand action by action, forward and back.
+/// - Shows the cards, the pot and each player's bet at the current step.
+pub fn draw_replay(app: &mut PlayerApp, ctx: &egui::Context) {
+    egui::CentralPanel::default()
+        .frame(Frame::default().fill(BACKGROUND_COLOR))
+        .show(ctx, |ui| {
+            ScrollArea::vertical().show(ui, |ui| {
+                // The only reply expected here is the requested hand.
+                while let Some(msg) = app.next_message() {
+                    match HandReplay::from_json(&msg) {
+                        Ok(replay) => {
+                            app.replay_status = String::new();
+                            app.replay = Some(replay);
+                            app.replay_step = 0;
+                        }
+                        Err(e) => app.replay_status = e,
+                    }
+                }
+
+                ui.vertical_centered(|ui| {
+                    ui.colored_label(HEADING_COLOR, RichText::new("Hand Replay").heading().strong());
+                    ui.add_space(10.0);
+                });
+
+                ui.horizontal(|ui| {
+                    ui.label("Hand ID:");
+                    ui.text_edit_singleline(&mut app.replay_hand_id);
+                    if ui.button("Load").clicked() {
+                        if let Some(tx) = &app.ui_to_net_tx {
+                            let msg = ClientCommand::HandHistory {
+                                hand_id: app.replay_hand_id.trim().to_string(),
+                            };
+                            let _ = tx.send(msg.to_json());
+                            app.replay_status = "Loading...".to_string();
+                        }
+                    }
+                });
+
+                if !app.replay_status.is_empty() {
+                    ui.label(&app.replay_status);
+                }
+
+                if let Some(replay) = &app.replay {
+                    let last = replay.steps.len().saturating_sub(1);
+                    app.replay_step = app.replay_step.min(last);
+                    let step = &replay.steps[app.replay_step];
+
+                    ui.separator();
+                    ui.label(format!("Hand #{} - {}", replay.hand_id, variant_name(&replay.variant)));
+                    if !step.street.is_empty() {
+                        ui.colored_label(STREET_COLOR, format!("Street: {}", step.street));
+                    }
+                    ui.label(RichText::new(&step.description).strong());
+                    ui.label(format!("Step {} of {}", app.replay_step + 1, last + 1));
+
+                    ui.horizontal(|ui| {
+                        if ui.button("|<").clicked() {
+                            app.replay_step = 0;
+                        }
+                        if ui.button("< Back").clicked() {
+                            app.replay_step = app.replay_step.saturating_sub(1);
+                        }
+                        if ui.button("Forward >").clicked() {
+                            app.replay_step = (app.replay_step + 1).min(last);
+                        }
+                        if ui.button(">|").clicked() {
+                            app.replay_step = last;
+                        }
+                    });
+
+                    // Board
+                    if !step.board.is_empty() {
+                        ui.separator();
+                        ui.heading("Community Cards:");
+                        ui.horizontal(|ui| {
+                            for card in &step.board {
+                                if let Some(texture) = load_card_texture(ctx, card) {
+                                    ui.image((texture.id(), egui::vec2(60.0, 100.0)));
+                                }
+                            }
+                        });
+                    }
+
+                    // Hands. Cards are face up if they are yours, were shown at the end, or
+                    // were dealt face up in seven card stud.
+                    ui.separator();
+                    ui.label("Hands:");
+                    let at_end = app.replay_step == last;
+                    for (player, cards) in &step.hands {
+                        let face_up = *player == app.username
+                            || (at_end && replay.shown.contains(player));
+                        ui.horizontal(|ui| {
+                            let mut label = player.clone();
+                            if *player == app.username {
+                                label.push_str(" (You)");
+                            }
+                            if step.folded.contains(player) {
+                                label.push_str(" [folded]");
+                            }
+                            ui.label(format!("{}:", label));
+
+                            for (i, card) in cards.iter().enumerate() {
+                                let stud_up_card = replay.variant == "7card" && (2..6).contains(&i);
+                                if face_up || stud_up_card {
+                                    if let Some(texture) = load_card_texture(ctx, card) {
+                                        ui.image((texture.id(), egui::vec2(60.0, 100.0)));
+                                    }
+                                } else {
+                                    ui.label("X");
+                                }
+                            }
+                        });
+                    }
+
+                    // Pot and bets
+                    ui.separator();
+                    ui.label(format!("Pot: {}", step.pot));
+                    ui.label("Bets This Street:");
+                    for (player, bet) in &step.bets {
+                        ui.label(format!("{}: {}", player, bet));
+                    }
+                }
+
+                ui.add_space(20.0);
+                ui.with_layout(Layout::bottom_up(Align::Center), |ui| {
+                    ui.vertical_centered(|ui| {
+                        if ui.button("Back").clicked() {
+                            app.state = AppState::Ready;
+                        }
+                    });
+                });
+            });
+        });
+}
@@ -8,28 +8,27 @@ use eframe::egui::Align;
 use eframe::egui::Frame;
 use eframe::egui::Layout;
 use egui::{Color32, Vec2};
+use protocol::{BehaviourStats, ClientCommand, LeaderboardPeriod, LeaderboardSort, PlayerStats, ServerEvent};
+use crate::bankroll::{chart_points, date_range, format_date};
+use crate::leaderboard::VARIANTS;
 
 /// Draws the player statistics page.
-/// - Displays a list of players.
+/// - Displays the leaderboard, ranked and filtered as chosen, a page at a time.
 /// - Allows searching for specific player statistics.
-/// - Displays detailed user stats.
+/// - Displays detailed user stats, per variant, and their habits at the table.
+/// - Takes an optional date range for the stats, and charts the bankroll over it.
 pub fn draw_stats_page(app: &mut PlayerApp, ctx: &egui::Context) {
     egui::CentralPanel::default()
         .frame(Frame::default().fill(BACKGROUND_COLOR))
         .show(ctx, |ui| {
             ScrollArea::vertical().show(ui, |ui| {
                 // Process incoming messages from the network thread.
-                if let Some(rx) = &app.net_to_ui_rx {
-                    while let Ok(msg) = rx.try_recv() {
-                        // If the message contains the searched username, assume it's the stats reply.
-                        if !app.stats_search_query.is_empty()
-                            && msg.contains(&app.stats_search_query)
-                        {
-                            app.user_stats = msg;
-                        } else {
-                            // Otherwise, assume it's the list of users.
-                            *app.output.lock().unwrap() = msg;
-                        }
+                while let Some(msg) = app.next_message() {
+                    match ServerEvent::from_json(&msg) {
+                        Ok(ServerEvent::Leaderboard(page)) => app.leaderboard.shown = Some(page),
+                        Ok(ServerEvent::PlayerStats(stats)) => app.user_stats = Ok(Some(stats)),
+                        Ok(ServerEvent::Error { message }) => app.user_stats = Err(message),
+                        _ => {}
                     }
                 }
 
@@ -47,15 +46,12 @@ pub fn draw_stats_page(app: &mut PlayerApp, ctx: &egui::Context) {
                     ui.vertical_centered(|ui| {
                         ui.colored_label(
                             LIST_HEADER_COLOR,
-                            RichText::new("Users").heading().strong(),
+                            RichText::new("Leaderboard").heading().strong(),
                         );
                         ui.add_space(10.0);
                     });
 
-                    let list_output = app.output.lock().unwrap().clone();
-                    for user in list_output.split(',').map(|s| s.trim()) {
-                        ui.label(RichText::new(format!("    - {}", user)).color(Color32::WHITE));
-                    }
+                    draw_leaderboard(app, ui);
                 });
 
                 ui.separator();
@@ -65,86 +61,80 @@ pub fn draw_stats_page(app: &mut PlayerApp, ctx: &egui::Context) {
                     ui.label(RichText::new("Search user:").color(LIST_HEADER_COLOR));
                     ui.text_edit_singleline(&mut app.stats_search_query);
                     if ui.button("Get").clicked() {
-                        if let Some(tx) = &app.ui_to_net_tx {
-                            // Send a JSON message to the server to request stats for the specified user.
-                            let msg = serde_json::json!({
-                                "command": "get_user_stats",
-                                "username": app.stats_search_query,
-                            })
-                            .to_string();
-                            let _ = tx.send(msg);
+                        match date_range(&app.stats_from, &app.stats_to) {
+                            Ok((from, to)) => {
+                                if let Some(tx) = &app.ui_to_net_tx {
+                                    // Ask the server for the specified user's stats.
+                                    let msg = ClientCommand::GetUserStats {
+                                        username: app.stats_search_query.clone(),
+                                        from,
+                                        to,
+                                    };
+                                    let _ = tx.send(msg.to_json());
+                                }
+                            }
+                            Err(message) => app.user_stats = Err(message),
                         }
                     }
                 });
+                ui.horizontal(|ui| {
+                    ui.label(RichText::new("From:").color(LIST_HEADER_COLOR));
+                    ui.add(egui::TextEdit::singleline(&mut app.stats_from).hint_text("YYYY-MM-DD").desired_width(90.0));
+                    ui.label(RichText::new("To:").color(LIST_HEADER_COLOR));
+                    ui.add(egui::TextEdit::singleline(&mut app.stats_to).hint_text("YYYY-MM-DD").desired_width(90.0));
+                    ui.label(RichText::new("(leave blank for all time)").color(USER_ITEM_COLOR));
+                });
 
                 ui.separator();
 
                 // Display the searched user's stats if available.
-                if !app.user_stats.is_empty() {
-                    ui.group(|ui| {
-                        ui.set_min_size(Vec2::new(ui.available_width(), 0.0));
-                        ui.label(
-                            RichText::new("User Stats:")
-                                .strong()
-                                .underline()
-                                .color(USER_NAME_COLOR),
-                        );
-
-                        match serde_json::from_str::<serde_json::Value>(&app.user_stats) {
-                            Ok(parsed) => {
-                                let name =
-                                    parsed.get("name").and_then(|v| v.as_str()).unwrap_or("N/A");
-                                let wins = parsed.get("wins").and_then(|v| v.as_i64()).unwrap_or(0);
-                                let losses =
-                                    parsed.get("losses").and_then(|v| v.as_i64()).unwrap_or(0);
-                                let games_played = parsed
-                                    .get("games_played")
-                                    .and_then(|v| v.as_i64())
-                                    .unwrap_or(0);
-                                let money_win = parsed
-                                    .get("money_win")
-                                    .and_then(|v| v.as_i64())
-                                    .unwrap_or(0);
-                                let money_lost = parsed
-                                    .get("money_lost")
-                                    .and_then(|v| v.as_i64())
-                                    .unwrap_or(0);
-
-                                ui.label(
-                                    RichText::new(format!("• Name: {}", name))
-                                        .color(USER_ITEM_COLOR),
-                                );
-                                ui.label(
-                                    RichText::new(format!("• Wins: {}", wins))
-                                        .color(USER_ITEM_COLOR),
-                                );
-                                ui.label(
-                                    RichText::new(format!("• Losses: {}", losses))
-                                        .color(USER_ITEM_COLOR),
-                                );
-                                ui.label(
-                                    RichText::new(format!(
-                                        "• Total Games Played: {}",
-                                        games_played
-                                    ))
+                match &app.user_stats {
+                    Ok(Some(stats)) => {
+                        ui.group(|ui| {
+                            ui.set_min_size(Vec2::new(ui.available_width(), 0.0));
+                            ui.label(
+                                RichText::new("User Stats:")
+                                    .strong()
+                                    .underline()
+                                    .color(USER_NAME_COLOR),
+                            );
+                            ui.label(
+                                RichText::new(format!("• Name: {}", stats.name))
                                     .color(USER_ITEM_COLOR),
-                                );
-                                ui.label(
-                                    RichText::new(format!("• Money Won: ${}", money_win))
-                                        .color(Color32::GREEN),
-                                );
-                                ui.label(
-                                    RichText::new(format!("• Money Lost: ${}", money_lost))
-                                        .color(Color32::RED),
-                                );
-                            }
-                            Err(_) => {
-                                ui.label(
-                                    RichText::new("Invalid user stats data.").color(Color32::RED),
-                                );
-                            }
-                        }
-                    });
+                            );
+                            ui.label(
+                                RichText::new(format!("• Wins: {}", stats.wins))
+                                    .color(USER_ITEM_COLOR),
+                            );
+                            ui.label(
+                                RichText::new(format!("• Losses: {}", stats.losses))
+                                    .color(USER_ITEM_COLOR),
+                            );
+                            ui.label(
+                                RichText::new(format!(
+                                    "• Total Games Played: {}",
+                                    stats.games_played
+                                ))
+                                .color(USER_ITEM_COLOR),
+                            );
+                            ui.label(
+                                RichText::new(format!("• Money Won: ${}", stats.money_win))
+                                    .color(Color32::GREEN),
+                            );
+                            ui.label(
+                                RichText::new(format!("• Money Lost: ${}", stats.money_lost))
+                                    .color(Color32::RED),
+                            );
+                            ui.add_space(10.0);
+                            draw_variant_stats(ui, stats);
+                            ui.add_space(10.0);
+                            draw_bankroll_chart(ui, stats);
+                        });
+                    }
+                    Ok(None) => {}
+                    Err(message) => {
+                        ui.label(RichText::new(message).color(Color32::RED));
+                    }
                 }
 
                 ui.with_layout(Layout::bottom_up(Align::Center), |ui| {
@@ -157,3 +147,218 @@ pub fn draw_stats_page(app: &mut PlayerApp, ctx: &egui::Context) {
             });
         });
 }
+
+/// Draws the leaderboard's filters, the page of ranked players and the page buttons.
+/// Changing a filter goes back to the first page and asks the dealer for it.
+fn draw_leaderboard(app: &mut PlayerApp, ui: &mut egui::Ui) {
+    let board = &mut app.leaderboard;
+    let before = (board.sort, board.variant.clone(), board.period);
+
+    ui.horizontal(|ui| {
+        ui.label(RichText::new("Rank by:").color(LIST_HEADER_COLOR));
+        egui::ComboBox::from_id_source("leaderboard_sort")
+            .selected_text(board.sort.label())
+            .show_ui(ui, |ui| {
+                for sort in LeaderboardSort::ALL {
+                    ui.selectable_value(&mut board.sort, sort, sort.label());
+                }
+            });
+
+        ui.label(RichText::new("Game:").color(LIST_HEADER_COLOR));
+        egui::ComboBox::from_id_source("leaderboard_variant")
+            .selected_text(board.variant_label().to_string())
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut board.variant, None, "All variants");
+                for (id, label) in VARIANTS {
+                    ui.selectable_value(&mut board.variant, Some(id.to_string()), label);
+                }
+            });
+
+        ui.label(RichText::new("Period:").color(LIST_HEADER_COLOR));
+        egui::ComboBox::from_id_source("leaderboard_period")
+            .selected_text(board.period.label())
+            .show_ui(ui, |ui| {
+                for period in LeaderboardPeriod::ALL {
+                    ui.selectable_value(&mut board.period, period, period.label());
+                }
+            });
+    });
+
+    let mut changed = before != (board.sort, board.variant.clone(), board.period);
+    if changed {
+        board.page = 1;
+    }
+    ui.add_space(5.0);
+
+    match &board.shown {
+        Some(page) if page.entries.is_empty() => {
+            ui.label(RichText::new("No hands played yet.").color(USER_ITEM_COLOR));
+        }
+        Some(page) => {
+            egui::Grid::new("leaderboard_grid").striped(true).show(ui, |ui| {
+                for heading in ["#", "Player", "Net", "Win rate", "Games", "Biggest pot"] {
+                    ui.label(RichText::new(heading).strong().color(USER_NAME_COLOR));
+                }
+                ui.end_row();
+
+                for entry in &page.entries {
+                    let net_color = if entry.net_winnings < 0 { Color32::RED } else { Color32::GREEN };
+                    ui.label(RichText::new(entry.rank.to_string()).color(USER_ITEM_COLOR));
+                    ui.label(RichText::new(&entry.name).color(USER_ITEM_COLOR));
+                    ui.label(RichText::new(format!("{:+}", entry.net_winnings)).color(net_color));
+                    ui.label(RichText::new(format!("{:.1}%", entry.win_rate * 100.0)).color(USER_ITEM_COLOR));
+                    ui.label(RichText::new(entry.games_played.to_string()).color(USER_ITEM_COLOR));
+                    ui.label(RichText::new(format!("${}", entry.biggest_pot)).color(USER_ITEM_COLOR));
+                    ui.end_row();
+                }
+            });
+        }
+        None => {
+            ui.label(RichText::new("Loading...").color(USER_ITEM_COLOR));
+        }
+    }
+
+    ui.horizontal(|ui| {
+        if ui.button("< Prev").clicked() {
+            changed |= board.previous_page();
+        }
+        let pages = board.shown.as_ref().map_or(1, |page| page.page_count());
+        ui.label(RichText::new(format!("Page {} of {}", board.page, pages)).color(USER_ITEM_COLOR));
+        if ui.button("Next >").clicked() {
+            changed |= board.next_page();
+        }
+    });
+
+    if changed {
+        if let Some(tx) = &app.ui_to_net_tx {
+            let _ = tx.send(app.leaderboard.request().to_json());
+        }
+    }
+}
+
+/// Column headings of the per-variant table, with what each one means.
+const VARIANT_COLUMNS: [(&str, &str); 10] = [
+    ("Game", "Which variant the row counts"),
+    ("Games", "Games played"),
+    ("Wins", "Games won"),
+    ("Net", "Money won minus money lost"),
+    ("Hands", "Hands in the history the habits are worked out from"),
+    ("VPIP", "Voluntarily put money in pot: how often you call, bet or raise in the first betting round"),
+    ("PFR", "Pre-flop raise: how often you bet or raise in the first betting round"),
+    ("AF", "Aggression factor: bets and raises per call"),
+    ("WTSD", "Went to showdown: how often you stay in until the showdown"),
+    ("W$SD", "Won at showdown: how often you win once you get there"),
+];
+
+/// Draws a searched player's stats for every variant they played, and overall,
+/// with their habits from the hand history.
+fn draw_variant_stats(ui: &mut egui::Ui, stats: &PlayerStats) {
+    ui.label(RichText::new("By Variant:").strong().underline().color(USER_NAME_COLOR));
+    egui::Grid::new("variant_stats_grid").striped(true).show(ui, |ui| {
+        for (heading, meaning) in VARIANT_COLUMNS {
+            ui.label(RichText::new(heading).strong().color(USER_NAME_COLOR)).on_hover_text(meaning);
+        }
+        ui.end_row();
+
+        let overall = (stats.games_played, stats.wins, stats.money_win + stats.money_lost);
+        draw_variant_row(ui, "All games", overall, &stats.behaviour);
+        for (variant, totals) in &stats.variants {
+            let label = VARIANTS.iter().find(|(id, _)| id == variant).map_or(variant.as_str(), |(_, label)| label);
+            let row = (totals.games_played, totals.wins, totals.money_win + totals.money_lost);
+            draw_variant_row(ui, label, row, &totals.behaviour);
+        }
+    });
+}
+
+/// Draws one row of the per-variant table.
+///
+/// # Arguments
+/// * `ui` - The grid being drawn.
+/// * `label` - Name of the variant, or "All games".
+/// * `(games, wins, net)` - The player's totals for the row.
+/// * `behaviour` - The player's habits in the row's hands.
+fn draw_variant_row(ui: &mut egui::Ui, label: &str, (games, wins, net): (i32, i32, i32), behaviour: &BehaviourStats) {
+    let percent = |rate: f64| format!("{:.0}%", rate * 100.0);
+    let net_color = if net < 0 { Color32::RED } else { Color32::GREEN };
+    ui.label(RichText::new(label).color(USER_ITEM_COLOR));
+    ui.label(RichText::new(games.to_string()).color(USER_ITEM_COLOR));
+    ui.label(RichText::new(wins.to_string()).color(USER_ITEM_COLOR));
+    ui.label(RichText::new(format!("{:+}", net)).color(net_color));
+    ui.label(RichText::new(behaviour.hands.to_string()).color(USER_ITEM_COLOR));
+    for value in [
+        percent(behaviour.vpip),
+        percent(behaviour.pfr),
+        format!("{:.1}", behaviour.aggression_factor),
+        percent(behaviour.went_to_showdown),
+        percent(behaviour.won_at_showdown),
+    ] {
+        ui.label(RichText::new(value).color(USER_ITEM_COLOR));
+    }
+    ui.end_row();
+}
+
+/// Draws the searched player's bankroll after each hand as a line chart, with
+/// the zero line, the highest and lowest bankroll and the dates at either end.
+/// Hovering shows the nearest hand's date and bankroll.
+fn draw_bankroll_chart(ui: &mut egui::Ui, stats: &PlayerStats) {
+    let range = match (stats.from, stats.to) {
+        (None, None) => "all time".to_string(),
+        (from, to) => format!(
+            "{} to {}",
+            from.map_or("the start".to_string(), format_date),
+            to.map_or("now".to_string(), format_date)
+        ),
+    };
+    ui.label(RichText::new(format!("Bankroll ({}):", range)).strong().underline().color(USER_NAME_COLOR));
+    if stats.bankroll.is_empty() {
+        ui.label(RichText::new("No hands in this range.").color(USER_ITEM_COLOR));
+        return;
+    }
+
+    let (response, painter) = ui.allocate_painter(Vec2::new(ui.available_width(), 180.0), egui::Sense::hover());
+    let frame = response.rect;
+    painter.rect_filled(frame, 4.0, Color32::from_black_alpha(60));
+    let plot = frame.shrink2(Vec2::new(10.0, 20.0));
+    let points = chart_points(&stats.bankroll, plot);
+
+    // The zero line, where the player is even
+    let low = stats.bankroll.iter().map(|p| p.bankroll).min().unwrap_or(0).min(0);
+    let high = stats.bankroll.iter().map(|p| p.bankroll).max().unwrap_or(0).max(0);
+    if high > low {
+        let zero = plot.bottom() - (-low) as f32 / (high - low) as f32 * plot.height();
+        painter.hline(plot.x_range(), zero, egui::Stroke::new(1.0, Color32::DARK_GRAY));
+    }
+
+    if points.len() == 1 {
+        painter.circle_filled(points[0], 3.0, USER_NAME_COLOR);
+    } else {
+        painter.add(egui::Shape::line(points.clone(), egui::Stroke::new(2.0, USER_NAME_COLOR)));
+    }
+
+    let font = egui::FontId::proportional(12.0);
+    painter.text(frame.left_top() + Vec2::new(4.0, 2.0), egui::Align2::LEFT_TOP, format!("{:+}", high), font.clone(), USER_ITEM_COLOR);
+    painter.text(frame.left_bottom() + Vec2::new(4.0, -2.0), egui::Align2::LEFT_BOTTOM, format!("{:+}", low), font.clone(), USER_ITEM_COLOR);
+    if let (Some(first), Some(last)) = (stats.bankroll.first(), stats.bankroll.last()) {
+        painter.text(frame.center_bottom() + Vec2::new(-40.0, -2.0), egui::Align2::RIGHT_BOTTOM, format_date(first.timestamp), font.clone(), USER_ITEM_COLOR);
+        painter.text(frame.right_bottom() + Vec2::new(-4.0, -2.0), egui::Align2::RIGHT_BOTTOM, format_date(last.timestamp), font.clone(), USER_ITEM_COLOR);
+    }
+
+    if let Some(hover) = response.hover_pos() {
+        let nearest = points
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| (a.x - hover.x).abs().total_cmp(&(b.x - hover.x).abs()))
+            .map(|(i, _)| i);
+        if let Some(i) = nearest {
+            let point = &stats.bankroll[i];
+            painter.circle_filled(points[i], 4.0, Color32::WHITE);
+            painter.text(
+                frame.center_top() + Vec2::new(0.0, 2.0),
+                egui::Align2::CENTER_TOP,
+                format!("{}: {:+}", format_date(point.timestamp), point.bankroll),
+                font,
+                Color32::WHITE,
+            );
+        }
+    }
+}
@@ -8,14 +8,35 @@ use eframe::egui;
 use eframe::egui::Align;
 use eframe::egui::Frame;
 use eframe::egui::Layout;
-use serde_json::json;
+use protocol::{ClientCommand, ServerEvent};
+
+/// Goes to the table if the dealer has sent a snapshot of the hand we are seated
+/// in, as it does after a reconnect.
+///
+/// Every message read, the snapshot included, is held in order for the next
+/// screen (see `PlayerApp::next_message`), so nothing sent meanwhile is lost.
+pub fn return_on_snapshot(app: &mut PlayerApp) {
+    let Some(rx) = &app.net_to_ui_rx else {
+        return;
+    };
+    while let Ok(msg) = rx.try_recv() {
+        if let Ok(ServerEvent::Snapshot(_)) = ServerEvent::from_json(&msg) {
+            app.state = AppState::InGame;
+        }
+        app.held_messages.push_back(msg);
+    }
+}
 
 /// Draws the "Ready" screen where the player can start the game or view stats.
 ///
 /// - Allows the player to signal they are ready to play the game.
 /// - Allows navigating to the stats page
 /// - Allows player to spectate the current game
+/// - Allows replaying a stored hand
+/// - Returns straight to the table when reconnecting to a hand in progress
 pub fn draw_ready(app: &mut PlayerApp, ctx: &egui::Context) {
+    return_on_snapshot(app);
+
     egui::CentralPanel::default()
         .frame(Frame::default().fill(BACKGROUND_COLOR))
         .show(ctx, |ui| {
@@ -53,23 +74,14 @@ pub fn draw_ready(app: &mut PlayerApp, ctx: &egui::Context) {
                     ui.vertical_centered(|ui| {
                         if ui.button("Ready").clicked() {
                             if let Some(tx) = &app.ui_to_net_tx {
-                                let msg = json!({
-                                    "command": "ready",
-                                    "username": app.username
-                                })
-                                .to_string();
-                                let _ = tx.send(msg);
+                                let _ = tx.send(ClientCommand::Ready { username: None }.to_json());
                             }
                             app.state = AppState::InGame;
                         }
 
                         if ui.button("See Stats").clicked() {
                             if let Some(tx) = &app.ui_to_net_tx {
-                                let msg = json!({
-                                    "command": "stats",
-                                })
-                                .to_string();
-                                let _ = tx.send(msg);
+                                let _ = tx.send(app.leaderboard.request().to_json());
                             }
                             app.state = AppState::Stats;
                         }
@@ -77,6 +89,10 @@ pub fn draw_ready(app: &mut PlayerApp, ctx: &egui::Context) {
                         if ui.button("Spectate").clicked() {
                             app.state = AppState::Spectator;
                         }
+
+                        if ui.button("Replay Hand").clicked() {
+                            app.state = AppState::Replay;
+                        }
                     });
                 });
             });
@@ -1,5 +1,10 @@
+// The tests mimic the UI editing fields on a default app, one at a time.
+#![allow(clippy::field_reassign_with_default)]
+
 #[cfg(test)]
 use crate::*;
+#[cfg(test)]
+use std::sync::mpsc;
 
 /// 1) Ensure PlayerApp::default() yields expected default values.
 #[test]
@@ -98,6 +103,7 @@ fn test_stats_search_query_input() {
 fn test_user_stats_parsing() {
     let mut app = PlayerApp::default();
     let json_data = r#"{
+            "event": "player_stats",
             "name": "testuser",
             "wins": 5,
             "losses": 3,
@@ -107,12 +113,14 @@ fn test_user_stats_parsing() {
         }"#;
 
     app.stats_search_query = "testuser".to_string();
-    app.user_stats = json_data.to_string();
+    if let Ok(ServerEvent::PlayerStats(stats)) = ServerEvent::from_json(json_data) {
+        app.user_stats = Ok(Some(stats));
+    }
 
-    let parsed: Value = serde_json::from_str(&app.user_stats).unwrap();
-    assert_eq!(parsed["name"], "testuser");
-    assert_eq!(parsed["wins"], 5);
-    assert_eq!(parsed["games_played"], 8);
+    let stats = app.user_stats.unwrap().unwrap();
+    assert_eq!(stats.name, "testuser");
+    assert_eq!(stats.wins, 5);
+    assert_eq!(stats.games_played, 8);
 }
 
 #[test]
@@ -140,3 +148,289 @@ fn test_current_bet_and_swap_input() {
     assert_eq!(app.current_bet, "10");
     assert_eq!(app.current_swap, "0,2,4");
 }
+
+#[test]
+fn test_replay_steps_track_pot_and_bets() {
+    let json = r#"{
+        "event": "hand_history",
+        "hand_id": "42", "timestamp": 0, "variant": "texas", "shuffle_seed": "00",
+        "seats": [
+            {"seat": 1, "player": "alice", "stack": 0, "cards": ["A of Hearts", "K of Hearts"]},
+            {"seat": 2, "player": "bob", "stack": 0, "cards": ["2 of Clubs", "7 of Spades"]}
+        ],
+        "streets": [
+            {"name": "preflop", "actions": [
+                {"player": "alice", "action": "small_blind", "amount": 2, "total": 2},
+                {"player": "bob", "action": "big_blind", "amount": 4, "total": 4},
+                {"player": "alice", "action": "raise", "amount": 10, "total": 12},
+                {"player": "bob", "action": "call", "amount": 8, "total": 12}
+            ]},
+            {"name": "flop", "actions": [
+                {"player": "alice", "action": "bet", "amount": 10, "total": 10},
+                {"player": "bob", "action": "fold", "amount": 0, "total": 0}
+            ]}
+        ],
+        "board": ["Q of Hearts", "J of Hearts", "10 of Hearts"],
+        "shown_hands": {},
+        "pots": [{"player": "alice", "amount": 34}],
+        "total_pot": 34
+    }"#;
+
+    let replay = replay::HandReplay::from_json(json).unwrap();
+    assert_eq!(replay.hand_id, "42");
+    // deal + 4 preflop actions + flop header + 2 flop actions + result
+    assert_eq!(replay.steps.len(), 9);
+
+    let raise = &replay.steps[3];
+    assert_eq!(raise.description, "alice raises 10 to 12");
+    assert_eq!(raise.pot, 16);
+    assert_eq!(raise.bets, vec![("alice".to_string(), 12), ("bob".to_string(), 4)]);
+    assert!(raise.board.is_empty());
+
+    let flop = &replay.steps[5];
+    assert_eq!(flop.board.len(), 3);
+    assert_eq!(flop.bets, vec![("alice".to_string(), 0), ("bob".to_string(), 0)]);
+    assert_eq!(flop.pot, 24);
+
+    let end = replay.steps.last().unwrap();
+    assert_eq!(end.folded, vec!["bob".to_string()]);
+    assert_eq!(end.pot, 34);
+    assert_eq!(end.description, "alice wins 34");
+}
+
+#[test]
+fn test_replay_undoes_draws_before_the_draw() {
+    let json = r#"{
+        "event": "hand_history",
+        "hand_id": "7", "timestamp": 0, "variant": "5card", "shuffle_seed": "00",
+        "seats": [{"seat": 1, "player": "carol", "stack": 0,
+            "cards": ["A of Spades", "9 of Hearts", "A of Clubs", "3 of Diamonds", "A of Hearts"]}],
+        "streets": [
+            {"name": "predraw", "actions": []},
+            {"name": "draw", "actions": [
+                {"player": "carol", "action": "draw", "amount": 2, "total": 0,
+                 "discarded": ["2 of Clubs", "5 of Hearts"], "drawn": ["A of Clubs", "A of Hearts"]}
+            ]}
+        ],
+        "board": [], "shown_hands": {"carol": []}, "pots": [], "total_pot": 0
+    }"#;
+
+    let replay = replay::HandReplay::from_json(json).unwrap();
+    let dealt = &replay.steps[0].hands[0].1;
+    assert_eq!(dealt[2], "2 of Clubs");
+    assert_eq!(dealt[4], "5 of Hearts");
+
+    let drawn = replay.steps.iter().find(|s| s.description == "carol draws 2").unwrap();
+    assert_eq!(drawn.hands[0].1[2], "A of Clubs");
+    assert_eq!(replay.shown, vec!["carol".to_string()]);
+}
+
+#[test]
+fn test_replay_reports_server_errors() {
+    let err = replay::HandReplay::from_json(r#"{"event": "error", "message": "No hand found with id: 1"}"#).unwrap_err();
+    assert_eq!(err, "No hand found with id: 1");
+    assert!(replay::HandReplay::from_json("not json").is_err());
+}
+
+#[test]
+fn test_login_reply_carries_session_token() {
+    use crate::screens::auth::login_token;
+
+    let reply = r#"{"event":"logged_in","message":"Welcome, test! You are now in the waiting room.","username":"test","token":"abc123"}"#;
+    assert_eq!(login_token(reply), Some("abc123".to_string()));
+
+    // Refused logins and connection problems carry no token
+    assert_eq!(login_token(&ServerEvent::error("Invalid password.").to_json()), None);
+    assert_eq!(login_token(&ServerEvent::info("Registration successful.").to_json()), None);
+    assert_eq!(login_token("Connection failed: connection refused"), None);
+}
+
+#[test]
+fn test_tls_fingerprint_pinning_and_first_use() {
+    use crate::tls::{check_fingerprint, Trust};
+
+    let fp = "AB:CD:EF:01";
+    // Pinned fingerprints must match, ignoring case and separators
+    assert_eq!(check_fingerprint("", "10.0.0.5:8080", fp, "abcdef01"), Ok(Trust::Known));
+    assert!(check_fingerprint("", "10.0.0.5:8080", fp, "AB:CD:EF:02").is_err());
+
+    // Without a pin, the first certificate is trusted and later ones must match it
+    assert_eq!(check_fingerprint("", "10.0.0.5:8080", fp, ""), Ok(Trust::FirstUse));
+    let known = "10.0.0.9:8080 11:22\n10.0.0.5:8080 AB:CD:EF:01\n";
+    assert_eq!(check_fingerprint(known, "10.0.0.5:8080", fp, ""), Ok(Trust::Known));
+    assert!(check_fingerprint(known, "10.0.0.9:8080", fp, "").is_err());
+
+    assert_eq!(crate::tls::fingerprint(b"").len(), 32 * 3 - 1);
+    assert!(!PlayerApp::default().tls.enabled);
+}
+
+#[test]
+fn test_framing_reassembles_split_messages() {
+    use protocol::framing::{encode_frame, FrameReader};
+
+    let long = "y".repeat(5000);
+    let mut bytes = encode_frame("hello");
+    bytes.extend(encode_frame(&long));
+
+    // The reader pulls 4096 bytes at a time, so the second message arrives in
+    // pieces, and the first read also returns the start of the second message.
+    let mut reader = FrameReader::default();
+    let mut socket = bytes.as_slice();
+    assert_eq!(reader.read_message(&mut socket).unwrap(), "hello");
+    assert_eq!(reader.read_message(&mut socket).unwrap(), long);
+    assert!(reader.read_message(&mut socket).is_err());
+}
+
+#[test]
+fn test_handshake_reports_upgrade_required() {
+    use crate::screens::auth::handshake;
+    use protocol::framing::encode_frame;
+    use std::io::{Read, Write};
+
+    /// Replays canned dealer replies and keeps what the client wrote.
+    struct FakeDealer {
+        replies: std::io::Cursor<Vec<u8>>,
+        sent: Vec<u8>,
+    }
+    impl Read for FakeDealer {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.replies.read(buf)
+        }
+    }
+    impl Write for FakeDealer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.sent.write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    let dealer = |reply: &ServerEvent| FakeDealer {
+        replies: std::io::Cursor::new(encode_frame(&reply.to_json())),
+        sent: Vec::new(),
+    };
+
+    let mut welcoming = dealer(&ServerEvent::Welcome { version: 1, capabilities: vec!["lobby".into()] });
+    assert_eq!(handshake(&mut welcoming), Ok(vec!["lobby".to_string()]));
+    assert!(String::from_utf8_lossy(&welcoming.sent).contains(r#""command":"hello""#));
+
+    let mut newer = dealer(&ServerEvent::UpgradeRequired {
+        min_version: 2,
+        max_version: 2,
+        message: "Upgrade required".into(),
+    });
+    assert_eq!(handshake(&mut newer), Err("Upgrade required".to_string()));
+
+    let mut older = dealer(&ServerEvent::error("Invalid command: unknown variant `hello`"));
+    assert!(handshake(&mut older).unwrap_err().contains("too old"));
+}
+
+#[test]
+fn test_reconnect_snapshot_returns_to_table() {
+    let mut app = PlayerApp::default();
+    app.state = AppState::Ready;
+    app.username = "alice".to_string();
+    let (net_to_ui_tx, net_to_ui_rx) = mpsc::channel::<String>();
+    app.net_to_ui_rx = Some(net_to_ui_rx);
+
+    // Messages other than a snapshot leave the ready screen where it is
+    let notice = ServerEvent::info("Notice from the operator: back in five").to_json();
+    net_to_ui_tx.send(notice.clone()).unwrap();
+    screens::waiting::return_on_snapshot(&mut app);
+    assert_eq!(app.state, AppState::Ready);
+
+    let msg = r#"{"event":"snapshot","variant":"5card","hands":{"alice":["A of Spades"]},"pot":30,"to_act":"alice"}"#;
+    net_to_ui_tx.send(msg.to_string()).unwrap();
+    screens::waiting::return_on_snapshot(&mut app);
+    assert_eq!(app.state, AppState::InGame);
+
+    // The table gets both, in the order they arrived
+    assert_eq!(app.next_message(), Some(notice));
+    let Some(Ok(ServerEvent::Snapshot(table))) = app.next_message().map(|msg| ServerEvent::from_json(&msg)) else {
+        panic!("expected the snapshot");
+    };
+    assert_eq!(table.to_act.as_deref(), Some(app.username.as_str()));
+    assert_eq!(table.pot, 30);
+    assert_eq!(app.next_message(), None);
+}
+
+/// The leaderboard asks for the chosen page and stops at the first and last.
+#[test]
+fn test_leaderboard_paging() {
+    use crate::leaderboard::LeaderboardView;
+    use protocol::{LeaderboardPage, LeaderboardPeriod};
+
+    let mut board = LeaderboardView::default();
+    assert!(board.request().to_json().contains(r#""page":1"#));
+    assert!(!board.previous_page(), "Already on the first page");
+    assert!(!board.next_page(), "Nothing sent yet, so there is one page");
+
+    let json = r#"{"event":"leaderboard","page":1,"page_size":10,"total_players":15,"entries":[{"rank":1,"name":"alice","net_winnings":40}]}"#;
+    if let Ok(ServerEvent::Leaderboard(page)) = ServerEvent::from_json(json) {
+        board.shown = Some(page);
+    }
+    assert_eq!(board.shown.as_ref().map(LeaderboardPage::page_count), Some(2));
+    assert!(board.next_page());
+    assert!(!board.next_page(), "Already on the last page");
+    assert!(board.previous_page());
+
+    board.variant = Some("texas".to_string());
+    board.period = LeaderboardPeriod::Week;
+    assert_eq!(board.variant_label(), "Texas Hold'em");
+    let request = board.request().to_json();
+    assert!(request.contains(r#""variant":"texas""#) && request.contains(r#""period":"week""#), "{}", request);
+}
+
+/// Stats from older dealers, without per-variant totals or habits, still parse.
+#[test]
+fn test_player_stats_variants_and_behaviour() {
+    let json = r#"{"event":"player_stats","name":"amy","wins":2,"variants":{"texas":{"wins":2,"behaviour":{"hands":4,"vpip":0.75}}},"behaviour":{"hands":4,"vpip":0.75,"pfr":0.25,"aggression_factor":1.5}}"#;
+    let Ok(ServerEvent::PlayerStats(stats)) = ServerEvent::from_json(json) else {
+        panic!("player stats should parse");
+    };
+    assert_eq!(stats.variants["texas"].wins, 2);
+    assert_eq!(stats.variants["texas"].behaviour.vpip, 0.75);
+    assert_eq!(stats.behaviour.aggression_factor, 1.5);
+
+    let old = r#"{"event":"player_stats","name":"amy","wins":2}"#;
+    let Ok(ServerEvent::PlayerStats(stats)) = ServerEvent::from_json(old) else {
+        panic!("stats without the new fields should parse");
+    };
+    assert!(stats.variants.is_empty());
+    assert_eq!(stats.behaviour.hands, 0);
+}
+
+/// Dates typed on the stats screen become a range of seconds, and the bankroll
+/// chart keeps its points inside the chart with zero in view.
+#[test]
+fn test_stats_date_range_and_bankroll_chart() {
+    use crate::bankroll::*;
+    use eframe::egui::{pos2, Rect};
+    use protocol::BankrollPoint;
+
+    assert_eq!(parse_date("1970-01-02"), Some(86_400));
+    assert_eq!(parse_date("2024-02-29").map(format_date), Some("2024-02-29".to_string()));
+    assert_eq!(parse_date("2023-02-29"), None);
+    assert_eq!(parse_date("yesterday"), None);
+    assert_eq!(format_date(1_700_000_000), "2023-11-14");
+
+    assert_eq!(date_range("", ""), Ok((None, None)));
+    assert_eq!(date_range("1970-01-02", "1970-01-02"), Ok((Some(86_400), Some(2 * 86_400 - 1))));
+    assert!(date_range("1970-01-03", "1970-01-02").is_err());
+    assert!(date_range("1970-13-01", "").is_err());
+
+    let rect = Rect::from_min_max(pos2(0.0, 0.0), pos2(100.0, 50.0));
+    assert!(chart_points(&[], rect).is_empty());
+    let points = [
+        BankrollPoint { timestamp: 0, bankroll: 10 },
+        BankrollPoint { timestamp: 50, bankroll: -10 },
+        BankrollPoint { timestamp: 100, bankroll: 30 },
+    ];
+    let placed = chart_points(&points, rect);
+    assert_eq!(placed, [pos2(0.0, 25.0), pos2(50.0, 50.0), pos2(100.0, 0.0)]);
+    // One point sits in the middle of the chart
+    assert_eq!(chart_points(&points[..1], rect)[0].x, 50.0);
+
+    let json = ClientCommand::GetUserStats { username: "amy".into(), from: Some(86_400), to: None }.to_json();
+    assert!(json.contains(r#""from":86400"#) && !json.contains(r#""to""#), "{}", json);
+}
@@ -0,0 +1,226 @@
+//! # Dealer Connections
+//!
+//! Opens the TCP connection to the dealer, optionally encrypted with TLS.
+//!
+//! Dealers usually run with a self-signed certificate, so instead of checking it
+//! against certificate authorities the client checks its SHA-256 fingerprint:
+//! - If a fingerprint is pinned in the login screen, the certificate must match it.
+//! - Otherwise the first certificate seen from a dealer is trusted and saved to
+//!   `known_dealers.txt`, and later connections must present the same one.
+
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, ClientConnection, DigitallySignedStruct, SignatureScheme, StreamOwned};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+
+/// File where trust-on-first-use fingerprints are remembered.
+const KNOWN_DEALERS_FILE: &str = "known_dealers.txt";
+
+/// How to secure the connection to the dealer.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TlsOptions {
+    /// Whether to connect with TLS.
+    pub enabled: bool,
+    /// Expected certificate fingerprint. Empty means trust on first use.
+    pub pinned_fingerprint: String,
+}
+
+/// A connection to the dealer, plain or encrypted.
+pub enum Connection {
+    Plain(TcpStream),
+    Tls(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl Connection {
+    /// Switches the underlying socket to non-blocking mode.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Connection::Plain(stream) => stream.set_nonblocking(nonblocking),
+            Connection::Tls(stream) => stream.sock.set_nonblocking(nonblocking),
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Plain(stream) => stream.read(buf),
+            Connection::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Plain(stream) => stream.write(buf),
+            Connection::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Plain(stream) => stream.flush(),
+            Connection::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+/// Connects to the dealer. With TLS the handshake is completed before returning,
+/// so a rejected certificate is reported here.
+///
+/// # Arguments
+/// * `host` - The dealer's IP address or host name.
+/// * `port` - The dealer's port.
+/// * `options` - Whether to use TLS, and the pinned fingerprint if any.
+pub fn connect(host: &str, port: u16, options: &TlsOptions) -> io::Result<Connection> {
+    let mut socket = TcpStream::connect((host, port))?;
+    if !options.enabled {
+        return Ok(Connection::Plain(socket));
+    }
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let verifier = FingerprintVerifier {
+        host: format!("{}:{}", host, port),
+        pinned: options.pinned_fingerprint.clone(),
+        provider: Arc::clone(&provider),
+    };
+    let config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(io::Error::other)?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+    let server_name = ServerName::try_from(host.to_string()).map_err(io::Error::other)?;
+    let mut conn = ClientConnection::new(Arc::new(config), server_name).map_err(io::Error::other)?;
+
+    while conn.is_handshaking() {
+        conn.complete_io(&mut socket)?;
+    }
+    Ok(Connection::Tls(Box::new(StreamOwned::new(conn, socket))))
+}
+
+/// SHA-256 fingerprint of a DER certificate, as colon-separated hex pairs (the
+/// format the dealer prints at startup).
+pub fn fingerprint(cert: &[u8]) -> String {
+    Sha256::digest(cert)
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// Result of checking a dealer's certificate fingerprint.
+#[derive(Debug, PartialEq)]
+pub enum Trust {
+    /// Matches the pin or the remembered fingerprint.
+    Known,
+    /// First time this dealer has been seen; should be remembered.
+    FirstUse,
+}
+
+/// Decides whether to trust a dealer's certificate.
+///
+/// # Arguments
+/// * `known_dealers` - Contents of the known dealers file (`host fingerprint` lines).
+/// * `host` - The dealer's `host:port`.
+/// * `fingerprint` - Fingerprint of the certificate the dealer presented.
+/// * `pinned` - Fingerprint entered by the player, or empty.
+///
+/// # Returns
+/// How the certificate is trusted, or why it is rejected.
+pub fn check_fingerprint(known_dealers: &str, host: &str, fingerprint: &str, pinned: &str) -> Result<Trust, String> {
+    if !pinned.trim().is_empty() {
+        return if normalize(pinned) == normalize(fingerprint) {
+            Ok(Trust::Known)
+        } else {
+            Err(format!("Dealer certificate {} does not match the pinned fingerprint", fingerprint))
+        };
+    }
+
+    let remembered = known_dealers.lines().find_map(|line| {
+        let (known_host, known_fingerprint) = line.trim().split_once(' ')?;
+        (known_host == host).then(|| known_fingerprint.trim())
+    });
+    match remembered {
+        Some(known) if normalize(known) == normalize(fingerprint) => Ok(Trust::Known),
+        Some(_) => Err(format!(
+            "Dealer certificate for {} has changed to {}. Remove it from {} if this is expected.",
+            host, fingerprint, KNOWN_DEALERS_FILE
+        )),
+        None => Ok(Trust::FirstUse),
+    }
+}
+
+fn normalize(fingerprint: &str) -> String {
+    fingerprint
+        .chars()
+        .filter(|c| c.is_ascii_hexdigit())
+        .collect::<String>()
+        .to_ascii_uppercase()
+}
+
+/// Accepts the dealer's certificate by fingerprint instead of by certificate authority.
+#[derive(Debug)]
+struct FingerprintVerifier {
+    host: String,
+    pinned: String,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for FingerprintVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let fingerprint = fingerprint(end_entity);
+        let known_dealers = fs::read_to_string(KNOWN_DEALERS_FILE).unwrap_or_default();
+
+        match check_fingerprint(&known_dealers, &self.host, &fingerprint, &self.pinned) {
+            Ok(Trust::Known) => Ok(ServerCertVerified::assertion()),
+            Ok(Trust::FirstUse) => {
+                println!("[Client] Trusting new dealer {} with certificate {}", self.host, fingerprint);
+                let mut file = fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(KNOWN_DEALERS_FILE)
+                    .map_err(|e| rustls::Error::General(e.to_string()))?;
+                writeln!(file, "{} {}", self.host, fingerprint)
+                    .map_err(|e| rustls::Error::General(e.to_string()))?;
+                Ok(ServerCertVerified::assertion())
+            }
+            Err(e) => Err(rustls::Error::General(e)),
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
@@ -1,5 +1,6 @@
 pub mod auth;
 pub mod cards;
 pub mod in_game;
+pub mod replay;
 pub mod stats;
 pub mod waiting;
@@ -0,0 +1,124 @@
+//! # Client Commands
+//!
+//! Requests sent from a client to the dealer.
+use serde::{Deserialize, Serialize};
+use crate::leaderboard::{LeaderboardPeriod, LeaderboardSort};
+
+/// A request from a client.
+///
+/// Commands that act for a player (`ready`, `bet`, `swap`) run as the user the
+/// connection is bound to. They may still carry a `username`, which the dealer
+/// only checks against the bound user.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ClientCommand {
+    /// Opens every connection: the client's protocol version and the optional
+    /// features it understands (see `handshake`).
+    Hello {
+        version: u32,
+        #[serde(default)]
+        capabilities: Vec<String>,
+    },
+    /// Create an account.
+    Register { username: String, password: String },
+    /// Sign in. The reply carries a session token.
+    Login { username: String, password: String },
+    /// Bind this connection to a session token from `Login`.
+    Bind { token: String },
+    /// Take a seat in the next game.
+    Ready {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        username: Option<String>,
+    },
+    /// Bet when it is this player's turn. `0` checks and `-1` folds.
+    Bet {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        username: Option<String>,
+        amount: i32,
+    },
+    /// Replace cards in five card draw, e.g. `"0,2,4"`.
+    Swap {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        username: Option<String>,
+        indices: String,
+    },
+    /// List the registered players.
+    Stats,
+    /// Look up one player's statistics, optionally only counting results between
+    /// two times (seconds since the Unix epoch, both included).
+    GetUserStats {
+        username: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        from: Option<i64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        to: Option<i64>,
+    },
+    /// Rank players by their results, a page at a time, optionally only counting
+    /// one variant's hands or a recent period.
+    Leaderboard {
+        #[serde(default)]
+        sort: LeaderboardSort,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        variant: Option<String>,
+        #[serde(default)]
+        period: LeaderboardPeriod,
+        /// Page number, starting at 1.
+        #[serde(default = "first_page")]
+        page: usize,
+        /// Players per page. The dealer picks if left out.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        page_size: Option<usize>,
+    },
+    /// Fetch a stored hand.
+    HandHistory { hand_id: String },
+    /// Export stored hands as `"pokerstars"` (the default) or `"ohh"` text.
+    /// When `hand_ids` is empty, the hands the player was dealt into are
+    /// exported a page at a time, newest page first.
+    ExportHands {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        format: Option<String>,
+        #[serde(default)]
+        hand_ids: Vec<String>,
+        /// Page number, starting at 1. Only used when `hand_ids` is empty.
+        #[serde(default = "first_page")]
+        page: usize,
+    },
+    /// Show the table's variant, seats and status.
+    Lobby,
+    /// Watch the game in progress.
+    Spectate,
+}
+
+fn first_page() -> usize {
+    1
+}
+
+impl ClientCommand {
+    /// Parses a command received from a client.
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    /// The `hello` this build of the protocol sends.
+    pub fn hello() -> Self {
+        ClientCommand::Hello {
+            version: crate::handshake::PROTOCOL_VERSION,
+            capabilities: crate::handshake::CAPABILITIES.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    /// Encodes the command for sending.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("commands always serialize")
+    }
+
+    /// The `username` a player command claims to act as, if any.
+    pub fn claimed_username(&self) -> Option<&str> {
+        match self {
+            ClientCommand::Ready { username }
+            | ClientCommand::Bet { username, .. }
+            | ClientCommand::Swap { username, .. } => username.as_deref(),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,203 @@
+//! # Server Events
+//!
+//! Replies and game updates sent from the dealer to clients.
+use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+use crate::hand::HandRecord;
+use crate::leaderboard::LeaderboardPage;
+
+/// A message from the dealer.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ServerEvent {
+    /// The handshake succeeded, with the features both sides support.
+    Welcome { version: u32, capabilities: Vec<String> },
+    /// The dealer cannot talk to this client's protocol version.
+    UpgradeRequired {
+        min_version: u32,
+        max_version: u32,
+        message: String,
+    },
+    /// Something worked, e.g. registration or taking a seat.
+    Info { message: String },
+    /// A command failed.
+    Error { message: String },
+    /// Login succeeded. `token` is bound to the persistent connection.
+    LoggedIn {
+        username: String,
+        token: String,
+        message: String,
+    },
+    /// A game has started with these players.
+    GameStarted { variant: String, players: Vec<String> },
+    /// The table changed: cards dealt, a bet placed, or a player's turn came up.
+    Table(TableState),
+    /// Sent to a player who reconnects to a hand in progress: the whole table as a
+    /// `table` update would show it, including whose turn it is.
+    Snapshot(TableState),
+    /// A player folded.
+    Folded { player: String },
+    /// Everyone else folded, so the hand ended without a showdown.
+    HandOver { winner: String, hand_id: String },
+    /// The hand went to showdown.
+    Showdown {
+        winner: String,
+        hand_id: String,
+        /// Every remaining player's cards.
+        hands: BTreeMap<String, Vec<String>>,
+        pot: i32,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        community: Vec<String>,
+    },
+    /// Names of the registered players.
+    PlayerList { players: Vec<String> },
+    /// One player's statistics.
+    PlayerStats(PlayerStats),
+    /// A page of ranked players.
+    Leaderboard(LeaderboardPage),
+    /// A stored hand.
+    HandHistory(HandRecord),
+    /// Hands exported as text in the requested format.
+    HandExport { format: String, text: String },
+    /// The table's variant, seats and status.
+    Lobby(LobbyInfo),
+}
+
+impl ServerEvent {
+    /// Shorthand for an `Info` event.
+    pub fn info(message: impl Into<String>) -> Self {
+        ServerEvent::Info { message: message.into() }
+    }
+
+    /// Shorthand for an `Error` event.
+    pub fn error(message: impl Into<String>) -> Self {
+        ServerEvent::Error { message: message.into() }
+    }
+
+    /// Parses an event received from the dealer.
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    /// Encodes the event for sending.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("events always serialize")
+    }
+}
+
+/// What a player (or spectator) can see of the table.
+///
+/// Players receive every hand at the table; the client hides the cards that are
+/// face down.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TableState {
+    /// Game variant (`"5card"`, `"7card"` or `"texas"`).
+    pub variant: String,
+    /// Cards held by each player.
+    pub hands: BTreeMap<String, Vec<String>>,
+    /// Community cards (Texas Hold'em only).
+    pub community: Vec<String>,
+    /// Player whose turn it is to bet.
+    pub to_act: Option<String>,
+    /// Player whose turn it is to swap cards.
+    pub to_swap: Option<String>,
+    /// Chips in the pot.
+    pub pot: i32,
+    /// Highest bet on this street. Negative before anyone has bet.
+    pub current_bet: i32,
+    /// Each player's bet on this street.
+    pub bets: BTreeMap<String, i32>,
+    /// Extra information, such as who posted the blinds.
+    pub info: Option<String>,
+    /// Winner of the last hand, for spectators.
+    pub winner: Option<String>,
+    /// ID of the hand being played.
+    pub hand_id: Option<String>,
+}
+
+/// A player's statistics.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlayerStats {
+    pub name: String,
+    pub wins: i32,
+    pub losses: i32,
+    pub games_played: i32,
+    pub money_win: i32,
+    pub money_lost: i32,
+    /// The same totals for each variant played (`"5card"`, `"7card"` or `"texas"`).
+    pub variants: BTreeMap<String, VariantStats>,
+    /// Habits over every hand in the history.
+    pub behaviour: BehaviourStats,
+    /// Start of the date range the stats count, in seconds since the Unix epoch,
+    /// or `None` for all time.
+    pub from: Option<i64>,
+    /// End of the date range the stats count, or `None` for up to now.
+    pub to: Option<i64>,
+    /// The player's bankroll after each hand in the range, oldest first.
+    pub bankroll: Vec<BankrollPoint>,
+}
+
+/// A player's bankroll at one point in time.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BankrollPoint {
+    /// When the hand ended, in seconds since the Unix epoch.
+    pub timestamp: i64,
+    /// Net chips won over every hand up to and including this one.
+    pub bankroll: i32,
+}
+
+/// A player's statistics in one variant.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VariantStats {
+    pub wins: i32,
+    pub losses: i32,
+    pub games_played: i32,
+    pub money_win: i32,
+    pub money_lost: i32,
+    /// Habits over the variant's hands in the history.
+    pub behaviour: BehaviourStats,
+}
+
+/// How a player plays, worked out from the actions in the hand history.
+///
+/// Rates run from 0 to 1. The first betting round is preflop in Texas Hold'em,
+/// before the draw in Five Card Draw and third street in Seven Card Stud.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BehaviourStats {
+    /// Hands the stats are worked out from.
+    pub hands: i32,
+    /// Voluntarily put money in pot: share of hands the player called, bet or
+    /// raised in the first betting round. Antes and blinds do not count.
+    pub vpip: f64,
+    /// Pre-flop raise: share of hands the player bet or raised in the first
+    /// betting round.
+    pub pfr: f64,
+    /// Aggression factor: bets and raises per call, over every betting round.
+    /// With no calls it is the number of bets and raises.
+    pub aggression_factor: f64,
+    /// Went to showdown: share of hands the player stayed in until the showdown.
+    pub went_to_showdown: f64,
+    /// Won at showdown: share of the player's showdowns they won.
+    pub won_at_showdown: f64,
+}
+
+/// The table's lobby.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LobbyInfo {
+    /// Game variant (`"5card"`, `"7card"` or `"texas"`).
+    pub variant: String,
+    /// Number of seats.
+    pub max_players: usize,
+    /// Players who are ready.
+    pub players: Vec<String>,
+    /// Seated players who left mid-game and are folded on every turn.
+    pub sitting_out: Vec<String>,
+    /// `"waiting"` or `"in_game"`.
+    pub status: String,
+}
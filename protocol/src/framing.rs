@@ -0,0 +1,182 @@
+//! # Message Framing
+//!
+//! Every message between the dealer and a client is sent as a frame: a 4-byte
+//! big-endian length followed by that many bytes of UTF-8 text.
+//!
+//! TCP delivers a byte stream, so one read may return part of a frame or several
+//! frames at once. `FrameReader` buffers incoming bytes and hands back whole
+//! messages, and `FrameWriter` queues outgoing frames and writes as much as the
+//! (non-blocking) socket accepts each time it is polled.
+//!
+//! Until the dealer has accepted a connection's `hello`, it reads frames of at
+//! most `HANDSHAKE_FRAME_LEN` bytes, so a connection that never completes the
+//! handshake cannot make it buffer much. After that the limit is `MAX_FRAME_LEN`.
+//!
+//! Builds from before framing sent bare JSON zero-padded to `LEGACY_MESSAGE_LEN`
+//! bytes. The dealer spots them by their opening `{` and answers once in that
+//! format, so they can tell their user to upgrade.
+use std::io::{self, ErrorKind, Read, Write};
+
+/// Largest message accepted once the handshake is done. Anything bigger is
+/// treated as a protocol error. The largest real messages are hand history
+/// exports, which the dealer pages to stay well under it.
+pub const MAX_FRAME_LEN: usize = 1024 * 1024;
+
+/// Largest message the dealer accepts before the handshake is done. The `hello`
+/// fits in far less.
+pub const HANDSHAKE_FRAME_LEN: usize = 4 * 1024;
+
+/// Size of the length prefix.
+const HEADER_LEN: usize = 4;
+
+/// Size of every message sent by builds from before framing.
+pub const LEGACY_MESSAGE_LEN: usize = 2048;
+
+/// Whether the first bytes of a connection are a message from a build that
+/// predates framing, i.e. bare JSON rather than a length prefix.
+///
+/// A length prefix starting with `{` would announce a frame of over 2 GB, far
+/// beyond `MAX_FRAME_LEN`, so the two cannot be confused.
+pub fn is_legacy_message(data: &[u8]) -> bool {
+    data.first() == Some(&b'{')
+}
+
+/// Encodes a message the way builds from before framing read it: zero-padded to
+/// `LEGACY_MESSAGE_LEN` bytes.
+pub fn encode_legacy(message: &str) -> Vec<u8> {
+    let mut padded = message.as_bytes().to_vec();
+    padded.resize(padded.len().max(LEGACY_MESSAGE_LEN), 0);
+    padded
+}
+
+/// Encodes a message as a frame.
+pub fn encode_frame(message: &str) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(HEADER_LEN + message.len());
+    frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    frame.extend_from_slice(message.as_bytes());
+    frame
+}
+
+/// Reassembles frames from the bytes read off a socket.
+#[derive(Debug)]
+pub struct FrameReader {
+    buffer: Vec<u8>,
+    /// Largest frame accepted.
+    limit: usize,
+}
+
+impl Default for FrameReader {
+    fn default() -> Self {
+        Self::with_limit(MAX_FRAME_LEN)
+    }
+}
+
+impl FrameReader {
+    /// Creates a reader that rejects frames over `limit` bytes.
+    pub fn with_limit(limit: usize) -> Self {
+        Self { buffer: Vec::new(), limit }
+    }
+
+    /// Changes the largest frame accepted, e.g. once the handshake is done.
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+    }
+
+    /// Adds bytes read from the socket.
+    pub fn push(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Takes the next complete message out of the buffer.
+    ///
+    /// # Returns
+    /// `Ok(None)` if no complete frame has arrived yet, or an error if the frame
+    /// is too large or not valid UTF-8.
+    pub fn next_message(&mut self) -> io::Result<Option<String>> {
+        if self.buffer.len() < HEADER_LEN {
+            return Ok(None);
+        }
+        let mut header = [0u8; HEADER_LEN];
+        header.copy_from_slice(&self.buffer[..HEADER_LEN]);
+        let len = u32::from_be_bytes(header) as usize;
+        if len > self.limit {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("frame of {} bytes exceeds the {} byte limit", len, self.limit),
+            ));
+        }
+        if self.buffer.len() < HEADER_LEN + len {
+            return Ok(None);
+        }
+
+        let payload: Vec<u8> = self.buffer.drain(..HEADER_LEN + len).skip(HEADER_LEN).collect();
+        String::from_utf8(payload)
+            .map(Some)
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+    }
+
+    /// Reads from a blocking socket until one whole message has arrived.
+    pub fn read_message(&mut self, socket: &mut impl Read) -> io::Result<String> {
+        let mut chunk = [0u8; 4096];
+        loop {
+            if let Some(message) = self.next_message()? {
+                return Ok(message);
+            }
+            match socket.read(&mut chunk) {
+                Ok(0) => return Err(io::Error::new(ErrorKind::UnexpectedEof, "connection closed")),
+                Ok(n) => self.push(&chunk[..n]),
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Reads everything currently available from a non-blocking socket.
+    ///
+    /// # Returns
+    /// `Ok(false)` once the peer has closed the connection.
+    pub fn fill_from(&mut self, socket: &mut impl Read) -> io::Result<bool> {
+        let mut chunk = [0u8; 4096];
+        loop {
+            match socket.read(&mut chunk) {
+                Ok(0) => return Ok(false),
+                Ok(n) => self.push(&chunk[..n]),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(true),
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Queues frames for a non-blocking socket.
+#[derive(Debug, Default)]
+pub struct FrameWriter {
+    pending: Vec<u8>,
+}
+
+impl FrameWriter {
+    /// Queues a message to be sent.
+    pub fn queue(&mut self, message: &str) {
+        self.pending.extend_from_slice(&encode_frame(message));
+    }
+
+    /// Writes as much of the queue as the socket accepts without blocking.
+    pub fn flush_to(&mut self, socket: &mut impl Write) -> io::Result<()> {
+        while !self.pending.is_empty() {
+            match socket.write(&self.pending) {
+                Ok(0) => return Err(io::Error::new(ErrorKind::WriteZero, "connection closed")),
+                Ok(n) => {
+                    self.pending.drain(..n);
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        match socket.flush() {
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => Ok(()),
+            result => result,
+        }
+    }
+}
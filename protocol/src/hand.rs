@@ -0,0 +1,100 @@
+//! # Hand Records
+//!
+//! A completed hand as the dealer stores it in its history and sends it back for
+//! the `hand_history` command. The client steps through these records in its
+//! replay screen.
+use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+
+/// A completed hand, as stored in the history.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HandRecord {
+    /// Unique hand number.
+    pub hand_id: String,
+    /// When the hand started, in seconds since the Unix epoch.
+    pub timestamp: i64,
+    /// Game variant (`"5card"`, `"7card"` or `"texas"`).
+    pub variant: String,
+    /// Seed the deck was shuffled from, as 16 hex digits. Empty in hands sent
+    /// to players, since the whole deck can be rebuilt from it.
+    pub shuffle_seed: String,
+    /// Players in seat order.
+    pub seats: Vec<SeatRecord>,
+    /// Streets in the order they were played.
+    pub streets: Vec<StreetRecord>,
+    /// Community cards (Texas Hold'em only).
+    pub board: Vec<String>,
+    /// Cards shown at showdown, by player. Empty if everyone else folded.
+    pub shown_hands: BTreeMap<String, Vec<String>>,
+    /// Who was paid from the pot, and how much.
+    pub pots: Vec<PotAward>,
+    /// Total chips in the pot at the end of the hand.
+    pub total_pot: i32,
+}
+
+/// A player's seat at the table.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SeatRecord {
+    /// Seat number, starting at 1.
+    pub seat: usize,
+    /// Player name.
+    pub player: String,
+    /// The player's net chips (`money_win + money_lost`, plus any operator
+    /// adjustment) when the hand started. There are no buy-ins, so this is the
+    /// player's stack.
+    pub stack: i32,
+    /// Cards the player held when they folded or the hand ended.
+    pub cards: Vec<String>,
+}
+
+/// One betting (or drawing) round.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StreetRecord {
+    /// Street name, e.g. `"preflop"`, `"draw"` or `"fifth"`.
+    pub name: String,
+    /// Actions in the order they happened.
+    pub actions: Vec<ActionRecord>,
+}
+
+/// Kind of action taken by a player.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionKind {
+    Ante,
+    SmallBlind,
+    BigBlind,
+    Fold,
+    Check,
+    Call,
+    Bet,
+    Raise,
+    Draw,
+}
+
+/// A single player action.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ActionRecord {
+    /// Player who acted.
+    pub player: String,
+    /// What they did.
+    pub action: ActionKind,
+    /// Chips put in by this action. For draws, the number of cards replaced.
+    pub amount: i32,
+    /// The player's total bet on this street after the action.
+    pub total: i32,
+    /// Cards thrown away (draws only).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub discarded: Vec<String>,
+    /// Cards received (draws only).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub drawn: Vec<String>,
+}
+
+/// Chips paid to a player from the pot.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PotAward {
+    /// Player who was paid.
+    pub player: String,
+    /// Chips won.
+    pub amount: i32,
+}
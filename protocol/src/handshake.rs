@@ -0,0 +1,70 @@
+//! # Handshake
+//!
+//! The first message on every connection is a `hello` carrying the client's
+//! protocol version and the optional features it understands. The dealer answers
+//! `welcome` with the features both sides share, or `upgrade_required` when it
+//! cannot talk to that version.
+//!
+//! Until a connection has said hello the dealer answers everything with
+//! `upgrade_required`, so builds from before the handshake show an error instead
+//! of waiting for messages they cannot read.
+use crate::ServerEvent;
+
+/// Version of the message format. Bump it whenever a change would confuse older builds.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Oldest client version the dealer still accepts.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Optional features this version of the protocol knows about.
+pub const CAPABILITIES: &[&str] = &["hand_history", "export_hands", "lobby", "spectate", "leaderboard"];
+
+/// Decides whether the dealer can talk to a client.
+///
+/// # Arguments
+/// * `version` - Protocol version from the client's `hello`.
+/// * `capabilities` - Features the client understands.
+/// * `supported` - Features the dealer offers.
+///
+/// # Returns
+/// `Welcome` with the features both sides support, or `UpgradeRequired` if the
+/// version is outside `MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION`.
+pub fn negotiate(version: u32, capabilities: &[String], supported: &[&str]) -> ServerEvent {
+    if version < MIN_PROTOCOL_VERSION {
+        return upgrade_required(format!(
+            "Upgrade required: this client speaks protocol version {}, but the dealer needs version {} to {}. Please update your client.",
+            version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+        ));
+    }
+    if version > PROTOCOL_VERSION {
+        return upgrade_required(format!(
+            "This client speaks protocol version {}, but the dealer only supports up to version {}. Please update the dealer.",
+            version, PROTOCOL_VERSION
+        ));
+    }
+
+    ServerEvent::Welcome {
+        version,
+        capabilities: supported
+            .iter()
+            .filter(|name| capabilities.iter().any(|c| c == *name))
+            .map(|name| name.to_string())
+            .collect(),
+    }
+}
+
+/// The reply to anything sent before a `hello`.
+pub fn handshake_required() -> ServerEvent {
+    upgrade_required(format!(
+        "Upgrade required: this dealer speaks protocol version {}. Please update your client.",
+        PROTOCOL_VERSION
+    ))
+}
+
+fn upgrade_required(message: String) -> ServerEvent {
+    ServerEvent::UpgradeRequired {
+        min_version: MIN_PROTOCOL_VERSION,
+        max_version: PROTOCOL_VERSION,
+        message,
+    }
+}
@@ -0,0 +1,126 @@
+//! # Leaderboards
+//!
+//! Players ranked by their stored results in each hand, sent a page at a
+//! time for the `leaderboard` command. The client shows them on its stats screen.
+use serde::{Deserialize, Serialize};
+
+/// What players are ranked by, highest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LeaderboardSort {
+    /// Chips won minus chips put in.
+    #[default]
+    NetWinnings,
+    /// Hands won out of hands played.
+    WinRate,
+    /// Hands played.
+    GamesPlayed,
+    /// The biggest pot the player won.
+    BiggestPot,
+}
+
+impl LeaderboardSort {
+    /// Every way of ranking, in the order the client lists them.
+    pub const ALL: [LeaderboardSort; 4] = [
+        LeaderboardSort::NetWinnings,
+        LeaderboardSort::WinRate,
+        LeaderboardSort::GamesPlayed,
+        LeaderboardSort::BiggestPot,
+    ];
+
+    /// Name shown to players.
+    pub fn label(self) -> &'static str {
+        match self {
+            LeaderboardSort::NetWinnings => "Net winnings",
+            LeaderboardSort::WinRate => "Win rate",
+            LeaderboardSort::GamesPlayed => "Games played",
+            LeaderboardSort::BiggestPot => "Biggest pot",
+        }
+    }
+}
+
+/// Which hands count, by when they were played.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LeaderboardPeriod {
+    /// Every hand.
+    #[default]
+    All,
+    /// The last 24 hours.
+    Day,
+    /// The last 7 days.
+    Week,
+    /// The last 30 days.
+    Month,
+}
+
+impl LeaderboardPeriod {
+    /// Every period, in the order the client lists them.
+    pub const ALL: [LeaderboardPeriod; 4] = [
+        LeaderboardPeriod::All,
+        LeaderboardPeriod::Day,
+        LeaderboardPeriod::Week,
+        LeaderboardPeriod::Month,
+    ];
+
+    /// Length of the period in seconds, or `None` for all time.
+    pub fn seconds(self) -> Option<i64> {
+        match self {
+            LeaderboardPeriod::All => None,
+            LeaderboardPeriod::Day => Some(24 * 60 * 60),
+            LeaderboardPeriod::Week => Some(7 * 24 * 60 * 60),
+            LeaderboardPeriod::Month => Some(30 * 24 * 60 * 60),
+        }
+    }
+
+    /// Name shown to players.
+    pub fn label(self) -> &'static str {
+        match self {
+            LeaderboardPeriod::All => "All time",
+            LeaderboardPeriod::Day => "Last 24 hours",
+            LeaderboardPeriod::Week => "Last 7 days",
+            LeaderboardPeriod::Month => "Last 30 days",
+        }
+    }
+}
+
+/// One page of a leaderboard.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LeaderboardPage {
+    pub sort: LeaderboardSort,
+    /// Game variant the hands were filtered to, or `None` for every variant.
+    pub variant: Option<String>,
+    pub period: LeaderboardPeriod,
+    /// Page number, starting at 1.
+    pub page: usize,
+    pub page_size: usize,
+    /// Players on every page.
+    pub total_players: usize,
+    /// This page's players, best first.
+    pub entries: Vec<LeaderboardEntry>,
+}
+
+impl LeaderboardPage {
+    /// Number of pages, at least 1.
+    pub fn page_count(&self) -> usize {
+        self.total_players.div_ceil(self.page_size.max(1)).max(1)
+    }
+}
+
+/// A player's place on a leaderboard.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LeaderboardEntry {
+    /// Place, starting at 1.
+    pub rank: usize,
+    pub name: String,
+    /// Chips won minus chips put in.
+    pub net_winnings: i32,
+    pub wins: i32,
+    pub games_played: i32,
+    /// `wins / games_played`, from 0 to 1.
+    pub win_rate: f64,
+    /// The biggest pot the player won, 0 if they won none.
+    pub biggest_pot: i32,
+}
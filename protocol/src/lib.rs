@@ -0,0 +1,127 @@
+//! # Poker Protocol
+//!
+//! The messages exchanged between the dealer (server) and the player (client).
+//!
+//! Both crates depend on this one, so a change to a message is checked by the
+//! compiler on both sides instead of being discovered at the table.
+//!
+//! - `handshake`: the `hello` that opens every connection, and the protocol version.
+//! - `ClientCommand`: everything a client can ask the dealer to do, tagged by
+//!   `"command"` on the wire.
+//! - `ServerEvent`: everything the dealer sends back, tagged by `"event"`.
+//! - `hand`: stored hand records, sent for the `hand_history` command.
+//! - `leaderboard`: ranked players, sent for the `leaderboard` command.
+//! - `framing`: the length-prefixed frames every message is sent in.
+//!
+//! Messages are JSON, one per frame, e.g.
+//! ```json
+//! {"command": "bet", "amount": 20}
+//! {"event": "folded", "player": "alice"}
+//! ```
+
+pub mod command;
+pub mod event;
+pub mod framing;
+pub mod handshake;
+pub mod hand;
+pub mod leaderboard;
+
+pub use command::ClientCommand;
+pub use event::{BankrollPoint, BehaviourStats, LobbyInfo, PlayerStats, ServerEvent, TableState, VariantStats};
+pub use handshake::PROTOCOL_VERSION;
+pub use leaderboard::{LeaderboardEntry, LeaderboardPage, LeaderboardPeriod, LeaderboardSort};
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::{json, Value};
+
+    // 1) commands use the "command" tag and accept the fields older clients send
+    #[test]
+    fn test_client_command_wire_format() {
+        let bet = ClientCommand::Bet { username: None, amount: 20 };
+        assert_eq!(bet.to_json(), r#"{"command":"bet","amount":20}"#);
+        assert_eq!(ClientCommand::from_json(&bet.to_json()).unwrap(), bet);
+
+        let named = ClientCommand::from_json(r#"{"command":"bet","username":"alice","amount":-1}"#).unwrap();
+        assert_eq!(named.claimed_username(), Some("alice"));
+        assert_eq!(ClientCommand::from_json(r#"{"command":"stats"}"#).unwrap(), ClientCommand::Stats);
+        assert_eq!(
+            ClientCommand::from_json(r#"{"command":"export_hands"}"#).unwrap(),
+            ClientCommand::ExportHands { format: None, hand_ids: Vec::new(), page: 1 }
+        );
+
+        assert!(ClientCommand::from_json(r#"{"command":"bet"}"#).is_err(), "amount is required");
+        assert!(ClientCommand::from_json(r#"{"command":"shuffle"}"#).is_err());
+        assert!(ClientCommand::from_json("not json").is_err());
+    }
+
+    // 2) events use the "event" tag, and table updates fill in missing fields
+    #[test]
+    fn test_server_event_wire_format() {
+        let folded: Value = serde_json::from_str(&ServerEvent::Folded { player: "bob".into() }.to_json()).unwrap();
+        assert_eq!(folded, json!({ "event": "folded", "player": "bob" }));
+
+        let table = ServerEvent::from_json(r#"{"event":"table","pot":15,"to_act":"alice"}"#).unwrap();
+        let ServerEvent::Table(table) = table else {
+            panic!("expected a table update, got {:?}", table);
+        };
+        assert_eq!(table.pot, 15);
+        assert_eq!(table.to_act.as_deref(), Some("alice"));
+        assert!(table.hands.is_empty() && table.info.is_none());
+
+        let showdown = ServerEvent::Showdown {
+            winner: "alice".into(),
+            hand_id: "42".into(),
+            hands: [("alice".to_string(), vec!["A of Spades".to_string()])].into(),
+            pot: 30,
+            community: Vec::new(),
+        };
+        let json = showdown.to_json();
+        assert!(!json.contains("community"), "Empty boards are left out: {}", json);
+        assert_eq!(ServerEvent::from_json(&json).unwrap(), showdown);
+    }
+
+    // 3) the handshake accepts supported versions and shares only common features
+    #[test]
+    fn test_handshake_negotiation() {
+        let hello = ClientCommand::from_json(r#"{"command":"hello","version":1,"capabilities":["spectate","teleport"]}"#).unwrap();
+        let ClientCommand::Hello { version, capabilities } = hello else {
+            panic!("expected a hello, got {:?}", hello);
+        };
+        assert_eq!(
+            handshake::negotiate(version, &capabilities, handshake::CAPABILITIES),
+            ServerEvent::Welcome { version: 1, capabilities: vec!["spectate".to_string()] }
+        );
+
+        for version in [0, PROTOCOL_VERSION + 1] {
+            let reply = handshake::negotiate(version, &[], handshake::CAPABILITIES);
+            assert!(matches!(reply, ServerEvent::UpgradeRequired { .. }), "version {} got {:?}", version, reply);
+        }
+        assert!(handshake::handshake_required().to_json().contains("upgrade_required"));
+    }
+
+    // 4) leaderboard requests fill in defaults, and pages count up from 1
+    #[test]
+    fn test_leaderboard_wire_format() {
+        let request = ClientCommand::from_json(r#"{"command":"leaderboard"}"#).unwrap();
+        assert_eq!(
+            request,
+            ClientCommand::Leaderboard {
+                sort: LeaderboardSort::NetWinnings,
+                variant: None,
+                period: LeaderboardPeriod::All,
+                page: 1,
+                page_size: None,
+            }
+        );
+        let filtered = ClientCommand::from_json(r#"{"command":"leaderboard","sort":"win_rate","variant":"texas","period":"week","page":3}"#).unwrap();
+        assert_eq!(ClientCommand::from_json(&filtered.to_json()).unwrap(), filtered);
+
+        let page = LeaderboardPage { page_size: 10, total_players: 21, ..LeaderboardPage::default() };
+        assert_eq!(page.page_count(), 3);
+        assert_eq!(LeaderboardPage::default().page_count(), 1, "An empty board still has a page");
+        assert_eq!(LeaderboardPeriod::Week.seconds(), Some(7 * 24 * 60 * 60));
+        assert!(ServerEvent::Leaderboard(page).to_json().starts_with(r#"{"event":"leaderboard""#));
+    }
+}
@@ -0,0 +1,269 @@
+//! # Admin Console
+//!
+//! Lets the operator manage the running dealer by typing commands on its standard
+//! input, once the game has been configured. See `HELP` for the commands.
+//!
+//! A thread reads the lines and hands each parsed command to the dispatcher in
+//! `main.rs`, which runs it between client messages, so commands never race with
+//! the players' own.
+use std::{
+    collections::HashMap,
+    io::BufRead,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::mpsc::UnboundedSender;
+use protocol::ServerEvent;
+use crate::comms::{broadcast_message, broadcast_to_game_players, disconnect_client, send_to_client};
+use crate::departures::refresh_lobby;
+use crate::db::{adjust_player_chips, player_stack, set_sitting_out, set_table_paused, table_paused};
+use crate::session::Sessions;
+use crate::storage::Storage;
+use crate::{ClientInfo, GAME_PLAYERS, GAME_VARIANT, NUM_PLAYERS};
+
+/// The console's commands and what they do.
+const HELP: &[(&str, &str)] = &[
+    ("tables", "show the table, its seats and each player's stack"),
+    ("players", "list connected clients and who they are signed in as"),
+    ("kick <player>", "sign a player out and close their connections"),
+    ("pause", "stop play at the table; bets and swaps are refused"),
+    ("resume", "restart play at the table"),
+    ("notice <message>", "send a message to every connected client"),
+    ("chips <player> <amount>", "add chips to a player's stack, or remove them with a negative amount"),
+    ("shutdown", "let the current hand finish (or void it), then stop the server"),
+    ("help", "show this list"),
+];
+
+/// A command typed at the admin console.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AdminCommand {
+    Tables,
+    Players,
+    Kick(String),
+    Pause,
+    Resume,
+    Notice(String),
+    Chips { username: String, amount: i32 },
+    Shutdown,
+    Help,
+}
+
+/// Parses one line typed at the console.
+///
+/// # Arguments
+/// * `line` - The line, without its newline.
+///
+/// # Returns
+/// The command, or a message explaining what is wrong with the line.
+pub fn parse_command(line: &str) -> Result<AdminCommand, String> {
+    let line = line.trim();
+    let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let name = name.to_lowercase();
+    let args: Vec<&str> = rest.split_whitespace().collect();
+
+    match (name.as_str(), args.as_slice()) {
+        ("tables", []) => Ok(AdminCommand::Tables),
+        ("players", []) => Ok(AdminCommand::Players),
+        ("kick", [username]) => Ok(AdminCommand::Kick(username.to_string())),
+        ("pause", []) => Ok(AdminCommand::Pause),
+        ("resume", []) => Ok(AdminCommand::Resume),
+        ("notice", [_, ..]) => Ok(AdminCommand::Notice(rest.trim().to_string())),
+        ("chips", [username, amount]) => match amount.parse::<i32>() {
+            Ok(amount) => Ok(AdminCommand::Chips { username: username.to_string(), amount }),
+            Err(_) => Err(format!("'{}' is not a number of chips.", amount)),
+        },
+        ("shutdown", []) => Ok(AdminCommand::Shutdown),
+        ("help", []) => Ok(AdminCommand::Help),
+        _ => match HELP.iter().find(|(usage, _)| usage.split(' ').next() == Some(name.as_str())) {
+            Some((usage, _)) => Err(format!("Usage: {}", usage)),
+            None => Err(format!("Unknown command '{}'. Type 'help' for the list.", name)),
+        },
+    }
+}
+
+/// Starts reading admin commands from standard input on a thread of its own.
+///
+/// The thread stops when standard input closes, e.g. when the dealer runs in the
+/// background with its input redirected.
+///
+/// # Arguments
+/// * `admin_tx` - Sender to the dispatcher.
+pub fn spawn_console(admin_tx: UnboundedSender<AdminCommand>) {
+    println!("[Admin] Console ready. Type 'help' for commands.");
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if line.trim().is_empty() {
+                continue;
+            }
+            match parse_command(&line) {
+                Ok(command) => {
+                    if admin_tx.send(command).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => eprintln!("[Admin] {}", e),
+            }
+        }
+    });
+}
+
+/// Runs an admin command.
+///
+/// `Shutdown` is left to the dispatcher, which runs the steps in `shutdown.rs`.
+///
+/// # Arguments
+/// * `command` - The command to run.
+/// * `storage` - The server's storage backend.
+/// * `clients` - Shared list of connected clients.
+/// * `sessions` - Live sessions, for finding and signing out players.
+pub async fn run_command(
+    command: AdminCommand,
+    storage: &dyn Storage,
+    clients: &Arc<Mutex<HashMap<SocketAddr, ClientInfo>>>,
+    sessions: &Mutex<Sessions>,
+) {
+    match command {
+        AdminCommand::Tables => print_tables(storage, clients, sessions).await,
+        AdminCommand::Players => print_players(clients, &sessions.lock().unwrap()),
+        AdminCommand::Kick(username) => kick(storage, clients, sessions, &username).await,
+        AdminCommand::Pause | AdminCommand::Resume => {
+            let pause = command == AdminCommand::Pause;
+            if table_paused() == pause {
+                println!("[Admin] The table is already {}.", if pause { "paused" } else { "running" });
+                return;
+            }
+            set_table_paused(pause);
+            println!("[Admin] Table {}.", if pause { "paused" } else { "resumed" });
+            let message = if pause {
+                "The operator has paused the table."
+            } else {
+                "The operator has resumed the table."
+            };
+            broadcast_to_game_players(clients, &ServerEvent::info(message));
+        }
+        AdminCommand::Notice(text) => {
+            println!("[Admin] Sending notice: {}", text);
+            broadcast_message(clients, &ServerEvent::info(format!("Notice from the operator: {}", text)));
+        }
+        AdminCommand::Chips { username, amount } => match adjust_player_chips(storage, &username, amount).await {
+            Ok(true) => {
+                let stack = match storage.find_player(&username).await {
+                    Ok(Some(doc)) => player_stack(&doc),
+                    _ => 0,
+                };
+                println!("[Admin] Adjusted {}'s chips by {:+}; their stack is now {}.", username, amount, stack);
+                let message = ServerEvent::info(format!(
+                    "The operator adjusted your chips by {:+}. Your stack is now {}.",
+                    amount, stack
+                ));
+                let connections = sessions.lock().unwrap().connections_for(&username);
+                for addr in connections {
+                    send_to_client(clients, &addr, &message);
+                }
+            }
+            Ok(false) => eprintln!("[Admin] No player named {}.", username),
+            Err(e) => eprintln!("[DB] Failed to adjust chips for {}: {}", username, e),
+        },
+        // Started by the dispatcher, see `shutdown.rs`
+        AdminCommand::Shutdown => {}
+        AdminCommand::Help => {
+            for (usage, description) in HELP {
+                println!("[Admin]   {:<24} {}", usage, description);
+            }
+        }
+    }
+}
+
+/// Prints the table: variant, seats, status, and each seated player.
+async fn print_tables(
+    storage: &dyn Storage,
+    clients: &Arc<Mutex<HashMap<SocketAddr, ClientInfo>>>,
+    sessions: &Mutex<Sessions>,
+) {
+    let max_players = *NUM_PLAYERS.get().unwrap();
+    let seats: Vec<String> = GAME_PLAYERS.get().unwrap().lock().unwrap().iter().cloned().collect();
+    let status = if seats.len() == max_players { "in progress" } else { "waiting for players" };
+    println!(
+        "[Admin] Table: {}, {}/{} seats, {}{}",
+        GAME_VARIANT.get().unwrap(),
+        seats.len(),
+        max_players,
+        status,
+        if table_paused() { " (paused)" } else { "" }
+    );
+
+    for username in seats {
+        let (stack, sitting_out) = match storage.find_player(&username).await {
+            Ok(Some(doc)) => (player_stack(&doc), doc.get_bool("sitting_out") == Ok(true)),
+            _ => (0, false),
+        };
+        let connections = sessions.lock().unwrap().connections_for(&username);
+        let connected = {
+            let clients = clients.lock().unwrap();
+            connections.iter().any(|addr| clients.contains_key(addr))
+        };
+        println!(
+            "[Admin]   {:<16} stack {:>6}  {}{}",
+            username,
+            stack,
+            if connected { "connected" } else { "disconnected" },
+            if sitting_out { ", sitting out" } else { "" }
+        );
+    }
+}
+
+/// Prints each connected client and the user it is signed in as.
+fn print_players(clients: &Arc<Mutex<HashMap<SocketAddr, ClientInfo>>>, sessions: &Sessions) {
+    let mut addrs: Vec<SocketAddr> = clients.lock().unwrap().keys().cloned().collect();
+    addrs.sort();
+    let seats = GAME_PLAYERS.get().unwrap().lock().unwrap().clone();
+
+    println!("[Admin] {} connected client(s)", addrs.len());
+    for addr in addrs {
+        match sessions.user_for(&addr) {
+            Some(username) => {
+                let seated = seats.contains(&username);
+                println!("[Admin]   {:<22} {}{}", addr, username, if seated { " (seated)" } else { "" });
+            }
+            None => println!("[Admin]   {:<22} not signed in", addr),
+        }
+    }
+}
+
+/// Signs a player out and closes their connections.
+///
+/// During a game the player also sits out at once, so the table does not wait for
+/// them. They may log in again and take their seat back.
+async fn kick(
+    storage: &dyn Storage,
+    clients: &Arc<Mutex<HashMap<SocketAddr, ClientInfo>>>,
+    sessions: &Mutex<Sessions>,
+    username: &str,
+) {
+    let connections = sessions.lock().unwrap().revoke(username);
+    for addr in &connections {
+        disconnect_client(clients, addr, &ServerEvent::error("You were removed from the table by the operator."));
+    }
+
+    let (seated, game_started) = {
+        let seats = GAME_PLAYERS.get().unwrap().lock().unwrap();
+        (seats.contains(username), seats.len() == *NUM_PLAYERS.get().unwrap())
+    };
+    if seated && game_started {
+        if let Err(e) = set_sitting_out(storage, username, true).await {
+            eprintln!("[DB] Failed to sit out {}: {}", username, e);
+        }
+        broadcast_to_game_players(
+            clients,
+            &ServerEvent::info(format!("{} was removed by the operator and is sitting out.", username)),
+        );
+        refresh_lobby(storage).await;
+    }
+
+    if connections.is_empty() && !seated {
+        println!("[Admin] {} is not signed in.", username);
+    } else {
+        println!("[Admin] Kicked {} ({} connection(s) closed).", username, connections.len());
+    }
+}
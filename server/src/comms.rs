@@ -1,190 +1,365 @@
 
 use std::{
     collections::HashMap,
-    io::{ErrorKind, Read, Write},
-    net::{TcpListener, TcpStream},
-    sync::mpsc::{self, Sender},
     sync::{Arc, Mutex},
-    thread,
-    time::Duration,
 };
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio_rustls::TlsAcceptor;
 use crate::*;
+use protocol::framing::{encode_frame, encode_legacy, is_legacy_message, FrameReader, HANDSHAKE_FRAME_LEN, MAX_FRAME_LEN};
+use protocol::{handshake, ServerEvent};
 
 
+/// What a connection reports to the dispatcher in `main.rs`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClientEvent {
+    /// A complete message from the client.
+    Message(String),
+    /// The connection closed or failed.
+    Disconnected,
+    /// The grace period of the named player, who disconnected mid-game, ran out
+    /// (see `departures.rs`).
+    GraceExpired(String),
+}
+
+/// Accepts client connections for as long as the server runs.
+///
+/// Each client is registered in `clients` and served by its own task running
+/// `handle_client`, after the TLS handshake if TLS is enabled.
+///
+/// # Arguments
+/// * `listener` - The bound listening socket.
+/// * `clients` - Shared list of connected clients.
+/// * `server_tx` - Sender to forward client messages to the server.
+/// * `tls` - The TLS acceptor, or `None` for plain TCP.
+pub async fn accept_clients(
+    listener: TcpListener,
+    clients: Arc<Mutex<HashMap<std::net::SocketAddr, ClientInfo>>>,
+    server_tx: UnboundedSender<(std::net::SocketAddr, ClientEvent)>,
+    tls: Option<TlsAcceptor>,
+) {
+    loop {
+        let (socket, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                eprintln!("Error accepting client: {}", e);
+                continue;
+            }
+        };
+        println!("Client connected: {}", addr);
+        let client_rx = register_client(&clients, addr);
+
+        let server_tx = server_tx.clone();
+        let tls = tls.clone();
+        tokio::spawn(async move {
+            match tls {
+                Some(acceptor) => match acceptor.accept(socket).await {
+                    Ok(stream) => handle_client(stream, addr, server_tx, client_rx).await,
+                    Err(e) => {
+                        eprintln!("[TLS] Failed to start session with {}: {}", addr, e);
+                        let _ = server_tx.send((addr, ClientEvent::Disconnected));
+                    }
+                },
+                None => handle_client(socket, addr, server_tx, client_rx).await,
+            }
+        });
+    }
+}
+
+/// Adds a newly connected client to the shared client list.
+///
+/// # Arguments
+/// * `clients` - Shared list of connected clients.
+/// * `addr` - The network address of the client.
+///
+/// # Returns
+/// The receiver for messages the server sends to this client.
+pub fn register_client(
+    clients: &Arc<Mutex<HashMap<std::net::SocketAddr, ClientInfo>>>,
+    addr: std::net::SocketAddr,
+) -> UnboundedReceiver<String> {
+    let (client_tx, client_rx) = mpsc::unbounded_channel::<String>();
+    clients.lock().unwrap().insert(
+        addr,
+        ClientInfo {
+            addr,
+            sender: client_tx,
+        },
+    );
+    println!("[Server] Inserting client: addr = {}, sender = mpsc::UnboundedSender", addr);
+    client_rx
+}
+
 /// Handles communication with a single connected client.
 ///
-/// This function runs in its own thread for each client. It:
-/// - Reads messages sent from the client and forwards them to the main server thread.
-/// - Listens for messages from the server and sends them back to the client.
-/// - Uses non-blocking I/O and a small sleep to avoid busy-waiting.
+/// This function runs as its own task for each client. It:
+/// - Reads messages sent from the client and forwards them to the main server task.
+/// - Waits for messages from the server and sends them back to the client.
+/// - Messages in both directions are length-prefixed frames (see `protocol::framing`).
+///   Frames from the client are limited to `HANDSHAKE_FRAME_LEN` bytes until the
+///   dealer's `welcome` has been sent, and to `MAX_FRAME_LEN` after it.
+/// - A client from before framing is told to upgrade, in its own padded format,
+///   and disconnected.
 ///
 /// # Arguments
-/// * `socket` - The TCP stream connected to the client.
+/// * `socket` - The stream connected to the client: a `TcpStream`, or a TLS stream
+///   wrapping one.
 /// * `addr` - The network address of the client.
 /// * `server_tx` - Sender to forward client messages to the server.
 /// * `client_rx` - Receiver to get messages from the server for this client.
 ///
-/// This loop continues until the client disconnects or an error occurs.
-pub fn handle_client(
-    mut socket: TcpStream,
+/// This loop continues until the client disconnects or an error occurs, and then
+/// reports the disconnect to the server.
+pub async fn handle_client<S: AsyncRead + AsyncWrite>(
+    socket: S,
     addr: std::net::SocketAddr,
-    server_tx: Sender<(std::net::SocketAddr, String)>,
-    client_rx: mpsc::Receiver<String>,
+    server_tx: UnboundedSender<(std::net::SocketAddr, ClientEvent)>,
+    mut client_rx: UnboundedReceiver<String>,
 ) {
-    socket
-        .set_nonblocking(true)
-        .expect("Failed to set client socket non-blocking");
+    let (mut read_half, mut write_half) = tokio::io::split(socket);
+    let mut reader = FrameReader::with_limit(HANDSHAKE_FRAME_LEN);
+    let mut greeted = false;
+    let mut buf = [0u8; 4096];
+    let mut first_read = true;
 
-    let mut buffer = [0u8; MSG_SIZE];
-
-    loop {
-        // Read client message
-        match socket.read(&mut buffer) {
-            Ok(_) => {
-                println!("[Server] Received data from client: {}", addr);
-                
-                let msg = buffer
-                    .iter()
-                    .cloned()
-                    .take_while(|&x| x != 0)
-                    .collect::<Vec<u8>>();
-        
-                match String::from_utf8(msg) {
-                    Ok(text) => {
-                        println!("[Server] Parsed UTF-8 message from {}: {}", addr, text);
-                        if let Err(e) = server_tx.send((addr, text.clone())) {
-                            eprintln!("[Server] Failed to forward message from {}: {}", addr, e);
-                        }
+    'connection: loop {
+        tokio::select! {
+            read = read_half.read(&mut buf) => {
+                let n = match read {
+                    Ok(0) => {
+                        println!("[Server] Client {} closed the connection", addr);
+                        break;
                     }
+                    Ok(n) => n,
                     Err(e) => {
-                        eprintln!("[Server] Invalid UTF-8 from {}: {}", addr, e);
+                        println!("[Server] Client {} disconnected (read error): {}", addr, e);
+                        break;
+                    }
+                };
+
+                if std::mem::take(&mut first_read) && is_legacy_message(&buf[..n]) {
+                    println!("[Handshake] {} sent an unframed message; telling it to upgrade", addr);
+                    let reply = encode_legacy(&handshake::handshake_required().to_json());
+                    if let Err(e) = write_half.write_all(&reply).await {
+                        eprintln!("[Server] Failed to send reply to {}: {}", addr, e);
                     }
+                    let _ = write_half.flush().await;
+                    break;
                 }
 
-                buffer.fill(0);
-            }
-        
-            Err(ref err) if err.kind() == ErrorKind::WouldBlock => {
-                // No data yet — expected with non-blocking I/O
-            }
-        
-            Err(e) => {
-                println!("[Server] Client {} disconnected (read error): {}", addr, e);
-                break;
+                // Forward each complete message
+                reader.push(&buf[..n]);
+                loop {
+                    match reader.next_message() {
+                        Ok(Some(text)) => {
+                            println!("[Server] Received message from {}: {}", addr, user_info::redact_secrets(&text));
+                            if let Err(e) = server_tx.send((addr, ClientEvent::Message(text))) {
+                                eprintln!("[Server] Failed to forward message from {}: {}", addr, e);
+                            }
+                        }
+                        Ok(None) => break,
+                        Err(e) => {
+                            eprintln!("[Server] Invalid message from {}: {}", addr, e);
+                            break 'connection;
+                        }
+                    }
+                }
             }
-        }
-        
-        // Check if the server sent a message to this client
-        match client_rx.try_recv() {
-            Ok(reply) => {
-                println!("[Server] Sending reply to {}: {}", addr, reply);
-                let mut reply_bytes = reply.into_bytes();
-                reply_bytes.resize(MSG_SIZE, 0);
-                if let Err(e) = socket.write_all(&reply_bytes) {
+            reply = client_rx.recv() => {
+                let Some(reply) = reply else {
+                    println!("[Server] Channel to {} closed", addr);
+                    break;
+                };
+                println!("[Server] Sending reply to {}: {}", addr, describe_reply(&reply));
+                if let Err(e) = write_half.write_all(&encode_frame(&reply)).await {
                     eprintln!("[Server] Failed to send reply to {}: {}", addr, e);
+                    break;
+                }
+                if !greeted && event_kind(&reply) == "welcome" {
+                    greeted = true;
+                    reader.set_limit(MAX_FRAME_LEN);
                 }
-            }
-            Err(std::sync::mpsc::TryRecvError::Empty) => {
-                // No message for client this round
-            }
-            Err(std::sync::mpsc::TryRecvError::Disconnected) => {
-                println!("[Server] Channel to {} closed", addr);
-                break;
             }
         }
-
-        thread::sleep(Duration::from_millis(100));
     }
+
+    let _ = server_tx.send((addr, ClientEvent::Disconnected));
+}
+
+/// Describes a reply for the log by its event kind and length.
+///
+/// Replies can carry a player's hole cards or a session token, so their bodies
+/// are never logged.
+pub fn describe_reply(reply: &str) -> String {
+    format!("{} ({} bytes)", event_kind(reply), reply.len())
 }
 
-/// Sends a message to a specific connected client.
+/// The `event` field of a reply, or `"unknown"`.
+pub fn event_kind(reply: &str) -> String {
+    serde_json::from_str::<serde_json::Value>(reply)
+        .ok()
+        .and_then(|json| json.get("event").and_then(|event| event.as_str()).map(str::to_string))
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Sends an event to a specific connected client.
 ///
 /// # Arguments
 /// * `clients` - Shared list of connected clients.
 /// * `addr` - The address of the target client.
-/// * `message` - The message to send.
+/// * `event` - The event to send.
 ///
-/// If the client is connected, the message is sent over its channel.
+/// If the client is connected, the event is sent over its channel.
 pub fn send_to_client(
     clients: &Arc<Mutex<HashMap<std::net::SocketAddr, ClientInfo>>>,
     addr: &std::net::SocketAddr,
-    message: &str,
+    event: &ServerEvent,
 ) {
     if let Some(client) = clients.lock().unwrap().get(addr) {
-        let _ = client.sender.send(message.to_string());
+        let _ = client.sender.send(event.to_json());
     }
 }
 
-/// Broadcasts a message to all connected clients.
+/// Sends a client a final event and closes its connection.
+///
+/// Dropping the client's sender ends its connection task once the event has been
+/// written, and the task then reports the disconnect as usual.
 ///
 /// # Arguments
 /// * `clients` - Shared list of connected clients.
-/// * `message` - The message to send.
+/// * `addr` - The address of the client to disconnect.
+/// * `event` - The last event it receives, e.g. why it was disconnected.
+pub fn disconnect_client(
+    clients: &Arc<Mutex<HashMap<std::net::SocketAddr, ClientInfo>>>,
+    addr: &std::net::SocketAddr,
+    event: &ServerEvent,
+) {
+    if let Some(client) = clients.lock().unwrap().remove(addr) {
+        let _ = client.sender.send(event.to_json());
+    }
+}
+
+/// Broadcasts an event to all connected clients.
 ///
-/// The message is cloned and sent to every client.
+/// # Arguments
+/// * `clients` - Shared list of connected clients.
+/// * `event` - The event to send.
+///
+/// The event is encoded once and sent to every client.
 pub fn broadcast_message(
     clients: &Arc<Mutex<HashMap<std::net::SocketAddr, ClientInfo>>>,
-    message: &str,
+    event: &ServerEvent,
 ) {
+    let message = event.to_json();
     for client in clients.lock().unwrap().values() {
-        let _ = client.sender.send(message.to_string());
+        let _ = client.sender.send(message.clone());
     }
 }
 
-/// Broadcasts a message to all players currently in the game.
+/// The connections of every seated player, found through their sessions.
+///
+/// # Returns
+/// Each seated player with one of their bound connections. A player with no
+/// connection (e.g. during their grace period) is left out.
+fn seat_connections() -> Vec<(String, std::net::SocketAddr)> {
+    let Some(player_map) = GAME_PLAYERS.get() else {
+        return Vec::new();
+    };
+    let players: Vec<String> = player_map.lock().unwrap().iter().cloned().collect();
+    connections_of(&players)
+}
+
+/// The connections bound to each of the given players' sessions.
+fn connections_of(players: &[String]) -> Vec<(String, std::net::SocketAddr)> {
+    let Some(sessions) = SESSIONS.get() else {
+        return Vec::new();
+    };
+    let sessions = sessions.lock().unwrap();
+    players
+        .iter()
+        .flat_map(|player| {
+            sessions
+                .connections_for(player)
+                .into_iter()
+                .map(move |addr| (player.clone(), addr))
+        })
+        .collect()
+}
+
+/// Broadcasts an event to all players currently in the game.
 ///
 /// # Arguments
 /// * `clients` - Shared list of connected clients.
-/// * `message` - The message to send.
+/// * `event` - The event to send.
 ///
-/// Only clients who are registered as active game players will receive the message.
+/// Only the connections bound to seated players' sessions receive the event.
 pub fn broadcast_to_game_players(
     clients: &Arc<Mutex<HashMap<std::net::SocketAddr, ClientInfo>>>,
-    message: &str,
+    event: &ServerEvent,
 ) {
-    if let Some(player_map) = GAME_PLAYERS.get() {
-        let player_addrs: Vec<std::net::SocketAddr> = {
-            let map = player_map.lock().unwrap();
-            map.values().cloned().collect()
-        };
+    let message = event.to_json();
+    println!("[Broadcast] {}", message);
+    let seats = seat_connections();
+    let clients = clients.lock().unwrap();
+    for (_, addr) in seats {
+        if let Some(client) = clients.get(&addr) {
+            if let Err(e) = client.sender.send(message.clone()) {
+                eprintln!("[Broadcast] Error sending to {}: {}", addr, e);
+            }
+        }
+    }
+}
 
-        let clients = clients.lock().unwrap();
-        for addr in player_addrs {
-            if let Some(client) = clients.get(&addr) {
-                if let Err(e) = client.sender.send(message.to_string()) {
-                    eprintln!("[Broadcast] Error sending to {}: {}", addr, e);
-                }
+/// Sends each seated player their own version of an event, such as the table
+/// with only their cards in it.
+///
+/// # Arguments
+/// * `clients` - Shared list of connected clients.
+/// * `event_for` - Builds the event for a player, given their ID.
+pub fn send_to_each_game_player(
+    clients: &Arc<Mutex<HashMap<std::net::SocketAddr, ClientInfo>>>,
+    event_for: impl Fn(&str) -> ServerEvent,
+) {
+    for (player, addr) in seat_connections() {
+        let message = event_for(&player).to_json();
+        if let Some(client) = clients.lock().unwrap().get(&addr) {
+            if let Err(e) = client.sender.send(message) {
+                eprintln!("[SendToPlayer] Failed to send message to {}: {}", player, e);
             }
         }
     }
 }
 
-/// Sends a message to a specific player by their player ID.
+/// Sends an event to a specific player by their player ID.
 ///
 /// # Arguments
 /// * `clients` - Shared list of connected clients.
 /// * `player_id` - The player’s unique id.
-/// * `message` - The message to send.
+/// * `event` - The event to send.
 ///
-/// If the player is found in the active game player list, the message is sent to them.
+/// The event goes to every connection bound to the player's session.
 pub fn send_to_player_by_id(
     clients: &Arc<Mutex<HashMap<std::net::SocketAddr, ClientInfo>>>,
     player_id: &str,
-    message: &str,
+    event: &ServerEvent,
 ) {
-    if let Some(player_map) = GAME_PLAYERS.get() {
-        if let Some(addr) = player_map.lock().unwrap().get(player_id) {
-            if let Some(client) = clients.lock().unwrap().get(addr) {
-                if let Err(e) = client.sender.send(message.to_string()) {
+    let connections = connections_of(&[player_id.to_string()]);
+    if connections.is_empty() {
+        eprintln!("[SendToPlayer] No connection found for player: {}", player_id);
+        return;
+    }
+    let message = event.to_json();
+    let clients = clients.lock().unwrap();
+    for (_, addr) in connections {
+        match clients.get(&addr) {
+            Some(client) => {
+                if let Err(e) = client.sender.send(message.clone()) {
                     eprintln!("[SendToPlayer] Failed to send message to {}: {}", player_id, e);
                 }
-            } else {
-                eprintln!("[SendToPlayer] No client found for address: {}", addr);
             }
-        } else {
-            eprintln!("[SendToPlayer] No address found for player: {}", player_id);
+            None => eprintln!("[SendToPlayer] No client found for address: {}", addr),
         }
-    } else {
-        eprintln!("[SendToPlayer] GAME_PLAYERS not initialized.");
     }
 }
@@ -0,0 +1,237 @@
+//! # Server Configuration
+//!
+//! Loads optional server settings from a TOML file.
+//!
+//! The file is `dealer.toml` in the working directory, or the path given in the
+//! `DEALER_CONFIG` environment variable. A missing file, section or field falls back
+//! to the defaults below, so the server runs without any configuration at all.
+//!
+//! # Example
+//! ```toml
+//! [storage]
+//! backend = "sqlite"   # "mongodb" (default), "sqlite" or "memory"
+//! sqlite_path = "dealer.db"
+//! mongodb_uri = "mongodb://localhost:27017"
+//! mongodb_database = "dealer"
+//!
+//! [tls]
+//! enabled = true
+//! cert_path = "dealer-cert.pem"   # generated (self-signed) if neither file exists
+//! key_path = "dealer-key.pem"
+//!
+//! [websocket]
+//! enabled = true                  # on by default
+//! port = 8081
+//!
+//! [limits]
+//! messages_per_second = 10.0      # per connection, and per account across its connections
+//! message_burst = 40
+//! login_attempts = 5              # failed logins per address
+//! account_login_attempts = 20     # failed logins per account, from any address
+//! login_window_secs = 300
+//! ban_secs = 600
+//!
+//! [shutdown]
+//! hand_timeout_secs = 120         # how long a hand may play on before it is voided
+//!
+//! [metrics]
+//! enabled = true                  # on by default
+//! port = 8082                     # served on 127.0.0.1 only
+//! ```
+use std::fs;
+use std::io::ErrorKind;
+use serde::Deserialize;
+
+/// Config file read when `DEALER_CONFIG` is not set.
+const DEFAULT_CONFIG_PATH: &str = "dealer.toml";
+
+
+/// All server settings.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// Where players, stats and game state are stored.
+    pub storage: StorageConfig,
+    /// Encryption of client connections.
+    pub tls: TlsConfig,
+    /// WebSocket listener for browser clients.
+    pub websocket: WebSocketConfig,
+    /// Flood protection.
+    pub limits: LimitsConfig,
+    /// Graceful shutdown.
+    pub shutdown: ShutdownConfig,
+    /// Prometheus metrics endpoint.
+    pub metrics: MetricsConfig,
+}
+
+/// Storage backend selection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+pub enum StorageBackend {
+    /// MongoDB server (requires a running `mongod`).
+    #[default]
+    #[serde(rename = "mongodb")]
+    MongoDb,
+    /// SQLite database file. No database server needed.
+    #[serde(rename = "sqlite")]
+    Sqlite,
+    /// In-process memory. Nothing survives a restart.
+    #[serde(rename = "memory")]
+    Memory,
+}
+
+/// Settings for the `[storage]` section.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct StorageConfig {
+    /// Which backend to use.
+    pub backend: StorageBackend,
+    /// MongoDB connection string.
+    pub mongodb_uri: String,
+    /// MongoDB database name.
+    pub mongodb_database: String,
+    /// SQLite database file, created if missing.
+    pub sqlite_path: String,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            backend: StorageBackend::MongoDb,
+            mongodb_uri: "mongodb://localhost:27017".to_string(),
+            mongodb_database: "dealer".to_string(),
+            sqlite_path: "dealer.db".to_string(),
+        }
+    }
+}
+
+/// Settings for the `[tls]` section.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TlsConfig {
+    /// Whether clients must connect with TLS. Off by default.
+    pub enabled: bool,
+    /// PEM certificate (chain) presented to clients.
+    pub cert_path: String,
+    /// PEM private key for the certificate. Keep it readable by the dealer's user
+    /// only (`chmod 600`); a generated key is written that way.
+    pub key_path: String,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            cert_path: "dealer-cert.pem".to_string(),
+            key_path: "dealer-key.pem".to_string(),
+        }
+    }
+}
+
+/// Settings for the `[websocket]` section.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct WebSocketConfig {
+    /// Whether to accept WebSocket clients. On by default.
+    pub enabled: bool,
+    /// Port for WebSocket clients, next to the TCP listener on 8080.
+    pub port: u16,
+}
+
+impl Default for WebSocketConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            port: 8081,
+        }
+    }
+}
+
+/// Settings for the `[limits]` section.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LimitsConfig {
+    /// Messages a connection (or an account, over all its connections) may send
+    /// per second on average.
+    pub messages_per_second: f64,
+    /// Messages that may be sent at once before the rate applies.
+    pub message_burst: u32,
+    /// Failed logins allowed per address within the window.
+    pub login_attempts: u32,
+    /// Failed logins allowed per account within the window, from any address.
+    pub account_login_attempts: u32,
+    /// Window for counting failed logins, in seconds.
+    pub login_window_secs: u64,
+    /// How long offenders are banned, in seconds.
+    pub ban_secs: u64,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            messages_per_second: 10.0,
+            message_burst: 40,
+            login_attempts: 5,
+            account_login_attempts: 20,
+            login_window_secs: 300,
+            ban_secs: 600,
+        }
+    }
+}
+
+/// Settings for the `[shutdown]` section.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ShutdownConfig {
+    /// How long a hand in progress may play on after a shutdown starts, in
+    /// seconds. A hand still going after that is voided and its bets refunded.
+    pub hand_timeout_secs: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self { hand_timeout_secs: 120 }
+    }
+}
+
+/// Settings for the `[metrics]` section.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+    /// Whether to serve metrics. On by default.
+    pub enabled: bool,
+    /// Port for the metrics endpoint, on localhost only.
+    pub port: u16,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            port: 8082,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Loads the configuration file, falling back to defaults if it does not exist.
+    ///
+    /// # Errors
+    /// Returns a message if the file exists but cannot be read or parsed.
+    pub fn load() -> Result<Self, String> {
+        let path = std::env::var("DEALER_CONFIG").unwrap_or_else(|_| DEFAULT_CONFIG_PATH.to_string());
+
+        match fs::read_to_string(&path) {
+            Ok(contents) => {
+                println!("[Config] Loaded settings from {}", path);
+                Self::parse(&contents).map_err(|e| format!("Invalid config file {}: {}", path, e))
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("Failed to read config file {}: {}", path, e)),
+        }
+    }
+
+    /// Parses configuration from TOML text.
+    pub fn parse(contents: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(contents)
+    }
+}
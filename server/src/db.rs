@@ -2,68 +2,54 @@
 //!
 //! This module handles all database operations for the server.
 //!
-//! Uses MongoDB to store and update player data, game states, and history.
-//! Includes functions to initialize the database, update game results, handle player actions, and retrieve stats.
+//! Stores and updates player data, game states and the lobby through the `Storage` trait,
+//! so the same functions work with MongoDB, SQLite or the in-memory backend.
+//! Includes functions to initialize the game state, update game results, handle player actions, and retrieve stats.
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
 use mongodb::{
-    bson::Document,
     bson::doc,
-    options::{ClientOptions, ServerApi, ServerApiVersion},
-    Client, Collection, bson
+    bson
 };
-use futures_util::stream::StreamExt;
 use crate::five_card_draw::Player;
+use crate::hand_events::{append_event, stored_events, HandEvent, HandState};
+use crate::hand_stats::behaviour_stats;
+use crate::history::load_player_history;
+use crate::results::{apply_totals, bankroll_series, load_results, ResultRecord};
+use crate::metrics::METRICS;
+use crate::recovery::{self, TurnAction};
+use crate::storage::*;
+use protocol::{LobbyInfo, PlayerStats, ServerEvent};
 use serde_json::json;
-use serde_json::Value;
 
+/// How often the game loop checks whether a player has acted, if it is not woken sooner.
+const TURN_POLL_INTERVAL: Duration = Duration::from_millis(300);
+
+/// Wakes the game loop as soon as a player submits a bet or swap.
+static TURN_SUBMITTED: Notify = Notify::const_new();
+
+/// Game state field holding what `update_players_folded` has written for the
+/// hand in progress, so `void_hand` can take it back.
+const FOLDED_CHARGES: &str = "folded_charges";
+
+/// Set while the operator has paused the table; turns wait until it is resumed.
+static TABLE_PAUSED: AtomicBool = AtomicBool::new(false);
 
-/// Connects to the MongoDB database and returns the main collections used by the server.
-///
-/// Collections returned:
-/// - `players`: Stores player data and stats.
-/// - `lobbies`: Stores game lobby information.
-/// - `games`: Stores active game states.
-/// - `history`: Stores game history and past results.
-///
-/// # Returns
-/// A tuple with the four MongoDB collections.
-///
-/// # Errors
-/// Returns an error if the database connection or collection setup fails.
-pub async fn init_db() -> mongodb::error::Result<(
-    Collection<Document>,
-    Collection<Document>,
-    Collection<Document>,
-    Collection<Document>,
-)> {
-    let uri = "mongodb://localhost:27017";
-    let mut client_options = ClientOptions::parse(uri).await?;
-    let server_api = ServerApi::builder().version(ServerApiVersion::V1).build();
-    client_options.server_api = Some(server_api);
-
-    let client = Client::with_options(client_options)?;
-    let players_collection = client.database("dealer").collection("players");
-    let lobbies_collection = client.database("dealer").collection("lobbies");
-    let games_collection = client.database("dealer").collection("games");
-    let history_collection = client.database("dealer").collection("history");
-
-    Ok((players_collection, lobbies_collection, games_collection, history_collection))
-}
 
 /// Retrieves the current bet amount for a specific player.
 ///
 /// # Arguments
-/// * `players_collection` - Reference to the MongoDB players collection.
+/// * `storage` - The server's storage backend.
 /// * `username` - The name of the player to query.
 ///
 /// # Returns
 /// An `Option<i32>` containing the bet amount if found.
 pub async fn get_player_bet(
-    players_collection: &Collection<Document>,
+    storage: &dyn Storage,
     username: &str,
 ) -> Option<i32> {
-    let filter = doc! { "name": username };
-
-    match players_collection.find_one(filter).await {
+    match storage.find_player(username).await {
         Ok(Some(player_doc)) => {
             // Try to extract the "bet" field
             match player_doc.get_i32("bet") {
@@ -85,55 +71,372 @@ pub async fn get_player_bet(
     }
 }
 
+/// Records a player's bet and ends their betting turn.
+///
+/// Called when a client sends the `"bet"` command. The game loop waiting in
+/// `wait_for_player_bet` is woken to pick the amount up.
+///
+/// # Arguments
+/// * `storage` - The server's storage backend.
+/// * `username` - The player placing the bet.
+/// * `amount` - Chips added this turn, `0` to check or `-1` to fold.
+pub async fn submit_player_bet(
+    storage: &dyn Storage,
+    username: &str,
+    amount: i32,
+) -> StorageResult<bool> {
+    let result = storage
+        .set_player_fields(username, doc! { "bet": amount, "bet_turn": false })
+        .await;
+    TURN_SUBMITTED.notify_waiters();
+    result
+}
+
+/// Records a player's swap selection and ends their swap turn.
+///
+/// # Arguments
+/// * `storage` - The server's storage backend.
+/// * `username` - The player swapping cards.
+/// * `indices` - Comma-separated card indices to replace.
+pub async fn submit_player_swap(
+    storage: &dyn Storage,
+    username: &str,
+    indices: &str,
+) -> StorageResult<bool> {
+    let result = storage
+        .set_player_fields(username, doc! { "swap": indices, "swap_turn": false })
+        .await;
+    TURN_SUBMITTED.notify_waiters();
+    result
+}
+
+/// Marks a player as sitting out, or back in.
+///
+/// The game folds for a player who is sitting out whenever it is their turn.
+///
+/// # Arguments
+/// * `storage` - The server's storage backend.
+/// * `username` - The player.
+/// * `sitting_out` - Whether they are sitting out.
+pub async fn set_sitting_out(
+    storage: &dyn Storage,
+    username: &str,
+    sitting_out: bool,
+) -> StorageResult<bool> {
+    let result = storage
+        .set_player_fields(username, doc! { "sitting_out": sitting_out })
+        .await;
+    // Wake a turn that may be waiting on this player
+    TURN_SUBMITTED.notify_waiters();
+    result
+}
+
+/// Pauses or resumes the table. While paused, bets and swaps are refused and the
+/// game waits on the current turn, without folding anyone who is sitting out.
+pub fn set_table_paused(paused: bool) {
+    TABLE_PAUSED.store(paused, Ordering::SeqCst);
+    TURN_SUBMITTED.notify_waiters();
+}
+
+/// Whether the operator has paused the table.
+pub fn table_paused() -> bool {
+    TABLE_PAUSED.load(Ordering::SeqCst)
+}
+
+/// Gives a player chips, or takes them away, outside of play.
+///
+/// The amount is kept in `chips_adjusted`, apart from the player's winnings and
+/// losses, and counts toward their stack (see `player_stack`).
+///
+/// # Arguments
+/// * `storage` - The server's storage backend.
+/// * `username` - The player.
+/// * `amount` - Chips to add; negative to remove.
+///
+/// # Returns
+/// `true` if the player exists.
+pub async fn adjust_player_chips(
+    storage: &dyn Storage,
+    username: &str,
+    amount: i32,
+) -> StorageResult<bool> {
+    storage
+        .increment_player_fields(username, doc! { "chips_adjusted": amount })
+        .await
+}
+
+/// A player's stack: winnings, less losses, plus any operator adjustments.
+pub fn player_stack(player_doc: &bson::Document) -> i32 {
+    let won = player_doc.get_i32("money_win").unwrap_or(0);
+    let lost = player_doc.get_i32("money_lost").unwrap_or(0);
+    let adjusted = player_doc.get_i32("chips_adjusted").unwrap_or(0);
+    won + lost + adjusted
+}
+
+/// Lists which of the given players are sitting out.
+pub async fn sitting_out_players(storage: &dyn Storage, players: &[String]) -> Vec<String> {
+    let mut sitting_out = Vec::new();
+    for player in players {
+        if let Ok(Some(doc)) = storage.find_player(player).await {
+            if doc.get_bool("sitting_out") == Ok(true) {
+                sitting_out.push(player.clone());
+            }
+        }
+    }
+    sitting_out
+}
+
+/// Gives a player the betting turn and waits until they have placed a bet.
+///
+/// Sets `bet_turn = true` on the player, then waits until a `"bet"` command clears it.
+/// A player who is sitting out folds. The turn and the bet are recorded in the live
+/// hand, so a resumed hand can take the recorded bet instead of waiting.
+///
+/// # Arguments
+/// * `storage` - The server's storage backend.
+/// * `player_id` - The player whose turn it is.
+///
+/// # Returns
+/// The bet amount the player submitted, if it could be read.
+pub async fn wait_for_player_bet(
+    storage: &dyn Storage,
+    player_id: &str,
+) -> Option<i32> {
+    let prompted = Instant::now();
+    let _ = storage.set_player_fields(player_id, doc! { "bet_turn": true }).await;
+    if let Err(e) = recovery::set_pending_turn(storage, player_id, "bet").await {
+        eprintln!("[DB] Failed to save pending turn: {}", e);
+    }
+
+    // Wait till the bet is made
+    loop {
+        wait_for_turn_submission().await;
+        if table_paused() {
+            continue;
+        }
+
+        // Fetch the latest value
+        if let Ok(Some(doc)) = storage.find_player(player_id).await {
+            if let Ok(false) = doc.get_bool("bet_turn") {
+                println!("{} has completed their bet.", player_id);
+                break;
+            }
+            if let Ok(true) = doc.get_bool("sitting_out") {
+                println!("[Game] {} is sitting out and folds.", player_id);
+                let _ = submit_player_bet(storage, player_id, -1).await;
+                break;
+            }
+        }
+    }
+
+    METRICS.observe_action("bet", prompted.elapsed());
+
+    let bet = get_player_bet(storage, player_id).await;
+    if let Some(amount) = bet {
+        let action = TurnAction::Bet { player: player_id.to_string(), amount };
+        if let Err(e) = recovery::record_action(storage, action).await {
+            eprintln!("[DB] Failed to save bet: {}", e);
+        }
+    }
+    bet
+}
+
+/// Gives a player the swap turn and waits until they have chosen cards to replace.
+///
+/// # Arguments
+/// * `storage` - The server's storage backend.
+/// * `player_id` - The player whose turn it is.
+///
+/// A player who is sitting out keeps their cards. Like bets, swaps are recorded
+/// in the live hand, to be taken from it when the hand is resumed.
+///
+/// # Returns
+/// The comma-separated indices the player submitted, if they could be read.
+pub async fn wait_for_player_swap(
+    storage: &dyn Storage,
+    player_id: &str,
+) -> Option<String> {
+    let prompted = Instant::now();
+    let _ = storage.set_player_fields(player_id, doc! { "swap_turn": true }).await;
+    if let Err(e) = recovery::set_pending_turn(storage, player_id, "swap").await {
+        eprintln!("[DB] Failed to save pending turn: {}", e);
+    }
+
+    loop {
+        wait_for_turn_submission().await;
+        if table_paused() {
+            continue;
+        }
+        if let Ok(Some(doc)) = storage.find_player(player_id).await {
+            if let Ok(false) = doc.get_bool("swap_turn") {
+                println!("[Swap] {} finished their swap selection.", player_id);
+                break;
+            }
+            if let Ok(true) = doc.get_bool("sitting_out") {
+                println!("[Swap] {} is sitting out and keeps their cards.", player_id);
+                let _ = submit_player_swap(storage, player_id, "").await;
+                break;
+            }
+        }
+    }
+
+    METRICS.observe_action("swap", prompted.elapsed());
+
+    let swap = get_player_swap(storage, player_id).await;
+    if let Some(indices) = &swap {
+        let action = TurnAction::Swap { player: player_id.to_string(), indices: indices.clone() };
+        if let Err(e) = recovery::record_action(storage, action).await {
+            eprintln!("[DB] Failed to save swap: {}", e);
+        }
+    }
+    swap
+}
+
+/// Waits until any player submits a bet or swap, or `TURN_POLL_INTERVAL` passes.
+///
+/// The timeout catches turns completed without `submit_player_bet` or
+/// `submit_player_swap`, so the storage stays the source of truth.
+async fn wait_for_turn_submission() {
+    let _ = tokio::time::timeout(TURN_POLL_INTERVAL, TURN_SUBMITTED.notified()).await;
+}
+
 /// Updates stats for players who folded in the current game.
 ///
-/// Increases games played, losses, and money lost for each folded player.
+/// Increases games played, losses, and money lost for each folded player, both
+/// overall and for the variant. Pass each player once per hand: `play_hand`
+/// passes those who folded in the round of betting just ended.
 ///
 /// # Arguments
-/// * `players_collection` - Reference to the MongoDB players collection.
+/// * `storage` - The server's storage backend.
+/// * `variant` - The game variant being played.
 /// * `folded_players` - List of players who folded.
 ///
 /// # Returns
-/// MongoDB operation result.
+/// Storage operation result.
 pub async fn update_players_folded(
-    players_collection: &Collection<Document>,
+    storage: &dyn Storage,
+    variant: &str,
     folded_players: &[Player],
-) -> mongodb::error::Result<()> {
+) -> StorageResult<()> {
     for player in folded_players {
-        let filter = doc! { "name": &player.id };
-
-        let update = doc! {
-            "$inc": {
-                "games_played": 1,
-                "losses": 1,
-                "money_lost": player.money_lost*-1,
-            },
-        };
+        let update = with_variant_totals(doc! {
+            "games_played": 1,
+            "losses": 1,
+            "money_lost": -player.money_lost,
+        }, variant);
 
-        if let Err(e) = players_collection.update_one(filter, update).await {
+        if let Err(e) = storage.increment_player_fields(&player.id, update.clone()).await {
             eprintln!("[DB] Failed to update folded player {}: {}", player.id, e);
         } else {
             println!("[DB] Updated folded player: {}", player.id);
+            record_folded_charge(storage, &player.id, &update).await?;
         }
     }
 
     Ok(())
 }
 
+/// Adds the same stats update under `variants.<variant>`, so each variant's totals
+/// are kept alongside the overall ones.
+fn with_variant_totals(mut update: bson::Document, variant: &str) -> bson::Document {
+    let variant_fields: Vec<(String, bson::Bson)> = update
+        .iter()
+        .map(|(field, amount)| (format!("variants.{}.{}", variant, field), amount.clone()))
+        .collect();
+    update.extend(variant_fields);
+    update
+}
+
+/// Adds a folded player's update to the hand's `folded_charges`.
+async fn record_folded_charge(
+    storage: &dyn Storage,
+    player_id: &str,
+    update: &bson::Document,
+) -> StorageResult<()> {
+    let Some(state) = storage.game_state().await? else {
+        return Ok(());
+    };
+    let mut charges = state.get_document(FOLDED_CHARGES).cloned().unwrap_or_default();
+    let mut charge = charges.get_document(player_id).cloned().unwrap_or_default();
+    for (field, amount) in update {
+        let total = charge.get_i32(field).unwrap_or(0) + amount.as_i32().unwrap_or(0);
+        charge.insert(field, total);
+    }
+    charges.insert(player_id, charge);
+    storage.set_game_state_field(FOLDED_CHARGES, charges.into()).await
+}
+
+/// Voids the hand in progress and refunds every bet in it. Call this only once the
+/// game has stopped.
+///
+/// # Arguments
+/// * `storage` - The server's storage backend.
+///
+/// # Returns
+/// The players whose folded bets were refunded.
+pub async fn void_hand(storage: &dyn Storage) -> StorageResult<Vec<String>> {
+    let refunded = refund_folded_charges(storage).await?;
+    recovery::end_live_hand(storage).await?;
+    append_event(storage, HandEvent::Info { message: "Hand voided; all bets refunded".to_string() }).await?;
+    Ok(refunded)
+}
+
+/// Takes back what `update_players_folded` has written for the hand in progress:
+/// the folded players' games played, losses and chips lost. Chips bet by players
+/// still in the hand are only written when it ends, so this undoes every write the
+/// hand has made.
+///
+/// # Arguments
+/// * `storage` - The server's storage backend.
+///
+/// # Returns
+/// The players whose folded bets were refunded.
+pub async fn refund_folded_charges(storage: &dyn Storage) -> StorageResult<Vec<String>> {
+    let charges = match storage.game_state().await? {
+        Some(state) => state.get_document(FOLDED_CHARGES).cloned().unwrap_or_default(),
+        None => bson::Document::new(),
+    };
+
+    let mut refunded = Vec::new();
+    for (player_id, charge) in &charges {
+        let Some(charge) = charge.as_document() else { continue };
+        let refund: bson::Document = charge
+            .iter()
+            .map(|(field, amount)| (field.clone(), bson::Bson::Int32(-amount.as_i32().unwrap_or(0))))
+            .collect();
+        storage.increment_player_fields(player_id, refund).await?;
+        println!("[DB] Refunded {}: {}", player_id, charge);
+        refunded.push(player_id.clone());
+    }
+
+    storage.set_game_state_field(FOLDED_CHARGES, bson::Document::new().into()).await?;
+    Ok(refunded)
+}
+
+/// Settles a hand that has written its results: clears the live hand and its
+/// folded charges, so neither a restart nor `void_hand` writes or takes them back
+/// again.
+///
+/// # Arguments
+/// * `storage` - The server's storage backend.
+pub async fn settle_hand(storage: &dyn Storage) -> StorageResult<()> {
+    recovery::end_live_hand(storage).await?;
+    storage.set_game_state_field(FOLDED_CHARGES, bson::Document::new().into()).await
+}
+
 /// Gets the swap choice for a specific player.
 ///
 /// # Arguments
-/// * `players_collection` - Reference to MongoDB players collection.
+/// * `storage` - The server's storage backend.
 /// * `player_id` - Player's unique identifier.
 ///
 /// # Returns
 /// An `Option<String>` containing the swap string if found.
 pub async fn get_player_swap(
-    players_collection: &Collection<Document>,
+    storage: &dyn Storage,
     player_id: &str,
 ) -> Option<String> {
-    let filter = doc! { "name": player_id };
-    if let Ok(Some(doc)) = players_collection.find_one(filter).await {
+    if let Ok(Some(doc)) = storage.find_player(player_id).await {
         doc.get_str("swap").ok().map(|s| s.to_string())
     } else {
         None
@@ -144,55 +447,45 @@ pub async fn get_player_swap(
 ///
 /// - Increments games played for all players.
 /// - Updates wins, losses, money won or lost based on game results.
+/// - Keeps the same totals for the variant.
 ///
 /// # Arguments
-/// * `players_collection` - Reference to the MongoDB players collection.
+/// * `storage` - The server's storage backend.
+/// * `variant` - The game variant played.
 /// * `winner_id` - The ID of the winning player.
 /// * `folded_players` - List of players who folded.
 /// * `pot_amount` - Total pot amount to assign to the winner.
 ///
 /// # Returns
-/// MongoDB operation result.
+/// Storage operation result.
 pub async fn update_game_results(
-    players_collection: &Collection<Document>,
+    storage: &dyn Storage,
+    variant: &str,
     winner_id: &str,
     folded_players: &[Player],
     pot_amount: i32,
-) -> mongodb::error::Result<()> {
+) -> StorageResult<()> {
     for player in folded_players {
-        let filter = doc! { "name": &player.id };
-
-        if winner_id == player.id {
-            let winner_update = doc! {
-                "$inc": {
-                    "games_played": 1,
-                    "wins": 1,
-                    "money_win": pot_amount,
-                    "money_lost": player.money_lost*-1,
-                },
-            };
-
-            if let Err(e) = players_collection.update_one(filter, winner_update).await {
-                eprintln!("[DB] Failed to update folded player {}: {}", player.id, e);
-            } else {
-                println!("[DB] Updated folded player: {}", player.id);
+        let update = if winner_id == player.id {
+            doc! {
+                "games_played": 1,
+                "wins": 1,
+                "money_win": pot_amount,
+                "money_lost": -player.money_lost,
             }
-        }else {
-            let update = doc! {
-                "$inc": {
-                    "games_played": 1,
-                    "losses": 1,
-                    "money_lost": player.money_lost*-1,
-                },
-            };
-    
-            if let Err(e) = players_collection.update_one(filter, update).await {
-                eprintln!("[DB] Failed to update folded player {}: {}", player.id, e);
-            } else {
-                println!("[DB] Updated folded player: {}", player.id);
+        } else {
+            doc! {
+                "games_played": 1,
+                "losses": 1,
+                "money_lost": -player.money_lost,
             }
-        }
+        };
 
+        if let Err(e) = storage.increment_player_fields(&player.id, with_variant_totals(update, variant)).await {
+            eprintln!("[DB] Failed to update folded player {}: {}", player.id, e);
+        } else {
+            println!("[DB] Updated folded player: {}", player.id);
+        }
     }
 
     Ok(())
@@ -201,167 +494,236 @@ pub async fn update_game_results(
 /// Retrieves a list of all player names from database.
 ///
 /// # Arguments
-/// * `players_collection` - Reference to the MongoDB players collection.
+/// * `storage` - The server's storage backend.
 ///
 /// # Returns
-/// A comma-separated `String` of player names.
+/// A `PlayerList` event with every player's name.
 pub async fn handle_stats(
-    players_collection: &Collection<Document>,
-) -> String {
-    // Use an empty filter to retrieve all documents.
-    let filter = doc! {};
-    let mut cursor = match players_collection.find(filter).await {
-        Ok(cursor) => cursor,
+    storage: &dyn Storage,
+) -> ServerEvent {
+    let players = match storage.list_players().await {
+        Ok(players) => players,
         Err(e) => {
             println!("Database error: {}", e);
-            return "".to_string();
+            return ServerEvent::error(format!("Database error: {}", e));
         }
     };
 
     let mut names: Vec<String> = Vec::new();
 
-    while let Some(result) = cursor.next().await {
-        match result {
-            Ok(doc) => {
-                // Attempt to extract the "name" field.
-                if let Ok(name) = doc.get_str("name") {
-                    names.push(name.to_string());
-                } else {
-                    println!("Failed to get 'name' field from document: {:?}", doc);
-                }
-            }
-            Err(e) => {
-                println!("Error retrieving document: {}", e);
-            }
+    for doc in players {
+        // Attempt to extract the "name" field.
+        if let Ok(name) = doc.get_str("name") {
+            names.push(name.to_string());
+        } else {
+            println!("Failed to get 'name' field from document: {:?}", doc);
         }
     }
 
-    // Return all names as a comma-separated string.
-    names.join(", ")
+    ServerEvent::PlayerList { players: names }
 }
 
 /// Retrieves detailed statistics for a specific player.
 ///
-/// Converts BSON doc to a JSON string.
+/// Only the stats fields are read from the player document, so the password
+/// hash and turn state are never sent. The totals for each variant come from the
+/// document too; the behavioural stats (VPIP, PFR, aggression, showdowns) are
+/// worked out from the hands the player was dealt into, looked up in the history
+/// by player and time, overall and per variant.
+///
+/// Given a date range, the totals are instead added up from the player's results
+/// in it (see `results.rs`), and only hands started in it count towards the
+/// behavioural stats. The bankroll series covers the range either way.
 ///
 /// # Arguments
-/// * `players_collection` - Reference to the MongoDB players collection.
+/// * `storage` - The server's storage backend.
 /// * `username` - The name of the player to query.
+/// * `from` - Start of the range in seconds since the Unix epoch, if any.
+/// * `to` - End of the range, if any.
 ///
 /// # Returns
-/// A JSON `String` of the player's stats, or an error message if not found.
+/// A `PlayerStats` event, or an error if the player is not found.
 pub async fn get_user_stats(
-    players_collection: &Collection<Document>,
+    storage: &dyn Storage,
     username: &str,
-) -> String {
-    let filter = doc! { "name": username };
-    match players_collection.find_one(filter).await {
-        Ok(Some(player_doc)) => {
-            // Convert the BSON document to a JSON string.
-            match serde_json::to_string(&player_doc) {
-                Ok(json_str) => json_str,
-                Err(e) => format!("Error serializing player data: {}", e),
-            }
-        }
-        Ok(None) => format!("No player found with name: {}", username),
-        Err(e) => format!("Database error: {}", e),
+    from: Option<i64>,
+    to: Option<i64>,
+) -> ServerEvent {
+    let mut stats = match storage.find_player(username).await {
+        Ok(Some(player_doc)) => match bson::from_document::<PlayerStats>(player_doc) {
+            Ok(stats) => stats,
+            Err(e) => return ServerEvent::error(format!("Error reading player data: {}", e)),
+        },
+        Ok(None) => return ServerEvent::error(format!("No player found with name: {}", username)),
+        Err(e) => return ServerEvent::error(format!("Database error: {}", e)),
+    };
+
+    // Earlier results still count towards the bankroll
+    let results = match load_results(storage, username, None, to).await {
+        Ok(results) => results,
+        Err(e) => return ServerEvent::error(format!("Database error: {}", e)),
+    };
+    stats.bankroll = bankroll_series(&results, from);
+    let in_range = |timestamp: i64| from.is_none_or(|from| timestamp >= from) && to.is_none_or(|to| timestamp <= to);
+    if from.is_some() || to.is_some() {
+        let results: Vec<ResultRecord> = results.into_iter().filter(|r| in_range(r.timestamp)).collect();
+        apply_totals(&mut stats, &results);
+    }
+    stats.from = from;
+    stats.to = to;
+
+    let played = match load_player_history(storage, username, from, to).await {
+        Ok(records) => records,
+        Err(e) => return ServerEvent::error(format!("Database error: {}", e)),
+    };
+    stats.behaviour = behaviour_stats(&played, username);
+    for record in &played {
+        stats.variants.entry(record.variant.clone()).or_default();
     }
+    for (variant, totals) in &mut stats.variants {
+        totals.behaviour = behaviour_stats(played.iter().filter(|r| &r.variant == variant), username);
+    }
+    ServerEvent::PlayerStats(stats)
 }
 
 
-/// Clears the games collection and initializes a new game state document with _id = 1.
-pub async fn init_game_state(
-    games_collection: &Collection<Document>,
-) -> mongodb::error::Result<()> {
-    // Step 1: Clear the collection
-    match games_collection.delete_many(doc! {}).await {
-        Ok(result) => println!("[DB] Cleared games collection ({} docs deleted)", result.deleted_count),
-        Err(e) => {
-            eprintln!("[DB] Failed to clear games collection: {}", e);
-            return Err(e);
-        }
+/// Saves the current lobby so it can be queried with the `"lobby"` command.
+///
+/// # Arguments
+/// * `storage` - The server's storage backend.
+/// * `variant` - The selected game variant (e.g. `"5card"`).
+/// * `max_players` - Number of seats at the table.
+/// * `players` - Players who are ready.
+/// * `sitting_out` - Seated players who left mid-game and did not come back.
+///
+/// # Returns
+/// Storage operation result.
+pub async fn update_lobby(
+    storage: &dyn Storage,
+    variant: &str,
+    max_players: usize,
+    players: &[String],
+    sitting_out: &[String],
+) -> StorageResult<()> {
+    let status = if players.len() >= max_players { "in_game" } else { "waiting" };
+    let lobby = doc! {
+        "variant": variant,
+        "max_players": max_players as i32,
+        "players": players,
+        "sitting_out": sitting_out,
+        "status": status,
+    };
+
+    storage.save_lobby(lobby).await
+}
+
+/// Retrieves the lobby.
+///
+/// # Arguments
+/// * `storage` - The server's storage backend.
+///
+/// # Returns
+/// A `Lobby` event with the lobby's variant, seats, players and status, or an error.
+pub async fn handle_lobby_command(storage: &dyn Storage) -> ServerEvent {
+    match storage.lobby().await {
+        Ok(Some(doc)) => match bson::from_document::<LobbyInfo>(doc) {
+            Ok(lobby) => ServerEvent::Lobby(lobby),
+            Err(e) => ServerEvent::error(format!("Invalid lobby: {}", e)),
+        },
+        Ok(None) => ServerEvent::error("Lobby not initialized."),
+        Err(e) => ServerEvent::error(format!("Database error: {}", e)),
     }
+}
 
+/// Clears the stored game state and initializes a new game state document with _id = 1.
+pub async fn init_game_state(
+    storage: &dyn Storage,
+) -> StorageResult<()> {
     let initial_state = json!({
-        "pot": 0,
-        "round": 0,
-        "round current bet": 0,
-        "player current bets": null,
-        "cards": null,
-        "community": null,
-        "info": "Game initialized"
+        "events": [],
+        "folded_charges": {}
     });
 
-    // Step 2: Convert JSON state to BSON document and insert it
+    // Convert JSON state to BSON document and store it
     let mut doc = bson::to_document(&initial_state)
         .map_err(|e| {
             eprintln!("[DB] Failed to serialize initial game state: {}", e);
-            mongodb::error::Error::from(e)
+            StorageError::from(e)
         })?;
 
     doc.insert("_id", 1);
 
-    games_collection.insert_one(doc).await.map(|_| {
-        println!("[DB] Initialized new game state with _id = 1");
-    })
+    match storage.reset_game_state(doc).await {
+        Ok(()) => {
+            println!("[DB] Initialized new game state with _id = 1");
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("[DB] Failed to reset game state: {}", e);
+            Err(e)
+        }
+    }
 }
 
 
-/// Retrieves the current game state from the MongoDB collection for spectators.
+/// Folds the hand events in the game state document with `_id = 1` into the
+/// hand they describe.
 ///
-/// Converts the full game state BSON document with `_id = 1` to a JSON string
-/// so it can be displayed on the client side.
+/// # Returns
+/// The hand, or an error event if the document is missing.
+async fn stored_hand(storage: &dyn Storage) -> Result<HandState, ServerEvent> {
+    match storage.game_state().await {
+        Ok(Some(doc)) => match stored_events(&doc) {
+            Ok(events) => Ok(HandState::from_events(&events)),
+            Err(e) => Err(ServerEvent::error(format!("Invalid hand events: {}", e))),
+        },
+        Ok(None) => Err(ServerEvent::error("Game state not initialized.")),
+        Err(e) => Err(ServerEvent::error(format!("Database error: {}", e))),
+    }
+}
+
+/// Retrieves the current table from storage for spectators.
+///
+/// Spectators get the board, the pot, the bets and whose turn it is, but no
+/// player's cards until the showdown.
 ///
 /// # Arguments
-/// * `games_collection` - Reference to the MongoDB `games` collection.
+/// * `storage` - The server's storage backend.
 ///
 /// # Returns
-/// A `String` containing the serialized JSON game state or an error message
-/// if the document is missing or fails to serialize.
-pub async fn handle_spectate_command(games_collection: &Collection<Document>) -> String {
-    let filter = doc! { "_id": 1 };
-
-    match games_collection.find_one(filter).await {
-        Ok(Some(doc)) => {
-            // Serialize the entire document into a JSON string
-            match serde_json::to_string(&doc) {
-                Ok(json_str) => json_str,
-                Err(e) => json!({ "error": format!("Serialization failed: {}", e) }).to_string(),
-            }
-        }
-        Ok(None) => json!({ "error": "Game state not initialized." }).to_string(),
-        Err(e) => json!({ "error": format!("Database error: {}", e) }).to_string(),
+/// A `Table` event folded from the stored hand events, or an error if the game
+/// state is missing.
+pub async fn handle_spectate_command(storage: &dyn Storage) -> ServerEvent {
+    match stored_hand(storage).await {
+        Ok(hand) => ServerEvent::Table(hand.table_for(None)),
+        Err(error) => error,
     }
 }
 
-
-/// Updates a specific field in the game state document with `_id = 1`.
-///
-/// This is used by the server to update various parts of the game state
-/// (e.g., pot, current bets, community cards) in the MongoDB `games` collection.
+/// Builds the snapshot sent to a player who reconnects to a hand in progress.
 ///
 /// # Arguments
-/// * `games_collection` - Reference to the MongoDB `games` collection.
-/// * `key` - The field name to update (e.g., `"pot"`, `"cards"`).
-/// * `value` - The new JSON value to set for the specified field.
+/// * `storage` - The server's storage backend.
+/// * `seated` - Players seated in the game.
+/// * `player` - The player reconnecting, the only one whose cards are sent.
 ///
 /// # Returns
-/// A `mongodb::error::Result<()>` indicating success or failure of the update.
-pub async fn update_game_state_field(
-    games_collection: &Collection<Document>,
-    key: &str,
-    value: Value,
-) -> mongodb::error::Result<()> {
-    let update = doc! {
-        "$set": {
-            key: bson::to_bson(&value).unwrap()
-        }
+/// A `Snapshot` event: the stored table plus whichever seated player is due to
+/// bet or swap, or an error if the game state is missing.
+pub async fn handle_snapshot(storage: &dyn Storage, seated: &[String], player: &str) -> ServerEvent {
+    let mut table = match stored_hand(storage).await {
+        Ok(hand) => hand.table_for(Some(player)),
+        Err(error) => return error,
     };
-
-    games_collection
-        .update_one(doc! { "_id": 1 }, update)
-        .await?;
-
-    println!("[DB] Updated game field: {} = {}", key, value);
-    Ok(())
+    for player in seated {
+        if let Ok(Some(doc)) = storage.find_player(player).await {
+            if doc.get_bool("bet_turn") == Ok(true) {
+                table.to_act = Some(player.clone());
+            }
+            if doc.get_bool("swap_turn") == Ok(true) {
+                table.to_swap = Some(player.clone());
+            }
+        }
+    }
+    ServerEvent::Snapshot(table)
 }
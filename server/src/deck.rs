@@ -8,14 +8,15 @@
 //! - Utility functions for cards and suits
 //!
 //! Used by the server to deal cards and determine winning hands.
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
-use rand::thread_rng;
-use std::collections::HashMap;
+use rand::{thread_rng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
 use std::fmt;
 
 
 /// Represents suit of a playing card.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Suit {
     Hearts,
     Diamonds,
@@ -35,7 +36,7 @@ impl fmt::Display for Suit {
 }
 
 /// Represents a playing card with a rank and suit.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Card {
     pub rank: u8,  // 2–14 (2..10, J=11, Q=12, K=13, A=14)
     pub suit: Suit,
@@ -49,7 +50,7 @@ impl fmt::Display for Card {
 }
 
 /// Represents a deck of playing cards.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Deck {
     pub cards: Vec<Card>,
 }
@@ -63,6 +64,14 @@ impl Deck {
     /// let card = deck.deal_one();
     /// ```
     pub fn new() -> Self {
+        Self::with_seed(thread_rng().gen())
+    }
+
+    /// Creates a deck of 52 cards shuffled from `seed`.
+    ///
+    /// The same seed always produces the same card order, so a hand can be
+    /// dealt again from the seed stored in its history record.
+    pub fn with_seed(seed: u64) -> Self {
         // Here, define the rank strings in the order you want to use them
         let rank_strings = ["A", "2", "3", "4", "5", "6", "7", 
                             "8", "9", "10", "J", "Q", "K"];
@@ -80,13 +89,13 @@ impl Deck {
         }
 
         let mut deck = Self { cards };
-        deck.shuffle();
+        deck.shuffle(seed);
         deck
     }
 
-    /// Shuffles the deck.
-    pub fn shuffle(&mut self) {
-        let mut rng = thread_rng();
+    /// Shuffles the deck with a random number generator seeded from `seed`.
+    pub fn shuffle(&mut self, seed: u64) {
+        let mut rng = StdRng::seed_from_u64(seed);
         self.cards.shuffle(&mut rng);
     }
 
@@ -96,6 +105,7 @@ impl Deck {
     }
 }
 
+#[allow(dead_code)]
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum HandRank {
     HighCard(u8, u8, u8, u8, u8),       // "High Card: Ace, King, 10, 5, 3"
@@ -123,6 +133,7 @@ pub enum HandRank {
 /// let cards = [/* your 5 Card instances */];
 /// let rank = rank_poker_hand(cards);
 /// ```
+#[allow(dead_code)]
 pub fn rank_poker_hand(mut cards: [Card; 5]) -> HandRank {
     // 1) Sort by rank descending (Ace = 14 is highest).
     cards.sort_by_key(|c| c.rank);
@@ -207,6 +218,7 @@ pub fn rank_poker_hand(mut cards: [Card; 5]) -> HandRank {
 }
 
 /// A helper function to check if five descending ranks are a straight.
+#[allow(dead_code)]
 fn is_5card_straight(ranks: &[u8]) -> bool {
     // For a normal descending sequence:  (e.g. [14, 13, 12, 11, 10])
     // we check if each subsequent rank is exactly 1 less than the previous
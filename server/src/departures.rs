@@ -0,0 +1,221 @@
+//! # Departures
+//!
+//! What happens to a seat when its connection drops.
+//!
+//! Before the game starts the seat is simply freed. During a game the player has
+//! `SEAT_GRACE_PERIOD` to log back in and bind a new connection, which the seat
+//! then follows (see `session.rs`). If they do not, they are marked as sitting out: the
+//! game folds for them whenever it is their turn, and the lobby lists them as
+//! sitting out until they return.
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::task::AbortHandle;
+use protocol::ServerEvent;
+use crate::comms::{broadcast_to_game_players, ClientEvent};
+use crate::db::{set_sitting_out, sitting_out_players, update_lobby};
+use crate::storage::Storage;
+use crate::{ClientInfo, GAME_PLAYERS, GAME_VARIANT, NUM_PLAYERS, SESSIONS};
+
+/// How long a player who drops mid-game keeps their seat before sitting out.
+pub const SEAT_GRACE_PERIOD: Duration = Duration::from_secs(30);
+
+/// The running grace period timer of each player whose seat is held, and the
+/// connection whose drop started it.
+static GRACE_TIMERS: Mutex<BTreeMap<String, (SocketAddr, AbortHandle)>> = Mutex::new(BTreeMap::new());
+
+/// What a dropped connection means for the seats.
+#[derive(Debug, PartialEq)]
+pub enum Departure {
+    /// The connection did not hold a seat.
+    NotSeated,
+    /// The game had not started, so the player's seat was freed.
+    SeatFreed(String),
+    /// The player is in a game; their seat is held for the grace period.
+    SeatHeld(String),
+}
+
+/// Works out what a player's last connection dropping means for their seat,
+/// freeing it if the game has not started yet.
+///
+/// # Arguments
+/// * `seats` - Seated players.
+/// * `username` - The player whose last connection dropped.
+/// * `max_players` - Number of seats; the game starts once they are all taken.
+pub fn depart(seats: &mut HashSet<String>, username: &str, max_players: usize) -> Departure {
+    if !seats.contains(username) {
+        return Departure::NotSeated;
+    }
+
+    if seats.len() < max_players {
+        seats.remove(username);
+        Departure::SeatFreed(username.to_string())
+    } else {
+        Departure::SeatHeld(username.to_string())
+    }
+}
+
+/// Cleans up after a connection that closed or failed.
+///
+/// Removes the client. If it was its user's last connection, frees their seat or
+/// starts the grace period. When the grace period ends,
+/// `ClientEvent::GraceExpired` is sent back to the dispatcher.
+///
+/// # Arguments
+/// * `storage` - The server's storage backend.
+/// * `clients` - Shared list of connected clients.
+/// * `addr` - The connection that dropped.
+/// * `username` - The user the connection was bound to, if any.
+/// * `server_tx` - Sender to the dispatcher, for the grace period timer.
+pub async fn handle_disconnect(
+    storage: &dyn Storage,
+    clients: &Arc<Mutex<HashMap<SocketAddr, ClientInfo>>>,
+    addr: SocketAddr,
+    username: Option<String>,
+    server_tx: &UnboundedSender<(SocketAddr, ClientEvent)>,
+) {
+    clients.lock().unwrap().remove(&addr);
+    let Some(username) = username else {
+        return;
+    };
+    if is_connected(&username) {
+        return;
+    }
+    let departure = {
+        let mut seats = GAME_PLAYERS.get().unwrap().lock().unwrap();
+        depart(&mut seats, &username, *NUM_PLAYERS.get().unwrap())
+    };
+
+    match departure {
+        Departure::NotSeated => {}
+        Departure::SeatFreed(username) => {
+            println!("[Game] {} left before the game started; seat freed.", username);
+            refresh_lobby(storage).await;
+        }
+        Departure::SeatHeld(username) => {
+            println!("[Game] {} disconnected mid-game; holding their seat.", username);
+            broadcast_to_game_players(
+                clients,
+                &ServerEvent::info(format!(
+                    "{} disconnected. Their seat is held for {} seconds.",
+                    username,
+                    SEAT_GRACE_PERIOD.as_secs()
+                )),
+            );
+            hold_seat(&username, addr, server_tx);
+        }
+    }
+}
+
+/// Starts the grace period for a seat whose player is not connected, replacing
+/// any earlier one. When it ends, `ClientEvent::GraceExpired` is sent to the
+/// dispatcher.
+///
+/// # Arguments
+/// * `username` - The seated player.
+/// * `addr` - The connection that dropped, which the event is reported for.
+/// * `server_tx` - Sender to the dispatcher.
+pub fn hold_seat(username: &str, addr: SocketAddr, server_tx: &UnboundedSender<(SocketAddr, ClientEvent)>) {
+    let server_tx = server_tx.clone();
+    let username = username.to_string();
+    let timer = tokio::spawn({
+        let username = username.clone();
+        async move {
+            tokio::time::sleep(SEAT_GRACE_PERIOD).await;
+            let _ = server_tx.send((addr, ClientEvent::GraceExpired(username)));
+        }
+    });
+    if let Some((_, earlier)) = GRACE_TIMERS.lock().unwrap().insert(username, (addr, timer.abort_handle())) {
+        earlier.abort();
+    }
+}
+
+/// Whether a user has a connection bound to their session.
+fn is_connected(username: &str) -> bool {
+    SESSIONS
+        .get()
+        .is_some_and(|sessions| !sessions.lock().unwrap().connections_for(username).is_empty())
+}
+
+/// Sits a player out if they are still seated and have not bound a new
+/// connection during the grace period, and they are not sitting out already
+/// (e.g. after being kicked).
+///
+/// # Arguments
+/// * `storage` - The server's storage backend.
+/// * `clients` - Shared list of connected clients.
+/// * `username` - The player whose grace period ran out.
+/// * `addr` - The connection whose drop started it. A timer replaced by a later
+///   drop is ignored.
+pub async fn handle_grace_expired(
+    storage: &dyn Storage,
+    clients: &Arc<Mutex<HashMap<SocketAddr, ClientInfo>>>,
+    username: &str,
+    addr: SocketAddr,
+) {
+    {
+        let mut timers = GRACE_TIMERS.lock().unwrap();
+        if timers.get(username).is_none_or(|(started_by, _)| *started_by != addr) {
+            return;
+        }
+        timers.remove(username);
+    }
+    let seated = GAME_PLAYERS.get().unwrap().lock().unwrap().contains(username);
+    if !seated || is_connected(username) {
+        return;
+    }
+    if !sitting_out_players(storage, &[username.to_string()]).await.is_empty() {
+        return;
+    }
+    println!("[Game] {} did not return; sitting them out.", username);
+    if let Err(e) = set_sitting_out(storage, username, true).await {
+        eprintln!("[DB] Failed to sit out {}: {}", username, e);
+    }
+    broadcast_to_game_players(
+        clients,
+        &ServerEvent::info(format!("{} did not return and is sitting out.", username)),
+    );
+    refresh_lobby(storage).await;
+}
+
+/// Brings a seated player who bound a new connection back into the game.
+///
+/// # Arguments
+/// * `storage` - The server's storage backend.
+/// * `clients` - Shared list of connected clients.
+/// * `username` - The player who reconnected.
+pub async fn handle_return(
+    storage: &dyn Storage,
+    clients: &Arc<Mutex<HashMap<SocketAddr, ClientInfo>>>,
+    username: &str,
+) {
+    if let Some((_, timer)) = GRACE_TIMERS.lock().unwrap().remove(username) {
+        timer.abort();
+    }
+    if let Err(e) = set_sitting_out(storage, username, false).await {
+        eprintln!("[DB] Failed to seat {}: {}", username, e);
+    }
+    broadcast_to_game_players(clients, &ServerEvent::info(format!("{} is back at the table.", username)));
+    refresh_lobby(storage).await;
+}
+
+/// Saves the lobby from the current seats.
+pub async fn refresh_lobby(storage: &dyn Storage) {
+    let players: Vec<String> = GAME_PLAYERS.get().unwrap().lock().unwrap().iter().cloned().collect();
+    let sitting_out = sitting_out_players(storage, &players).await;
+    if let Err(e) = update_lobby(
+        storage,
+        GAME_VARIANT.get().unwrap(),
+        *NUM_PLAYERS.get().unwrap(),
+        &players,
+        &sitting_out,
+    )
+    .await
+    {
+        eprintln!("[DB] Failed to save lobby: {}", e);
+    }
+}
@@ -1,9 +1,10 @@
 // src/poker/five_card_draw.rs
 use crate::deck::{Card, Deck};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 /// Represents a player in the poker game.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Player {
     /// Player's unique identifier.
     pub id: String,
@@ -12,6 +13,7 @@ pub struct Player {
     /// Indicates if the player has folded.
     pub folded: bool,
     /// Total money won by the player.
+    #[allow(dead_code)]
     pub money_won: i32,
     /// Total money lost by the player.
     pub money_lost: i32,
@@ -21,12 +23,14 @@ pub struct Player {
 
 
 /// State of a poker game.
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct PokerGame {
     /// List of all players who started.
     pub players: Vec<Player>,
     /// Deck of cards used.
     pub deck: Deck,
+    /// Seed the deck is shuffled from when the cards are dealt.
+    pub shuffle_seed: u64,
     /// Total of chips in the pot.
     pub pot: i32,
     /// Current hihgest bet in round.
@@ -43,6 +47,7 @@ impl PokerGame {
     /// * `player_ids` - A vector of player identifiers.
     pub fn new(player_ids: Vec<String>) -> Self {
         let deck = Deck::new();
+        let shuffle_seed = crate::recovery::shuffle_seed(None);
         let current_players = player_ids
             .into_iter()
             .map(|id| Player {
@@ -58,6 +63,7 @@ impl PokerGame {
         Self {
             players: Vec::new(),
             deck,
+            shuffle_seed,
             pot: 0,
             current_bet: 0,
             current_players,
@@ -66,7 +72,7 @@ impl PokerGame {
 
     /// Deals 5 cards to each active player and resets player states.
     pub fn deal_cards(&mut self) {
-        self.deck = Deck::new();
+        self.deck = Deck::with_seed(self.shuffle_seed);
         for player in &mut self.current_players {
             player.hand.clear();
             player.folded = false;
@@ -1,6 +1,6 @@
 //! # Five Card Draw Poker Game
 //!
-//! This runs the game loop for a round of Five Card Draw poker.
+//! This runs a round of Five Card Draw poker.
 //!
 //! Features include:
 //! - Initial game setup and ante collection
@@ -8,507 +8,231 @@
 //! - Two rounds of betting
 //! - Optional card swapping phase
 //! - Automatic determination of the winner
-//! - Broadcasting updates to connected clients
+//! - Emitting each step as a hand event, which is broadcast, stored and recorded
+//!   in the hand history (see `hand_events.rs`)
 //! - Persisting game results to the database
 //!
+//! The hand is the state machine `FiveCardDrawHand` (see `hand_machine.rs`):
+//!
+//! ```text
+//! Antes -> Deal(predraw) -> Betting(predraw) -> Draw -> Betting(postdraw) -> Showdown -> Over
+//! ```
+//!
 //! Used by the game server to manage and progress poker games.
 use std::{
     collections::HashMap,
-    sync::mpsc::{self, Sender},
     sync::{Arc, Mutex},
-    thread,
-    time::Duration,
 };
-use mongodb::{
-    bson::Document,
-    Collection,
-    bson::doc
-};
-use serde_json::json;
-use crate::comms::*;
+use serde::{Deserialize, Serialize};
+use crate::hand_events::*;
+use crate::hand_machine::*;
+use crate::history::ActionKind;
+use crate::recovery::Replay;
+use crate::storage::Storage;
 use crate::*;
 use crate::five_card_draw::*;
 
-
-/// Runs a Five Card Draw poker game.
-///
-/// This function controls the full game lifecycle:
-/// - Initializes the game and broadcasts the start
-/// - Collects ante from players
-/// - Deals cards
-/// - Handles the first betting round
-/// - Manages the card swap phase
-/// - Handles the second betting round
-/// - Determines the winner and broadcasts results
-/// - Updates the game outcome in the database
-///
-/// # Arguments
-/// * `clients` - shared list of connected clients.
-/// * `player_names` - Names of players in the game.
-/// * `players_collection` - MongoDB collection for player data.
-/// * `lobbies_collection` - MongoDB collection for lobby data.
-/// * `games_collection` - MongoDB collection for ongoing games.
-/// * `history_collection` - MongoDB collection for game history.
-///
-pub async fn run_five_card_game(
-    clients: Arc<Mutex<HashMap<std::net::SocketAddr, ClientInfo>>>,
-    player_names: Vec<String>,
-    players_collection: Arc<Collection<Document>>,
-    lobbies_collection: Arc<Collection<Document>>,
-    games_collection: Arc<Collection<Document>>,
-    history_collection: Arc<Collection<Document>>,
-
-) {
-    let variant = GAME_VARIANT.get().unwrap();
-    broadcast_to_game_players(&clients, "Started the game: {}");
-
-    let mut poker_game:PokerGame = PokerGame::new(player_names.clone());
-    // poker_game.current_players = poker_game.players.clone();
-
-    println!("[Game] Created PokerGame for variant: {}", variant);
-    println!("[Game] Players: {:?}", poker_game.current_players.iter().map(|p| &p.id).collect::<Vec<_>>());
-
-
-    
-    // Collecting ante
-    let ante = 5;
-    broadcast_to_game_players(&clients, "Collecting ante of 5");
-    
-    for player in &mut poker_game.current_players {
-        player.money_lost += ante;
-        poker_game.pot += ante;
+/// Chips each player puts in before the cards are dealt.
+const ANTE: i32 = 5;
+
+/// A hand of Five Card Draw.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FiveCardDrawHand {
+    game: PokerGame,
+    phase: Phase,
+    round: BettingRound,
+    turn: Option<Turn>,
+    /// Seat of the next player to swap.
+    swap_seat: usize,
+    winner: Option<String>,
+}
+
+impl FiveCardDrawHand {
+    /// Sets up a hand, before the antes.
+    ///
+    /// # Arguments
+    /// * `player_names` - Players in seat order.
+    /// * `shuffle_seed` - Seed the deck is shuffled from.
+    pub fn new(player_names: Vec<String>, shuffle_seed: u64) -> Self {
+        let mut game = PokerGame::new(player_names);
+        game.shuffle_seed = shuffle_seed;
+        Self {
+            game,
+            phase: Phase::Antes,
+            round: BettingRound::open(0),
+            turn: None,
+            swap_seat: 0,
+            winner: None,
+        }
     }
 
-    update_game_state_field(&games_collection, "info", "Collecting ante for all players".into()).await.unwrap();
-    update_game_state_field(&games_collection, "pot", poker_game.pot.clone().into()).await.unwrap();
-
-
-
-    // Deal cards
-    poker_game.deal_cards();
-    let mut hands_map: HashMap<String, Vec<String>> = HashMap::new();
-    for player in &poker_game.current_players {
-        let cards = player
-            .hand
-            .iter()
-            .map(|card| format!("{}", card))
-            .collect::<Vec<_>>();
-            // .join(", ");
-
-        hands_map.insert(player.id.clone(), cards.clone());
-        println!(" - {}: {}", player.id, cards.join(", "));
+    /// Starts a round of betting on `street`.
+    fn start_betting(&mut self, street: Street) {
+        for player in &mut self.game.current_players {
+            player.bet_amount = 0;
+        }
+        self.game.current_bet = -2;
+        self.round = BettingRound::open(0);
+        self.phase = Phase::Betting(street);
     }
 
-    let message = json!({
-        "cards": hands_map
-    });
-    
-    let json_str = message.to_string();
-    println!("JSON to send: {}", json_str);
-    broadcast_to_game_players(&clients, json_str.as_str());
-
-    let hands_value = serde_json::to_value(&hands_map).unwrap();
-    update_game_state_field(&games_collection, "cards", hands_value).await.unwrap();
-
-
-
-    // First round of betting
-    poker_game.current_bet = -2;
-    let mut first_highest_bet = "none".to_string();
-    let mut player_bet_index:i32 = 0;
-
-    loop {
-        if player_bet_index >= poker_game.current_players.len() as i32{
-            player_bet_index = 0;
+    /// Ends a round of betting: the hand is over if one player is left.
+    fn end_betting(&mut self, next: Phase) -> Vec<HandEvent> {
+        if let Some(winner) = last_player_standing(&self.game.current_players) {
+            self.winner = Some(winner.clone());
+            self.phase = Phase::Over;
+            return vec![HandEvent::HandOver { winner }];
         }
-
-        let player_id = poker_game.current_players[player_bet_index as usize].id.clone();
-
-        if player_id == first_highest_bet || poker_game.current_players.len() == 1 {
-            break;
+        self.phase = next;
+        match next {
+            Phase::Draw => vec![HandEvent::Street { name: "draw".to_string() }],
+            _ => Vec::new(),
         }
+    }
+}
 
-        loop {
-            let mut bet_amounts_map: HashMap<String, i32> = HashMap::new();
-            for p in &poker_game.current_players {
-                bet_amounts_map.insert(p.id.clone(), p.bet_amount);
-            }
+impl HandMachine for FiveCardDrawHand {
+    fn phase(&self) -> Phase {
+        self.phase
+    }
 
-            let message = json!({
-                "cards": hands_map,
-                "bet": player_id.clone(),
-                "pot": poker_game.pot,
-                "round current bet": poker_game.current_bet,
-                "player bet amount": bet_amounts_map,
-            });
-            
-            let json_str = message.to_string();
-            println!("JSON to send: {}", json_str);
-            broadcast_to_game_players(&clients, json_str.as_str());
+    fn turn(&self) -> Option<&Turn> {
+        self.turn.as_ref()
+    }
 
-            update_game_state_field(&games_collection, "pot", poker_game.pot.into()).await.unwrap();
-            update_game_state_field(&games_collection, "round current bet", poker_game.current_bet.into()).await.unwrap();
-            let bet_amounts_value = serde_json::to_value(&bet_amounts_map).unwrap();
-            update_game_state_field(&games_collection, "player current bets", bet_amounts_value).await.unwrap();
-            let hands_value = serde_json::to_value(&hands_map).unwrap();
-            update_game_state_field(&games_collection, "cards", hands_value).await.unwrap();
-            
-            let filter = doc! { "name": &player_id.clone() };
-            let update = doc! { "$set": { "bet_turn": true } };
-            let _ = players_collection.update_one(filter.clone(), update).await;
-            
-            // Wait till the bet is made
-            loop {
-                // Sleep to avoid hammering the DB
-                thread::sleep(Duration::from_millis(300));
-                
-                // Fetch the latest value
-                if let Ok(Some(doc)) = players_collection.find_one(filter.clone()).await {
-                    if let Some(bet_turn) = doc.get_bool("bet_turn").ok() {
-                        if !bet_turn {
-                            println!("{} has completed their bet.", player_id);
-                            break;
-                        }
-                    }
+    fn step(&mut self) -> Vec<HandEvent> {
+        if self.turn.is_some() {
+            return Vec::new();
+        }
+        match self.phase {
+            Phase::Antes => {
+                let mut events = vec![
+                    HandEvent::Street { name: Street::Predraw.name().to_string() },
+                    HandEvent::Info { message: format!("Collecting ante of {}", ANTE) },
+                ];
+                for player in &mut self.game.current_players {
+                    player.money_lost += ANTE;
+                    self.game.pot += ANTE;
+                    events.push(HandEvent::Posted { player: player.id.clone(), kind: ActionKind::Ante, amount: ANTE });
                 }
+                self.phase = Phase::Deal(Street::Predraw);
+                events
             }
-            
-            if let Some(bet_amount) = get_player_bet(&players_collection, &player_id).await {
-                if let Some(player) = poker_game.current_players.iter_mut().find(|p| p.id == player_id) {
-                    if bet_amount == -1 {
-                        println!("{} folds.", player_id.clone());
-                        if let Some(player) = poker_game.current_players.iter_mut().find(|p| p.id == player_id) {
-                            poker_game.players.push(player.clone());
-                        }
-                        poker_game.current_players.remove(player_bet_index as usize);
-                        player_bet_index -= 1;
-                        send_to_player_by_id(&clients, player_id.as_str(), "You folded");
-                        update_game_state_field(&games_collection, "info", format!("Player {} folded", player_id).into()).await.unwrap();
-                        break;
-                    }
-
-                    if player.bet_amount + bet_amount >= poker_game.current_bet {
-                        if player.bet_amount + bet_amount > poker_game.current_bet {
-                            first_highest_bet = player.id.clone();
-                            if poker_game.current_bet < 0 {
-                                poker_game.current_bet = 0;
-                            }
-                            poker_game.current_bet = player.bet_amount + bet_amount;
-                        }
-                        player.money_lost += bet_amount;
-                        player.bet_amount += bet_amount;
-                        poker_game.pot += bet_amount;
-                        update_game_state_field(&games_collection, "info", format!("Player {} raised the bet by {}", player_id, bet_amount).into()).await.unwrap();
-                        break;
-                    }
+            Phase::Deal(_) => {
+                self.game.deal_cards();
+                self.start_betting(Street::Predraw);
+                vec![HandEvent::Dealt { cards: dealt_cards(&self.game.current_players, 0) }]
+            }
+            Phase::Betting(street) => match self.round.next_to_act(&self.game.current_players) {
+                Some(player) => {
+                    self.turn = Some(Turn::Bet(player.clone()));
+                    vec![HandEvent::ToAct { player }]
+                }
+                None if street == Street::Predraw => self.end_betting(Phase::Draw),
+                None => self.end_betting(Phase::Showdown),
+            },
+            Phase::Draw => match self.game.current_players.get(self.swap_seat) {
+                Some(player) => {
+                    self.turn = Some(Turn::Swap(player.id.clone()));
+                    vec![HandEvent::ToSwap { player: player.id.clone() }]
+                }
+                None => {
+                    self.start_betting(Street::Postdraw);
+                    vec![HandEvent::Street { name: Street::Postdraw.name().to_string() }]
                 }
-            }        
+            },
+            Phase::Showdown => {
+                self.winner = self.game.determine_winner_id();
+                self.phase = Phase::Over;
+                self.winner.iter().map(|winner| HandEvent::Showdown { winner: winner.clone() }).collect()
+            }
+            Phase::Blinds | Phase::Over => Vec::new(),
         }
-        player_bet_index += 1;
     }
 
-
-
-    // After 1st round of betting
-    update_players_folded(&players_collection, &poker_game.players.clone()).await.unwrap();
-
-    if poker_game.current_players.len() == 1 {
-        let winner_id = poker_game.current_players[0].id.clone();
-        broadcast_to_game_players(&clients, format!("Game is over, winner is {}", winner_id).as_str());
-        update_game_state_field(&games_collection, "winner", format!("Game is over, winner is {}", winner_id).as_str().into()).await.unwrap();
-
-        println!("[Game] Winner determined: {}", winner_id);
-        
-        // Update database with results
-        if let Err(e) = db::update_game_results(
-            &players_collection,
-            &winner_id,
-            &poker_game.current_players.clone(),
-            poker_game.pot,
-        ).await {
-            eprintln!("Failed to update game results: {}", e);
+    fn bet(&mut self, player: &str, amount: i32) -> Result<Vec<HandEvent>, String> {
+        if self.turn != Some(Turn::Bet(player.to_string())) {
+            return Ok(Vec::new());
         }
-        return;
+        self.turn = None;
+        let seats = Seats {
+            current: &mut self.game.current_players,
+            folded: &mut self.game.players,
+            pot: &mut self.game.pot,
+            current_bet: &mut self.game.current_bet,
+        };
+        Ok(vec![self.round.bet(seats, player, amount)?])
     }
 
-
-
-    // Replacing cards
-    let swap_ids: Vec<String> = poker_game
-        .current_players
-        .iter()
-        .map(|p| p.id.clone())
-        .collect();
-
-    for player_id in swap_ids {
-        // Getting the most updated hands
-        hands_map = HashMap::new();
-        for player in &poker_game.current_players {
-            let cards = player
-                .hand
-                .iter()
-                .map(|card| format!("{}", card))
-                .collect::<Vec<_>>();
-                // .join(", ");
-
-            hands_map.insert(player.id.clone(), cards.clone());
-            println!(" - {}: {}", player.id, cards.join(", "));
+    fn swap(&mut self, player: &str, indices: &[usize]) -> Vec<HandEvent> {
+        if self.turn != Some(Turn::Swap(player.to_string())) {
+            return Vec::new();
         }
-
-        // 1. Prompt player to select swap indices
-        let swap_msg = json!({
-            "cards": hands_map,
-            "swap": player_id.clone()
-        })
-        .to_string();
-
-        broadcast_message(&clients, &swap_msg.to_string());
-        // 2. Set swap_turn = true in DB
-        let filter = doc! { "name": &player_id };
-        let update = doc! { "$set": { "swap_turn": true } };
-        let _ = players_collection.update_one(filter.clone(), update).await;
-
-        // 3. Wait for the player to respond (by setting swap_turn = false)
-        loop {
-            thread::sleep(Duration::from_millis(300));
-            if let Ok(Some(doc)) = players_collection.find_one(filter.clone()).await {
-                if let Some(turn) = doc.get_bool("swap_turn").ok() {
-                    if !turn {
-                        println!("[Swap] {} finished their swap selection.", player_id);
-                        break;
-                    }
-                }
+        self.turn = None;
+        self.swap_seat += 1;
+
+        let before = self.game.current_players.iter().find(|p| p.id == player).map(|p| p.hand.clone()).unwrap_or_default();
+        self.game.replace_cards(player, indices);
+        match self.game.current_players.iter().find(|p| p.id == player) {
+            Some(after) => {
+                let (discarded, drawn) = replaced_cards(&before, &after.hand);
+                vec![HandEvent::Drew { player: player.to_string(), discarded, drawn }]
             }
-        }
-
-        // 4. Retrieve swap indices
-        if let Some(indices_str) = get_player_swap(&players_collection, &player_id).await {
-            let indices: Vec<usize> = indices_str
-                .split(',')
-                .filter_map(|s| s.trim().parse().ok())
-                .collect();
-            
-            update_game_state_field(&games_collection, "info", format!("Player {} swap cards {}", player_id, indices_str).into()).await.unwrap();
-            println!("[Swap] {} will replace indices {:?}", player_id, indices);
-            poker_game.replace_cards(&player_id, &indices);
+            None => Vec::new(),
         }
     }
 
-
-
-    // Showing cards hand after swap
-    hands_map = HashMap::new();
-    for player in &poker_game.current_players {
-        let cards = player
-            .hand
-            .iter()
-            .map(|card| format!("{}", card))
-            .collect::<Vec<_>>();
-            // .join(", ");
-
-        hands_map.insert(player.id.clone(), cards.clone());
-        println!(" - {}: {}", player.id, cards.join(", "));
+    fn shuffle_seed(&self) -> u64 {
+        self.game.shuffle_seed
     }
 
-    let swap_msg = json!({
-        "cards": hands_map,
-    })
-    .to_string();
-
-    broadcast_message(&clients, &swap_msg);
-
-    let hands_value = serde_json::to_value(&hands_map).unwrap();
-    update_game_state_field(&games_collection, "cards", hands_value).await.unwrap();
-
-
-
-    // Second round of betting
-    // Resetting bet for all players to 0
-    for player in &mut poker_game.current_players {
-        player.bet_amount = 0;
+    fn active_players(&self) -> &[Player] {
+        &self.game.current_players
     }
 
-    poker_game.current_bet = -2;
-    let mut first_highest_bet = "none".to_string();
-    let mut player_bet_index:i32 = 0;
-
-    loop {
-        if player_bet_index >= poker_game.current_players.len() as i32{
-            player_bet_index = 0;
-        }
-
-        let player_id = poker_game.current_players[player_bet_index as usize].id.clone();
-
-        if player_id == first_highest_bet || poker_game.current_players.len() == 1 {
-            break;
-        }
-
-        loop {
-            let mut bet_amounts_map: HashMap<String, i32> = HashMap::new();
-            for p in &poker_game.current_players {
-                bet_amounts_map.insert(p.id.clone(), p.bet_amount);
-            }
-
-            let message = json!({
-                "cards": hands_map,
-                "bet": player_id.clone(),
-                "pot": poker_game.pot,
-                "round current bet": poker_game.current_bet,
-                "player bet amount": bet_amounts_map,
-            });
-            
-            let json_str = message.to_string();
-            println!("JSON to send: {}", json_str);
-            broadcast_to_game_players(&clients, json_str.as_str());
-
-            update_game_state_field(&games_collection, "pot", poker_game.pot.into()).await.unwrap();
-            update_game_state_field(&games_collection, "round current bet", poker_game.current_bet.into()).await.unwrap();
-            let bet_amounts_value = serde_json::to_value(&bet_amounts_map).unwrap();
-            update_game_state_field(&games_collection, "player current bets", bet_amounts_value).await.unwrap();
-            let hands_value = serde_json::to_value(&hands_map).unwrap();
-            update_game_state_field(&games_collection, "cards", hands_value).await.unwrap();
-            
-            let filter = doc! { "name": &player_id.clone() };
-            let update = doc! { "$set": { "bet_turn": true } };
-            let _ = players_collection.update_one(filter.clone(), update).await;
-            
-            // Wait till the bet is made
-            loop {
-                // Sleep to avoid hammering the DB
-                thread::sleep(Duration::from_millis(300));
-                
-                // Fetch the latest value
-                if let Ok(Some(doc)) = players_collection.find_one(filter.clone()).await {
-                    if let Some(bet_turn) = doc.get_bool("bet_turn").ok() {
-                        if !bet_turn {
-                            println!("{} has completed their bet.", player_id);
-                            break;
-                        }
-                    }
-                }
-            }
-            
-            if let Some(bet_amount) = get_player_bet(&players_collection, &player_id).await {
-                if let Some(player) = poker_game.current_players.iter_mut().find(|p| p.id == player_id) {
-                    if bet_amount == -1 {
-                        println!("{} folds.", player_id.clone());
-                        if let Some(player) = poker_game.current_players.iter_mut().find(|p| p.id == player_id) {
-                            poker_game.players.push(player.clone());
-                        }
-                        poker_game.current_players.remove(player_bet_index as usize);
-                        player_bet_index -= 1;
-                        send_to_player_by_id(&clients, player_id.as_str(), "You folded");
-                        update_game_state_field(&games_collection, "info", format!("Player {} folded", player_id).into()).await.unwrap();
-                        break;
-                    }
-
-                    if player.bet_amount + bet_amount >= poker_game.current_bet {
-                        if player.bet_amount + bet_amount > poker_game.current_bet {
-                            first_highest_bet = player.id.clone();
-                            if poker_game.current_bet < 0 {
-                                poker_game.current_bet = 0;
-                            }
-                            poker_game.current_bet = player.bet_amount + bet_amount;
-                        }
-                        player.money_lost += bet_amount;
-                        player.bet_amount += bet_amount;
-                        poker_game.pot += bet_amount;
-                        update_game_state_field(&games_collection, "info", format!("Player {} raised the bet by {}", player_id, bet_amount).into()).await.unwrap();
-                        break;
-                    }
-                }
-            }        
-        }
-        player_bet_index += 1;
-    }
-
-
-
-    // After 2nd round of betting
-    update_players_folded(&players_collection, &poker_game.players.clone()).await.unwrap();
-
-    if poker_game.current_players.len() == 1 {
-        let winner_id = poker_game.current_players[0].id.clone();
-        broadcast_to_game_players(&clients, format!("Game is over, winner is {}", winner_id).as_str());
-        update_game_state_field(&games_collection, "winner", format!("Game is over, winner is {}", winner_id).as_str().into()).await.unwrap();
-
-        println!("[Game] Winner determined: {}", winner_id);
-        
-        // Update database with results
-        if let Err(e) = db::update_game_results(
-            &players_collection,
-            &winner_id,
-            &poker_game.current_players.clone(),
-            poker_game.pot,
-        ).await {
-            eprintln!("Failed to update game results: {}", e);
-        }
-        
-        return;
+    fn folded(&self) -> &[Player] {
+        &self.game.players
     }
 
-
-
-    // Showing cards hand after 2nd round of betting
-    hands_map = HashMap::new();
-    for player in &poker_game.current_players {
-        let cards = player
-            .hand
-            .iter()
-            .map(|card| format!("{}", card))
-            .collect::<Vec<_>>();
-            // .join(", ");
-
-        hands_map.insert(player.id.clone(), cards.clone());
-        println!(" - {}: {}", player.id, cards.join(", "));
+    fn pot(&self) -> i32 {
+        self.game.pot
     }
 
-    let swap_msg = json!({
-        "cards": hands_map,
-    })
-    .to_string();
-
-    broadcast_message(&clients, &swap_msg);
-
-    let hands_value = serde_json::to_value(&hands_map).unwrap();
-    update_game_state_field(&games_collection, "cards", hands_value).await.unwrap();
-    
-    
-    
-    // Showdown
-    if let Some(winner) = poker_game.determine_winner_id() {
-        println!("[Game] Winner determined: {}", winner);
-        update_game_state_field(&games_collection, "winner", format!("Game is over, winner is {}", winner).as_str().into()).await.unwrap();
-        
-        // Create a showdown JSON message to send to all players.
-        let showdown_msg = json!({
-            "winner": winner,
-            "showdown": hands_map,
-            "pot": poker_game.pot
-        })
-        .to_string();
-    
-        broadcast_to_game_players(&clients, showdown_msg.as_str());
+    fn winner(&self) -> Option<&str> {
+        self.winner.as_deref()
     }
+}
 
+/// Runs a Five Card Draw poker game.
+///
+/// This function controls the full game lifecycle by playing a
+/// `FiveCardDrawHand` through `play_hand`:
+/// - Initializes the game and broadcasts the start
+/// - Collects ante from players
+/// - Deals cards
+/// - Handles the first betting round
+/// - Manages the card swap phase
+/// - Handles the second betting round
+/// - Determines the winner and broadcasts results
+/// - Updates the game outcome in the database
+///
+/// # Arguments
+/// * `clients` - shared list of connected clients.
+/// * `player_names` - Names of players in the game.
+/// * `storage` - Storage backend for player data and the live game state.
+/// * `replay` - The stored hand to play again after a crash, if any (see `recovery.rs`).
+///
+pub async fn run_five_card_game(
+    clients: Arc<Mutex<HashMap<std::net::SocketAddr, ClientInfo>>>,
+    player_names: Vec<String>,
+    storage: Arc<dyn Storage>,
+    replay: Option<Replay>,
+) {
+    let variant = GAME_VARIANT.get().unwrap();
+    let hand = FiveCardDrawHand::new(player_names.clone(), recovery::shuffle_seed(replay.as_ref()));
 
+    println!("[Game] Created PokerGame for variant: {}", variant);
+    println!("[Game] Players: {:?}", hand.active_players().iter().map(|p| &p.id).collect::<Vec<_>>());
 
-    // Updating results after game
-    if let Some(winner) = poker_game.determine_winner_id() {
-        println!("[Game] Winner determined: {}", winner);
-        
-        // Update database with results
-        if let Err(e) = db::update_game_results(
-            &players_collection,
-            &winner,
-            &poker_game.current_players.clone(),
-            poker_game.pot,
-        ).await {
-            eprintln!("Failed to update game results: {}", e);
-        }
-    }
-
-    // Simulate game running
-    thread::sleep(Duration::from_secs(5));
-    println!("[Game] Game finished.");
-}
\ No newline at end of file
+    play_hand(hand, clients, &player_names, storage, replay).await;
+}
@@ -0,0 +1,402 @@
+//! # Hand Events
+//!
+//! Every hand is played as an ordered stream of typed events: who posted and bet,
+//! which cards were dealt or drawn, who folded, each new street, and how the hand
+//! ended. Each variant's state machine decides what happens next (see
+//! `hand_machine.rs`) and `play_hand` emits it; everything else is driven from the
+//! stream:
+//!
+//! - The table is a pure fold over the events (`HandState::apply`), so players,
+//!   spectators and reconnecting players all see the same thing. Each of them is
+//!   sent only the cards they may see (`HandState::table_for`): a player their
+//!   own hand, a spectator none, until the showdown.
+//! - Each event is appended to the game state's `events` field on its own (see
+//!   `Storage::push_hand_event`), which is what the `spectate` command and
+//!   reconnect snapshots read.
+//! - Each event changes the table that is broadcast, or sends its own message
+//!   (`Folded`, `HandOver`, `Showdown`).
+//! - The hand history is written from the events, and saved once the hand ends.
+//!
+//! A hand resumed after a crash (see `recovery.rs`) plays its recorded bets and
+//! swaps again, which emits the same events from the start.
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use mongodb::bson;
+use serde::{Deserialize, Serialize};
+use protocol::{ServerEvent, TableState};
+use crate::comms::{broadcast_to_game_players, send_to_each_game_player, send_to_player_by_id};
+use crate::deck::Card;
+use crate::five_card_draw::Player;
+use crate::history::{classify_bet, save_hand, start_hand, ActionKind, HandRecord, HandRecorder};
+use crate::storage::{Storage, StorageResult};
+use crate::ClientInfo;
+
+/// Game state field holding the events of the current hand.
+const EVENTS: &str = "events";
+
+/// The table's current bet before anyone has bet on a street.
+const NO_BET: i32 = -2;
+
+/// Something that happened in a hand. Cards are named as they are sent to
+/// clients (e.g. `"A of Hearts"`).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum HandEvent {
+    /// A hand started with these players, in seat order.
+    Started {
+        hand_id: String,
+        variant: String,
+        players: Vec<String>,
+    },
+    /// A forced bet: an ante or a blind.
+    Posted { player: String, kind: ActionKind, amount: i32 },
+    /// Cards dealt to each player, added to their hands.
+    Dealt { cards: BTreeMap<String, Vec<String>> },
+    /// Community cards dealt (Texas Hold'em only).
+    Board { cards: Vec<String> },
+    /// A new round of betting or drawing starts, and every bet goes back to 0.
+    Street { name: String },
+    /// A message for the table, such as who posted the blinds.
+    Info { message: String },
+    /// A player is due to bet.
+    ToAct { player: String },
+    /// A player is due to swap cards.
+    ToSwap { player: String },
+    /// A player put chips in, `0` to check.
+    Bet { player: String, amount: i32 },
+    /// A player folded.
+    Folded { player: String },
+    /// A player replaced cards.
+    Drew {
+        player: String,
+        discarded: Vec<String>,
+        drawn: Vec<String>,
+    },
+    /// Everyone else folded, and the last player won the pot.
+    HandOver { winner: String },
+    /// The remaining players showed their cards, and the best hand won the pot.
+    Showdown { winner: String },
+}
+
+/// A hand as its events so far describe it.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct HandState {
+    /// What players and spectators see. Players who folded have no hand in it.
+    pub table: TableState,
+    /// Cards of players who folded, as they held them.
+    pub mucked: BTreeMap<String, Vec<String>>,
+    /// Whether the hands still in were shown at a showdown.
+    pub shown_down: bool,
+}
+
+impl HandState {
+    /// Folds a stream of events into the hand they describe.
+    pub fn from_events(events: &[HandEvent]) -> Self {
+        let mut state = Self::default();
+        for event in events {
+            state.apply(event);
+        }
+        state
+    }
+
+    /// The table as one player sees it: only their own cards, or none for a
+    /// spectator, until the hands still in are shown down.
+    ///
+    /// # Arguments
+    /// * `viewer` - The seated player, or `None` for a spectator.
+    pub fn table_for(&self, viewer: Option<&str>) -> TableState {
+        let mut table = self.table.clone();
+        if !self.shown_down {
+            table.hands.retain(|player, _| Some(player.as_str()) == viewer);
+        }
+        table
+    }
+
+    /// Applies one event.
+    pub fn apply(&mut self, event: &HandEvent) {
+        let table = &mut self.table;
+        match event {
+            HandEvent::Started { hand_id, variant, players } => {
+                *self = Self {
+                    table: TableState {
+                        variant: variant.clone(),
+                        hand_id: Some(hand_id.clone()),
+                        hands: players.iter().map(|p| (p.clone(), Vec::new())).collect(),
+                        bets: players.iter().map(|p| (p.clone(), 0)).collect(),
+                        current_bet: NO_BET,
+                        ..Default::default()
+                    },
+                    mucked: BTreeMap::new(),
+                    shown_down: false,
+                };
+            }
+            HandEvent::Posted { player, kind, amount } => {
+                table.pot += amount;
+                if *kind != ActionKind::Ante {
+                    let total = table.bets.entry(player.clone()).or_insert(0);
+                    *total += amount;
+                    table.current_bet = table.current_bet.max(*total);
+                }
+            }
+            HandEvent::Dealt { cards } => {
+                for (player, cards) in cards {
+                    table.hands.entry(player.clone()).or_default().extend(cards.iter().cloned());
+                }
+            }
+            HandEvent::Board { cards } => table.community.extend(cards.iter().cloned()),
+            HandEvent::Street { .. } => {
+                table.bets.values_mut().for_each(|bet| *bet = 0);
+                table.current_bet = NO_BET;
+                table.to_act = None;
+                table.to_swap = None;
+            }
+            HandEvent::Info { message } => table.info = Some(message.clone()),
+            HandEvent::ToAct { player } => {
+                table.to_act = Some(player.clone());
+                table.to_swap = None;
+            }
+            HandEvent::ToSwap { player } => {
+                table.to_swap = Some(player.clone());
+                table.to_act = None;
+            }
+            HandEvent::Bet { player, amount } => {
+                let already_in = table.bets.get(player).copied().unwrap_or(0);
+                let total = already_in + amount;
+                table.info = Some(match classify_bet(*amount, already_in, table.current_bet) {
+                    ActionKind::Check => format!("{} checks", player),
+                    ActionKind::Call => format!("{} calls {}", player, amount),
+                    ActionKind::Bet => format!("{} bets {}", player, amount),
+                    _ => format!("{} raises to {}", player, total),
+                });
+                table.bets.insert(player.clone(), total);
+                table.current_bet = table.current_bet.max(total);
+                table.pot += amount;
+                table.to_act = None;
+            }
+            HandEvent::Folded { player } => {
+                if let Some(cards) = table.hands.remove(player) {
+                    self.mucked.insert(player.clone(), cards);
+                }
+                table.bets.remove(player);
+                table.info = Some(format!("{} folds", player));
+                table.to_act = None;
+            }
+            HandEvent::Drew { player, discarded, drawn } => {
+                if let Some(hand) = table.hands.get_mut(player) {
+                    for (old, new) in discarded.iter().zip(drawn) {
+                        if let Some(card) = hand.iter_mut().find(|card| *card == old) {
+                            *card = new.clone();
+                        }
+                    }
+                }
+                table.info = Some(format!("{} replaces {} card(s)", player, drawn.len()));
+                table.to_swap = None;
+            }
+            HandEvent::HandOver { winner } | HandEvent::Showdown { winner } => {
+                self.shown_down = matches!(event, HandEvent::Showdown { .. });
+                table.info = Some(format!("{} wins the pot of {}", winner, table.pot));
+                table.winner = Some(winner.clone());
+                table.to_act = None;
+                table.to_swap = None;
+            }
+        }
+    }
+}
+
+/// Plays a hand's events: keeps the table, saves and broadcasts each event, and
+/// writes the hand history.
+pub struct HandLog {
+    clients: Arc<Mutex<HashMap<SocketAddr, ClientInfo>>>,
+    storage: Arc<dyn Storage>,
+    recorder: Option<HandRecorder>,
+    state: HandState,
+}
+
+impl HandLog {
+    /// Starts a hand: begins its history record and emits `Started`.
+    ///
+    /// # Arguments
+    /// * `clients` - Shared list of connected clients.
+    /// * `storage` - The server's storage backend.
+    /// * `variant` - The game variant being played.
+    /// * `players` - Players in seat order.
+    /// * `shuffle_seed` - Seed the game's deck is shuffled from.
+    /// * `resumed_hand_id` - ID of the hand, if it is being resumed.
+    pub async fn start(
+        clients: Arc<Mutex<HashMap<SocketAddr, ClientInfo>>>,
+        storage: Arc<dyn Storage>,
+        variant: &str,
+        players: &[String],
+        shuffle_seed: u64,
+        resumed_hand_id: Option<&str>,
+    ) -> Self {
+        let recorder = start_hand(&*storage, variant, players, shuffle_seed, resumed_hand_id).await;
+        let hand_id = recorder.hand_id().to_string();
+        let mut log = Self {
+            clients,
+            storage,
+            recorder: Some(recorder),
+            state: HandState::default(),
+        };
+        log.emit(HandEvent::Started {
+            hand_id,
+            variant: variant.to_string(),
+            players: players.to_vec(),
+        })
+        .await;
+        log
+    }
+
+    /// ID of the hand.
+    pub fn hand_id(&self) -> &str {
+        self.state.table.hand_id.as_deref().unwrap_or_default()
+    }
+
+    /// Emits the next event of the hand.
+    pub async fn emit(&mut self, event: HandEvent) {
+        let before = self.state.table.clone();
+        self.state.apply(&event);
+
+        if let Err(e) = save_event(&*self.storage, &event).await {
+            eprintln!("[DB] Failed to save hand events: {}", e);
+        }
+        self.broadcast(&event);
+        if let Some(record) = self.record(&event, &before) {
+            if let Err(e) = save_hand(&*self.storage, &record).await {
+                eprintln!("[DB] Failed to save hand history: {}", e);
+            }
+        }
+    }
+
+    /// Sends what the event changed to the players.
+    fn broadcast(&self, event: &HandEvent) {
+        let table = &self.state.table;
+        let hand_id = table.hand_id.clone().unwrap_or_default();
+        match event {
+            HandEvent::Started { variant, players, .. } => broadcast_to_game_players(
+                &self.clients,
+                &ServerEvent::GameStarted { variant: variant.clone(), players: players.clone() },
+            ),
+            HandEvent::Folded { player } => {
+                send_to_player_by_id(&self.clients, player, &ServerEvent::Folded { player: player.clone() })
+            }
+            HandEvent::HandOver { winner } => broadcast_to_game_players(
+                &self.clients,
+                &ServerEvent::HandOver { winner: winner.clone(), hand_id },
+            ),
+            HandEvent::Showdown { winner } => broadcast_to_game_players(
+                &self.clients,
+                &ServerEvent::Showdown {
+                    winner: winner.clone(),
+                    hand_id,
+                    hands: table.hands.clone(),
+                    pot: table.pot,
+                    community: table.community.clone(),
+                },
+            ),
+            // The next prompt or deal shows these
+            HandEvent::Posted { .. } | HandEvent::Street { .. } | HandEvent::Bet { .. } => {}
+            HandEvent::Dealt { .. }
+            | HandEvent::Board { .. }
+            | HandEvent::Info { .. }
+            | HandEvent::ToAct { .. }
+            | HandEvent::ToSwap { .. }
+            | HandEvent::Drew { .. } => {
+                send_to_each_game_player(&self.clients, |player| ServerEvent::Table(self.state.table_for(Some(player))))
+            }
+        }
+    }
+
+    /// Writes the event to the hand history.
+    ///
+    /// # Arguments
+    /// * `event` - The event, already applied.
+    /// * `before` - The table before the event.
+    ///
+    /// # Returns
+    /// The finished record, once the event ends the hand.
+    fn record(&mut self, event: &HandEvent, before: &TableState) -> Option<HandRecord> {
+        let recorder = self.recorder.as_mut()?;
+        match event {
+            HandEvent::Posted { player, kind, amount } => recorder.post(player, *kind, *amount),
+            HandEvent::Street { name } => recorder.start_street(name),
+            HandEvent::Bet { player, amount } => recorder.bet(
+                player,
+                *amount,
+                before.bets.get(player).copied().unwrap_or(0),
+                before.current_bet,
+            ),
+            HandEvent::Folded { player } => recorder.fold(player, before.bets.get(player).copied().unwrap_or(0)),
+            HandEvent::Drew { player, discarded, drawn } => recorder.draw(player, discarded, drawn),
+            HandEvent::Board { .. } => recorder.set_board(&self.state.table.community),
+            HandEvent::HandOver { winner } | HandEvent::Showdown { winner } => {
+                let table = &self.state.table;
+                let mut held = self.state.mucked.clone();
+                held.extend(table.hands.clone());
+                let shown = match event {
+                    HandEvent::Showdown { .. } => table.hands.clone(),
+                    _ => BTreeMap::new(),
+                };
+                return self.recorder.take().map(|recorder| recorder.finish(&held, &shown, winner, table.pot));
+            }
+            HandEvent::Started { .. }
+            | HandEvent::Dealt { .. }
+            | HandEvent::Info { .. }
+            | HandEvent::ToAct { .. }
+            | HandEvent::ToSwap { .. } => {}
+        }
+        None
+    }
+}
+
+/// Formats cards the same way they are sent to clients (e.g. `"A of Hearts"`).
+pub fn card_names(cards: &[Card]) -> Vec<String> {
+    cards.iter().map(|card| card.to_string()).collect()
+}
+
+/// The cards each player was dealt since they held `already_held` cards, for a
+/// `Dealt` event.
+pub fn dealt_cards(players: &[Player], already_held: usize) -> BTreeMap<String, Vec<String>> {
+    players
+        .iter()
+        .map(|player| (player.id.clone(), card_names(player.hand.get(already_held..).unwrap_or_default())))
+        .collect()
+}
+
+/// The cards replaced in a draw, found by comparing the hand before and after.
+///
+/// # Returns
+/// The discarded cards and the cards drawn in their place, in hand order.
+pub fn replaced_cards(before: &[Card], after: &[Card]) -> (Vec<String>, Vec<String>) {
+    before
+        .iter()
+        .zip(after)
+        .filter(|(old, new)| old != new)
+        .map(|(old, new)| (old.to_string(), new.to_string()))
+        .unzip()
+}
+
+/// Appends an event to the current hand's events in the game state. `Started`
+/// clears the last hand's events first.
+async fn save_event(storage: &dyn Storage, event: &HandEvent) -> StorageResult<()> {
+    if matches!(event, HandEvent::Started { .. }) {
+        storage.set_game_state_field(EVENTS, bson::Bson::Array(Vec::new())).await?;
+    }
+    storage.push_hand_event(bson::to_document(event)?).await
+}
+
+/// Loads the events of the current (or last) hand from a game state document.
+/// A game state without events, e.g. before the first hand, has none.
+pub fn stored_events(state: &bson::Document) -> StorageResult<Vec<HandEvent>> {
+    match state.get(EVENTS) {
+        Some(bson::Bson::Array(events)) => Ok(bson::from_bson(bson::Bson::Array(events.clone()))?),
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// Adds an event to the stored hand from outside the game, e.g. when the hand
+/// is voided after the game has stopped.
+pub async fn append_event(storage: &dyn Storage, event: HandEvent) -> StorageResult<()> {
+    save_event(storage, &event).await
+}
@@ -0,0 +1,604 @@
+//! # Hand History Export
+//!
+//! Converts stored hands into formats that hand tracking tools (PokerTracker,
+//! Hand2Note, ...) can import:
+//! - PokerStars-style text
+//! - Open Hand History (OHH) JSON
+//!
+//! Hands are read from the storage backend's history. All three variants
+//! (5 Card Draw, 7 Card Stud and Texas Hold'em) are supported.
+//!
+//! The export is available to clients through the `"export_hands"` command and
+//! to the operator by running the server as `dealer export <format> <file> [hand_id...]`.
+//! Clients get hands as they saw them at the table (see `history::seen_by`): their
+//! own cards and the hands shown down, but no one else's hole cards. They export
+//! at most `MAX_EXPORT_HANDS` hands at once: the hands they name, or a page of the
+//! hands they were dealt into, looked up by seat in storage. The operator export
+//! keeps every card, and exports every stored hand if none are named.
+use std::fmt::Write as _;
+use std::str::FromStr;
+use chrono::DateTime;
+use serde_json::{json, Value};
+use crate::history::*;
+use crate::storage::*;
+use protocol::ServerEvent;
+
+/// Table name written into exported hands.
+const TABLE_NAME: &str = "Dealer";
+
+/// Most hands a client can export at once. Keeps an export well inside one frame
+/// (see `protocol::framing::MAX_FRAME_LEN`).
+pub const MAX_EXPORT_HANDS: usize = 50;
+
+
+/// Supported export formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// PokerStars-style hand history text.
+    PokerStars,
+    /// Open Hand History JSON.
+    Ohh,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "pokerstars" | "stars" | "txt" => Ok(ExportFormat::PokerStars),
+            "ohh" | "json" => Ok(ExportFormat::Ohh),
+            other => Err(format!("Unknown export format: {} (use \"pokerstars\" or \"ohh\")", other)),
+        }
+    }
+}
+
+/// Reads hands from the history and exports them with every card, for the operator.
+///
+/// # Arguments
+/// * `storage` - The server's storage backend.
+/// * `hand_ids` - Hands to export. An empty list exports every stored hand.
+/// * `format` - Output format.
+///
+/// # Returns
+/// The exported hands, separated the way each format expects, or an error message.
+pub async fn export_hands(storage: &dyn Storage, hand_ids: &[String], format: ExportFormat) -> Result<String, String> {
+    let records = if hand_ids.is_empty() {
+        let docs = storage.list_history().await.map_err(|e| format!("Database error: {}", e))?;
+        decode_hands(docs)?
+    } else {
+        load_hands(storage, hand_ids).await?
+    };
+    Ok(format_hands(&records, format))
+}
+
+/// Exports hands for a player, as they saw them at the table.
+///
+/// # Arguments
+/// * `storage` - The server's storage backend.
+/// * `hand_ids` - Hands to export, at most `MAX_EXPORT_HANDS`. When empty, a page
+///   of the hands the viewer was dealt into is exported instead.
+/// * `page` - Which page of `MAX_EXPORT_HANDS` hands, newest first, starting at 1.
+/// * `format` - Output format.
+/// * `viewer` - The player the hands are exported for.
+///
+/// # Returns
+/// The exported hands, oldest first, or an error message.
+pub async fn export_player_hands(
+    storage: &dyn Storage,
+    hand_ids: &[String],
+    page: usize,
+    format: ExportFormat,
+    viewer: &str,
+) -> Result<String, String> {
+    let records = if hand_ids.is_empty() {
+        let skip = page.saturating_sub(1).saturating_mul(MAX_EXPORT_HANDS);
+        let docs = storage
+            .page_player_history(viewer, skip, MAX_EXPORT_HANDS)
+            .await
+            .map_err(|e| format!("Database error: {}", e))?;
+        let mut records = decode_hands(docs)?;
+        records.reverse();
+        records
+    } else if hand_ids.len() > MAX_EXPORT_HANDS {
+        return Err(format!("At most {} hands can be exported at once.", MAX_EXPORT_HANDS));
+    } else {
+        load_hands(storage, hand_ids).await?
+    };
+    let records: Vec<HandRecord> = records.iter().map(|record| seen_by(record, viewer)).collect();
+    Ok(format_hands(&records, format))
+}
+
+/// Reads the named hands from the history.
+async fn load_hands(storage: &dyn Storage, hand_ids: &[String]) -> Result<Vec<HandRecord>, String> {
+    let mut records = Vec::new();
+    for hand_id in hand_ids {
+        match load_hand(storage, hand_id).await {
+            Ok(Some(record)) => records.push(record),
+            Ok(None) => return Err(format!("No hand found with id: {}", hand_id)),
+            Err(e) => return Err(format!("Database error: {}", e)),
+        }
+    }
+    Ok(records)
+}
+
+fn decode_hands(docs: Vec<mongodb::bson::Document>) -> Result<Vec<HandRecord>, String> {
+    docs.into_iter()
+        .map(|doc| mongodb::bson::from_document::<HandRecord>(doc).map_err(|e| format!("Invalid hand record: {}", e)))
+        .collect()
+}
+
+/// Formats hands, separated the way each format expects.
+fn format_hands(records: &[HandRecord], format: ExportFormat) -> String {
+    match format {
+        ExportFormat::PokerStars => records.iter().map(to_pokerstars).collect::<Vec<_>>().join("\n\n\n"),
+        ExportFormat::Ohh => records
+            .iter()
+            .map(|record| to_ohh(record).to_string())
+            .collect::<Vec<_>>()
+            .join("\n\n"),
+    }
+}
+
+/// Handles the `"export_hands"` command.
+///
+/// # Arguments
+/// * `storage` - The server's storage backend.
+/// * `format` - `"pokerstars"` (the default) or `"ohh"`.
+/// * `hand_ids` - Hands to export; a page of the hands the viewer played is exported when it is empty.
+/// * `page` - Which page, when `hand_ids` is empty.
+/// * `viewer` - The player asking. Other players' cards are left out unless shown down.
+///
+/// # Returns
+/// A `HandExport` event with the exported text, or an error.
+pub async fn handle_export_command(
+    storage: &dyn Storage,
+    format: Option<&str>,
+    hand_ids: &[String],
+    page: usize,
+    viewer: &str,
+) -> ServerEvent {
+    let format_name = format.unwrap_or("pokerstars");
+    let format = match format_name.parse() {
+        Ok(format) => format,
+        Err(e) => return ServerEvent::error(e),
+    };
+
+    match export_player_hands(storage, hand_ids, page, format, viewer).await {
+        Ok(text) => ServerEvent::HandExport { format: format_name.to_string(), text },
+        Err(e) => ServerEvent::error(e),
+    }
+}
+
+/// Runs the operator export: `dealer export <format> <file> [hand_id...]`.
+///
+/// # Arguments
+/// * `storage` - The server's storage backend.
+/// * `args` - Command line arguments after `export`.
+///
+/// # Returns
+/// A message describing what was written, or an error message.
+pub async fn run_export_cli(storage: &dyn Storage, args: &[String]) -> Result<String, String> {
+    let [format, path, hand_ids @ ..] = args else {
+        return Err("Usage: dealer export <pokerstars|ohh> <file> [hand_id...]".to_string());
+    };
+    let format: ExportFormat = format.parse()?;
+    let text = export_hands(storage, hand_ids, format).await?;
+
+    std::fs::write(path, text).map_err(|e| format!("Failed to write {}: {}", path, e))?;
+    Ok(format!("Exported hand history to {}", path))
+}
+
+/// Converts a card name such as `"10 of Hearts"` to the short form `"Th"`.
+pub fn card_code(name: &str) -> String {
+    let (rank, suit) = name.split_once(" of ").unwrap_or((name, ""));
+    let rank = match rank {
+        "10" => "T",
+        other => other,
+    };
+    let suit = match suit {
+        "Hearts" => "h",
+        "Diamonds" => "d",
+        "Clubs" => "c",
+        "Spades" => "s",
+        _ => "?",
+    };
+    format!("{}{}", rank, suit)
+}
+
+fn card_codes(cards: &[String]) -> Vec<String> {
+    cards.iter().map(|c| card_code(c)).collect()
+}
+
+/// Formats cards as PokerStars does, e.g. `[Ah Kd]`.
+fn bracket(cards: &[String]) -> String {
+    format!("[{}]", card_codes(cards).join(" "))
+}
+
+/// The cards a player had at the start of the hand, before any draw.
+fn starting_hand(record: &HandRecord, seat: &SeatRecord) -> Vec<String> {
+    let mut cards = seat.cards.clone();
+    for action in record.streets.iter().flat_map(|s| &s.actions) {
+        if action.player == seat.player && action.action == ActionKind::Draw {
+            for (old, new) in action.discarded.iter().zip(&action.drawn) {
+                if let Some(card) = cards.iter_mut().find(|c| *c == new) {
+                    *card = old.clone();
+                }
+            }
+        }
+    }
+    cards
+}
+
+/// Cards dealt to a seat at the start of a street, as `(cards already held, new cards)`.
+///
+/// Cards the viewer never saw are blank in their copy of the hand, and are left
+/// out; `None` if none of the new cards were seen.
+fn dealt_on_street(record: &HandRecord, seat: &SeatRecord, street: usize) -> Option<(Vec<String>, Vec<String>)> {
+    let (held, new) = match (record.variant.as_str(), street) {
+        ("texas", 0) => (Vec::new(), seat.cards.iter().take(2).cloned().collect()),
+        ("7card", 0) => (Vec::new(), seat.cards.iter().take(3).cloned().collect()),
+        ("7card", n) => {
+            let index = n + 2;
+            let card = seat.cards.get(index)?;
+            (seat.cards[..index].to_vec(), vec![card.clone()])
+        }
+        ("5card", 0) => (Vec::new(), starting_hand(record, seat)),
+        _ => return None,
+    };
+    let seen = |cards: Vec<String>| cards.into_iter().filter(|card| !card.is_empty()).collect::<Vec<_>>();
+    let new = seen(new);
+    (!new.is_empty()).then(|| (seen(held), new))
+}
+
+/// Amount of the first forced bet of the given kind, or 0.
+fn forced_bet(record: &HandRecord, kind: ActionKind) -> i32 {
+    record
+        .streets
+        .iter()
+        .flat_map(|s| &s.actions)
+        .find(|a| a.action == kind)
+        .map(|a| a.amount)
+        .unwrap_or(0)
+}
+
+/// The street a player folded on, if they folded.
+fn folded_street<'a>(record: &'a HandRecord, player: &str) -> Option<&'a str> {
+    record
+        .streets
+        .iter()
+        .find(|s| s.actions.iter().any(|a| a.player == player && a.action == ActionKind::Fold))
+        .map(|s| s.name.as_str())
+}
+
+fn format_date(timestamp: i64, pattern: &str) -> String {
+    DateTime::from_timestamp(timestamp, 0)
+        .map(|date| date.format(pattern).to_string())
+        .unwrap_or_default()
+}
+
+/// Converts a hand to PokerStars-style text.
+pub fn to_pokerstars(record: &HandRecord) -> String {
+    let mut out = String::new();
+    let date = format_date(record.timestamp, "%Y/%m/%d %H:%M:%S UTC");
+    let ante = forced_bet(record, ActionKind::Ante);
+    let small_blind = forced_bet(record, ActionKind::SmallBlind);
+    let big_blind = forced_bet(record, ActionKind::BigBlind);
+
+    let game = match record.variant.as_str() {
+        "texas" => format!("Hold'em No Limit ({}/{})", small_blind, big_blind),
+        "7card" => format!("7 Card Stud No Limit (Ante {})", ante),
+        _ => format!("5 Card Draw No Limit (Ante {})", ante),
+    };
+    let _ = writeln!(out, "PokerStars Hand #{}: {} - {}", record.hand_id, game, date);
+
+    let button = if record.seats.len() == 2 { 1 } else { record.seats.len() };
+    if record.variant == "7card" {
+        let _ = writeln!(out, "Table '{}' {}-max", TABLE_NAME, record.seats.len());
+    } else {
+        let _ = writeln!(out, "Table '{}' {}-max Seat #{} is the button", TABLE_NAME, record.seats.len(), button);
+    }
+    for seat in &record.seats {
+        let _ = writeln!(out, "Seat {}: {} ({} in chips)", seat.seat, seat.player, seat.stack);
+    }
+
+    for (index, street) in record.streets.iter().enumerate() {
+        let (forced, voluntary): (Vec<&ActionRecord>, Vec<&ActionRecord>) = street
+            .actions
+            .iter()
+            .partition(|a| matches!(a.action, ActionKind::Ante | ActionKind::SmallBlind | ActionKind::BigBlind));
+        let mut street_bet = 0;
+        for action in &forced {
+            write_stars_action(&mut out, action, &mut street_bet);
+        }
+
+        if let Some(header) = stars_street_header(record, &street.name) {
+            let _ = writeln!(out, "{}", header);
+        }
+        for seat in &record.seats {
+            if let Some((held, new)) = dealt_on_street(record, seat, index) {
+                if held.is_empty() {
+                    let _ = writeln!(out, "Dealt to {} {}", seat.player, bracket(&new));
+                } else {
+                    let _ = writeln!(out, "Dealt to {} {} {}", seat.player, bracket(&held), bracket(&new));
+                }
+            }
+        }
+
+        for action in &voluntary {
+            write_stars_action(&mut out, action, &mut street_bet);
+        }
+    }
+
+    if !record.shown_hands.is_empty() {
+        let _ = writeln!(out, "*** SHOW DOWN ***");
+        for (player, cards) in &record.shown_hands {
+            let _ = writeln!(out, "{}: shows {}", player, bracket(cards));
+        }
+    }
+    for pot in &record.pots {
+        let _ = writeln!(out, "{} collected {} from pot", pot.player, pot.amount);
+    }
+
+    let _ = writeln!(out, "*** SUMMARY ***");
+    let _ = writeln!(out, "Total pot {} | Rake 0", record.total_pot);
+    if !record.board.is_empty() {
+        let _ = writeln!(out, "Board {}", bracket(&record.board));
+    }
+    for seat in &record.seats {
+        let won = record.pots.iter().filter(|p| p.player == seat.player).map(|p| p.amount).sum::<i32>();
+        let summary = if let Some(street) = folded_street(record, &seat.player) {
+            format!("folded on {}", street)
+        } else if let Some(cards) = record.shown_hands.get(&seat.player) {
+            if won > 0 {
+                format!("showed {} and won ({})", bracket(cards), won)
+            } else {
+                format!("showed {} and lost", bracket(cards))
+            }
+        } else if won > 0 {
+            format!("collected ({})", won)
+        } else {
+            "mucked".to_string()
+        };
+        let _ = writeln!(out, "Seat {}: {} {}", seat.seat, seat.player, summary);
+    }
+
+    out
+}
+
+/// PokerStars header line for a street, e.g. `"*** FLOP *** [2c 3d 4h]"`, or `None` if the street has no header.
+fn stars_street_header(record: &HandRecord, street: &str) -> Option<String> {
+    let board = &record.board;
+    let header = match street {
+        "preflop" => "*** HOLE CARDS ***".to_string(),
+        "flop" => format!("*** FLOP *** {}", bracket(&board[..board.len().min(3)])),
+        "turn" if board.len() >= 4 => format!("*** TURN *** {} {}", bracket(&board[..3]), bracket(&board[3..4])),
+        "river" if board.len() >= 5 => format!("*** RIVER *** {} {}", bracket(&board[..4]), bracket(&board[4..5])),
+        "third" => "*** 3rd STREET ***".to_string(),
+        "fourth" => "*** 4th STREET ***".to_string(),
+        "fifth" => "*** 5th STREET ***".to_string(),
+        "sixth" => "*** 6th STREET ***".to_string(),
+        "seventh" => "*** RIVER ***".to_string(),
+        "predraw" => "*** DEALING HANDS ***".to_string(),
+        "draw" => "*** DRAW ***".to_string(),
+        _ => return None,
+    };
+    Some(header)
+}
+
+fn write_stars_action(out: &mut String, action: &ActionRecord, street_bet: &mut i32) {
+    let player = &action.player;
+    let line = match action.action {
+        ActionKind::Ante => format!("{}: posts the ante {}", player, action.amount),
+        ActionKind::SmallBlind => format!("{}: posts small blind {}", player, action.amount),
+        ActionKind::BigBlind => format!("{}: posts big blind {}", player, action.amount),
+        ActionKind::Fold => format!("{}: folds", player),
+        ActionKind::Check => format!("{}: checks", player),
+        ActionKind::Call => format!("{}: calls {}", player, action.amount),
+        ActionKind::Bet => format!("{}: bets {}", player, action.amount),
+        ActionKind::Raise => format!("{}: raises {} to {}", player, action.total - *street_bet, action.total),
+        ActionKind::Draw if action.amount == 0 => format!("{}: stands pat", player),
+        // Another player's draw, without the cards
+        ActionKind::Draw if action.drawn.is_empty() => format!(
+            "{}: discards {} card{}",
+            player,
+            action.amount,
+            if action.amount == 1 { "" } else { "s" },
+        ),
+        ActionKind::Draw => format!(
+            "{}: discards {} card{} {}\nDealt to {} {}",
+            player,
+            action.amount,
+            if action.amount == 1 { "" } else { "s" },
+            bracket(&action.discarded),
+            player,
+            bracket(&action.drawn),
+        ),
+    };
+    *street_bet = (*street_bet).max(action.total);
+    let _ = writeln!(out, "{}", line);
+}
+
+/// OHH name of a street.
+fn ohh_street_name(street: &str) -> String {
+    match street {
+        "third" => "Third Street".to_string(),
+        "fourth" => "Fourth Street".to_string(),
+        "fifth" => "Fifth Street".to_string(),
+        "sixth" => "Sixth Street".to_string(),
+        "seventh" => "Seventh Street".to_string(),
+        other => {
+            let mut chars = other.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        }
+    }
+}
+
+/// Converts a hand to an Open Hand History object (`{"ohh": {...}}`).
+pub fn to_ohh(record: &HandRecord) -> Value {
+    let player_id = |name: &str| record.seats.iter().find(|s| s.player == name).map(|s| s.seat).unwrap_or(0);
+
+    let players: Vec<Value> = record
+        .seats
+        .iter()
+        .map(|seat| json!({
+            "id": seat.seat,
+            "seat": seat.seat,
+            "name": seat.player,
+            "display": seat.player,
+            "starting_stack": seat.stack,
+        }))
+        .collect();
+
+    let mut rounds = Vec::new();
+    let mut action_number = 0;
+    let mut next_action = |action: Value| {
+        action_number += 1;
+        let mut action = action;
+        action["action_number"] = json!(action_number);
+        action
+    };
+
+    for (index, street) in record.streets.iter().enumerate() {
+        let mut actions = Vec::new();
+        let (forced, voluntary): (Vec<&ActionRecord>, Vec<&ActionRecord>) = street
+            .actions
+            .iter()
+            .partition(|a| matches!(a.action, ActionKind::Ante | ActionKind::SmallBlind | ActionKind::BigBlind));
+
+        for action in forced {
+            let name = match action.action {
+                ActionKind::Ante => "Post Ante",
+                ActionKind::SmallBlind => "Post SB",
+                _ => "Post BB",
+            };
+            actions.push(next_action(json!({
+                "player_id": player_id(&action.player),
+                "action": name,
+                "amount": action.amount,
+                "is_allin": false,
+            })));
+        }
+        for seat in &record.seats {
+            if let Some((_, new)) = dealt_on_street(record, seat, index) {
+                actions.push(next_action(json!({
+                    "player_id": seat.seat,
+                    "action": "Dealt Cards",
+                    "cards": card_codes(&new),
+                })));
+            }
+        }
+        for action in voluntary {
+            let id = player_id(&action.player);
+            match action.action {
+                ActionKind::Draw => {
+                    actions.push(next_action(json!({
+                        "player_id": id,
+                        "action": if action.amount == 0 { "Stands Pat" } else { "Discard" },
+                        "cards": card_codes(&action.discarded),
+                    })));
+                    if action.amount > 0 && !action.drawn.is_empty() {
+                        actions.push(next_action(json!({
+                            "player_id": id,
+                            "action": "Dealt Cards",
+                            "cards": card_codes(&action.drawn),
+                        })));
+                    }
+                }
+                kind => {
+                    let name = match kind {
+                        ActionKind::Fold => "Fold",
+                        ActionKind::Check => "Check",
+                        ActionKind::Call => "Call",
+                        ActionKind::Bet => "Bet",
+                        _ => "Raise",
+                    };
+                    actions.push(next_action(json!({
+                        "player_id": id,
+                        "action": name,
+                        "amount": action.amount,
+                        "is_allin": false,
+                    })));
+                }
+            }
+        }
+
+        let cards: Vec<String> = match street.name.as_str() {
+            "flop" => record.board.iter().take(3).cloned().collect(),
+            "turn" => record.board.iter().skip(3).take(1).cloned().collect(),
+            "river" => record.board.iter().skip(4).take(1).cloned().collect(),
+            _ => Vec::new(),
+        };
+        rounds.push(json!({
+            "id": index,
+            "street": ohh_street_name(&street.name),
+            "cards": card_codes(&cards),
+            "actions": actions,
+        }));
+    }
+
+    if !record.shown_hands.is_empty() {
+        let actions: Vec<Value> = record
+            .shown_hands
+            .iter()
+            .map(|(player, cards)| next_action(json!({
+                "player_id": player_id(player),
+                "action": "Shows Cards",
+                "cards": card_codes(cards),
+            })))
+            .collect();
+        rounds.push(json!({
+            "id": rounds.len(),
+            "street": "Showdown",
+            "actions": actions,
+        }));
+    }
+
+    let pots: Vec<Value> = record
+        .pots
+        .iter()
+        .enumerate()
+        .map(|(number, pot)| json!({
+            "number": number,
+            "amount": pot.amount,
+            "rake": 0,
+            "player_wins": [{
+                "player_id": player_id(&pot.player),
+                "win_amount": pot.amount,
+                "contributed_rake": 0,
+            }],
+        }))
+        .collect();
+
+    let game_type = match record.variant.as_str() {
+        "texas" => "Holdem",
+        "7card" => "Stud",
+        _ => "Draw",
+    };
+    let dealer_seat = if record.seats.len() == 2 { 1 } else { record.seats.len() };
+
+    json!({
+        "ohh": {
+            "spec_version": "1.4.6",
+            "site_name": TABLE_NAME,
+            "network_name": TABLE_NAME,
+            "internal_version": env!("CARGO_PKG_VERSION"),
+            "tournament": false,
+            "game_number": record.hand_id,
+            "start_date_utc": format_date(record.timestamp, "%Y-%m-%dT%H:%M:%SZ"),
+            "table_name": TABLE_NAME,
+            "table_size": record.seats.len(),
+            "game_type": game_type,
+            "bet_limit": { "bet_type": "NL", "bet_cap": 0 },
+            "dealer_seat": dealer_seat,
+            "small_blind_amount": forced_bet(record, ActionKind::SmallBlind),
+            "big_blind_amount": forced_bet(record, ActionKind::BigBlind),
+            "ante_amount": forced_bet(record, ActionKind::Ante),
+            "players": players,
+            "rounds": rounds,
+            "pots": pots,
+        }
+    })
+}
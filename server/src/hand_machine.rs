@@ -0,0 +1,423 @@
+//! # Hand State Machine
+//!
+//! Each variant plays a hand as a state machine (`FiveCardDrawHand`,
+//! `SevenCardStudHand` and `TexasHoldemHand`) that moves through explicit phases:
+//!
+//! ```text
+//! Antes | Blinds -> Deal(street) -> Betting(street) -> ... -> Draw -> ... -> Showdown -> Over
+//! ```
+//!
+//! A machine does no I/O. `step` moves it on by one transition, and `bet` and
+//! `swap` feed it a player's input when it is waiting on one (`turn`). Each call
+//! returns the hand events it produced (see `hand_events.rs`), so a hand can be
+//! played step by step in a unit test without sockets or a database.
+//!
+//! Machines are `serde`-serializable, so a hand can be saved at any step and
+//! restored to carry on from there.
+//!
+//! `play_hand` drives a machine in a real game: it emits and logs the events, waits
+//! for the players' bets and swaps, and writes the results to storage.
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use serde::{Deserialize, Serialize};
+use crate::db::{self, update_players_folded, wait_for_player_bet, wait_for_player_swap};
+use crate::five_card_draw::Player;
+use crate::hand_events::{HandEvent, HandLog};
+use crate::history::ActionKind;
+use crate::metrics::METRICS;
+use crate::recovery::{self, Replay};
+use crate::results;
+use crate::storage::Storage;
+use crate::comms::send_to_player_by_id;
+use crate::{ClientInfo, GAME_VARIANT};
+use protocol::ServerEvent;
+
+/// A round of betting or dealing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Street {
+    /// Five Card Draw, before the draw.
+    Predraw,
+    /// Five Card Draw, after the draw.
+    Postdraw,
+    /// Seven Card Stud streets, named by the cards each player holds.
+    Third,
+    Fourth,
+    Fifth,
+    Sixth,
+    Seventh,
+    /// Texas Hold'em streets.
+    Preflop,
+    Flop,
+    Turn,
+    River,
+}
+
+impl Street {
+    /// The street's name, as in `HandEvent::Street` and the hand history.
+    pub fn name(self) -> &'static str {
+        match self {
+            Street::Predraw => "predraw",
+            Street::Postdraw => "postdraw",
+            Street::Third => "third",
+            Street::Fourth => "fourth",
+            Street::Fifth => "fifth",
+            Street::Sixth => "sixth",
+            Street::Seventh => "seventh",
+            Street::Preflop => "preflop",
+            Street::Flop => "flop",
+            Street::Turn => "turn",
+            Street::River => "river",
+        }
+    }
+}
+
+/// Where a hand is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "phase", content = "street", rename_all = "snake_case")]
+pub enum Phase {
+    /// Every player posts the ante.
+    Antes,
+    /// The small and big blinds are posted (Texas Hold'em).
+    Blinds,
+    /// The street's cards are dealt.
+    Deal(Street),
+    /// A round of betting on the street.
+    Betting(Street),
+    /// Each player in turn may replace cards (Five Card Draw).
+    Draw,
+    /// The players still in show their cards.
+    Showdown,
+    /// The hand has ended.
+    Over,
+}
+
+/// Input a hand is waiting on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", content = "player", rename_all = "snake_case")]
+pub enum Turn {
+    /// The player is due to bet.
+    Bet(String),
+    /// The player is due to swap cards.
+    Swap(String),
+}
+
+/// A hand of one variant, played one step at a time.
+pub trait HandMachine {
+    /// Where the hand is.
+    fn phase(&self) -> Phase;
+
+    /// The input the hand is waiting on, if any. `step` does nothing until it is
+    /// given.
+    fn turn(&self) -> Option<&Turn>;
+
+    /// Moves the hand on by one transition.
+    ///
+    /// # Returns
+    /// The events it produced; none if the hand is waiting on input or is over.
+    fn step(&mut self) -> Vec<HandEvent>;
+
+    /// Takes the bet of the player due to bet: chips added, `0` to check or `-1`
+    /// to fold. A bet the rules do not allow is refused, and the next `step`
+    /// asks the player again.
+    ///
+    /// # Returns
+    /// The events it produced, none if it is not the player's turn, or why the
+    /// bet was refused.
+    fn bet(&mut self, player: &str, amount: i32) -> Result<Vec<HandEvent>, String>;
+
+    /// Takes the swap of the player due to swap.
+    ///
+    /// # Arguments
+    /// * `player` - The player swapping.
+    /// * `indices` - Positions in their hand of the cards to replace.
+    ///
+    /// # Returns
+    /// The events it produced; none if it is not the player's turn.
+    fn swap(&mut self, _player: &str, _indices: &[usize]) -> Vec<HandEvent> {
+        Vec::new()
+    }
+
+    /// Seed the deck is shuffled from.
+    fn shuffle_seed(&self) -> u64;
+
+    /// Players still in the hand, in seat order.
+    fn active_players(&self) -> &[Player];
+
+    /// Players who have folded, in the order they folded.
+    fn folded(&self) -> &[Player];
+
+    /// Chips in the pot.
+    fn pot(&self) -> i32;
+
+    /// The winner, once the hand is over.
+    fn winner(&self) -> Option<&str>;
+}
+
+/// The players and chips a betting round works on, borrowed from a variant's game.
+pub struct Seats<'a> {
+    /// Players still in the hand.
+    pub current: &'a mut Vec<Player>,
+    /// Players who have folded.
+    pub folded: &'a mut Vec<Player>,
+    pub pot: &'a mut i32,
+    /// The highest total bet on the street, `-2` before anyone has bet.
+    pub current_bet: &'a mut i32,
+}
+
+/// Which bets a round accepts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+pub enum BetRule {
+    /// Any bet that brings the player up to the current bet; more is a raise.
+    Open,
+    /// Exactly enough to match the big blind (Texas Hold'em, before the flop).
+    MatchBlind,
+    /// Open betting from the big blind, who may check to end the round if no one
+    /// has added to the pot (Texas Hold'em, before the flop).
+    BlindOption {
+        big_blind: String,
+        pot_before: i32,
+        checked: bool,
+    },
+}
+
+/// A round of betting: who acts next, and when the round ends.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BettingRound {
+    /// Seat (among the players still in) of the player due to act.
+    seat: usize,
+    /// The round ends when the action comes back to this player, the last to raise.
+    closer: Option<String>,
+    rule: BetRule,
+}
+
+impl BettingRound {
+    /// Starts a round.
+    ///
+    /// # Arguments
+    /// * `seat` - Seat of the first player to act.
+    /// * `closer` - Player the round ends at, before anyone raises.
+    /// * `rule` - Which bets the round accepts.
+    pub fn new(seat: usize, closer: Option<String>, rule: BetRule) -> Self {
+        Self { seat, closer, rule }
+    }
+
+    /// Starts an open round of betting, led by `seat`.
+    pub fn open(seat: usize) -> Self {
+        Self::new(seat, None, BetRule::Open)
+    }
+
+    /// Which bets the round accepts.
+    pub fn rule(&self) -> &BetRule {
+        &self.rule
+    }
+
+    /// The player due to act.
+    ///
+    /// # Returns
+    /// `None` once the round is over: the action is back at the last raiser, one
+    /// player is left, or the big blind has checked.
+    pub fn next_to_act(&mut self, players: &[Player]) -> Option<String> {
+        if matches!(self.rule, BetRule::BlindOption { checked: true, .. }) || players.is_empty() {
+            return None;
+        }
+        if self.seat >= players.len() {
+            self.seat = 0;
+        }
+        let player = &players[self.seat].id;
+        if self.closer.as_ref() == Some(player) || players.len() == 1 {
+            return None;
+        }
+        Some(player.clone())
+    }
+
+    /// Takes a bet from the player due to act (see `next_to_act`).
+    ///
+    /// # Returns
+    /// `Bet` or `Folded`, or why the rules refuse the bet.
+    pub fn bet(&mut self, seats: Seats, player: &str, amount: i32) -> Result<HandEvent, String> {
+        let Seats { current, folded, pot, current_bet } = seats;
+        if let BetRule::BlindOption { big_blind, pot_before, checked } = &mut self.rule {
+            if player == big_blind && *pot == *pot_before && !*checked && amount == 0 {
+                *checked = true;
+                self.seat += 1;
+                return Ok(HandEvent::Bet { player: player.to_string(), amount });
+            }
+        }
+
+        if amount == -1 {
+            folded.push(current.remove(self.seat));
+            return Ok(HandEvent::Folded { player: player.to_string() });
+        }
+
+        let seat = &mut current[self.seat];
+        let total = seat.bet_amount + amount;
+        let to_call = (*current_bet - seat.bet_amount).max(0);
+        if self.rule == BetRule::MatchBlind {
+            // Matching the blind is not charged to the player's losses
+            if total != *current_bet {
+                return Err(format!("Bet {} to match the big blind, or -1 to fold.", to_call));
+            }
+        } else {
+            if total < *current_bet {
+                return Err(format!("Bet at least {} to call, or -1 to fold.", to_call));
+            }
+            if total > *current_bet {
+                self.closer = Some(seat.id.clone());
+                *current_bet = total;
+            }
+            seat.money_lost += amount;
+        }
+        seat.bet_amount = total;
+        *pot += amount;
+        self.seat += 1;
+        Ok(HandEvent::Bet { player: player.to_string(), amount })
+    }
+}
+
+/// The winner if everyone else has folded.
+pub fn last_player_standing(players: &[Player]) -> Option<String> {
+    match players {
+        [player] => Some(player.id.clone()),
+        _ => None,
+    }
+}
+
+/// Parses the card positions of a `swap` command, e.g. `"0,3"`.
+pub fn parse_swap_indices(indices: &str) -> Vec<usize> {
+    indices.split(',').filter_map(|s| s.trim().parse().ok()).collect()
+}
+
+/// Logs the forced bets, the board and what the players did to the server's output.
+fn log_event(event: &HandEvent) {
+    match event {
+        HandEvent::Posted { player, kind: ActionKind::SmallBlind, amount } => println!("[Blinds] {} (SB) posts {}", player, amount),
+        HandEvent::Posted { player, kind: ActionKind::BigBlind, amount } => println!("[Blinds] {} (BB) posts {}", player, amount),
+        HandEvent::Board { cards } => println!("[Game] Board: {}", cards.join(", ")),
+        HandEvent::Folded { player } => println!("[Game] {} folds.", player),
+        HandEvent::Drew { player, discarded, .. } => println!("[Swap] {} replaced {} card(s)", player, discarded.len()),
+        _ => {}
+    }
+}
+
+/// Plays a hand at the table.
+///
+/// Steps the machine until it is over: emits each event it produces, asks the
+/// players for their bets and swaps (telling a player why a bet was refused), charges the players who folded in each round
+/// of betting once it ends, and writes the results, with a timestamped record for each player,
+/// once there is a winner. The hand is settled as soon as they are written, so it
+/// is not played or refunded again after a crash or shutdown.
+///
+/// A resumed hand keeps its ID, and its bets and swaps are answered from the
+/// replay until it has caught up.
+///
+/// # Arguments
+/// * `machine` - The hand, before its first step.
+/// * `clients` - Shared list of connected clients.
+/// * `player_names` - Players in seat order.
+/// * `storage` - Storage backend for player data and the live game state.
+/// * `replay` - The stored hand being played again, if any (see `recovery.rs`).
+pub async fn play_hand(
+    mut machine: impl HandMachine,
+    clients: Arc<Mutex<HashMap<std::net::SocketAddr, ClientInfo>>>,
+    player_names: &[String],
+    storage: Arc<dyn Storage>,
+    mut replay: Option<Replay>,
+) {
+    let variant = GAME_VARIANT.get().unwrap();
+    let resumed_hand_id = replay.as_ref().map(|replay| replay.hand_id().to_string());
+    let mut hand = HandLog::start(
+        clients.clone(),
+        storage.clone(),
+        variant,
+        player_names,
+        machine.shuffle_seed(),
+        resumed_hand_id.as_deref(),
+    )
+    .await;
+    METRICS.hand_dealt(variant);
+    let mut showdown = false;
+    // Folded players already charged; players only ever join the end of the list
+    let mut charged = 0;
+
+    loop {
+        let phase = machine.phase();
+        let events = match machine.turn().cloned() {
+            Some(Turn::Bet(player)) => {
+                let amount = match recovery::replayed(&mut replay, |replay| replay.next_bet(&player)) {
+                    Some(amount) => Some(amount),
+                    None => wait_for_player_bet(&*storage, &player).await,
+                };
+                let Some(amount) = amount else {
+                    continue;
+                };
+                match machine.bet(&player, amount) {
+                    Ok(events) => events,
+                    Err(reason) => {
+                        // A replayed bet was refused the first time too, and the player told then
+                        if replay.is_none() {
+                            send_to_player_by_id(&clients, &player, &ServerEvent::error(reason));
+                        }
+                        Vec::new()
+                    }
+                }
+            }
+            Some(Turn::Swap(player)) => {
+                let indices = match recovery::replayed(&mut replay, |replay| replay.next_swap(&player)) {
+                    Some(indices) => Some(indices),
+                    None => wait_for_player_swap(&*storage, &player).await,
+                };
+                let indices = parse_swap_indices(&indices.unwrap_or_default());
+                machine.swap(&player, &indices)
+            }
+            None if phase == Phase::Over => break,
+            None => machine.step(),
+        };
+
+        // After each round of betting
+        if matches!(phase, Phase::Betting(_)) && !matches!(machine.phase(), Phase::Betting(_)) {
+            let folded = machine.folded();
+            if let Err(e) = update_players_folded(&*storage, variant, &folded[charged..]).await {
+                eprintln!("[DB] Failed to update folded players: {}", e);
+            }
+            charged = folded.len();
+        }
+        showdown |= phase == Phase::Showdown;
+        for event in events {
+            // A resumed hand's events were logged when they first happened
+            if replay.is_none() {
+                log_event(&event);
+            }
+            hand.emit(event).await;
+        }
+        if phase == Phase::Blinds {
+            tokio::time::sleep(Duration::from_secs(3)).await;
+        }
+    }
+
+    METRICS.observe_pot(variant, machine.pot());
+    if let Some(winner) = machine.winner() {
+        println!("[Game] Winner determined: {}", winner);
+        // Update database with results
+        if let Err(e) = db::update_game_results(&*storage, variant, winner, machine.active_players(), machine.pot()).await {
+            eprintln!("Failed to update game results: {}", e);
+        }
+        let players = [machine.active_players(), machine.folded()].concat();
+        if let Err(e) = results::record_results(&*storage, variant, hand.hand_id(), winner, &players, machine.pot()).await {
+            eprintln!("[DB] Failed to record results: {}", e);
+        }
+    }
+    if let Err(e) = db::settle_hand(&*storage).await {
+        eprintln!("[DB] Failed to settle the hand: {}", e);
+    }
+
+    if showdown {
+        // Simulate game running
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        println!("[Game] Game finished.");
+    }
+}
@@ -0,0 +1,68 @@
+//! # Behavioural Stats
+//!
+//! Works out how a player plays from the actions in the hand history (see
+//! `history.rs`), for `get_user_stats`:
+//!
+//! - VPIP: how often they call, bet or raise in the first betting round.
+//! - PFR: how often they bet or raise in the first betting round.
+//! - Aggression factor: bets and raises per call.
+//! - WTSD: how often they stay in until the showdown.
+//! - W$SD: how often they win once they get there.
+//!
+//! The first betting round is the first street recorded: preflop, before the
+//! draw, or third street. Antes and blinds are forced, so they never count.
+use crate::history::{ActionKind, HandRecord};
+use protocol::BehaviourStats;
+
+/// Works out a player's habits over some hands.
+///
+/// # Arguments
+/// * `records` - Hands from the history. Hands the player was not dealt into are skipped.
+/// * `player` - The player's name.
+///
+/// # Returns
+/// The player's stats, all zero if they played none of the hands.
+pub fn behaviour_stats<'a>(records: impl IntoIterator<Item = &'a HandRecord>, player: &str) -> BehaviourStats {
+    let (mut hands, mut vpip, mut pfr, mut aggressive, mut calls, mut showdowns, mut won) = (0, 0, 0, 0, 0, 0, 0);
+
+    for record in records {
+        if !record.seats.iter().any(|seat| seat.player == player) {
+            continue;
+        }
+        hands += 1;
+
+        let first_round = record.streets.first().map(|street| &street.actions[..]).unwrap_or_default();
+        let first_actions: Vec<ActionKind> = first_round.iter().filter(|a| a.player == player).map(|a| a.action).collect();
+        if first_actions.iter().any(|a| matches!(a, ActionKind::Call | ActionKind::Bet | ActionKind::Raise)) {
+            vpip += 1;
+        }
+        if first_actions.iter().any(|a| matches!(a, ActionKind::Bet | ActionKind::Raise)) {
+            pfr += 1;
+        }
+
+        for action in record.streets.iter().flat_map(|street| &street.actions).filter(|a| a.player == player) {
+            match action.action {
+                ActionKind::Bet | ActionKind::Raise => aggressive += 1,
+                ActionKind::Call => calls += 1,
+                _ => {}
+            }
+        }
+
+        if record.shown_hands.contains_key(player) {
+            showdowns += 1;
+            if record.pots.iter().any(|award| award.player == player) {
+                won += 1;
+            }
+        }
+    }
+
+    let rate = |count: i32, of: i32| if of == 0 { 0.0 } else { count as f64 / of as f64 };
+    BehaviourStats {
+        hands,
+        vpip: rate(vpip, hands),
+        pfr: rate(pfr, hands),
+        aggression_factor: aggressive as f64 / calls.max(1) as f64,
+        went_to_showdown: rate(showdowns, hands),
+        won_at_showdown: rate(won, showdowns),
+    }
+}
@@ -0,0 +1,331 @@
+//! # Hand History
+//!
+//! Records every completed hand so it can be looked up later for disputes,
+//! stats and replays.
+//!
+//! Each hand's `HandLog` (see `hand_events.rs`) creates a `HandRecorder` when the
+//! hand starts, reports each posted blind or ante, bet, fold and draw to it as the
+//! events come in, and calls `save_hand` when the hand ends. The finished `HandRecord` is written to the
+//! storage backend's history. The record types themselves live in the shared
+//! `protocol` crate, since the client reads them too.
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+use mongodb::bson;
+use rand::Rng;
+use crate::storage::*;
+use crate::db::player_stack;
+use crate::recovery::begin_live_hand;
+
+pub use protocol::hand::*;
+use protocol::ServerEvent;
+
+/// Builds a `HandRecord` while a hand is being played.
+#[derive(Debug)]
+pub struct HandRecorder {
+    record: HandRecord,
+}
+
+impl HandRecorder {
+    /// Starts recording a new hand.
+    ///
+    /// # Arguments
+    /// * `variant` - The game variant being played.
+    /// * `shuffle_seed` - Seed the game's deck is shuffled from.
+    /// * `seats` - Player names and starting stacks, in seat order.
+    pub fn new(variant: &str, shuffle_seed: u64, seats: &[(String, i32)]) -> Self {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let hand_id = format!("{}{:03}", now.as_millis(), rand::thread_rng().gen_range(0..1000));
+
+        let seats = seats
+            .iter()
+            .enumerate()
+            .map(|(i, (player, stack))| SeatRecord {
+                seat: i + 1,
+                player: player.clone(),
+                stack: *stack,
+                cards: Vec::new(),
+            })
+            .collect();
+
+        Self {
+            record: HandRecord {
+                hand_id,
+                timestamp: now.as_secs() as i64,
+                variant: variant.to_string(),
+                shuffle_seed: format!("{:016x}", shuffle_seed),
+                seats,
+                streets: Vec::new(),
+                board: Vec::new(),
+                shown_hands: BTreeMap::new(),
+                pots: Vec::new(),
+                total_pot: 0,
+            },
+        }
+    }
+
+    /// The ID of the hand being recorded.
+    pub fn hand_id(&self) -> &str {
+        &self.record.hand_id
+    }
+
+    /// Begins a new street. Later actions are recorded under it.
+    pub fn start_street(&mut self, name: &str) {
+        self.record.streets.push(StreetRecord {
+            name: name.to_string(),
+            actions: Vec::new(),
+        });
+    }
+
+    /// Records a forced bet (ante or blind).
+    pub fn post(&mut self, player: &str, action: ActionKind, amount: i32) {
+        let total = if action == ActionKind::Ante { 0 } else { amount };
+        self.push(player, action, amount, total);
+    }
+
+    /// Records an accepted bet and works out whether it was a check, call, bet or raise.
+    ///
+    /// # Arguments
+    /// * `player` - Player who bet.
+    /// * `amount` - Chips added by this bet.
+    /// * `already_in` - Chips the player had already bet on this street.
+    /// * `current_bet` - Highest bet on this street before this action (negative if none yet).
+    pub fn bet(&mut self, player: &str, amount: i32, already_in: i32, current_bet: i32) {
+        let action = classify_bet(amount, already_in, current_bet);
+        self.push(player, action, amount, already_in + amount);
+    }
+
+    /// Records a fold.
+    pub fn fold(&mut self, player: &str, already_in: i32) {
+        self.push(player, ActionKind::Fold, 0, already_in);
+    }
+
+    /// Records a draw.
+    ///
+    /// # Arguments
+    /// * `player` - Player who drew.
+    /// * `discarded` - Cards thrown away.
+    /// * `drawn` - Cards received in their place.
+    pub fn draw(&mut self, player: &str, discarded: &[String], drawn: &[String]) {
+        self.push(player, ActionKind::Draw, drawn.len() as i32, 0);
+        if let Some(action) = self.current_street().actions.last_mut() {
+            action.discarded = discarded.to_vec();
+            action.drawn = drawn.to_vec();
+        }
+    }
+
+    /// Sets the community cards.
+    pub fn set_board(&mut self, cards: &[String]) {
+        self.record.board = cards.to_vec();
+    }
+
+    /// Completes the record.
+    ///
+    /// # Arguments
+    /// * `held` - The cards each player held when they folded or the hand ended.
+    /// * `shown` - Cards shown at showdown, empty if everyone else folded.
+    /// * `winner` - Player who won the pot.
+    /// * `pot` - Total chips in the pot.
+    pub fn finish(
+        mut self,
+        held: &BTreeMap<String, Vec<String>>,
+        shown: &BTreeMap<String, Vec<String>>,
+        winner: &str,
+        pot: i32,
+    ) -> HandRecord {
+        for seat in &mut self.record.seats {
+            if let Some(cards) = held.get(&seat.player) {
+                seat.cards = cards.clone();
+            }
+        }
+        self.record.shown_hands = shown.clone();
+        self.record.pots = vec![PotAward { player: winner.to_string(), amount: pot }];
+        self.record.total_pot = pot;
+        self.record
+    }
+
+    fn current_street(&mut self) -> &mut StreetRecord {
+        if self.record.streets.is_empty() {
+            self.start_street("predeal");
+        }
+        self.record.streets.last_mut().unwrap()
+    }
+
+    fn push(&mut self, player: &str, action: ActionKind, amount: i32, total: i32) {
+        self.current_street().actions.push(ActionRecord {
+            player: player.to_string(),
+            action,
+            amount,
+            total,
+            discarded: Vec::new(),
+            drawn: Vec::new(),
+        });
+    }
+}
+
+/// Works out whether a bet is a check, call, bet or raise.
+///
+/// # Arguments
+/// * `amount` - Chips added by this bet.
+/// * `already_in` - Chips the player had already bet on this street.
+/// * `current_bet` - Highest bet on this street before this action (negative if none yet).
+pub fn classify_bet(amount: i32, already_in: i32, current_bet: i32) -> ActionKind {
+    let total = already_in + amount;
+    if amount == 0 {
+        ActionKind::Check
+    } else if total <= current_bet {
+        ActionKind::Call
+    } else if current_bet <= 0 {
+        ActionKind::Bet
+    } else {
+        ActionKind::Raise
+    }
+}
+
+/// Starts recording a hand, reading each player's stack from storage.
+///
+/// Also saves a new hand as the live hand for crash recovery (see `recovery.rs`);
+/// a resumed hand is already stored, and keeps its original ID.
+///
+/// # Arguments
+/// * `storage` - The server's storage backend.
+/// * `variant` - The game variant being played.
+/// * `player_names` - Players in seat order.
+/// * `shuffle_seed` - Seed the game's deck is shuffled from.
+/// * `resumed_hand_id` - ID of the hand, if it is being resumed.
+pub async fn start_hand(
+    storage: &dyn Storage,
+    variant: &str,
+    player_names: &[String],
+    shuffle_seed: u64,
+    resumed_hand_id: Option<&str>,
+) -> HandRecorder {
+    let mut seats = Vec::new();
+    for name in player_names {
+        let stack = match storage.find_player(name).await {
+            Ok(Some(doc)) => player_stack(&doc),
+            _ => 0,
+        };
+        seats.push((name.clone(), stack));
+    }
+
+    let mut recorder = HandRecorder::new(variant, shuffle_seed, &seats);
+    if let Some(hand_id) = resumed_hand_id {
+        recorder.record.hand_id = hand_id.to_string();
+        println!("[History] Resumed hand {}", hand_id);
+        return recorder;
+    }
+    println!("[History] Started hand {}", recorder.hand_id());
+    if let Err(e) = begin_live_hand(storage, recorder.hand_id(), variant, player_names, shuffle_seed).await {
+        eprintln!("[History] Failed to save live hand {}: {}", recorder.hand_id(), e);
+    }
+    recorder
+}
+
+/// Writes a completed hand to the history.
+///
+/// # Arguments
+/// * `storage` - The server's storage backend.
+/// * `record` - The finished hand.
+///
+/// # Returns
+/// Storage operation result.
+pub async fn save_hand(storage: &dyn Storage, record: &HandRecord) -> StorageResult<()> {
+    let doc = bson::to_document(record)?;
+    match storage.insert_history(doc).await {
+        Ok(()) => {
+            println!("[History] Saved hand {}", record.hand_id);
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("[History] Failed to save hand {}: {}", record.hand_id, e);
+            Err(e)
+        }
+    }
+}
+
+/// Looks up a hand in the history.
+///
+/// # Arguments
+/// * `storage` - The server's storage backend.
+/// * `hand_id` - ID of the hand.
+///
+/// # Returns
+/// The hand, or `None` if it does not exist.
+pub async fn load_hand(storage: &dyn Storage, hand_id: &str) -> StorageResult<Option<HandRecord>> {
+    match storage.find_history(hand_id).await? {
+        Some(doc) => Ok(Some(bson::from_document(doc)?)),
+        None => Ok(None),
+    }
+}
+
+/// Reads the hands a player was dealt into.
+///
+/// # Arguments
+/// * `storage` - The server's storage backend.
+/// * `player` - The player's name.
+/// * `from` - Only hands started at or after this time, if given.
+/// * `to` - Only hands started at or before this time, if given.
+///
+/// # Returns
+/// The hands, oldest first.
+pub async fn load_player_history(
+    storage: &dyn Storage,
+    player: &str,
+    from: Option<i64>,
+    to: Option<i64>,
+) -> StorageResult<Vec<HandRecord>> {
+    let docs = storage.list_player_history(player, from, to).await?;
+    Ok(docs.into_iter().map(bson::from_document).collect::<Result<_, _>>()?)
+}
+
+/// A hand as one player saw it at the table.
+///
+/// The record keeps every seat's cards, folded and mucked hands included. Only the
+/// viewer's own cards and the hands shown at showdown are left; any other card is
+/// blanked to `""`, so the seat still shows how many cards it held. Seven Card
+/// Stud's face-up cards (the 3rd to 6th) were seen by everyone and are kept.
+/// Other players' draws keep how many cards they replaced, but not which.
+/// The shuffle seed is cleared too: the deck can be rebuilt from it, card by card.
+///
+/// # Arguments
+/// * `record` - The hand from the history.
+/// * `viewer` - The player asking for it.
+pub fn seen_by(record: &HandRecord, viewer: &str) -> HandRecord {
+    let mut record = record.clone();
+    record.shuffle_seed.clear();
+    let visible = |player: &str| player == viewer || record.shown_hands.contains_key(player);
+    let hidden: Vec<String> = record.seats.iter().map(|seat| seat.player.clone()).filter(|p| !visible(p)).collect();
+    let stud = record.variant == "7card";
+
+    for seat in record.seats.iter_mut().filter(|seat| hidden.contains(&seat.player)) {
+        for (i, card) in seat.cards.iter_mut().enumerate() {
+            if !(stud && (2..6).contains(&i)) {
+                card.clear();
+            }
+        }
+    }
+    let actions = record.streets.iter_mut().flat_map(|street| &mut street.actions);
+    for action in actions.filter(|action| hidden.contains(&action.player)) {
+        action.discarded.clear();
+        action.drawn.clear();
+    }
+    record
+}
+
+/// Retrieves a hand from the history, for the `"hand_history"` command.
+///
+/// # Arguments
+/// * `storage` - The server's storage backend.
+/// * `hand_id` - ID of the hand.
+/// * `viewer` - The player asking. They see only the cards they saw at the table
+///   (see `seen_by`).
+///
+/// # Returns
+/// A `HandHistory` event with the record, or an error.
+pub async fn handle_hand_history_command(storage: &dyn Storage, hand_id: &str, viewer: &str) -> ServerEvent {
+    match load_hand(storage, hand_id).await {
+        Ok(Some(record)) => ServerEvent::HandHistory(seen_by(&record, viewer)),
+        Ok(None) => ServerEvent::error(format!("No hand found with id: {}", hand_id)),
+        Err(e) => ServerEvent::error(format!("Database error: {}", e)),
+    }
+}
@@ -0,0 +1,101 @@
+//! # Leaderboards
+//!
+//! Ranks players by their results, for the `leaderboard` command.
+//!
+//! Every finished hand leaves a timestamped result for each player in it (see
+//! `results.rs`), with the hand's variant, the chips they won and the chips they
+//! put in. These are the same amounts as the totals on the stats page, so a
+//! player's net winnings here match what their stats show, and a leaderboard can
+//! count only one variant's hands or a recent period.
+//!
+//! The results are added up per player by the storage backend, so a request
+//! reads one row per player rather than every result ever recorded.
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::results::{load_result_totals, ResultTotals};
+use crate::storage::Storage;
+use protocol::{LeaderboardEntry, LeaderboardPage, LeaderboardPeriod, LeaderboardSort, ServerEvent};
+
+/// Players per page when the client does not say.
+pub const DEFAULT_PAGE_SIZE: usize = 10;
+
+/// Most players sent in one page.
+pub const MAX_PAGE_SIZE: usize = 50;
+
+/// Ranks every player with a result.
+///
+/// # Arguments
+/// * `totals` - Each player's totals, already filtered by variant and period.
+/// * `sort` - What to rank by, highest first. Ties are broken by name.
+///
+/// # Returns
+/// Every player, ranked from 1.
+pub fn rank_players(totals: &[ResultTotals], sort: LeaderboardSort) -> Vec<LeaderboardEntry> {
+    let mut entries: Vec<LeaderboardEntry> = totals
+        .iter()
+        .map(|total| LeaderboardEntry {
+            name: total.player.clone(),
+            net_winnings: (total.money_win + total.money_lost) as i32,
+            wins: total.wins as i32,
+            games_played: total.games_played as i32,
+            biggest_pot: total.biggest_pot as i32,
+            ..LeaderboardEntry::default()
+        })
+        .collect();
+    for entry in &mut entries {
+        entry.win_rate = entry.wins as f64 / entry.games_played.max(1) as f64;
+    }
+    entries.sort_by(|a, b| {
+        let by_sort = match sort {
+            LeaderboardSort::NetWinnings => b.net_winnings.cmp(&a.net_winnings),
+            LeaderboardSort::WinRate => b.win_rate.total_cmp(&a.win_rate),
+            LeaderboardSort::GamesPlayed => b.games_played.cmp(&a.games_played),
+            LeaderboardSort::BiggestPot => b.biggest_pot.cmp(&a.biggest_pot),
+        };
+        by_sort.then_with(|| a.name.cmp(&b.name))
+    });
+    for (i, entry) in entries.iter_mut().enumerate() {
+        entry.rank = i + 1;
+    }
+    entries
+}
+
+/// Builds a page of the leaderboard, for the `"leaderboard"` command.
+///
+/// # Arguments
+/// * `storage` - The server's storage backend.
+/// * `sort` - What to rank by.
+/// * `variant` - Only count this variant's hands, if given.
+/// * `period` - Only count hands from this recent period.
+/// * `page` - Page number, starting at 1.
+/// * `page_size` - Players per page, up to `MAX_PAGE_SIZE`.
+///
+/// # Returns
+/// A `Leaderboard` event with the page, or an error.
+pub async fn handle_leaderboard_command(
+    storage: &dyn Storage,
+    sort: LeaderboardSort,
+    variant: Option<&str>,
+    period: LeaderboardPeriod,
+    page: usize,
+    page_size: Option<usize>,
+) -> ServerEvent {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64;
+    let since = period.seconds().map(|seconds| now - seconds);
+    let totals = match load_result_totals(storage, variant, since).await {
+        Ok(totals) => totals,
+        Err(e) => return ServerEvent::error(format!("Database error: {}", e)),
+    };
+    let ranked = rank_players(&totals, sort);
+
+    let page = page.max(1);
+    let page_size = page_size.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    ServerEvent::Leaderboard(LeaderboardPage {
+        sort,
+        variant: variant.map(str::to_string),
+        period,
+        page,
+        page_size,
+        total_players: ranked.len(),
+        entries: ranked.into_iter().skip((page - 1).saturating_mul(page_size)).take(page_size).collect(),
+    })
+}
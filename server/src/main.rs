@@ -2,23 +2,51 @@
 //!
 //! This is the main entry point for the server application.
 //! It manages:
-//! - Client connections over TCP
-//! - User registration and login
-//! - Lobby and player management
+//! - Client connections over TCP, optionally encrypted with TLS
+//! - A WebSocket gateway for browser clients, speaking the same protocol
+//! - A protocol version handshake that turns away incompatible clients
+//! - User registration and login, with session tokens binding each connection to a user
+//! - Rate limits on messages and login attempts, with temporary bans for offenders
+//! - Lobby and player management, including players who disconnect mid-game
 //! - Game variant selection and game start
 //! - Command handling from clients
+//! - An admin console on standard input for the operator
+//! - Graceful shutdown that finishes or voids the hand in progress
+//! - Crash recovery: a hand cut short is resumed when the server restarts
 //!
-//! The server uses Tokio for asynchronous operations and MongoDB for persistent player data storage.
+//! The server uses Tokio for asynchronous operations and a pluggable storage backend
+//! (MongoDB by default, SQLite, or in-memory) for persistent player data storage.
 //! Supported game modes include:
 //! - 5 Card Draw
 //! - 7 Card Stud
 //! - Texas Hold'em
 //!
-//! The server starts by setting game configuration, binding to port 8080, and
+//! The server starts by loading `dealer.toml` (see `config.rs`), setting game configuration, binding to port 8080, and
 //! waits for player connections. Once the configured number of players join,
 //! the selected game variant is launched in a separate async task.
 
+mod admin;
+mod config;
 mod db;
+mod departures;
+mod hand_events;
+mod hand_machine;
+mod hand_stats;
+mod history;
+mod hand_export;
+mod leaderboard;
+mod storage;
+mod memory_storage;
+mod mongo_storage;
+mod sqlite_storage;
+mod metrics;
+mod rate_limit;
+mod recovery;
+mod results;
+mod session;
+mod shutdown;
+mod tls;
+mod websocket;
 mod user_info;
 mod comms;
 mod five_card_game;
@@ -30,249 +58,505 @@ mod texas_game;
 mod seven_card_game;
 
 use std::{
-    collections::HashMap,
-    io::ErrorKind,
-    net::TcpListener,
-    sync::mpsc::{self, Sender},
+    collections::{HashMap, HashSet},
     sync::{Arc, Mutex},
-    thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
-use mongodb::bson::doc;
-use serde_json::Value;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::{self, UnboundedSender};
+use protocol::{handshake, ClientCommand, ServerEvent};
 use std::sync::OnceLock;
+use admin::AdminCommand;
+use config::ServerConfig;
 use db::*;
+use session::*;
+use storage::open_storage;
 use user_info::*;
 use comms::*;
 use five_card_game::*;
 use texas_game::*;
 use seven_card_game::*;
 
+/// Server settings loaded from the config file at startup.
+static CONFIG: OnceLock<ServerConfig> = OnceLock::new();
 /// Number of players in the game, set at startup.
 static NUM_PLAYERS: OnceLock<usize> = OnceLock::new();
 /// Selected game variant, set at startup.
 static GAME_VARIANT: OnceLock<String> = OnceLock::new();
-/// Map of active game players and their socket addresses.
-static GAME_PLAYERS: OnceLock<Arc<Mutex<HashMap<String, std::net::SocketAddr>>>> = OnceLock::new();
-
-/// Buffer size for incoming messages.
-const MSG_SIZE: usize = 2048;
+/// Usernames of the players seated at the table. Their connections come from
+/// their sessions in `SESSIONS`.
+static GAME_PLAYERS: OnceLock<Arc<Mutex<HashSet<String>>>> = OnceLock::new();
+/// Live sessions and the connections bound to them.
+static SESSIONS: OnceLock<Arc<Mutex<Sessions>>> = OnceLock::new();
 
 #[derive(Debug)]
 struct ClientInfo {
+    #[allow(dead_code)]
     addr: std::net::SocketAddr,
-    sender: Sender<String>, // for future broadcasting
+    sender: UnboundedSender<String>, // for future broadcasting
 }
 /// Main function to start the server.
 ///
-/// - Initializes game configuration.
-/// - Connects to MongoDB.
+/// - Loads the server config file and initializes game configuration, or resumes
+///   a hand cut short by a crash (see `recovery.rs`).
+/// - Runs the hand history export instead when started as `dealer export ...`.
+/// - Opens the configured storage backend.
 /// - Starts TCP listener on port 8080.
-/// - Spawns new thread for each client.
+/// - Spawns a task to accept clients, and one task for each client.
+/// - Starts the WebSocket listener (port 8081 by default) unless disabled.
+/// - Serves Prometheus metrics on localhost (port 8082 by default) unless disabled.
+/// - Requires a protocol `hello` on each connection before anything else.
 /// - Handles client commands (register, login, ready, etc.).
+/// - Runs admin console commands typed on standard input.
+/// - Shuts down gracefully on `shutdown`, Ctrl+C or `SIGTERM` (see `shutdown.rs`).
 #[tokio::main]
 async fn main() {
-    setup_game_config();
+    let config = ServerConfig::load().unwrap_or_else(|e| {
+        eprintln!("[Server] {}", e);
+        std::process::exit(1);
+    });
+    let config = CONFIG.get_or_init(|| config);
+
+    // `dealer export <format> <file> [hand_id...]` writes hand history and exits
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("export") {
+        let storage = open_storage(&config.storage)
+            .await
+            .expect("Failed to open storage backend");
+        match hand_export::run_export_cli(&*storage, &args[1..]).await {
+            Ok(message) => println!("[Export] {}", message),
+            Err(e) => {
+                eprintln!("[Export] {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
 
-    let player_list = Arc::new(Mutex::new(HashMap::new()));
+    let player_list = Arc::new(Mutex::new(HashSet::new()));
     GAME_PLAYERS.set(Arc::clone(&player_list)).unwrap();
+    let sessions = Arc::new(Mutex::new(Sessions::default()));
+    SESSIONS.set(Arc::clone(&sessions)).unwrap();
+
+    let storage = open_storage(&config.storage)
+        .await
+        .expect("Failed to open storage backend");
+    let storage = metrics::MeteredStorage::wrap(storage);
+
+    // A hand cut short by a crash is resumed; otherwise the operator sets up a new game
+    let live_hand = match recovery::load_live_hand(&*storage).await {
+        Ok(Some(hand)) => match recovery::Replay::new(&hand) {
+            Some(replay) => {
+                println!("[Recovery] Found unfinished hand {} with {} recorded action(s)", hand.hand_id, hand.actions.len());
+                Some((hand, replay))
+            }
+            None => {
+                eprintln!("[Recovery] Hand {} has an invalid shuffle seed and cannot be resumed", hand.hand_id);
+                None
+            }
+        },
+        Ok(None) => None,
+        Err(e) => {
+            eprintln!("[Recovery] Failed to load the live hand: {}", e);
+            None
+        }
+    };
+    match &live_hand {
+        Some((hand, _)) => {
+            println!("[Recovery] Resuming hand {} ({}, {} players)", hand.hand_id, hand.variant, hand.players.len());
+            NUM_PLAYERS.set(hand.players.len()).unwrap();
+            GAME_VARIANT.set(hand.variant.clone()).unwrap();
+            if let Err(e) = refund_folded_charges(&*storage).await {
+                eprintln!("[Recovery] Failed to take back the hand's results: {}", e);
+            }
+            player_list.lock().unwrap().extend(hand.players.iter().cloned());
+            let sitting_out = sitting_out_players(&*storage, &hand.players).await;
+            let _ = update_lobby(&*storage, &hand.variant, hand.players.len(), &hand.players, &sitting_out).await;
+        }
+        None => {
+            setup_game_config();
+            let _ = init_game_state(&*storage).await;
+            let _ = update_lobby(&*storage, GAME_VARIANT.get().unwrap(), *NUM_PLAYERS.get().unwrap(), &[], &[]).await;
+        }
+    }
 
-    let (players_collection, lobbies_collection, games_collection, history_collection) =
-        init_db().await.expect("Failed to connect to MongoDB");
-    let players_collection = Arc::new(players_collection);
-    let lobbies_collection = Arc::new(lobbies_collection);
-    let games_collection = Arc::new(games_collection);
-    let history_collection = Arc::new(history_collection);
-    let _ = init_game_state(&games_collection).await;
-
-    println!("[Server] Connected to MongoDB and initialized collections.");
+    println!("[Server] Connected to {} storage and initialized collections.", storage.backend_name());
 
     let server_ip = get_local_ip().unwrap_or_else(|| "127.0.0.1".to_string());
     let server_addr = format!("{}:8080", server_ip);
-    let server = TcpListener::bind("0.0.0.0:8080").expect("Listener failed to bind");
-    server.set_nonblocking(true).expect("Failed to initialize non-blocking");
+    let tls_config = if config.tls.enabled {
+        match tls::server_config(&config.tls) {
+            Ok(tls_config) => Some(tls_config),
+            Err(e) => {
+                eprintln!("[TLS] {}", e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
 
-    let clients: Arc<Mutex<HashMap<std::net::SocketAddr, ClientInfo>>> = Arc::new(Mutex::new(HashMap::new()));
-    let (server_tx, server_rx) = mpsc::channel::<(std::net::SocketAddr, String)>();
+    let listener = TcpListener::bind("0.0.0.0:8080").await.expect("Listener failed to bind");
 
-    println!("Server listening on {}", server_addr);
+    let clients: Arc<Mutex<HashMap<std::net::SocketAddr, ClientInfo>>> = Arc::new(Mutex::new(HashMap::new()));
+    let (server_tx, mut server_rx) = mpsc::unbounded_channel::<(std::net::SocketAddr, ClientEvent)>();
+    let mut limiter = rate_limit::RateLimiter::new(config.limits.clone());
+    // Connections that have completed the protocol handshake
+    let mut greeted: HashSet<std::net::SocketAddr> = HashSet::new();
+    let (admin_tx, mut admin_rx) = mpsc::unbounded_channel::<AdminCommand>();
+    // The running game, and whether a shutdown is under way
+    let mut game_task: Option<tokio::task::JoinHandle<()>> = None;
+    let mut shutting_down = false;
+    let (shutdown_done_tx, mut shutdown_done_rx) = mpsc::unbounded_channel::<()>();
+    // Replies to logins, which are checked in their own tasks since Argon2 is slow
+    let (login_tx, mut login_rx) = mpsc::unbounded_channel::<(std::net::SocketAddr, String, ServerEvent)>();
+
+    println!("Server listening on {}{}", server_addr, if tls_config.is_some() { " (TLS)" } else { "" });
+
+    let tls_acceptor = tls_config.map(tokio_rustls::TlsAcceptor::from);
+    if config.websocket.enabled {
+        let ws_listener = TcpListener::bind(("0.0.0.0", config.websocket.port))
+            .await
+            .expect("WebSocket listener failed to bind");
+        println!(
+            "[WebSocket] Listening on {}://{}:{}",
+            if tls_acceptor.is_some() { "wss" } else { "ws" },
+            server_ip,
+            config.websocket.port
+        );
+        tokio::spawn(websocket::accept_websocket_clients(
+            ws_listener,
+            Arc::clone(&clients),
+            server_tx.clone(),
+            tls_acceptor.clone(),
+        ));
+    }
+    tokio::spawn(accept_clients(listener, Arc::clone(&clients), server_tx.clone(), tls_acceptor));
+    if config.metrics.enabled {
+        let metrics_listener = TcpListener::bind(("127.0.0.1", config.metrics.port))
+            .await
+            .expect("Metrics listener failed to bind");
+        println!("[Metrics] Serving metrics on http://127.0.0.1:{}/metrics", config.metrics.port);
+        tokio::spawn(metrics::serve_metrics(metrics_listener, Arc::clone(&clients)));
+    }
+    if let Some((hand, replay)) = live_hand {
+        game_task = Some(spawn_game(Arc::clone(&clients), hand.players.clone(), Arc::clone(&storage), Some(replay)));
+        for (seat, player) in hand.players.iter().enumerate() {
+            departures::hold_seat(player, recovery::placeholder_addr(seat + 1), &server_tx);
+        }
+    }
+    shutdown::forward_signals(admin_tx.clone());
+    admin::spawn_console(admin_tx);
 
+    // Process messages received from clients and the admin console, one at a time
     loop {
-        // Accept new clients
-        match server.accept() {
-            Ok((socket, addr)) => {
-                println!("Client connected: {}", addr);
-
-                let (client_tx, client_rx) = mpsc::channel::<String>();
-                let server_tx_clone = server_tx.clone();
-                let clients_clone = Arc::clone(&clients);
-
-                // Register the client
-                clients.lock().unwrap().insert(
-                    addr,
-                    ClientInfo {
-                        addr,
-                        sender: client_tx.clone(),
-                    },
-                );
-                println!(
-                    "[Server] Inserting client: addr = {}, sender = mpsc::Sender", addr);
-
-                // Handle client in separate thread
-                thread::spawn(move || handle_client(socket, addr, server_tx_clone, client_rx));
-            }
-            Err(ref err) if err.kind() == ErrorKind::WouldBlock => {} // no new connections
-            Err(e) => {
-                eprintln!("Error accepting client: {}", e);
+        let (addr, event) = tokio::select! {
+            received = server_rx.recv() => match received {
+                Some(received) => received,
+                None => break,
+            },
+            Some(command) = admin_rx.recv() => {
+                if command == AdminCommand::Shutdown && shutting_down {
+                    println!("[Shutdown] Already shutting down");
+                } else if command == AdminCommand::Shutdown {
+                    println!("[Shutdown] Shutting down; no new games will start");
+                    shutting_down = true;
+                    let finish = shutdown::finish_hand(
+                        game_task.take(),
+                        Arc::clone(&storage),
+                        Arc::clone(&clients),
+                        Duration::from_secs(config.shutdown.hand_timeout_secs),
+                    );
+                    let shutdown_done_tx = shutdown_done_tx.clone();
+                    tokio::spawn(async move {
+                        finish.await;
+                        let _ = shutdown_done_tx.send(());
+                    });
+                } else {
+                    admin::run_command(command, &*storage, &clients, &sessions).await;
+                }
+                continue;
             }
-        }
+            Some(()) = shutdown_done_rx.recv() => break,
+            Some((addr, username, response)) = login_rx.recv() => {
+                if matches!(response, ServerEvent::LoggedIn { .. }) {
+                    send_to_client(&clients, &addr, &response);
+                } else if let Err(reason) = limiter.record_login_failure(&addr, &username, Instant::now()) {
+                    println!("[Limits] Disconnecting {}: {}", addr, reason);
+                    disconnect_client(&clients, &addr, &ServerEvent::error(reason));
+                } else {
+                    send_to_client(&clients, &addr, &response);
+                }
+                continue;
+            }
+        };
+        let msg = match event {
+            ClientEvent::Message(msg) => msg,
+            ClientEvent::Disconnected => {
+                println!("[Server] {} disconnected", addr);
+                greeted.remove(&addr);
+                let username = {
+                    let mut sessions = sessions.lock().unwrap();
+                    let username = sessions.user_for(&addr);
+                    sessions.unbind(&addr);
+                    username
+                };
+                limiter.forget(&addr);
+                departures::handle_disconnect(&*storage, &clients, addr, username, &server_tx).await;
+                continue;
+            }
+            ClientEvent::GraceExpired(username) => {
+                departures::handle_grace_expired(&*storage, &clients, &username, addr).await;
+                continue;
+            }
+        };
+        println!("[{}] {}", addr, redact_secrets(&msg));
 
-        // Process messages received from clients
-        match server_rx.try_recv() {
-            Ok((addr, msg)) => {
-                println!("[{}] {}", addr, msg);
+        let session_user = sessions.lock().unwrap().user_for(&addr);
+        if let Err(reason) = limiter.check_message(&addr, session_user.as_deref(), Instant::now()) {
+            println!("[Limits] Disconnecting {}: {}", addr, reason);
+            disconnect_client(&clients, &addr, &ServerEvent::error(reason));
+            continue;
+        }
 
-                // Parse and respond to commands (simplified example)
-                if let Ok(json) = serde_json::from_str::<Value>(&msg) {
-                    if let Some(cmd) = json.get("command").and_then(|v| v.as_str()) {
-                        match cmd {
-                            "register" => {
-                                println!("{} is registering", addr);
-                                let response = handle_registration(&players_collection, &msg).await;
-                                send_to_client(&clients, &addr, &response);
+        match ClientCommand::from_json(&msg) {
+            // Clients must say hello first. Anything else, including messages from
+            // builds that predate the handshake, is told to upgrade.
+            Ok(ClientCommand::Hello { version, capabilities }) => {
+                let response = handshake::negotiate(version, &capabilities, handshake::CAPABILITIES);
+                if matches!(response, ServerEvent::Welcome { .. }) {
+                    println!("[Handshake] {} speaks protocol version {}", addr, version);
+                    greeted.insert(addr);
+                } else {
+                    println!("[Handshake] Rejected {}: protocol version {}", addr, version);
+                    greeted.remove(&addr);
+                }
+                send_to_client(&clients, &addr, &response);
+            }
+            _ if !greeted.contains(&addr) => {
+                send_to_client(&clients, &addr, &handshake::handshake_required());
+            }
+            Err(e) => {
+                send_to_client(&clients, &addr, &ServerEvent::error(format!("Invalid command: {}", e)));
+            }
+            Ok(command) => {
+                // Everything except signing in needs a session. Commands that act for a
+                // player run as the user bound to this connection, and may not name anyone else.
+                let identity_error = session_user
+                    .as_deref()
+                    .and_then(|user| check_identity(&command, user).err());
+
+                match &command {
+                    // Answered before the connection reaches here
+                    ClientCommand::Hello { .. } => {}
+                    // Both hash a password, so they run in their own tasks and the
+                    // table carries on meanwhile
+                    ClientCommand::Register { .. } => {
+                        println!("{} is registering", addr);
+                        let (storage, clients) = (Arc::clone(&storage), Arc::clone(&clients));
+                        tokio::spawn(async move {
+                            let response = handle_registration(&*storage, &msg).await;
+                            send_to_client(&clients, &addr, &response);
+                        });
+                    }
+                    ClientCommand::Login { username, .. } => {
+                        // Banned accounts, and accounts with too many failed logins, are
+                        // turned away before the password is checked
+                        if let Err(reason) = limiter.check_login(username, Instant::now()) {
+                            println!("[Limits] Refused login for {} from {}: {}", username, addr, reason);
+                            send_to_client(&clients, &addr, &ServerEvent::error(reason));
+                            continue;
+                        }
+                        println!("{} is logging in", addr);
+                        let (storage, sessions, login_tx) = (Arc::clone(&storage), Arc::clone(&sessions), login_tx.clone());
+                        let username = username.clone();
+                        tokio::spawn(async move {
+                            let response = handle_login(&*storage, &sessions, &msg).await;
+                            let _ = login_tx.send((addr, username, response));
+                        });
+                    }
+                    ClientCommand::Bind { token } => {
+                        let owner = sessions.lock().unwrap().owner(token);
+                        if let Some(remaining) = owner.and_then(|owner| limiter.account_ban_remaining(&owner, Instant::now())) {
+                            send_to_client(&clients, &addr, &ServerEvent::error(rate_limit::banned_message(remaining)));
+                            continue;
+                        }
+                        let bound = sessions.lock().unwrap().bind(addr, token);
+                        match bound {
+                            Some(username) => {
+                                // A seated player on a new connection is reached there from now on,
+                                // and catches up on the hand if the game has started
+                                let (seated, game_started, players) = {
+                                    let game_players = GAME_PLAYERS.get().unwrap().lock().unwrap();
+                                    let seated = game_players.contains(&username);
+                                    let game_started = game_players.len() == *NUM_PLAYERS.get().unwrap();
+                                    (seated, game_started, game_players.iter().cloned().collect::<Vec<String>>())
+                                };
+                                if seated {
+                                    departures::handle_return(&*storage, &clients, &username).await;
+                                }
+                                if seated && game_started {
+                                    println!("[Game] Sending {} a snapshot of the hand", username);
+                                    let snapshot = handle_snapshot(&*storage, &players, &username).await;
+                                    send_to_client(&clients, &addr, &snapshot);
+                                } else if seated {
+                                    send_to_client(&clients, &addr, &ServerEvent::info(format!("Welcome back {}, you still hold your seat.", username)));
+                                }
                             }
-                            "login" => {
-                                println!("{} is logging in", addr);
-                                let response = handle_login(&players_collection, &msg).await;
-                                send_to_client(&clients, &addr, &response);
+                            None => {
+                                send_to_client(&clients, &addr, &ServerEvent::error("Invalid session. Please log in again."));
                             }
-                            "ready" => {
-                                if let Some(json) = serde_json::from_str::<Value>(&msg).ok() {
-                                    if let Some(username) = json.get("username").and_then(|v| v.as_str()) {
-
-                                        let players = GAME_PLAYERS.get().unwrap();
-                                        let mut game_players = players.lock().unwrap();
-                                        let max_players = *NUM_PLAYERS.get().unwrap();
-
-                                        if game_players.len() < max_players {
-                                            if !game_players.contains_key(&username.to_string()) {
-                                                game_players.insert(username.to_string(), addr);
-                                                println!("[Game] {} added to game player list.", username);
-                                                send_to_client(&clients, &addr, &format!("Welcome {}, you are now in the game.", username));
-
-                                                if game_players.len() == max_players {
-                                                    println!("[Game] All players joined. Spawning game thread...");
-                                                
-                                                    let game_clients = Arc::clone(&clients);
-                                                    let players_for_game = game_players.keys().cloned().collect();
-                                                    
-                                                    let pc = Arc::clone(&players_collection);
-                                                    let lc = Arc::clone(&lobbies_collection);
-                                                    let gc = Arc::clone(&games_collection);
-                                                    let hc = Arc::clone(&history_collection);
-                                                    
-                                                    println!("[Game] Spawning game thread now...");
-                                                    let variant = GAME_VARIANT.get().unwrap().clone();
-
-                                                    println!("[Game] Selected variant: {}", variant);
-                                                    tokio::spawn(async move {
-                                                        match variant.as_str() {
-                                                            "5card" => {
-                                                                println!("[Game] Running 5 Card Draw");
-                                                                run_five_card_game(game_clients, players_for_game, pc, lc, gc, hc).await;
-                                                            }
-                                                            "7card" => {
-                                                                println!("[Game] Running 7 Card Stud");
-                                                                run_seven_card_game(game_clients, players_for_game, pc, lc, gc, hc).await;
-                                                            }
-                                                            "texas" => {
-                                                                println!("[Game] Running Texas Hold'em");
-                                                                run_texas_game(game_clients, players_for_game, pc, lc, gc, hc).await;
-                                                            }
-                                                            _ => {
-                                                                eprintln!("[Game] Unknown game variant selected.");
-                                                            }
-                                                        }
-                                                    });
-                                                    
-                                                }
-                                            } else {
-                                                send_to_client(&clients, &addr, "You are already in the game.");
-                                            }
-                                        } else {
-                                            println!("[Game] Game full, rejecting player: {}", username);
-                                            send_to_client(&clients, &addr, "Game is full. You are logged in but not in the game.");
-                                        }
-
-                                        println!("[Debug] Max players allowed: {}", max_players);
-                                        println!("[Debug] Current number of players: {}", game_players.len());
-
-                                        // Debug: Print each player and their address
-                                        for (username, addr) in game_players.iter() {
-                                            println!("[Debug] Player: {}, Address: {}", username, addr);
+                        }
+                    }
+                    _ if session_user.is_none() => {
+                        send_to_client(&clients, &addr, &ServerEvent::error("Not logged in."));
+                    }
+                    _ if identity_error.is_some() => {
+                        send_to_client(&clients, &addr, &ServerEvent::error(identity_error.clone().unwrap_or_default()));
+                    }
+                    ClientCommand::Ready { .. } if shutting_down => {
+                        send_to_client(&clients, &addr, &ServerEvent::error("The server is shutting down. No new games are starting."));
+                    }
+                    ClientCommand::Ready { .. } => {
+                        if let Some(username) = session_user.as_deref() {
+                            let players = GAME_PLAYERS.get().unwrap();
+                            let max_players = *NUM_PLAYERS.get().unwrap();
+                            let lobby_players: Vec<String> = {
+                                let mut game_players = players.lock().unwrap();
+
+                                if game_players.len() < max_players {
+                                    if game_players.insert(username.to_string()) {
+                                        println!("[Game] {} added to game player list.", username);
+                                        send_to_client(&clients, &addr, &ServerEvent::info(format!("Welcome {}, you are now in the game.", username)));
+
+                                        if game_players.len() == max_players {
+                                            println!("[Game] All players joined. Spawning game thread...");
+                                
+                                            let game_clients = Arc::clone(&clients);
+                                            let players_for_game = game_players.iter().cloned().collect();
+                                    
+                                            let game_storage = Arc::clone(&storage);
+                                    
+                                            println!("[Game] Spawning game thread now...");
+                                            game_task = Some(spawn_game(game_clients, players_for_game, game_storage, None));
+                                    
                                         }
+                                    } else {
+                                        send_to_client(&clients, &addr, &ServerEvent::info("You are already in the game."));
                                     }
-                                }
-                            }
-                            "stats" => { 
-                                println!("Showing Stats"); 
-                                let response = handle_stats(&players_collection).await;
-                                send_to_client(&clients, &addr, &response);
-
-                            }
-                            "get_user_stats" => {
-                                println!("Getting User Stats");
-                                if let Some(username) = json.get("username").and_then(|v| v.as_str()) {
-                                    // Note: `players_collection` is an Arc. We pass a reference to the inner collection.
-                                    let response = get_user_stats(&*players_collection, username).await;
-                                    send_to_client(&clients, &addr, &response);
                                 } else {
-                                    send_to_client(&clients, &addr, "Username not provided.");
+                                    println!("[Game] Game full, rejecting player: {}", username);
+                                    send_to_client(&clients, &addr, &ServerEvent::error("Game is full. You are logged in but not in the game."));
                                 }
+
+                                game_players.iter().cloned().collect()
+                            };
+
+                            if lobby_players.iter().any(|player| player == username) {
+                                // Clear a sit-out left over from an earlier game
+                                let _ = set_sitting_out(&*storage, username, false).await;
                             }
-                            "bet" => {
-                                let username = json.get("username").and_then(|v| v.as_str()).unwrap_or("");
-                                let amount = json.get("amount").and_then(|v| v.as_i64()).unwrap_or(0) as i32;
-
-                                let filter = doc! { "name": username };
-                                let update = doc! {
-                                    "$set": {
-                                        "bet": amount,
-                                        "bet_turn": false
-                                    }
-                                };
-                                let _ = players_collection.update_one(filter, update).await;
-                            }
-                            "swap" => {
-                                let username = json.get("username").and_then(|v| v.as_str()).unwrap_or("");
-                                let swap_str = json.get("indices").and_then(|v| v.as_str()).unwrap_or("");
-
-                                let filter = doc! { "name": username };
-                                let update = doc! {
-                                    "$set": {
-                                        "swap": swap_str,
-                                        "swap_turn": false
-                                    }
-                                };
-                                let _ = players_collection.update_one(filter, update).await;
-                            }
-                            "spectate" => {
-                                println!("{} requested spectate", addr);
-                                let response = handle_spectate_command(&games_collection).await;
-                                send_to_client(&clients, &addr, &response);
-                            }
-                            _ => {
-                                send_to_client(&clients, &addr, "Unknown command.");
+                            let sitting_out = sitting_out_players(&*storage, &lobby_players).await;
+                            if let Err(e) = update_lobby(&*storage, GAME_VARIANT.get().unwrap(), max_players, &lobby_players, &sitting_out).await {
+                                eprintln!("[DB] Failed to save lobby: {}", e);
                             }
                         }
                     }
+                    ClientCommand::Stats => {
+                        println!("Showing Stats");
+                        let response = handle_stats(&*storage).await;
+                        send_to_client(&clients, &addr, &response);
+                    }
+                    ClientCommand::GetUserStats { username, from, to } => {
+                        println!("Getting User Stats");
+                        let response = get_user_stats(&*storage, username, *from, *to).await;
+                        send_to_client(&clients, &addr, &response);
+                    }
+                    ClientCommand::Leaderboard { sort, variant, period, page, page_size } => {
+                        let response = leaderboard::handle_leaderboard_command(
+                            &*storage, *sort, variant.as_deref(), *period, *page, *page_size,
+                        ).await;
+                        send_to_client(&clients, &addr, &response);
+                    }
+                    ClientCommand::Bet { .. } | ClientCommand::Swap { .. } if table_paused() => {
+                        send_to_client(&clients, &addr, &ServerEvent::error("The table is paused. Wait for the operator to resume play."));
+                    }
+                    ClientCommand::Bet { amount, .. } => {
+                        let username = session_user.as_deref().unwrap_or("");
+                        let _ = submit_player_bet(&*storage, username, *amount).await;
+                    }
+                    ClientCommand::Swap { indices, .. } => {
+                        let username = session_user.as_deref().unwrap_or("");
+                        let _ = submit_player_swap(&*storage, username, indices).await;
+                    }
+                    ClientCommand::HandHistory { hand_id } => {
+                        let viewer = session_user.as_deref().unwrap_or("");
+                        let response = history::handle_hand_history_command(&*storage, hand_id, viewer).await;
+                        send_to_client(&clients, &addr, &response);
+                    }
+                    ClientCommand::ExportHands { format, hand_ids, page } => {
+                        let viewer = session_user.as_deref().unwrap_or("");
+                        let response = hand_export::handle_export_command(&*storage, format.as_deref(), hand_ids, *page, viewer).await;
+                        send_to_client(&clients, &addr, &response);
+                    }
+                    ClientCommand::Lobby => {
+                        let response = handle_lobby_command(&*storage).await;
+                        send_to_client(&clients, &addr, &response);
+                    }
+                    ClientCommand::Spectate => {
+                        println!("{} requested spectate", addr);
+                        let response = handle_spectate_command(&*storage).await;
+                        send_to_client(&clients, &addr, &response);
+                    }
                 }
             }
-            Err(_) => {}
         }
-
-        thread::sleep(Duration::from_millis(100));
     }
+
+    shutdown::close_connections(&clients).await;
+    println!("[Shutdown] Server stopped");
+}
+
+/// Runs the selected game variant in its own task, and clears the live hand (see
+/// `recovery.rs`) when the game ends.
+///
+/// # Arguments
+/// * `clients` - Shared list of connected clients.
+/// * `players` - Players in seat order.
+/// * `storage` - The server's storage backend.
+/// * `replay` - The hand cut short by a crash, to play again; `None` for a new hand.
+fn spawn_game(
+    clients: Arc<Mutex<HashMap<std::net::SocketAddr, ClientInfo>>>,
+    players: Vec<String>,
+    storage: Arc<dyn storage::Storage>,
+    replay: Option<recovery::Replay>,
+) -> tokio::task::JoinHandle<()> {
+    let variant = GAME_VARIANT.get().unwrap().clone();
+    println!("[Game] Selected variant: {}", variant);
+    tokio::spawn(async move {
+        let _table = metrics::METRICS.table_opened();
+        let game_storage = Arc::clone(&storage);
+        match variant.as_str() {
+            "5card" => {
+                println!("[Game] Running 5 Card Draw");
+                run_five_card_game(clients, players, game_storage, replay).await;
+            }
+            "7card" => {
+                println!("[Game] Running 7 Card Stud");
+                run_seven_card_game(clients, players, game_storage, replay).await;
+            }
+            "texas" => {
+                println!("[Game] Running Texas Hold'em");
+                run_texas_game(clients, players, game_storage, replay).await;
+            }
+            _ => {
+                eprintln!("[Game] Unknown game variant selected.");
+            }
+        }
+        if let Err(e) = recovery::end_live_hand(&*storage).await {
+            eprintln!("[DB] Failed to clear the live hand: {}", e);
+        }
+    })
 }
 
 /// Attempts to determine the local IP address of the machine by creating a UDP socket
@@ -346,37 +630,22 @@ fn setup_game_config() {
 
 
 
-// cargo test -- --test-threads=1
-// cargo test -- --test-threads=1
-// cargo test -- --test-threads=1
-// cargo test -- --test-threads=1
-
-
 #[cfg(test)]
 mod test {
-    use super::*;  
-    use mongodb::{
-        bson::{doc, Document},
-        options::{ClientOptions, ServerApi, ServerApiVersion},
-        Client, Collection,
-    };
+    use super::*;
+    use mongodb::bson::doc;
     use rand::{distributions::Alphanumeric, Rng};
-    use tokio::runtime::Runtime;
-
-    /// test collection
-    async fn get_test_players_collection() -> Collection<Document> {
-        let uri = "mongodb://localhost:27017";
-        let mut client_options = ClientOptions::parse(uri).await.unwrap();
-        let server_api = ServerApi::builder().version(ServerApiVersion::V1).build();
-        client_options.server_api = Some(server_api);
-
-        let client = Client::with_options(client_options).unwrap();
-        let db = client.database("test_server");
-
-        // clear players collection before each test
-        let coll = db.collection::<Document>("players");
-        coll.delete_many(doc! {}).await.unwrap(); 
-        coll
+    use memory_storage::MemoryStorage;
+    use storage::Storage;
+    use hand_events::*;
+    use hand_machine::*;
+    use std::collections::BTreeMap;
+    use serde_json::Value;
+    use std::time::Duration;
+
+    /// test storage, empty for every test
+    fn get_test_storage() -> MemoryStorage {
+        MemoryStorage::new()
     }
 
     fn random_username() -> String {
@@ -391,7 +660,7 @@ mod test {
     // 1) handle_registration with a new user
     #[tokio::test]
     async fn test_handle_registration_new_user() {
-        let players_collection = get_test_players_collection().await;
+        let storage = get_test_storage();
         let username = random_username();
         let json_str = format!(r#"{{
             "command": "register",
@@ -399,14 +668,13 @@ mod test {
             "password": "mypassword"
         }}"#, username);
 
-        let result = handle_registration(&players_collection, &json_str).await;
+        let result = handle_registration(&storage, &json_str).await.to_json();
         assert!(
             result.contains("registered successfully"),
             "Registration should succeed for a new user."
         );
 
-        let filter = doc! { "name": &username };
-        let user_doc = players_collection.find_one(filter).await.unwrap();
+        let user_doc = storage.find_player(&username).await.unwrap();
         assert!(
             user_doc.is_some(),
             "User should be in DB after registration"
@@ -416,7 +684,7 @@ mod test {
     // 2) handle_registration with an existing user
     #[tokio::test]
     async fn test_handle_registration_existing_user() {
-        let players_collection = get_test_players_collection().await;
+        let storage = get_test_storage();
         let username = random_username();
 
         let json_str_1 = format!(r#"{{
@@ -424,14 +692,14 @@ mod test {
             "username": "{}",
             "password": "password123"
         }}"#, username);
-        let _ = handle_registration(&players_collection, &json_str_1).await;
+        let _ = handle_registration(&storage, &json_str_1).await;
 
         let json_str_2 = format!(r#"{{
             "command": "register",
             "username": "{}",
             "password": "newpassword"
         }}"#, username);
-        let result2 = handle_registration(&players_collection, &json_str_2).await;
+        let result2 = handle_registration(&storage, &json_str_2).await.to_json();
         assert!(
             result2.contains("already exists"),
             "Should detect duplicate registration"
@@ -441,7 +709,7 @@ mod test {
     // 3) andle_login with valid credentials
     #[tokio::test]
     async fn test_handle_login_success() {
-        let players_collection = get_test_players_collection().await;
+        let storage = get_test_storage();
         let username = random_username();
         
         let reg_json = format!(r#"{{
@@ -449,7 +717,7 @@ mod test {
             "username": "{}",
             "password": "secret"
         }}"#, username);
-        handle_registration(&players_collection, &reg_json).await;
+        handle_registration(&storage, &reg_json).await;
 
         let login_json = format!(r#"{{
             "command": "login",
@@ -457,7 +725,7 @@ mod test {
             "password": "secret"
         }}"#, username);
 
-        let result = handle_login(&players_collection, &login_json).await;
+        let result = handle_login(&storage, &Mutex::default(), &login_json).await.to_json();
         assert!(result.contains("Welcome"), "Should log in successfully");
     }
 
@@ -465,7 +733,7 @@ mod test {
     // 4) handle_login with wrong password
     #[tokio::test]
     async fn test_handle_login_wrong_password() {
-        let players_collection = get_test_players_collection().await;
+        let storage = get_test_storage();
         let username = random_username();
 
         let reg_json = format!(r#"{{
@@ -473,7 +741,7 @@ mod test {
             "username": "{}",
             "password": "realpassword"
         }}"#, username);
-        handle_registration(&players_collection, &reg_json).await;
+        handle_registration(&storage, &reg_json).await;
 
         let login_json = format!(r#"{{
             "command": "login",
@@ -481,7 +749,7 @@ mod test {
             "password": "wrongpassword"
         }}"#, username);
 
-        let result = handle_login(&players_collection, &login_json).await;
+        let result = handle_login(&storage, &Mutex::default(), &login_json).await.to_json();
         assert!(result.contains("Invalid password"), "Should reject wrong password");
     }
 
@@ -489,7 +757,7 @@ mod test {
     // 5) handle_login with nonexistent user
     #[tokio::test]
     async fn test_handle_login_user_not_found() {
-        let players_collection = get_test_players_collection().await;
+        let storage = get_test_storage();
 
         let login_json = r#"{
             "command": "login",
@@ -497,7 +765,7 @@ mod test {
             "password": "whatever"
         }"#;
 
-        let result = handle_login(&players_collection, &login_json).await;
+        let result = handle_login(&storage, &Mutex::default(), login_json).await.to_json();
         assert!(
             result.contains("No such user found"),
             "Should fail for non-existing user"
@@ -507,11 +775,12 @@ mod test {
     // 6) handle_stats when no users exist
     #[tokio::test]
     async fn test_handle_stats_no_users() {
-        let players_collection = get_test_players_collection().await;
+        let storage = get_test_storage();
 
-        let result = handle_stats(&players_collection).await;
-        assert!(
-            result.is_empty(),
+        let result = handle_stats(&storage).await;
+        assert_eq!(
+            result,
+            ServerEvent::PlayerList { players: Vec::new() },
             "Should return empty stats when no users in DB"
         );
     }
@@ -519,16 +788,16 @@ mod test {
     // 7) handle_stats with multiple users
     #[tokio::test]
     async fn test_handle_stats_with_users() {
-        let players_collection = get_test_players_collection().await;
+        let storage = get_test_storage();
         
         let user1 = random_username();
         let user2 = random_username();
 
-        players_collection.insert_one(doc! { "name": &user1, "password": "pass" }).await.unwrap();
-        players_collection.insert_one(doc! { "name": &user2, "password": "pass" }).await.unwrap();
+        storage.insert_player(doc! { "name": &user1, "password": "pass" }).await.unwrap();
+        storage.insert_player(doc! { "name": &user2, "password": "pass" }).await.unwrap();
 
-        let stats_str = handle_stats(&players_collection).await;
-        // function returns a comma-separated string with user1, user2
+        let stats_str = handle_stats(&storage).await.to_json();
+        // function returns a player_list event naming user1 and user2
         assert!(stats_str.contains(&user1), "Stats should contain first user");
         assert!(stats_str.contains(&user2), "Stats should contain second user");
     }
@@ -536,17 +805,17 @@ mod test {
     // 8) get_user_stats for an existing user
     #[tokio::test]
     async fn test_get_user_stats_found() {
-        let players_collection = get_test_players_collection().await;
+        let storage = get_test_storage();
         let username = random_username();
 
-        players_collection.insert_one(doc! {
+        storage.insert_player(doc! {
             "name": &username,
             "password": "mypassword",
             "wins": 5,
             "losses": 2
         }).await.unwrap();
 
-        let stats_json = get_user_stats(&players_collection, &username).await;
+        let stats_json = get_user_stats(&storage, &username, None, None).await.to_json();
         assert!(stats_json.contains(&username), "Should show the correct user in JSON");
         assert!(stats_json.contains("\"wins\":5"), "Should contain correct wins");
         assert!(stats_json.contains("\"losses\":2"), "Should contain correct losses");
@@ -555,9 +824,9 @@ mod test {
     // 9) get_user_stats for a nonexistent user
     #[tokio::test]
     async fn test_get_user_stats_not_found() {
-        let players_collection = get_test_players_collection().await;
+        let storage = get_test_storage();
 
-        let result = get_user_stats(&players_collection, "does_not_exist").await;
+        let result = get_user_stats(&storage, "does_not_exist", None, None).await.to_json();
         assert!(
             result.contains("No player found"),
             "Should return not-found message"
@@ -567,11 +836,11 @@ mod test {
     // 10) bet update
     #[tokio::test]
     async fn test_bet_command_sim() {
-        let players_collection = get_test_players_collection().await;
+        let storage = get_test_storage();
         let username = random_username();
 
         // Insert the user doc (simulate registration)
-        players_collection.insert_one(doc! {
+        storage.insert_player(doc! {
             "name": &username,
             "password": "securepwd",
             "bet": -1,
@@ -580,18 +849,1871 @@ mod test {
 
    
         let bet_amount = 50;
-        let filter = doc! { "name": &username };
-        let update = doc! {
-            "$set": { "bet": bet_amount, "bet_turn": false }
-        };
-        let res = players_collection.update_one(filter.clone(), update).await.unwrap();
-        assert_eq!(res.matched_count, 1, "Update should affect 1 document");
+        let matched = submit_player_bet(&storage, &username, bet_amount).await.unwrap();
+        assert!(matched, "Update should affect 1 document");
 
-        let user_doc = players_collection.find_one(filter).await.unwrap().unwrap();
+        let user_doc = storage.find_player(&username).await.unwrap().unwrap();
         let bet_in_db = user_doc.get_i32("bet").unwrap();
         let bet_turn_in_db = user_doc.get_bool("bet_turn").unwrap();
 
         assert_eq!(bet_in_db, bet_amount, "Bet amount should be updated in DB");
-        assert_eq!(bet_turn_in_db, false, "bet_turn should now be false");
+        assert!(!bet_turn_in_db, "bet_turn should now be false");
+    }
+
+    // 11) the game loop's wait picks up a bet submitted by the client
+    #[tokio::test]
+    async fn test_wait_for_player_bet() {
+        let storage = Arc::new(get_test_storage());
+        let username = random_username();
+        storage.insert_player(doc! { "name": &username, "bet": -1, "bet_turn": false }).await.unwrap();
+
+        let client_storage = Arc::clone(&storage);
+        let client_name = username.clone();
+        tokio::spawn(async move {
+            // Wait until the game loop hands over the turn, then bet
+            loop {
+                let doc = client_storage.find_player(&client_name).await.unwrap().unwrap();
+                if doc.get_bool("bet_turn").unwrap() {
+                    break;
+                }
+                tokio::task::yield_now().await;
+            }
+            submit_player_bet(&*client_storage, &client_name, 20).await.unwrap();
+        });
+
+        let bet = wait_for_player_bet(&*storage, &username).await;
+        assert_eq!(bet, Some(20), "Should return the submitted bet");
+    }
+
+    // 12) game results update winner and loser stats
+    #[tokio::test]
+    async fn test_update_game_results() {
+        let storage = get_test_storage();
+        let winner = random_username();
+        let loser = random_username();
+        for name in [&winner, &loser] {
+            let reg_json = format!(r#"{{ "username": "{}", "password": "pw" }}"#, name);
+            handle_registration(&storage, &reg_json).await;
+        }
+
+        let mut players = five_card_draw::PokerGame::new(vec![winner.clone(), loser.clone()]).current_players;
+        players[0].money_lost = 15;
+        players[1].money_lost = 10;
+        update_game_results(&storage, "5card", &winner, &players, 25).await.unwrap();
+
+        let winner_doc = storage.find_player(&winner).await.unwrap().unwrap();
+        assert_eq!(winner_doc.get_i32("wins").unwrap(), 1);
+        assert_eq!(winner_doc.get_i32("money_win").unwrap(), 25);
+        assert_eq!(winner_doc.get_i32("money_lost").unwrap(), -15);
+
+        let loser_doc = storage.find_player(&loser).await.unwrap().unwrap();
+        assert_eq!(loser_doc.get_i32("losses").unwrap(), 1);
+        assert_eq!(loser_doc.get_i32("games_played").unwrap(), 1);
+    }
+
+    // 13) spectators see the table folded from the stored hand events
+    #[tokio::test]
+    async fn test_spectate_game_state() {
+        let storage = get_test_storage();
+
+        let result = handle_spectate_command(&storage).await.to_json();
+        assert!(result.contains("not initialized"), "Should report missing game state");
+
+        init_game_state(&storage).await.unwrap();
+        append_event(&storage, HandEvent::Posted { player: "alice".into(), kind: history::ActionKind::Ante, amount: 40 }).await.unwrap();
+
+        let result = handle_spectate_command(&storage).await.to_json();
+        assert!(result.contains("\"pot\":40"), "Should show the updated pot");
+    }
+
+    // 14) in-memory increments support nested fields like MongoDB
+    #[tokio::test]
+    async fn test_memory_storage_nested_increment() {
+        let storage = get_test_storage();
+        storage.insert_player(doc! { "name": "nested", "wins": 1 }).await.unwrap();
+
+        let matched = storage
+            .increment_player_fields("nested", doc! { "wins": 2, "stats.texas.wins": 1 })
+            .await
+            .unwrap();
+        assert!(matched);
+        assert!(!storage.increment_player_fields("missing", doc! { "wins": 1 }).await.unwrap());
+
+        let doc = storage.find_player("nested").await.unwrap().unwrap();
+        assert_eq!(doc.get_i32("wins").unwrap(), 3);
+        let texas = doc.get_document("stats").unwrap().get_document("texas").unwrap();
+        assert_eq!(texas.get_i32("wins").unwrap(), 1);
+    }
+
+    // 15) config file selects the storage backend
+    #[test]
+    fn test_config_storage_backend() {
+        let config = ServerConfig::parse("[storage]\nbackend = \"memory\"").unwrap();
+        assert_eq!(config.storage.backend, config::StorageBackend::Memory);
+        assert_eq!(config.storage.mongodb_database, "dealer");
+
+        let config = ServerConfig::parse("").unwrap();
+        assert_eq!(config.storage.backend, config::StorageBackend::MongoDb);
+
+        let config = ServerConfig::parse("[storage]\nbackend = \"sqlite\"\nsqlite_path = \"home.db\"").unwrap();
+        assert_eq!(config.storage.backend, config::StorageBackend::Sqlite);
+        assert_eq!(config.storage.sqlite_path, "home.db");
+    }
+
+    // 16) SQLite backend migrates its schema and stores players
+    #[tokio::test]
+    async fn test_sqlite_storage_players() {
+        let storage = sqlite_storage::SqliteStorage::open_in_memory().unwrap();
+        assert_eq!(storage.schema_version().unwrap(), 7, "Should apply all migrations");
+
+        let reg_json = r#"{ "username": "sqlite_user", "password": "pw" }"#;
+        assert!(handle_registration(&storage, reg_json).await.to_json().contains("registered successfully"));
+        assert!(handle_registration(&storage, reg_json).await.to_json().contains("already exists"));
+
+        submit_player_bet(&storage, "sqlite_user", 30).await.unwrap();
+        assert_eq!(get_player_bet(&storage, "sqlite_user").await, Some(30));
+
+        storage.increment_player_fields("sqlite_user", doc! { "wins": 2 }).await.unwrap();
+        let stats_json = get_user_stats(&storage, "sqlite_user", None, None).await.to_json();
+        assert!(stats_json.contains("\"wins\":2"), "Should contain incremented wins");
+
+        init_game_state(&storage).await.unwrap();
+        append_event(&storage, HandEvent::Posted { player: "sqlite_user".into(), kind: history::ActionKind::Ante, amount: 15 }).await.unwrap();
+        assert!(handle_spectate_command(&storage).await.to_json().contains("\"pot\":15"));
+
+        // Events are appended one row at a time, alongside the rest of the game state
+        storage.set_game_state_field("folded_charges", doc! { "sqlite_user": {} }.into()).await.unwrap();
+        append_event(&storage, HandEvent::Posted { player: "sqlite_user".into(), kind: history::ActionKind::Ante, amount: 15 }).await.unwrap();
+        let state = storage.game_state().await.unwrap().unwrap();
+        assert_eq!(stored_events(&state).unwrap().len(), 2);
+        assert!(state.get_document("folded_charges").is_ok());
+        append_event(&storage, HandEvent::Started { hand_id: "1".into(), variant: "5card".into(), players: vec![] }).await.unwrap();
+        assert_eq!(stored_events(&storage.game_state().await.unwrap().unwrap()).unwrap().len(), 1, "A new hand starts a new list");
+        init_game_state(&storage).await.unwrap();
+        assert!(stored_events(&storage.game_state().await.unwrap().unwrap()).unwrap().is_empty());
+    }
+
+    // 17) lobby is saved and reported by the "lobby" command
+    #[tokio::test]
+    async fn test_lobby_storage() {
+        let storage = get_test_storage();
+        assert!(handle_lobby_command(&storage).await.to_json().contains("not initialized"));
+
+        update_lobby(&storage, "texas", 2, &["alice".to_string()], &[]).await.unwrap();
+        let lobby: Value = serde_json::from_str(&handle_lobby_command(&storage).await.to_json()).unwrap();
+        assert_eq!(lobby["variant"], "texas");
+        assert_eq!(lobby["players"][0], "alice");
+        assert_eq!(lobby["status"], "waiting");
+
+        update_lobby(&storage, "texas", 2, &["alice".to_string(), "bob".to_string()], &[]).await.unwrap();
+        assert!(handle_lobby_command(&storage).await.to_json().contains("in_game"));
+    }
+
+    // 18) the same shuffle seed always deals the same deck
+    #[test]
+    fn test_seeded_deck() {
+        let first = deck::Deck::with_seed(42).cards;
+        let second = deck::Deck::with_seed(42).cards;
+        assert_eq!(first.len(), 52);
+        assert_eq!(first, second, "Same seed should give the same order");
+        assert_ne!(first, deck::Deck::with_seed(43).cards, "Different seeds should differ");
+    }
+
+    // 19) hand records classify actions and round-trip through every embedded backend, once each
+    #[tokio::test]
+    async fn test_hand_history_record() {
+        use history::*;
+
+        let names = vec!["alice".to_string(), "bob".to_string()];
+        let mut game = five_card_draw::PokerGame::new(names.clone());
+        game.deal_cards();
+
+        let mut recorder = HandRecorder::new("5card", game.shuffle_seed, &[("alice".to_string(), 100), ("bob".to_string(), -20)]);
+        recorder.start_street("predraw");
+        recorder.post("alice", ActionKind::Ante, 5);
+        recorder.bet("alice", 0, 0, -2);
+        recorder.bet("bob", 10, 0, -2);
+        recorder.bet("alice", 20, 0, 10);
+        recorder.bet("bob", 10, 10, 20);
+        recorder.start_street("draw");
+        let before = game.current_players[0].hand.clone();
+        game.replace_cards("alice", &[0, 2]);
+        let (discarded, drawn) = replaced_cards(&before, &game.current_players[0].hand);
+        recorder.draw("alice", &discarded, &drawn);
+        recorder.fold("bob", 0);
+
+        let held = game.current_players.iter().map(|p| (p.id.clone(), card_names(&p.hand))).collect();
+        let record = recorder.finish(&held, &BTreeMap::new(), "alice", 50);
+        let kinds: Vec<ActionKind> = record.streets[0].actions.iter().map(|a| a.action).collect();
+        assert_eq!(kinds, vec![ActionKind::Ante, ActionKind::Check, ActionKind::Bet, ActionKind::Raise, ActionKind::Call]);
+        let draw = &record.streets[1].actions[0];
+        assert_eq!(draw.amount, 2);
+        assert_eq!(draw.discarded.len(), 2);
+        assert_eq!(record.seats[1].cards.len(), 5, "Folded players keep their cards in the record");
+        assert!(record.shown_hands.is_empty());
+        assert_eq!(record.pots[0].amount, 50);
+
+        let memory = get_test_storage();
+        let sqlite = sqlite_storage::SqliteStorage::open_in_memory().unwrap();
+        for backend in [&memory as &dyn Storage, &sqlite] {
+            history::save_hand(backend, &record).await.unwrap();
+            let loaded = history::load_hand(backend, &record.hand_id).await.unwrap();
+            assert_eq!(loaded.as_ref(), Some(&record), "{} should return the saved hand", backend.backend_name());
+            assert!(
+                matches!(history::save_hand(backend, &record).await, Err(storage::StorageError::Duplicate(_))),
+                "{} should keep one copy of each hand",
+                backend.backend_name()
+            );
+            assert!(history::handle_hand_history_command(backend, "missing", "alice").await.to_json().contains("No hand found"));
+        }
+    }
+
+    /// Builds a finished two-player hand of the given variant that goes to showdown.
+    fn sample_hand(variant: &str, streets: &[&str]) -> history::HandRecord {
+        use history::*;
+
+        let names = vec!["alice".to_string(), "bob".to_string()];
+        let mut game = five_card_draw::PokerGame::new(names.clone());
+        game.deal_cards();
+        let mut board = deck::Deck::with_seed(7);
+        let board: Vec<deck::Card> = (0..5).filter_map(|_| board.deal_one()).collect();
+
+        let mut recorder = HandRecorder::new(variant, game.shuffle_seed, &[("alice".to_string(), 100), ("bob".to_string(), 80)]);
+        for (i, street) in streets.iter().enumerate() {
+            recorder.start_street(street);
+            if i == 0 {
+                if variant == "texas" {
+                    recorder.post("alice", ActionKind::SmallBlind, 2);
+                    recorder.post("bob", ActionKind::BigBlind, 4);
+                    recorder.bet("alice", 6, 2, 4);
+                    recorder.bet("bob", 4, 4, 8);
+                } else {
+                    recorder.post("alice", ActionKind::Ante, 5);
+                    recorder.post("bob", ActionKind::Ante, 5);
+                }
+            } else {
+                recorder.bet("alice", 0, 0, -2);
+                recorder.bet("bob", 0, 0, -2);
+            }
+        }
+        if variant == "texas" {
+            recorder.set_board(&card_names(&board));
+        }
+        let hands = game.current_players.iter().map(|p| (p.id.clone(), card_names(&p.hand))).collect();
+        recorder.finish(&hands, &hands, "alice", 16)
+    }
+
+    // 20) hands export as PokerStars text for every variant
+    #[tokio::test]
+    async fn test_export_pokerstars() {
+        let texas = sample_hand("texas", &["preflop", "flop", "turn", "river"]);
+        let text = hand_export::to_pokerstars(&texas);
+        assert!(text.starts_with(&format!("PokerStars Hand #{}: Hold'em No Limit (2/4)", texas.hand_id)));
+        assert!(text.contains("bob: posts big blind 4"));
+        assert!(text.contains("alice: raises 4 to 8"), "Raise should show the increment and total:\n{}", text);
+        assert!(text.contains("*** RIVER *** ["));
+        assert!(text.contains("*** SHOW DOWN ***"));
+        assert!(text.contains("alice collected 16 from pot"));
+
+        let stud = hand_export::to_pokerstars(&sample_hand("7card", &["third", "fourth"]));
+        assert!(stud.contains("7 Card Stud No Limit (Ante 5)"));
+        assert!(stud.contains("*** 4th STREET ***"));
+        let draw = hand_export::to_pokerstars(&sample_hand("5card", &["predraw", "draw", "postdraw"]));
+        assert!(draw.contains("*** DEALING HANDS ***"));
+
+        let storage = get_test_storage();
+        history::save_hand(&storage, &texas).await.unwrap();
+        let exported = hand_export::export_hands(&storage, &[], hand_export::ExportFormat::PokerStars).await.unwrap();
+        assert_eq!(exported, text, "Exporter should read hands from the history");
+        assert!(hand_export::export_hands(&storage, &["nope".to_string()], hand_export::ExportFormat::Ohh).await.is_err());
+    }
+
+    // 21) hands export as Open Hand History JSON
+    #[test]
+    fn test_export_ohh() {
+        let hand = sample_hand("texas", &["preflop", "flop", "turn", "river"]);
+        let ohh = hand_export::to_ohh(&hand);
+        let ohh = &ohh["ohh"];
+        assert_eq!(ohh["game_type"], "Holdem");
+        assert_eq!(ohh["game_number"], hand.hand_id.as_str());
+        assert_eq!(ohh["big_blind_amount"], 4);
+        assert_eq!(ohh["players"].as_array().unwrap().len(), 2);
+        assert_eq!(ohh["rounds"][1]["street"], "Flop");
+        assert_eq!(ohh["rounds"][1]["cards"].as_array().unwrap().len(), 3);
+        assert_eq!(ohh["rounds"][4]["street"], "Showdown");
+        assert_eq!(ohh["pots"][0]["player_wins"][0]["win_amount"], 16);
+
+        assert_eq!(hand_export::card_code("10 of Hearts"), "Th");
+        assert_eq!(hand_export::card_code("A of Spades"), "As");
+    }
+
+    // 22) passwords are stored hashed, and plain ones are upgraded on login
+    #[tokio::test]
+    async fn test_password_hashing_and_upgrade() {
+        let storage = get_test_storage();
+
+        let reg_json = r#"{ "username": "hashed_user", "password": "secret" }"#;
+        assert!(handle_registration(&storage, reg_json).await.to_json().contains("registered successfully"));
+        let stored = storage.find_player("hashed_user").await.unwrap().unwrap();
+        let hash = stored.get_str("password").unwrap().to_string();
+        assert!(hash.starts_with("$argon2id$"), "Password should be hashed, got {}", hash);
+        assert!(verify_password("secret", &hash).await);
+        assert!(!verify_password("wrong", &hash).await);
+        assert!(!get_user_stats(&storage, "hashed_user", None, None).await.to_json().contains("password"));
+
+        // An account saved before hashing keeps working and gets upgraded
+        storage.insert_player(doc! { "name": "legacy_user", "password": "plain" }).await.unwrap();
+        let wrong = handle_login(&storage, &Mutex::default(), r#"{ "username": "legacy_user", "password": "nope" }"#).await.to_json();
+        assert!(wrong.contains("Invalid password"));
+        assert_eq!(storage.find_player("legacy_user").await.unwrap().unwrap().get_str("password").unwrap(), "plain");
+
+        let login = handle_login(&storage, &Mutex::default(), r#"{ "username": "legacy_user", "password": "plain" }"#).await.to_json();
+        assert!(login.contains("Welcome"));
+        let upgraded = storage.find_player("legacy_user").await.unwrap().unwrap();
+        let upgraded = upgraded.get_str("password").unwrap();
+        assert!(upgraded.starts_with("$argon2id$"));
+        assert!(handle_login(&storage, &Mutex::default(), r#"{ "username": "legacy_user", "password": "plain" }"#).await.to_json().contains("Welcome"));
+
+        let logged = redact_secrets(r#"{"command":"login","username":"legacy_user","password":"plain"}"#);
+        assert!(!logged.contains("plain") && logged.contains("legacy_user"));
+    }
+
+    // 23) login issues a session token that binds a connection to the user
+    #[tokio::test]
+    async fn test_session_tokens() {
+        let storage = get_test_storage();
+        let sessions = Mutex::new(Sessions::default());
+        handle_registration(&storage, r#"{ "username": "alice", "password": "pw" }"#).await;
+
+        let reply = handle_login(&storage, &sessions, r#"{ "username": "alice", "password": "pw" }"#).await.to_json();
+        let reply: Value = serde_json::from_str(&reply).unwrap();
+        assert_eq!(reply["username"], "alice");
+        let token = reply["token"].as_str().unwrap().to_string();
+        assert_eq!(token.len(), 64);
+        // Neither the reply nor the bind that uses the token is logged with it
+        let logged_reply = redact_secrets(&serde_json::to_string(&reply).unwrap());
+        let logged_bind = redact_secrets(&ClientCommand::Bind { token: token.clone() }.to_json());
+        assert!(!logged_reply.contains(&token) && logged_reply.contains("alice"));
+        assert!(!logged_bind.contains(&token) && logged_bind.contains("bind"));
+
+        let addr: std::net::SocketAddr = "127.0.0.1:40000".parse().unwrap();
+        assert_eq!(sessions.lock().unwrap().user_for(&addr), None);
+        assert_eq!(sessions.lock().unwrap().bind(addr, "forged"), None);
+        assert_eq!(sessions.lock().unwrap().bind(addr, &token), Some("alice".to_string()));
+        assert_eq!(sessions.lock().unwrap().user_for(&addr), Some("alice".to_string()));
+
+        // Commands may not claim to be someone else
+        let bet_as = |username: Option<&str>| ClientCommand::Bet { username: username.map(str::to_string), amount: 10 };
+        assert!(check_identity(&bet_as(Some("alice")), "alice").is_ok());
+        assert!(check_identity(&bet_as(None), "alice").is_ok());
+        assert!(check_identity(&bet_as(Some("bob")), "alice").is_err());
+
+        // Logging in again revokes the old token and anything bound to it
+        let failed = handle_login(&storage, &sessions, r#"{ "username": "alice", "password": "bad" }"#).await.to_json();
+        assert!(!failed.contains("token"));
+        assert_eq!(sessions.lock().unwrap().user_for(&addr), Some("alice".to_string()));
+        handle_login(&storage, &sessions, r#"{ "username": "alice", "password": "pw" }"#).await;
+        assert_eq!(sessions.lock().unwrap().user_for(&addr), None);
+        assert_eq!(sessions.lock().unwrap().bind(addr, &token), None);
+    }
+
+    // 24) TLS: a self-signed certificate is generated once and carries client traffic
+    #[tokio::test]
+    async fn test_tls_self_signed_round_trip() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let config = ServerConfig::parse("[tls]\nenabled = true").unwrap();
+        assert!(config.tls.enabled);
+        assert_eq!(config.tls.cert_path, "dealer-cert.pem");
+
+        let dir = std::env::temp_dir().join(format!("dealer-tls-{}", random_username()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let tls_config = config::TlsConfig {
+            enabled: true,
+            cert_path: dir.join("cert.pem").to_string_lossy().into_owned(),
+            key_path: dir.join("key.pem").to_string_lossy().into_owned(),
+        };
+        let server_config = tls::server_config(&tls_config).unwrap();
+        let cert_pem = std::fs::read(&tls_config.cert_path).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&tls_config.key_path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600, "Only the owner may read the generated key");
+        }
+        // A second start reuses the saved certificate
+        tls::server_config(&tls_config).unwrap();
+        assert_eq!(std::fs::read(&tls_config.cert_path).unwrap(), cert_pem);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let clients = Arc::new(Mutex::new(HashMap::new()));
+        let (server_tx, mut server_rx) = mpsc::unbounded_channel();
+        let acceptor = tokio_rustls::TlsAcceptor::from(server_config);
+        tokio::spawn(accept_clients(listener, Arc::clone(&clients), server_tx, Some(acceptor)));
+
+        // Client trusting only the generated certificate
+        let mut roots = rustls::RootCertStore::empty();
+        for cert in rustls_pemfile::certs(&mut cert_pem.as_slice()) {
+            roots.add(cert.unwrap()).unwrap();
+        }
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let client_config = rustls::ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(client_config));
+        let socket = tokio::net::TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let mut stream = connector.connect("localhost".try_into().unwrap(), socket).await.unwrap();
+
+        stream.write_all(&protocol::framing::encode_frame(r#"{"command":"lobby"}"#)).await.unwrap();
+        let (addr, received) = tokio::time::timeout(Duration::from_secs(5), server_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(received, ClientEvent::Message(r#"{"command":"lobby"}"#.to_string()));
+
+        send_to_client(&clients, &addr, &ServerEvent::info("hi"));
+        let reply = ServerEvent::info("hi").to_json();
+        let mut framed = vec![0u8; 4 + reply.len()];
+        stream.read_exact(&mut framed).await.unwrap();
+        assert_eq!(framed, protocol::framing::encode_frame(&reply));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    // 25) framing survives split and merged reads, and long messages
+    #[test]
+    fn test_framing() {
+        use protocol::framing::{encode_frame, FrameReader, FrameWriter};
+
+        let long = "x".repeat(10_000);
+        let mut stream = encode_frame("first");
+        stream.extend(encode_frame(&long));
+        stream.extend(encode_frame(""));
+
+        // Feed one byte at a time: nothing comes out until a frame is complete
+        let mut reader = FrameReader::default();
+        let mut messages = Vec::new();
+        for byte in &stream {
+            reader.push(std::slice::from_ref(byte));
+            while let Some(msg) = reader.next_message().unwrap() {
+                messages.push(msg);
+            }
+        }
+        assert_eq!(messages, vec!["first".to_string(), long.clone(), String::new()]);
+
+        // Several frames in one read
+        let mut reader = FrameReader::default();
+        reader.push(&stream);
+        assert_eq!(reader.next_message().unwrap().as_deref(), Some("first"));
+        assert_eq!(reader.next_message().unwrap().as_deref(), Some(long.as_str()));
+        assert_eq!(reader.next_message().unwrap().as_deref(), Some(""));
+        assert_eq!(reader.next_message().unwrap(), None);
+
+        // Oversized frames are rejected instead of buffered forever
+        let mut reader = FrameReader::default();
+        reader.push(&u32::MAX.to_be_bytes());
+        assert!(reader.next_message().is_err());
+        let mut reader = FrameReader::with_limit(protocol::framing::HANDSHAKE_FRAME_LEN);
+        reader.push(&encode_frame(&long));
+        assert!(reader.next_message().is_err());
+        let mut reader = FrameReader::with_limit(protocol::framing::HANDSHAKE_FRAME_LEN);
+        reader.set_limit(protocol::framing::MAX_FRAME_LEN);
+        reader.push(&encode_frame(&long));
+        assert_eq!(reader.next_message().unwrap(), Some(long.clone()));
+
+        // Reading stops at the end of the stream
+        let mut reader = FrameReader::default();
+        assert!(!reader.fill_from(&mut stream.as_slice()).unwrap());
+        assert_eq!(reader.next_message().unwrap().as_deref(), Some("first"));
+
+        let mut writer = FrameWriter::default();
+        writer.queue("first");
+        writer.queue(&long);
+        let mut sent = Vec::new();
+        writer.flush_to(&mut sent).unwrap();
+        assert_eq!(sent, stream[..sent.len()]);
+        assert_eq!(sent.len(), 4 + 5 + 4 + long.len());
+    }
+
+    // 26) WebSocket clients send and receive the same JSON messages as text frames
+    #[tokio::test]
+    async fn test_websocket_gateway() {
+        use futures_util::{SinkExt, StreamExt};
+        use tokio_tungstenite::tungstenite::Message;
+
+        let config = ServerConfig::parse("").unwrap();
+        assert!(config.websocket.enabled);
+        assert_eq!(config.websocket.port, 8081);
+        assert!(!ServerConfig::parse("[websocket]\nenabled = false").unwrap().websocket.enabled);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let clients = Arc::new(Mutex::new(HashMap::new()));
+        let (server_tx, mut server_rx) = mpsc::unbounded_channel();
+        tokio::spawn(websocket::accept_websocket_clients(listener, Arc::clone(&clients), server_tx, None));
+
+        let socket = tokio::net::TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let (mut ws, _) = tokio_tungstenite::client_async(format!("ws://127.0.0.1:{}/", port), socket)
+            .await
+            .unwrap();
+
+        ws.send(Message::text(r#"{"command":"lobby"}"#)).await.unwrap();
+        let (addr, received) = tokio::time::timeout(Duration::from_secs(5), server_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(received, ClientEvent::Message(r#"{"command":"lobby"}"#.to_string()));
+        assert!(clients.lock().unwrap().contains_key(&addr), "Upgraded clients are registered");
+
+        send_to_client(&clients, &addr, &ServerEvent::info("hi"));
+        let reply = tokio::time::timeout(Duration::from_secs(5), ws.next()).await.unwrap().unwrap().unwrap();
+        assert_eq!(reply.into_text().unwrap().as_str(), ServerEvent::info("hi").to_json());
+
+        // Closing the socket is reported to the dispatcher
+        drop(ws);
+        let closed = tokio::time::timeout(Duration::from_secs(5), server_rx.recv()).await.unwrap();
+        assert_eq!(closed, Some((addr, ClientEvent::Disconnected)));
+    }
+
+    // 27) a reconnecting player is reached on their new connection and gets the hand so far
+    #[tokio::test]
+    async fn test_reconnect_reclaims_seat() {
+        let old_addr: std::net::SocketAddr = "127.0.0.1:40001".parse().unwrap();
+        let new_addr: std::net::SocketAddr = "127.0.0.1:40002".parse().unwrap();
+        let mut sessions = Sessions::default();
+        let token = sessions.issue("alice");
+        sessions.bind(old_addr, &token);
+        sessions.unbind(&old_addr);
+        assert!(sessions.connections_for("alice").is_empty());
+        sessions.bind(new_addr, &token);
+        assert_eq!(sessions.connections_for("alice"), vec![new_addr]);
+
+        let storage = get_test_storage();
+        init_game_state(&storage).await.unwrap();
+        let seated = vec!["alice".to_string(), "bob".to_string()];
+        let events = [
+            HandEvent::Started { hand_id: "1".into(), variant: "5card".into(), players: seated.clone() },
+            HandEvent::Posted { player: "alice".into(), kind: history::ActionKind::Ante, amount: 15 },
+            HandEvent::Posted { player: "bob".into(), kind: history::ActionKind::Ante, amount: 15 },
+            HandEvent::Dealt { cards: [("alice".to_string(), vec!["A of Spades".to_string()]), ("bob".to_string(), vec!["2 of Hearts".to_string()])].into() },
+        ];
+        for event in events {
+            append_event(&storage, event).await.unwrap();
+        }
+        for name in ["alice", "bob"] {
+            storage.insert_player(doc! { "name": name, "bet_turn": name == "bob" }).await.unwrap();
+        }
+
+        let ServerEvent::Snapshot(table) = handle_snapshot(&storage, &seated, "alice").await else {
+            panic!("expected a snapshot");
+        };
+        assert_eq!(table.variant, "5card");
+        assert_eq!(table.pot, 30);
+        assert_eq!(table.hands["alice"], vec!["A of Spades".to_string()]);
+        assert!(!table.hands.contains_key("bob"), "alice's snapshot must not show bob's cards");
+        assert_eq!(table.to_act.as_deref(), Some("bob"));
+        assert_eq!(table.to_swap, None);
+    }
+
+    // 28) a dropped player loses an unstarted seat, and sits out (folds) mid-game
+    #[tokio::test]
+    async fn test_departed_player_sits_out() {
+        use departures::{depart, Departure};
+
+        // Waiting for players: the seat is freed
+        let mut seats = HashSet::from(["alice".to_string()]);
+        assert_eq!(depart(&mut seats, "stranger", 2), Departure::NotSeated);
+        assert_eq!(depart(&mut seats, "alice", 2), Departure::SeatFreed("alice".to_string()));
+        assert!(seats.is_empty());
+
+        // Game in progress: the seat is held
+        let mut seats = HashSet::from(["alice".to_string(), "bob".to_string()]);
+        assert_eq!(depart(&mut seats, "bob", 2), Departure::SeatHeld("bob".to_string()));
+        assert_eq!(seats.len(), 2);
+
+        // Once sitting out, the game folds for them instead of waiting forever
+        let storage = get_test_storage();
+        storage.insert_player(doc! { "name": "bob", "bet": 0, "swap": "" }).await.unwrap();
+        set_sitting_out(&storage, "bob", true).await.unwrap();
+        let players = vec!["alice".to_string(), "bob".to_string()];
+        assert_eq!(sitting_out_players(&storage, &players).await, vec!["bob".to_string()]);
+        let bet = tokio::time::timeout(Duration::from_secs(5), wait_for_player_bet(&storage, "bob")).await;
+        assert_eq!(bet.unwrap(), Some(-1));
+        let swap = tokio::time::timeout(Duration::from_secs(5), wait_for_player_swap(&storage, "bob")).await;
+        assert_eq!(swap.unwrap().as_deref(), Some(""));
+
+        update_lobby(&storage, "5card", 2, &players, &["bob".to_string()]).await.unwrap();
+        let ServerEvent::Lobby(lobby) = handle_lobby_command(&storage).await else {
+            panic!("expected the lobby");
+        };
+        assert_eq!(lobby.status, "in_game");
+        assert_eq!(lobby.sitting_out, vec!["bob".to_string()]);
+
+        set_sitting_out(&storage, "bob", false).await.unwrap();
+        assert!(sitting_out_players(&storage, &players).await.is_empty());
+    }
+
+    // 29) floods and repeated failed logins get the connection banned for a while
+    #[test]
+    fn test_rate_limits_and_bans() {
+        let limits = ServerConfig::parse("[limits]\nmessage_burst = 3\nmessages_per_second = 1.0\nlogin_attempts = 2\nban_secs = 60")
+            .unwrap()
+            .limits;
+        assert_eq!(limits.login_window_secs, 300);
+        let mut limiter = rate_limit::RateLimiter::new(limits);
+        let start = std::time::Instant::now();
+        let a: std::net::SocketAddr = "10.0.0.1:5000".parse().unwrap();
+        let b: std::net::SocketAddr = "10.0.0.2:5000".parse().unwrap();
+        let c: std::net::SocketAddr = "10.0.0.3:5000".parse().unwrap();
+
+        // A burst is fine, and the bucket refills over time
+        for _ in 0..3 {
+            assert!(limiter.check_message(&a, None, start).is_ok());
+        }
+        assert!(limiter.check_message(&a, None, start + Duration::from_secs(1)).is_ok());
+
+        // Flooding bans the address, and the ban expires
+        assert!(limiter.check_message(&a, None, start + Duration::from_secs(1)).is_err());
+        let banned = limiter.check_message(&a, None, start + Duration::from_secs(30)).unwrap_err();
+        assert!(banned.contains("banned"), "{}", banned);
+        assert!(limiter.check_message(&a, None, start + Duration::from_secs(62)).is_ok());
+
+        // Accounts are limited across all their connections
+        for _ in 0..3 {
+            assert!(limiter.check_message(&b, Some("alice"), start).is_ok());
+        }
+        assert!(limiter.check_message(&c, Some("alice"), start).is_err());
+        assert!(limiter.account_ban_remaining("alice", start).is_some());
+
+        // Failed logins ban the address, but never the account they named
+        let later = start + Duration::from_secs(100);
+        assert!(limiter.record_login_failure(&c, "bob", later).is_ok());
+        assert!(limiter.record_login_failure(&c, "bob", later).is_ok());
+        let banned = limiter.record_login_failure(&c, "bob", later).unwrap_err();
+        assert!(banned.contains("Too many failed logins"), "{}", banned);
+        assert!(limiter.check_message(&c, None, later).is_err());
+        assert!(limiter.check_message(&b, Some("bob"), later).is_ok());
+        assert!(limiter.account_ban_remaining("bob", later).is_none());
+        assert!(limiter.check_login("bob", later).is_ok());
+        assert!(limiter.check_message(&c, None, later + Duration::from_secs(61)).is_ok());
+
+        // Failures age out of the window
+        let mut limiter = rate_limit::RateLimiter::new(config::LimitsConfig::default());
+        for _ in 0..5 {
+            assert!(limiter.record_login_failure(&a, "carol", start).is_ok());
+        }
+        assert!(limiter.record_login_failure(&a, "carol", start + Duration::from_secs(301)).is_ok());
+    }
+
+    // 30) admin console commands parse, kicks sign the player out, and chip adjustments change the stack
+    #[tokio::test]
+    async fn test_admin_console() {
+        use admin::parse_command;
+
+        assert_eq!(parse_command("tables"), Ok(AdminCommand::Tables));
+        assert_eq!(parse_command("  Players "), Ok(AdminCommand::Players));
+        assert_eq!(parse_command("kick alice"), Ok(AdminCommand::Kick("alice".to_string())));
+        assert_eq!(parse_command("notice Back in  5 minutes"), Ok(AdminCommand::Notice("Back in  5 minutes".to_string())));
+        assert_eq!(
+            parse_command("chips bob -50"),
+            Ok(AdminCommand::Chips { username: "bob".to_string(), amount: -50 })
+        );
+        assert_eq!(parse_command("shutdown"), Ok(AdminCommand::Shutdown));
+        assert_eq!(parse_command("kick"), Err("Usage: kick <player>".to_string()));
+        assert!(parse_command("chips bob lots").unwrap_err().contains("not a number"));
+        assert!(parse_command("reboot").unwrap_err().contains("Unknown command"));
+
+        // Kicking revokes the session on every connection bound to it
+        let mut sessions = Sessions::default();
+        let first: std::net::SocketAddr = "127.0.0.1:40006".parse().unwrap();
+        let second: std::net::SocketAddr = "127.0.0.1:40007".parse().unwrap();
+        let token = sessions.issue("alice");
+        sessions.bind(first, &token);
+        sessions.bind(second, &token);
+        let mut bound = sessions.connections_for("alice");
+        bound.sort();
+        assert_eq!(bound, vec![first, second]);
+        assert_eq!(sessions.revoke("alice").len(), 2);
+        assert_eq!(sessions.user_for(&first), None);
+        assert!(sessions.bind(first, &token).is_none());
+
+        // Adjustments count toward the stack without touching winnings or losses
+        let storage = get_test_storage();
+        storage.insert_player(doc! { "name": "bob", "money_win": 40, "money_lost": -10 }).await.unwrap();
+        assert!(adjust_player_chips(&storage, "bob", 100).await.unwrap());
+        assert!(adjust_player_chips(&storage, "bob", -30).await.unwrap());
+        assert!(!adjust_player_chips(&storage, "nobody", 10).await.unwrap());
+        let doc = storage.find_player("bob").await.unwrap().unwrap();
+        assert_eq!(player_stack(&doc), 100);
+        assert_eq!(doc.get_i32("money_win").unwrap(), 40);
+        assert_eq!(doc.get_i32("money_lost").unwrap(), -10);
+    }
+
+    // 31) voiding a hand on shutdown refunds what folded players were already charged
+    #[tokio::test]
+    async fn test_void_hand_refunds_folded_bets() {
+        let storage = get_test_storage();
+        init_game_state(&storage).await.unwrap();
+        let names = vec!["alice".to_string(), "bob".to_string(), "carol".to_string()];
+        for name in &names {
+            let reg_json = format!(r#"{{ "username": "{}", "password": "pw" }}"#, name);
+            handle_registration(&storage, &reg_json).await;
+        }
+
+        // alice folds in the first round and bob in the second; carol is still in
+        let mut players = five_card_draw::PokerGame::new(names.clone()).current_players;
+        players[0].money_lost = 5;
+        players[1].money_lost = 25;
+        update_players_folded(&storage, "5card", &players[..1]).await.unwrap();
+        update_players_folded(&storage, "5card", &players[..2]).await.unwrap();
+        let alice = storage.find_player("alice").await.unwrap().unwrap();
+        assert!(alice.get_i32("money_lost").unwrap() < 0);
+
+        let mut refunded = void_hand(&storage).await.unwrap();
+        refunded.sort();
+        assert_eq!(refunded, vec!["alice".to_string(), "bob".to_string()]);
+        for name in &names {
+            let doc = storage.find_player(name).await.unwrap().unwrap();
+            assert_eq!(doc.get_i32("money_lost").unwrap(), 0, "{} was not refunded", name);
+            assert_eq!(doc.get_i32("losses").unwrap(), 0);
+            assert_eq!(doc.get_i32("games_played").unwrap(), 0);
+        }
+
+        // Nothing is refunded twice
+        assert!(void_hand(&storage).await.unwrap().is_empty());
+        assert_eq!(ServerConfig::default().shutdown.hand_timeout_secs, 120);
+    }
+
+    // 32) the live hand keeps its deck, actions and pending turn, and replays the actions in order
+    #[tokio::test]
+    async fn test_live_hand_recovery() {
+        use recovery::{begin_live_hand, end_live_hand, load_live_hand, set_pending_turn, PendingTurn, Replay, TurnAction};
+
+        let storage = get_test_storage();
+        init_game_state(&storage).await.unwrap();
+        let players = vec!["amy".to_string(), "ben".to_string()];
+        for name in &players {
+            storage.insert_player(doc! { "name": name, "bet": 0, "swap": "" }).await.unwrap();
+        }
+        begin_live_hand(&storage, "hand-1", "5card", &players, 42).await.unwrap();
+
+        // Both players are sitting out, so the game takes their turns at once
+        set_sitting_out(&storage, "amy", true).await.unwrap();
+        set_sitting_out(&storage, "ben", true).await.unwrap();
+        assert_eq!(wait_for_player_bet(&storage, "amy").await, Some(-1));
+        assert_eq!(wait_for_player_swap(&storage, "ben").await.as_deref(), Some(""));
+        set_pending_turn(&storage, "ben", "bet").await.unwrap();
+
+        let hand = load_live_hand(&storage).await.unwrap().expect("the hand should be live");
+        assert_eq!(hand.hand_id, "hand-1");
+        assert_eq!(hand.players, players);
+        assert_eq!(hand.shuffle_seed, "000000000000002a");
+        assert_eq!(hand.deck.len(), 52);
+        assert_eq!(hand.deck[0], deck::Deck::with_seed(42).deal_one().unwrap().to_string());
+        assert_eq!(
+            hand.actions,
+            vec![
+                TurnAction::Bet { player: "amy".to_string(), amount: -1 },
+                TurnAction::Swap { player: "ben".to_string(), indices: String::new() },
+            ]
+        );
+        assert_eq!(hand.pending_turn, Some(PendingTurn { player: "ben".to_string(), action: "bet".to_string() }));
+
+        // Replaying answers the same turns in the same order
+        let mut replay = Replay::new(&hand).unwrap();
+        assert_eq!(replay.next_bet("ben"), None);
+        assert_eq!(replay.next_bet("amy"), Some(-1));
+        assert!(!replay.is_done());
+        assert_eq!(replay.next_swap("ben").as_deref(), Some(""));
+        assert!(replay.is_done());
+
+        // A finished hand is not resumed
+        end_live_hand(&storage).await.unwrap();
+        assert_eq!(load_live_hand(&storage).await.unwrap(), None);
+    }
+
+    // 33) the table is a fold over the hand's events, which are stored, shown to spectators and recorded in the history
+    #[tokio::test]
+    async fn test_hand_events_fold() {
+        use history::ActionKind;
+
+        let storage: Arc<dyn Storage> = Arc::new(get_test_storage());
+        init_game_state(&*storage).await.unwrap();
+        let clients = Arc::new(Mutex::new(HashMap::new()));
+        let players = vec!["amy".to_string(), "ben".to_string(), "cat".to_string()];
+        let mut hand = HandLog::start(clients, storage.clone(), "5card", &players, 7, None).await;
+
+        let cards = |names: &[&str]| names.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        let events = vec![
+            HandEvent::Street { name: "predraw".into() },
+            HandEvent::Posted { player: "amy".into(), kind: ActionKind::Ante, amount: 5 },
+            HandEvent::Posted { player: "ben".into(), kind: ActionKind::Ante, amount: 5 },
+            HandEvent::Posted { player: "cat".into(), kind: ActionKind::Ante, amount: 5 },
+            HandEvent::Dealt {
+                cards: [
+                    ("amy".to_string(), cards(&["A of Spades", "K of Spades"])),
+                    ("ben".to_string(), cards(&["2 of Hearts", "7 of Clubs"])),
+                    ("cat".to_string(), cards(&["9 of Diamonds", "9 of Clubs"])),
+                ]
+                .into(),
+            },
+            HandEvent::ToAct { player: "amy".into() },
+            HandEvent::Bet { player: "amy".into(), amount: 0 },
+            HandEvent::ToAct { player: "ben".into() },
+            HandEvent::Bet { player: "ben".into(), amount: 10 },
+            HandEvent::ToAct { player: "cat".into() },
+            HandEvent::Folded { player: "cat".into() },
+            HandEvent::ToAct { player: "amy".into() },
+        ];
+        for event in events.clone() {
+            hand.emit(event).await;
+        }
+
+        // The stored events fold into the table, for spectators and replays alike
+        let stored = stored_events(&storage.game_state().await.unwrap().unwrap()).unwrap();
+        assert_eq!(stored.len(), events.len() + 1, "Started comes first");
+        assert_eq!(&stored[1..], &events[..]);
+        let state = HandState::from_events(&stored);
+        let table = state.table.clone();
+        assert_eq!(table.pot, 25);
+        assert_eq!(table.current_bet, 10);
+        assert_eq!(table.bets, BTreeMap::from([("amy".to_string(), 0), ("ben".to_string(), 10)]));
+        assert!(!table.hands.contains_key("cat"), "Folded players have no hand at the table");
+        assert_eq!(table.to_act.as_deref(), Some("amy"));
+        assert_eq!(table.info.as_deref(), Some("cat folds"));
+        assert_eq!(state.mucked["cat"], cards(&["9 of Diamonds", "9 of Clubs"]));
+        let ServerEvent::Table(spectated) = handle_spectate_command(&*storage).await else {
+            panic!("expected the table");
+        };
+        assert!(spectated.hands.is_empty(), "Spectators see no cards before the showdown");
+        assert_eq!(protocol::TableState { hands: table.hands.clone(), ..spectated }, table);
+        let json = serde_json::to_value(&stored[9]).unwrap();
+        assert_eq!(json, serde_json::json!({ "type": "bet", "player": "ben", "amount": 10 }));
+
+        // Ending the hand writes its history from the same events
+        hand.emit(HandEvent::Folded { player: "amy".into() }).await;
+        hand.emit(HandEvent::HandOver { winner: "ben".into() }).await;
+        let hand_id = table.hand_id.unwrap();
+        let record = history::load_hand(&*storage, &hand_id).await.unwrap().expect("the hand should be saved");
+        let kinds: Vec<ActionKind> = record.streets[0].actions.iter().map(|a| a.action).collect();
+        assert_eq!(
+            kinds,
+            vec![ActionKind::Ante, ActionKind::Ante, ActionKind::Ante, ActionKind::Check, ActionKind::Bet, ActionKind::Fold, ActionKind::Fold]
+        );
+        assert_eq!(record.pots[0].player, "ben");
+        assert_eq!(record.total_pot, 25);
+        assert_eq!(record.seats[2].cards, cards(&["9 of Diamonds", "9 of Clubs"]));
+        assert!(record.shown_hands.is_empty());
+        let stored = stored_events(&storage.game_state().await.unwrap().unwrap()).unwrap();
+        assert_eq!(HandState::from_events(&stored).table.winner.as_deref(), Some("ben"));
+    }
+
+    /// Steps a hand until it waits on a player or ends.
+    fn step_to_turn(hand: &mut impl HandMachine) -> Vec<HandEvent> {
+        let mut events = Vec::new();
+        while hand.turn().is_none() && hand.phase() != Phase::Over {
+            events.extend(hand.step());
+        }
+        events
+    }
+
+    /// Plays a hand to the end with every player checking and keeping their cards.
+    ///
+    /// # Returns
+    /// The phases the hand passed through, in order, and its events.
+    fn check_down(hand: &mut impl HandMachine) -> (Vec<Phase>, Vec<HandEvent>) {
+        let (mut phases, mut events) = (Vec::new(), Vec::new());
+        loop {
+            let phase = hand.phase();
+            if phases.last() != Some(&phase) {
+                phases.push(phase);
+            }
+            match hand.turn().cloned() {
+                Some(Turn::Bet(player)) => events.extend(hand.bet(&player, 0).unwrap()),
+                Some(Turn::Swap(player)) => events.extend(hand.swap(&player, &[])),
+                None if phase == Phase::Over => break,
+                None => events.extend(hand.step()),
+            }
+        }
+        (phases, events)
+    }
+
+    /// The table a hand's events describe, from its start.
+    fn fold_hand(players: &[&str], variant: &str, events: &[HandEvent]) -> HandState {
+        let mut state = HandState::from_events(&[HandEvent::Started {
+            hand_id: "h1".into(),
+            variant: variant.into(),
+            players: players.iter().map(|p| p.to_string()).collect(),
+        }]);
+        for event in events {
+            state.apply(event);
+        }
+        state
+    }
+
+    // 34) a five card draw hand steps through its phases, refusing short bets and input out of turn
+    #[test]
+    fn test_five_card_draw_machine() {
+        let names = vec!["amy".to_string(), "ben".to_string(), "cat".to_string()];
+        let mut hand = FiveCardDrawHand::new(names, 42);
+        let mut events = Vec::new();
+        assert_eq!(hand.phase(), Phase::Antes);
+
+        events.extend(hand.step());
+        assert_eq!(hand.phase(), Phase::Deal(Street::Predraw));
+        assert_eq!(hand.pot(), 15);
+        events.extend(hand.step());
+        assert_eq!(hand.phase(), Phase::Betting(Street::Predraw));
+        assert!(matches!(events.last(), Some(HandEvent::Dealt { cards }) if cards.values().all(|c| c.len() == 5)));
+
+        events.extend(hand.step());
+        assert_eq!(hand.turn(), Some(&Turn::Bet("amy".into())));
+        assert_eq!(hand.bet("ben", 0), Ok(Vec::new()), "Bets out of turn are ignored");
+        assert!(hand.step().is_empty(), "The hand waits for amy");
+        events.extend(hand.bet("amy", 0).unwrap());
+        events.extend(hand.step());
+        events.extend(hand.bet("ben", 10).unwrap());
+        events.extend(hand.step());
+        assert_eq!(hand.bet("cat", 5), Err("Bet at least 10 to call, or -1 to fold.".to_string()), "A bet short of the current bet is refused");
+        assert_eq!(hand.step(), vec![HandEvent::ToAct { player: "cat".into() }], "cat is asked again");
+        events.extend(hand.bet("cat", -1).unwrap());
+        events.extend(hand.step());
+        events.extend(hand.bet("amy", 10).unwrap());
+
+        // Back to ben, who raised, so the round is over
+        events.extend(hand.step());
+        assert_eq!(hand.phase(), Phase::Draw);
+        assert_eq!(hand.folded().iter().map(|p| p.id.as_str()).collect::<Vec<_>>(), vec!["cat"]);
+        assert_eq!(hand.active_players().iter().map(|p| p.money_lost).collect::<Vec<_>>(), vec![15, 15]);
+
+        events.extend(hand.step());
+        assert_eq!(hand.turn(), Some(&Turn::Swap("amy".into())));
+        let drew = hand.swap("amy", &[0, 1]);
+        assert!(matches!(&drew[..], [HandEvent::Drew { discarded, drawn, .. }] if discarded.len() == 2 && drawn.len() == 2));
+        events.extend(drew);
+        events.extend(hand.step());
+        events.extend(hand.swap("ben", &[]));
+
+        let (phases, rest) = check_down(&mut hand);
+        events.extend(rest);
+        assert_eq!(phases, vec![Phase::Draw, Phase::Betting(Street::Postdraw), Phase::Showdown, Phase::Over]);
+        let winner = hand.winner().expect("the showdown has a winner").to_string();
+        assert_eq!(events.last(), Some(&HandEvent::Showdown { winner: winner.clone() }));
+
+        let state = fold_hand(&["amy", "ben", "cat"], "5 Card Draw", &events);
+        assert_eq!(state.table.pot, 35);
+        assert_eq!(state.table.winner, Some(winner));
+        assert_eq!(state.mucked["cat"].len(), 5);
+    }
+
+    // 35) a texas hold'em hand saved mid-way restores and plays on exactly as the original
+    #[test]
+    fn test_texas_machine_save_restore() {
+        let names = vec!["amy".to_string(), "ben".to_string(), "cat".to_string()];
+        let mut hand = TexasHoldemHand::new(names, 7);
+        let events = step_to_turn(&mut hand);
+        assert_eq!(hand.pot(), 6, "The blinds are in");
+        assert_eq!(hand.turn(), Some(&Turn::Bet("cat".into())), "The player after the big blind acts first");
+        assert!(matches!(events.last(), Some(HandEvent::ToAct { .. })));
+        assert!(matches!(&events[events.len() - 2], HandEvent::Info { message } if message.starts_with("Match the big blind")));
+
+        assert_eq!(hand.bet("cat", 3), Err("Bet 4 to match the big blind, or -1 to fold.".to_string()), "The big blind must be matched exactly");
+        assert_eq!(step_to_turn(&mut hand), vec![HandEvent::ToAct { player: "cat".into() }], "The prompt is not sent again");
+        hand.bet("cat", 4).unwrap();
+        step_to_turn(&mut hand);
+        hand.bet("amy", 2).unwrap();
+        let events = step_to_turn(&mut hand);
+        assert_eq!(hand.turn(), Some(&Turn::Bet("ben".into())));
+        assert!(matches!(&events[0], HandEvent::Info { message } if message.starts_with("You are the big blind")));
+
+        // Save and restore the hand while it waits on the big blind
+        let saved = serde_json::to_value(&hand).unwrap();
+        assert_eq!(saved["phase"], serde_json::json!({ "phase": "betting", "street": "preflop" }));
+        assert_eq!(saved["turn"], serde_json::json!({ "action": "bet", "player": "ben" }));
+        let mut restored: TexasHoldemHand = serde_json::from_value(saved).unwrap();
+
+        let (phases, events) = check_down(&mut hand);
+        let (restored_phases, restored_events) = check_down(&mut restored);
+        assert_eq!(restored_phases, phases);
+        assert_eq!(restored_events, events);
+        assert_eq!(restored.winner(), hand.winner());
+        assert_eq!(
+            phases,
+            vec![
+                Phase::Betting(Street::Preflop),
+                Phase::Deal(Street::Flop),
+                Phase::Betting(Street::Flop),
+                Phase::Deal(Street::Turn),
+                Phase::Betting(Street::Turn),
+                Phase::Deal(Street::River),
+                Phase::Betting(Street::River),
+                Phase::Showdown,
+                Phase::Over,
+            ]
+        );
+        assert_eq!(events[0], HandEvent::Bet { player: "ben".into(), amount: 0 }, "The big blind checks to end the round");
+        let board: usize = events.iter().map(|e| match e { HandEvent::Board { cards } => cards.len(), _ => 0 }).sum();
+        assert_eq!(board, 5);
+        assert_eq!(hand.pot(), 12);
+    }
+
+    // 36) a seven card stud hand deals every street to a showdown, or ends as soon as one player is left
+    #[test]
+    fn test_seven_card_stud_machine() {
+        let names = vec!["amy".to_string(), "ben".to_string()];
+        let mut hand = SevenCardStudHand::new(names.clone(), 99);
+        let (phases, events) = check_down(&mut hand);
+        let mut expected = vec![Phase::Antes];
+        for street in [Street::Third, Street::Fourth, Street::Fifth, Street::Sixth, Street::Seventh] {
+            expected.push(Phase::Deal(street));
+            expected.push(Phase::Betting(street));
+        }
+        expected.extend([Phase::Showdown, Phase::Over]);
+        assert_eq!(phases, expected);
+
+        let state = fold_hand(&["amy", "ben"], "7 Card Stud", &events);
+        assert!(state.table.hands.values().all(|cards| cards.len() == 7));
+        assert_eq!(state.table.pot, 10);
+        assert_eq!(state.table.winner.as_deref(), hand.winner());
+        assert!(hand.step().is_empty(), "A hand that is over stays over");
+
+        // Whoever opens the betting folds, and the other player wins at once
+        let mut hand = SevenCardStudHand::new(names, 99);
+        step_to_turn(&mut hand);
+        let Some(Turn::Bet(opener)) = hand.turn().cloned() else {
+            panic!("third street should wait on the bring-in");
+        };
+        hand.bet(&opener, -1).unwrap();
+        let events = step_to_turn(&mut hand);
+        let other = if opener == "amy" { "ben" } else { "amy" };
+        assert_eq!(events, vec![HandEvent::HandOver { winner: other.into() }]);
+        assert_eq!(hand.phase(), Phase::Over);
+        assert_eq!(hand.winner(), Some(other));
+    }
+
+    /// The value of a sample line in rendered metrics, e.g. `dealer_active_tables`.
+    fn sample(rendered: &str, series: &str) -> Option<f64> {
+        rendered
+            .lines()
+            .find_map(|line| line.strip_prefix(series)?.strip_prefix(' ')?.parse().ok())
+    }
+
+    // 37) metrics render in the prometheus text format, with cumulative histogram buckets
+    #[test]
+    fn test_metrics_render() {
+        let metrics = metrics::Metrics::new();
+        metrics.hand_dealt("5 Card Draw");
+        metrics.hand_dealt("5 Card Draw");
+        metrics.observe_action("bet", Duration::from_millis(700));
+        metrics.observe_action("bet", Duration::from_secs(200));
+        metrics.observe_storage_call("mongodb", "find_player", Duration::from_millis(3), true);
+        metrics.observe_pot("Texas Hold'em", 12);
+
+        let rendered = metrics.render(3);
+        assert!(rendered.contains("# TYPE dealer_connected_clients gauge"));
+        assert_eq!(sample(&rendered, "dealer_connected_clients"), Some(3.0));
+        assert_eq!(sample(&rendered, "dealer_active_tables"), Some(0.0));
+        assert_eq!(sample(&rendered, r#"dealer_hands_dealt_total{variant="5 Card Draw"}"#), Some(2.0));
+        assert_eq!(sample(&rendered, r#"dealer_action_latency_seconds_bucket{action="bet",le="0.5"}"#), Some(0.0));
+        assert_eq!(sample(&rendered, r#"dealer_action_latency_seconds_bucket{action="bet",le="1"}"#), Some(1.0));
+        assert_eq!(sample(&rendered, r#"dealer_action_latency_seconds_bucket{action="bet",le="120"}"#), Some(1.0));
+        assert_eq!(sample(&rendered, r#"dealer_action_latency_seconds_bucket{action="bet",le="+Inf"}"#), Some(2.0));
+        assert_eq!(sample(&rendered, r#"dealer_action_latency_seconds_count{action="bet"}"#), Some(2.0));
+        assert_eq!(
+            sample(&rendered, r#"dealer_storage_errors_total{backend="mongodb",operation="find_player"}"#),
+            Some(1.0)
+        );
+        assert_eq!(sample(&rendered, r#"dealer_pot_size_chips_bucket{variant="Texas Hold'em",le="25"}"#), Some(1.0));
+        assert_eq!(sample(&rendered, r#"dealer_pot_size_chips_sum{variant="Texas Hold'em"}"#), Some(12.0));
+    }
+
+    // 38) metered storage times every call and counts the ones that fail
+    #[tokio::test]
+    async fn test_metered_storage() {
+        let storage = metrics::MeteredStorage::wrap(Arc::new(get_test_storage()));
+        let errors = r#"dealer_storage_errors_total{backend="memory",operation="insert_player"}"#;
+        let calls = r#"dealer_storage_call_duration_seconds_count{backend="memory",operation="insert_player"}"#;
+        let before = metrics::METRICS.render(0);
+        let username = random_username();
+
+        storage.insert_player(doc! { "name": &username }).await.unwrap();
+        assert!(storage.insert_player(doc! { "name": &username }).await.is_err());
+        assert!(storage.find_player(&username).await.unwrap().is_some(), "Calls still reach the backend");
+        assert_eq!(storage.backend_name(), "memory");
+
+        let after = metrics::METRICS.render(0);
+        let count = |rendered: &str, series: &str| sample(rendered, series).unwrap_or(0.0);
+        assert!(count(&after, errors) - count(&before, errors) >= 1.0);
+        assert!(count(&after, calls) - count(&before, calls) >= 2.0);
+    }
+
+    // 39) the metrics endpoint answers GET /metrics and nothing else
+    #[tokio::test]
+    async fn test_metrics_endpoint() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let clients = Arc::new(Mutex::new(HashMap::new()));
+        tokio::spawn(metrics::serve_metrics(listener, clients));
+
+        let get = |path: &'static str| async move {
+            let mut socket = tokio::net::TcpStream::connect(addr).await.unwrap();
+            let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+            socket.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            socket.read_to_string(&mut response).await.unwrap();
+            response
+        };
+
+        let response = get("/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("Content-Type: text/plain; version=0.0.4"));
+        let body = response.split("\r\n\r\n").nth(1).unwrap();
+        assert_eq!(sample(body, "dealer_connected_clients"), Some(0.0));
+
+        assert!(get("/").await.starts_with("HTTP/1.1 404 Not Found"));
+    }
+
+    // 40) leaderboards rank players from their totals, added up by variant and period in storage, a page at a time
+    #[tokio::test]
+    async fn test_leaderboard() {
+        use protocol::{LeaderboardPeriod, LeaderboardSort};
+        use results::{load_result_totals, ResultRecord};
+
+        // alice wins both recent hands; bob wins a bigger pot 40 days ago
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as i64;
+        let result = |player: &str, hand_id: &str, variant: &str, money_win: i32, money_lost: i32| ResultRecord {
+            player: player.to_string(),
+            hand_id: hand_id.to_string(),
+            timestamp: if hand_id == "3" { now - 40 * 24 * 60 * 60 } else { now },
+            variant: variant.to_string(),
+            won: money_win > 0,
+            money_win,
+            money_lost,
+        };
+        let results = vec![
+            result("bob", "3", "7card", 40, -20),
+            result("alice", "3", "7card", 0, -20),
+            result("alice", "1", "texas", 16, -8),
+            result("bob", "1", "texas", 0, -8),
+            result("alice", "2", "5card", 10, -5),
+            result("bob", "2", "5card", 0, -5),
+        ];
+
+        let names = |entries: &[protocol::LeaderboardEntry]| entries.iter().map(|e| e.name.clone()).collect::<Vec<_>>();
+
+        // Every backend adds up the results, filtered by variant and time, itself
+        let sqlite = sqlite_storage::SqliteStorage::open_in_memory().unwrap();
+        let memory = get_test_storage();
+        for storage in [&sqlite as &dyn Storage, &memory] {
+            for result in &results {
+                storage.insert_result(mongodb::bson::to_document(result).unwrap()).await.unwrap();
+            }
+            let totals = load_result_totals(storage, None, None).await.unwrap();
+            let ranked = leaderboard::rank_players(&totals, LeaderboardSort::NetWinnings);
+            assert_eq!(names(&ranked), ["bob", "alice"]);
+            assert_eq!((ranked[0].net_winnings, ranked[0].wins, ranked[0].games_played, ranked[0].biggest_pot), (7, 1, 3, 40));
+            assert_eq!((ranked[1].net_winnings, ranked[1].wins, ranked[1].rank), (-7, 2, 2));
+            assert!((ranked[1].win_rate - 2.0 / 3.0).abs() < 1e-9);
+            assert_eq!(names(&leaderboard::rank_players(&totals, LeaderboardSort::WinRate)), ["alice", "bob"]);
+            assert_eq!(names(&leaderboard::rank_players(&totals, LeaderboardSort::BiggestPot)), ["bob", "alice"]);
+            assert_eq!(
+                names(&leaderboard::rank_players(&totals, LeaderboardSort::GamesPlayed)),
+                ["alice", "bob"],
+                "Ties are broken by name"
+            );
+
+            let texas_only = load_result_totals(storage, Some("texas"), None).await.unwrap();
+            let texas_ranked = leaderboard::rank_players(&texas_only, LeaderboardSort::NetWinnings);
+            assert_eq!(texas_ranked.iter().map(|e| e.net_winnings).collect::<Vec<_>>(), [8, -8]);
+            assert!(load_result_totals(storage, Some("omaha"), None).await.unwrap().is_empty());
+            let recent = load_result_totals(storage, None, Some(now - 60)).await.unwrap();
+            assert_eq!(recent.iter().map(|t| t.games_played).sum::<i64>(), 4);
+
+            let reply = leaderboard::handle_leaderboard_command(
+                storage, LeaderboardSort::NetWinnings, None, LeaderboardPeriod::Month, 2, Some(1),
+            ).await;
+            let ServerEvent::Leaderboard(page) = reply else {
+                panic!("expected a leaderboard, got {:?}", reply);
+            };
+            assert_eq!((page.page, page.page_size, page.total_players, page.page_count()), (2, 1, 2, 2));
+            assert_eq!(names(&page.entries), ["bob"], "The old hand no longer counts, so bob is second");
+            assert_eq!((page.entries[0].rank, page.entries[0].net_winnings), (2, -13));
+
+            // A page far past the end is empty rather than overflowing
+            let reply = leaderboard::handle_leaderboard_command(
+                storage, LeaderboardSort::NetWinnings, None, LeaderboardPeriod::All, usize::MAX, Some(leaderboard::MAX_PAGE_SIZE),
+            ).await;
+            let ServerEvent::Leaderboard(page) = reply else {
+                panic!("expected a leaderboard, got {:?}", reply);
+            };
+            assert!(page.entries.is_empty());
+            assert_eq!(page.total_players, 2);
+        }
+    }
+
+    // 41) results are kept per variant, and VPIP, PFR, aggression and showdown rates come from the hand history
+    #[tokio::test]
+    async fn test_variant_and_behaviour_stats() {
+        let storage = get_test_storage();
+        init_game_state(&storage).await.unwrap();
+        for name in ["alice", "bob"] {
+            let reg_json = format!(r#"{{ "username": "{}", "password": "pw" }}"#, name);
+            handle_registration(&storage, &reg_json).await;
+        }
+
+        let mut players = five_card_draw::PokerGame::new(vec!["alice".into(), "bob".into()]).current_players;
+        players[0].money_lost = 8;
+        players[1].money_lost = 8;
+        update_game_results(&storage, "texas", "alice", &players, 16).await.unwrap();
+        let alice = storage.find_player("alice").await.unwrap().unwrap();
+        let texas = alice.get_document("variants").unwrap().get_document("texas").unwrap();
+        assert_eq!((texas.get_i32("wins").unwrap(), texas.get_i32("money_win").unwrap()), (1, 16));
+
+        // A voided hand takes back the variant's totals too
+        update_players_folded(&storage, "5card", &players[1..]).await.unwrap();
+        void_hand(&storage).await.unwrap();
+        let bob = storage.find_player("bob").await.unwrap().unwrap();
+        let draw = bob.get_document("variants").unwrap().get_document("5card").unwrap();
+        assert_eq!(draw.get_i32("games_played").unwrap(), 0);
+
+        // Texas: alice raises preflop and bob calls; five card draw: both only ante. Both hands go to showdown.
+        let mut texas_hand = sample_hand("texas", &["preflop", "flop"]);
+        let mut draw_hand = sample_hand("5card", &["predraw", "postdraw"]);
+        (texas_hand.hand_id, draw_hand.hand_id) = ("1".into(), "2".into());
+        let alice = hand_stats::behaviour_stats([&texas_hand, &draw_hand], "alice");
+        assert_eq!(alice.hands, 2);
+        assert_eq!((alice.vpip, alice.pfr, alice.aggression_factor), (0.5, 0.5, 1.0));
+        assert_eq!((alice.went_to_showdown, alice.won_at_showdown), (1.0, 1.0));
+        assert_eq!(hand_stats::behaviour_stats([&texas_hand], "carol"), protocol::BehaviourStats::default());
+
+        history::save_hand(&storage, &texas_hand).await.unwrap();
+        history::save_hand(&storage, &draw_hand).await.unwrap();
+        let ServerEvent::PlayerStats(bob) = get_user_stats(&storage, "bob", None, None).await else {
+            panic!("expected bob's stats");
+        };
+        assert_eq!(bob.variants.keys().collect::<Vec<_>>(), ["5card", "texas"]);
+        assert_eq!(bob.variants["texas"].losses, 1);
+        let texas = &bob.variants["texas"].behaviour;
+        assert_eq!((texas.hands, texas.vpip, texas.pfr, texas.aggression_factor), (1, 1.0, 0.0, 0.0));
+        assert_eq!((bob.behaviour.hands, bob.behaviour.vpip, bob.behaviour.won_at_showdown), (2, 0.5, 0.0));
+    }
+
+    // 42) each player's result is kept with a timestamp, stats can cover a date range, and the bankroll runs across it
+    #[tokio::test]
+    async fn test_results_date_range_and_bankroll() {
+        let memory = get_test_storage();
+        let sqlite = sqlite_storage::SqliteStorage::open_in_memory().unwrap();
+        for storage in [&memory as &dyn Storage, &sqlite] {
+            let mut players = five_card_draw::PokerGame::new(vec!["alice".into(), "bob".into()]).current_players;
+            players[0].money_lost = 10;
+            players[1].money_lost = 10;
+            results::record_results(storage, "texas", "hand-1", "alice", &players, 20).await.unwrap();
+            let alice = results::load_results(storage, "alice", None, None).await.unwrap();
+            assert_eq!(alice.len(), 1);
+            assert_eq!((alice[0].won, alice[0].money_win, alice[0].money_lost, alice[0].net()), (true, 20, -10, 10));
+            let bob = results::load_results(storage, "bob", None, None).await.unwrap();
+            assert_eq!((bob[0].won, bob[0].net()), (false, -10));
+
+            // Results come back oldest first, and only inside the range
+            for (hand_id, timestamp) in [("hand-3", 300), ("hand-2", 200)] {
+                let record = results::ResultRecord {
+                    player: "carol".into(),
+                    hand_id: hand_id.into(),
+                    timestamp,
+                    variant: "5card".into(),
+                    won: false,
+                    money_win: 0,
+                    money_lost: -5,
+                };
+                storage.insert_result(mongodb::bson::to_document(&record).unwrap()).await.unwrap();
+            }
+            let carol = results::load_results(storage, "carol", None, None).await.unwrap();
+            assert_eq!(carol.iter().map(|r| r.timestamp).collect::<Vec<_>>(), [200, 300]);
+            assert_eq!(results::load_results(storage, "carol", Some(250), None).await.unwrap().len(), 1);
+            assert_eq!(results::load_results(storage, "carol", None, Some(200)).await.unwrap().len(), 1);
+        }
+
+        // alice: won 30 at t=100, lost 10 at t=200, won 40 at t=300
+        let storage = get_test_storage();
+        init_game_state(&storage).await.unwrap();
+        handle_registration(&storage, r#"{ "username": "alice", "password": "pw" }"#).await;
+        for (timestamp, won, money_win, money_lost) in [(100, true, 40, -10), (200, false, 0, -10), (300, true, 50, -10)] {
+            let record = results::ResultRecord {
+                player: "alice".into(),
+                hand_id: timestamp.to_string(),
+                timestamp,
+                variant: "texas".into(),
+                won,
+                money_win,
+                money_lost,
+            };
+            storage.insert_result(mongodb::bson::to_document(&record).unwrap()).await.unwrap();
+        }
+
+        let ServerEvent::PlayerStats(range) = get_user_stats(&storage, "alice", Some(150), Some(350)).await else {
+            panic!("expected alice's stats");
+        };
+        assert_eq!((range.from, range.to), (Some(150), Some(350)));
+        assert_eq!((range.wins, range.losses, range.games_played), (1, 1, 2));
+        assert_eq!((range.money_win, range.money_lost), (50, -20));
+        assert_eq!(range.variants["texas"].games_played, 2);
+        // The bankroll starts from what alice had already won before the range
+        let bankroll: Vec<(i64, i32)> = range.bankroll.iter().map(|p| (p.timestamp, p.bankroll)).collect();
+        assert_eq!(bankroll, [(200, 20), (300, 60)]);
+
+        let ServerEvent::PlayerStats(all) = get_user_stats(&storage, "alice", None, None).await else {
+            panic!("expected alice's stats");
+        };
+        assert_eq!((all.from, all.to), (None, None));
+        assert_eq!(all.bankroll.len(), 3);
+        let ServerEvent::PlayerStats(before) = get_user_stats(&storage, "alice", None, Some(150)).await else {
+            panic!("expected alice's stats");
+        };
+        assert_eq!((before.games_played, before.money_win), (1, 40));
+        assert_eq!(before.bankroll.len(), 1);
+    }
+
+    // 43) a hand from the history shows the player only their own cards, the ones shown down, and stud's face-up cards
+    #[tokio::test]
+    async fn test_hand_history_hides_hole_cards() {
+        use history::*;
+
+        let cards = |names: &[&str]| names.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        let mut recorder = HandRecorder::new("5card", 7, &[("alice".to_string(), 100), ("bob".to_string(), 80)]);
+        recorder.start_street("predraw");
+        recorder.post("alice", ActionKind::Ante, 5);
+        recorder.post("bob", ActionKind::Ante, 5);
+        recorder.start_street("draw");
+        recorder.draw("alice", &cards(&["2 of Hearts"]), &cards(&["A of Hearts"]));
+        recorder.draw("bob", &cards(&["3 of Clubs"]), &cards(&["K of Clubs"]));
+        recorder.fold("bob", 0);
+        let held = BTreeMap::from([
+            ("alice".to_string(), cards(&["A of Hearts", "A of Spades"])),
+            ("bob".to_string(), cards(&["K of Clubs", "K of Spades"])),
+        ]);
+        let record = recorder.finish(&held, &BTreeMap::new(), "alice", 10);
+
+        let storage = get_test_storage();
+        save_hand(&storage, &record).await.unwrap();
+        let ServerEvent::HandHistory(seen) = handle_hand_history_command(&storage, &record.hand_id, "alice").await else {
+            panic!("expected the hand");
+        };
+        assert_eq!(seen.seats[0].cards, held["alice"]);
+        assert_eq!(seen.seats[1].cards, cards(&["", ""]), "Bob mucked, so alice sees only how many cards he held");
+        let draws: Vec<&ActionRecord> = seen.streets[1].actions.iter().filter(|a| a.action == ActionKind::Draw).collect();
+        assert_eq!(draws[0].drawn, cards(&["A of Hearts"]));
+        assert!(draws[1].drawn.is_empty() && draws[1].discarded.is_empty());
+        assert_eq!(draws[1].amount, 1);
+        assert_eq!(seen_by(&record, "carol").seats[0].cards, cards(&["", ""]));
+
+        // The deck can be rebuilt from the seed, so players never get it
+        assert_eq!(record.shuffle_seed, "0000000000000007");
+        assert!(seen.shuffle_seed.is_empty());
+        assert!(seen_by(&record, "carol").shuffle_seed.is_empty());
+
+        // Hands shown at showdown, and stud's face-up cards, were seen by everyone
+        let mut shown = record.clone();
+        shown.shown_hands.insert("bob".to_string(), held["bob"].clone());
+        assert_eq!(seen_by(&shown, "alice").seats[1].cards, held["bob"]);
+        let mut stud = record.clone();
+        stud.variant = "7card".to_string();
+        stud.seats[1].cards = cards(&["2 of Clubs", "3 of Clubs", "4 of Clubs", "5 of Clubs", "6 of Clubs", "7 of Clubs", "8 of Clubs"]);
+        assert_eq!(seen_by(&stud, "alice").seats[1].cards, cards(&["", "", "4 of Clubs", "5 of Clubs", "6 of Clubs", "7 of Clubs", ""]));
+    }
+
+    // 44) clients export hands as they saw them, without anyone else's hole cards; the operator export keeps them
+    #[tokio::test]
+    async fn test_export_hides_hole_cards() {
+        use hand_export::{export_hands, export_player_hands, handle_export_command, ExportFormat};
+
+        let mut texas = sample_hand("texas", &["preflop", "flop", "turn", "river"]);
+        texas.shown_hands.clear();
+        let storage = get_test_storage();
+        history::save_hand(&storage, &texas).await.unwrap();
+
+        let ServerEvent::HandExport { text, .. } = handle_export_command(&storage, None, &[], 1, "alice").await else {
+            panic!("expected an export");
+        };
+        assert!(text.contains("Dealt to alice ["), "{}", text);
+        assert!(!text.contains("Dealt to bob"), "bob's hole cards leaked:\n{}", text);
+        let operator = export_hands(&storage, &[], ExportFormat::PokerStars).await.unwrap();
+        assert!(operator.contains("Dealt to bob ["));
+
+        // Without hand IDs, a player gets only the hands they played
+        let ServerEvent::HandExport { text, .. } = handle_export_command(&storage, None, &[], 1, "carol").await else {
+            panic!("expected an export");
+        };
+        assert!(text.is_empty());
+
+        let ohh = export_player_hands(&storage, &[texas.hand_id.clone()], 1, ExportFormat::Ohh, "bob").await.unwrap();
+        let ohh: serde_json::Value = serde_json::from_str(&ohh).unwrap();
+        let dealt: Vec<&serde_json::Value> = ohh["ohh"]["rounds"][0]["actions"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|action| action["action"] == "Dealt Cards")
+            .collect();
+        assert_eq!(dealt.len(), 1);
+        assert_eq!(dealt[0]["player_id"], 2, "Only bob's own cards are dealt in his export");
+
+        // A draw by someone else shows how many cards they took, but not which
+        let mut draw = sample_hand("5card", &["predraw", "draw", "postdraw"]);
+        draw.shown_hands.clear();
+        draw.streets[1].actions.push(history::ActionRecord {
+            player: "bob".into(),
+            action: history::ActionKind::Draw,
+            amount: 1,
+            total: 0,
+            discarded: vec![draw.seats[1].cards[0].clone()],
+            drawn: vec!["A of Clubs".into()],
+        });
+        let seen = hand_export::to_pokerstars(&history::seen_by(&draw, "alice"));
+        assert!(seen.contains("bob: discards 1 card\n"), "{}", seen);
+        assert!(!seen.contains("A of Clubs") && !seen.contains("[Ac]"));
+    }
+
+    // 45) a hand is settled as soon as its results are written, so a restart or shutdown after that does not pay it again
+    #[tokio::test]
+    async fn test_settled_hand_is_not_paid_twice() {
+        use five_card_game::FiveCardDrawHand;
+        let storage: Arc<dyn Storage> = Arc::new(get_test_storage());
+        init_game_state(&*storage).await.unwrap();
+        let names = vec!["alice".to_string(), "bob".to_string()];
+        for name in &names {
+            let reg_json = format!(r#"{{ "username": "{}", "password": "pw" }}"#, name);
+            handle_registration(&*storage, &reg_json).await;
+            set_sitting_out(&*storage, name, true).await.unwrap();
+        }
+
+        // Both sit out, so the first to bet folds and the other wins without a showdown
+        let variant = GAME_VARIANT.get_or_init(|| "5card".to_string());
+        let clients = Arc::new(Mutex::new(HashMap::new()));
+        play_hand(FiveCardDrawHand::new(names.clone(), 7), clients, &names, storage.clone(), None).await;
+
+        // Nothing is left to resume or to void
+        assert_eq!(recovery::load_live_hand(&*storage).await.unwrap(), None);
+        assert!(void_hand(&*storage).await.unwrap().is_empty());
+
+        let (mut wins, mut money_win) = (0, 0);
+        for name in &names {
+            let doc = storage.find_player(name).await.unwrap().unwrap();
+            assert_eq!(doc.get_i32("games_played").unwrap(), 1, "{} was charged more than once", name);
+            assert!(doc.get_i32("money_lost").unwrap() < 0, "{} was refunded", name);
+            let variant_totals = doc.get_document("variants").unwrap().get_document(variant).unwrap();
+            assert_eq!(variant_totals.get_i32("games_played").unwrap(), 1);
+            wins += doc.get_i32("wins").unwrap();
+            money_win += doc.get_i32("money_win").unwrap();
+            assert_eq!(results::load_results(&*storage, name, None, None).await.unwrap().len(), 1);
+        }
+        assert_eq!((wins, money_win), (1, 10));
+    }
+
+    // 46) a player who folds is charged once, however many rounds follow, so their totals match their results
+    #[tokio::test]
+    async fn test_folded_player_charged_once() {
+        use five_card_game::FiveCardDrawHand;
+        use recovery::{LiveHand, Replay, TurnAction};
+        let storage: Arc<dyn Storage> = Arc::new(get_test_storage());
+        init_game_state(&*storage).await.unwrap();
+        let names = vec!["alice".to_string(), "bob".to_string(), "carol".to_string()];
+        for name in &names {
+            let reg_json = format!(r#"{{ "username": "{}", "password": "pw" }}"#, name);
+            handle_registration(&*storage, &reg_json).await;
+            // Anyone the replay does not cover folds instead of holding up the test
+            set_sitting_out(&*storage, name, true).await.unwrap();
+        }
+
+        // alice folds before the draw; bob folds after it and carol wins without a showdown
+        let bet = |player: &str, amount| TurnAction::Bet { player: player.to_string(), amount };
+        let swap = |player: &str| TurnAction::Swap { player: player.to_string(), indices: String::new() };
+        let live = LiveHand {
+            hand_id: "1".to_string(),
+            variant: "5card".to_string(),
+            players: names.clone(),
+            shuffle_seed: format!("{:016x}", 7),
+            deck: Vec::new(),
+            actions: vec![bet("alice", -1), bet("bob", 0), bet("carol", 0), swap("bob"), swap("carol"), bet("bob", -1)],
+            pending_turn: None,
+        };
+        GAME_VARIANT.get_or_init(|| "5card".to_string());
+        let clients = Arc::new(Mutex::new(HashMap::new()));
+        play_hand(FiveCardDrawHand::new(names.clone(), 7), clients, &names, storage.clone(), Replay::new(&live)).await;
+
+        for (name, lost) in [("alice", 1), ("bob", 1), ("carol", 0)] {
+            let doc = storage.find_player(name).await.unwrap().unwrap();
+            assert_eq!(doc.get_i32("games_played").unwrap(), 1, "{} was charged more than once", name);
+            assert_eq!(doc.get_i32("losses").unwrap(), lost);
+            let results = results::load_results(&*storage, name, None, None).await.unwrap();
+            assert_eq!(results.len(), 1);
+            assert_eq!(doc.get_i32("money_lost").unwrap(), results[0].money_lost, "{}'s totals differ from their results", name);
+        }
+        assert_eq!(storage.find_player("alice").await.unwrap().unwrap().get_i32("money_lost").unwrap(), -5);
+
+        // The leaderboard agrees with the stats page
+        let totals = results::load_result_totals(&*storage, None, None).await.unwrap();
+        for entry in leaderboard::rank_players(&totals, protocol::LeaderboardSort::NetWinnings) {
+            let doc = storage.find_player(&entry.name).await.unwrap().unwrap();
+            assert_eq!(entry.net_winnings, doc.get_i32("money_win").unwrap() + doc.get_i32("money_lost").unwrap());
+        }
+    }
+
+    // 47) each backend looks up a player's hands by seat and time, and the stats use only those
+    #[tokio::test]
+    async fn test_player_history_lookup() {
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as i64;
+        let mut texas = sample_hand("texas", &["preflop", "flop"]);
+        let mut draw = sample_hand("5card", &["predraw"]);
+        let mut others = sample_hand("5card", &["predraw"]);
+        (texas.hand_id, draw.hand_id, others.hand_id) = ("1".into(), "2".into(), "3".into());
+        (texas.timestamp, draw.timestamp) = (now, now - 1000);
+        for (seat, name) in others.seats.iter_mut().zip(["carol", "dave"]) {
+            seat.player = name.to_string();
+        }
+
+        let sqlite = sqlite_storage::SqliteStorage::open_in_memory().unwrap();
+        let memory = get_test_storage();
+        for storage in [&sqlite as &dyn Storage, &memory] {
+            for record in [&texas, &draw, &others] {
+                history::save_hand(storage, record).await.unwrap();
+            }
+            let hand_ids = |records: Vec<history::HandRecord>| records.into_iter().map(|r| r.hand_id).collect::<Vec<_>>();
+            assert_eq!(hand_ids(history::load_player_history(storage, "alice", None, None).await.unwrap()), ["2", "1"]);
+            assert_eq!(hand_ids(history::load_player_history(storage, "alice", Some(now - 10), None).await.unwrap()), ["1"]);
+            assert_eq!(hand_ids(history::load_player_history(storage, "bob", None, Some(now - 10)).await.unwrap()), ["2"]);
+            assert_eq!(hand_ids(history::load_player_history(storage, "carol", None, None).await.unwrap()), ["3"]);
+            assert!(history::load_player_history(storage, "erin", None, None).await.unwrap().is_empty());
+
+            handle_registration(storage, r#"{ "username": "alice", "password": "pw" }"#).await;
+            let ServerEvent::PlayerStats(stats) = get_user_stats(storage, "alice", Some(now - 10), None).await else {
+                panic!("expected alice's stats");
+            };
+            assert_eq!(stats.behaviour.hands, 1);
+            assert_eq!(stats.variants.keys().collect::<Vec<_>>(), ["texas"]);
+        }
+    }
+
+    // 48) each seat is sent a table with only its own cards; spectators see none until the showdown
+    #[tokio::test]
+    async fn test_table_hides_other_hands() {
+        let storage: Arc<dyn Storage> = Arc::new(get_test_storage());
+        init_game_state(&*storage).await.unwrap();
+        let players = vec!["dee".to_string(), "eli".to_string()];
+        let dee_addr: std::net::SocketAddr = "127.0.0.1:40101".parse().unwrap();
+        let eli_addr: std::net::SocketAddr = "127.0.0.1:40102".parse().unwrap();
+        let seats = GAME_PLAYERS.get_or_init(|| Arc::new(Mutex::new(HashSet::new())));
+        seats.lock().unwrap().extend(players.iter().cloned());
+        {
+            let mut sessions = SESSIONS.get_or_init(|| Arc::new(Mutex::new(Sessions::default()))).lock().unwrap();
+            for (player, addr) in [("dee", dee_addr), ("eli", eli_addr)] {
+                let token = sessions.issue(player);
+                sessions.bind(addr, &token);
+            }
+        }
+        let (dee_tx, mut dee_rx) = tokio::sync::mpsc::unbounded_channel();
+        let (eli_tx, _eli_rx) = tokio::sync::mpsc::unbounded_channel();
+        let clients = Arc::new(Mutex::new(HashMap::from([
+            (dee_addr, ClientInfo { addr: dee_addr, sender: dee_tx }),
+            (eli_addr, ClientInfo { addr: eli_addr, sender: eli_tx }),
+        ])));
+
+        let mut hand = HandLog::start(clients, storage.clone(), "5card", &players, 7, None).await;
+        let dealt = [("dee".to_string(), vec!["A of Spades".to_string()]), ("eli".to_string(), vec!["2 of Hearts".to_string()])];
+        hand.emit(HandEvent::Dealt { cards: dealt.into() }).await;
+        hand.emit(HandEvent::ToAct { player: "dee".into() }).await;
+
+        let mut tables = 0;
+        while let Ok(message) = dee_rx.try_recv() {
+            assert!(!message.contains("2 of Hearts"), "dee was sent eli's cards: {}", message);
+            assert!(!describe_reply(&message).contains("A of Spades"), "dee's cards were logged");
+            if let Ok(ServerEvent::Table(table)) = serde_json::from_str(&message) {
+                assert_eq!(table.hands, BTreeMap::from([("dee".to_string(), vec!["A of Spades".to_string()])]));
+                tables += 1;
+            }
+        }
+        assert_eq!(tables, 2);
+        let ServerEvent::Table(spectated) = handle_spectate_command(&*storage).await else {
+            panic!("expected the table");
+        };
+        assert!(spectated.hands.is_empty());
+
+        // Once shown down, every hand still in is public
+        hand.emit(HandEvent::Showdown { winner: "dee".into() }).await;
+        let ServerEvent::Table(spectated) = handle_spectate_command(&*storage).await else {
+            panic!("expected the table");
+        };
+        assert_eq!(spectated.hands.len(), 2);
+    }
+
+    // 49) upgrading an SQLite database indexes the seats of the hands it already holds
+    #[tokio::test]
+    async fn test_sqlite_backfills_history_seats() {
+        let path = std::env::temp_dir().join(format!("dealer-upgrade-{}.db", random_username()));
+        let mut hand = sample_hand("5card", &["predraw"]);
+        hand.hand_id = "1".into();
+        {
+            // A database from before hands were looked up by seat
+            let conn = rusqlite::Connection::open(&path).unwrap();
+            for sql in &sqlite_storage::MIGRATIONS[..4] {
+                conn.execute_batch(sql).unwrap();
+            }
+            conn.pragma_update(None, "user_version", 4).unwrap();
+            let mut blob = Vec::new();
+            mongodb::bson::to_document(&hand).unwrap().to_writer(&mut blob).unwrap();
+            conn.execute("INSERT INTO history (hand_id, doc) VALUES ('1', ?1)", [blob]).unwrap();
+        }
+
+        let storage = sqlite_storage::SqliteStorage::open(&path).unwrap();
+        assert_eq!(storage.schema_version().unwrap(), sqlite_storage::MIGRATIONS.len());
+        for player in ["alice", "bob"] {
+            let hands = history::load_player_history(&storage, player, Some(hand.timestamp), None).await.unwrap();
+            assert_eq!(hands, vec![hand.clone()], "{}'s earlier hand was not indexed", player);
+        }
+        drop(storage);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    // 50) upgrading an SQLite database fills in the totals of the results it already holds
+    #[tokio::test]
+    async fn test_sqlite_backfills_result_totals() {
+        use results::{load_result_totals, ResultRecord};
+
+        let path = std::env::temp_dir().join(format!("dealer-upgrade-{}.db", random_username()));
+        let result = |player: &str, won: bool, money_win: i32, money_lost: i32| ResultRecord {
+            player: player.to_string(),
+            hand_id: "1".to_string(),
+            timestamp: 100,
+            variant: "texas".to_string(),
+            won,
+            money_win,
+            money_lost,
+        };
+        {
+            // A database from before results were added up in SQL, with a result from
+            // before they had a variant column
+            let conn = rusqlite::Connection::open(&path).unwrap();
+            for sql in &sqlite_storage::MIGRATIONS[..3] {
+                conn.execute_batch(sql).unwrap();
+            }
+            for record in [result("alice", true, 16, -8), result("bob", false, 0, -8)] {
+                let mut blob = Vec::new();
+                mongodb::bson::to_document(&record).unwrap().to_writer(&mut blob).unwrap();
+                conn.execute(
+                    "INSERT INTO results (player, timestamp, doc) VALUES (?1, 100, ?2)",
+                    rusqlite::params![record.player, blob],
+                ).unwrap();
+            }
+            conn.pragma_update(None, "user_version", 3).unwrap();
+        }
+
+        let storage = sqlite_storage::SqliteStorage::open(&path).unwrap();
+        assert_eq!(storage.schema_version().unwrap(), sqlite_storage::MIGRATIONS.len());
+        let totals = load_result_totals(&storage, Some("texas"), None).await.unwrap();
+        let ranked = leaderboard::rank_players(&totals, protocol::LeaderboardSort::NetWinnings);
+        assert_eq!(
+            ranked.iter().map(|e| (e.name.as_str(), e.net_winnings, e.wins, e.biggest_pot)).collect::<Vec<_>>(),
+            [("alice", 8, 1, 16), ("bob", -8, 0, 0)],
+            "Earlier results were not filled in"
+        );
+        drop(storage);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    // 51) a client from before framing is told to upgrade in its padded format, then disconnected
+    #[tokio::test]
+    async fn test_unframed_client_told_to_upgrade() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use protocol::framing::LEGACY_MESSAGE_LEN;
+
+        let (mut client, server) = tokio::io::duplex(8192);
+        let addr: std::net::SocketAddr = "127.0.0.1:40201".parse().unwrap();
+        let (server_tx, mut server_rx) = mpsc::unbounded_channel();
+        let (_client_tx, client_rx) = mpsc::unbounded_channel();
+        tokio::spawn(handle_client(server, addr, server_tx, client_rx));
+
+        let mut login = br#"{"command":"login","username":"alice","password":"pw"}"#.to_vec();
+        login.resize(LEGACY_MESSAGE_LEN, 0);
+        client.write_all(&login).await.unwrap();
+
+        let mut reply = Vec::new();
+        tokio::time::timeout(Duration::from_secs(5), client.read_to_end(&mut reply)).await.unwrap().unwrap();
+        assert_eq!(reply.len(), LEGACY_MESSAGE_LEN);
+        let text: Vec<u8> = reply.into_iter().take_while(|&b| b != 0).collect();
+        let event = ServerEvent::from_json(std::str::from_utf8(&text).unwrap()).unwrap();
+        assert!(matches!(event, ServerEvent::UpgradeRequired { .. }), "got {:?}", event);
+
+        // Nothing reaches the dispatcher but the disconnect
+        let closed = tokio::time::timeout(Duration::from_secs(5), server_rx.recv()).await.unwrap();
+        assert_eq!(closed, Some((addr, ClientEvent::Disconnected)));
+    }
+
+    // 52) a banned account is turned away at login and bind, and failed logins spread over addresses lock its password logins
+    #[test]
+    fn test_account_limits_checked_before_login() {
+        let limits = ServerConfig::parse("[limits]\nmessage_burst = 2\nlogin_attempts = 2\naccount_login_attempts = 3\nban_secs = 60")
+            .unwrap()
+            .limits;
+        let mut limiter = rate_limit::RateLimiter::new(limits);
+        let start = std::time::Instant::now();
+        let addr = |n: u8| std::net::SocketAddr::from(([10, 0, 1, n], 5000));
+
+        // alice floods from one address, then tries again from a fresh one
+        assert!(limiter.check_message(&addr(1), Some("alice"), start).is_ok());
+        assert!(limiter.check_message(&addr(1), Some("alice"), start).is_ok());
+        assert!(limiter.check_message(&addr(1), Some("alice"), start).is_err());
+        let refused = limiter.check_login("alice", start).unwrap_err();
+        assert!(refused.contains("banned"), "{}", refused);
+        // A bind with alice's token is checked against the same ban
+        let mut sessions = Sessions::default();
+        let token = sessions.issue("alice");
+        assert_eq!(sessions.owner(&token).as_deref(), Some("alice"));
+        assert!(limiter.account_ban_remaining("alice", start).is_some());
+        assert!(limiter.check_login("alice", start + Duration::from_secs(61)).is_ok());
+
+        // Guessing bob's password from a new address each time stays under the address limit,
+        // but not the account's
+        for n in 10..13 {
+            assert!(limiter.record_login_failure(&addr(n), "bob", start).is_ok());
+        }
+        assert!(limiter.check_login("bob", start).is_ok());
+        assert!(limiter.record_login_failure(&addr(13), "bob", start).is_ok());
+        let locked = limiter.check_login("bob", start).unwrap_err();
+        assert!(locked.contains("Too many failed logins for bob"), "{}", locked);
+        // bob is not banned, so his sessions carry on, and he may log in once it ends
+        assert!(limiter.account_ban_remaining("bob", start).is_none());
+        assert!(limiter.check_message(&addr(20), Some("bob"), start).is_ok());
+        assert!(limiter.check_login("bob", start + Duration::from_secs(61)).is_ok());
+        assert!(limiter.check_login("carol", start).is_ok());
+    }
+
+    // 53) limits on sprayed addresses and usernames are forgotten once they no longer apply
+    #[test]
+    fn test_rate_limiter_forgets_expired_entries() {
+        let limits = ServerConfig::parse("[limits]\nmessage_burst = 2\nmessages_per_second = 1.0\nban_secs = 120")
+            .unwrap()
+            .limits;
+        let mut limiter = rate_limit::RateLimiter::new(limits);
+        let start = std::time::Instant::now();
+        let addr = |n: u8| std::net::SocketAddr::from(([10, 0, 2, n], 5000));
+
+        for n in 0..50 {
+            let _ = limiter.check_message(&addr(n), Some(&format!("user{}", n)), start);
+            let _ = limiter.record_login_failure(&addr(n), &format!("guess{}", n), start);
+        }
+        // One address floods and is banned
+        for _ in 0..3 {
+            let _ = limiter.check_message(&addr(100), Some("mallory"), start);
+        }
+        assert!(limiter.tracked() >= 200);
+
+        // A minute on, the buckets have refilled but the bans and the failures still count
+        let minute = start + Duration::from_secs(61);
+        assert!(limiter.check_message(&addr(200), None, minute).is_ok());
+        assert!(limiter.check_message(&addr(100), None, minute).is_err());
+        assert_eq!(limiter.tracked(), 50 + 50 + 2 + 1);
+
+        // Once the ban and the login window are over, nothing is kept
+        let later = start + Duration::from_secs(301);
+        assert!(limiter.check_message(&addr(100), Some("mallory"), later).is_ok());
+        assert_eq!(limiter.tracked(), 2, "only the bucket of the connection just checked, and its account");
+    }
+
+    // 54) a connection may only send small frames until its hello is accepted
+    #[tokio::test]
+    async fn test_frame_limit_until_handshake() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use protocol::framing::{encode_frame, FrameReader, HANDSHAKE_FRAME_LEN};
+
+        let big = format!(r#"{{"command":"lobby","padding":"{}"}}"#, "x".repeat(HANDSHAKE_FRAME_LEN));
+
+        // Before the handshake, a big frame closes the connection unread
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        let addr: std::net::SocketAddr = "127.0.0.1:40202".parse().unwrap();
+        let (server_tx, mut server_rx) = mpsc::unbounded_channel();
+        let (_client_tx, client_rx) = mpsc::unbounded_channel();
+        tokio::spawn(handle_client(server, addr, server_tx, client_rx));
+        client.write_all(&encode_frame(&big)).await.unwrap();
+        let closed = tokio::time::timeout(Duration::from_secs(5), server_rx.recv()).await.unwrap();
+        assert_eq!(closed, Some((addr, ClientEvent::Disconnected)));
+
+        // Once the welcome has gone out, the same frame is read
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        let (server_tx, mut server_rx) = mpsc::unbounded_channel();
+        let (client_tx, client_rx) = mpsc::unbounded_channel();
+        tokio::spawn(handle_client(server, addr, server_tx, client_rx));
+        let hello = ClientCommand::hello().to_json();
+        client.write_all(&encode_frame(&hello)).await.unwrap();
+        let received = tokio::time::timeout(Duration::from_secs(5), server_rx.recv()).await.unwrap();
+        assert_eq!(received, Some((addr, ClientEvent::Message(hello))));
+
+        let welcome = handshake::negotiate(handshake::PROTOCOL_VERSION, &[], handshake::CAPABILITIES);
+        client_tx.send(welcome.to_json()).unwrap();
+        let mut reader = FrameReader::default();
+        let mut buf = [0u8; 4096];
+        let reply = loop {
+            if let Some(reply) = reader.next_message().unwrap() {
+                break reply;
+            }
+            let n = client.read(&mut buf).await.unwrap();
+            reader.push(&buf[..n]);
+        };
+        assert_eq!(reply, welcome.to_json());
+
+        client.write_all(&encode_frame(&big)).await.unwrap();
+        let received = tokio::time::timeout(Duration::from_secs(5), server_rx.recv()).await.unwrap();
+        assert_eq!(received, Some((addr, ClientEvent::Message(big))));
+    }
+
+    // 55) clients export their hands a bounded page at a time, looked up by seat
+    #[tokio::test]
+    async fn test_export_pages_player_hands() {
+        use hand_export::{export_player_hands, handle_export_command, ExportFormat, MAX_EXPORT_HANDS};
+
+        let sqlite = sqlite_storage::SqliteStorage::open_in_memory().unwrap();
+        let memory = get_test_storage();
+        for storage in [&sqlite as &dyn Storage, &memory] {
+            let hand = sample_hand("texas", &["preflop", "flop", "turn", "river"]);
+            for n in 0..MAX_EXPORT_HANDS + 5 {
+                let mut record = hand.clone();
+                record.hand_id = format!("h{}", n);
+                record.timestamp = 1_000 + n as i64;
+                history::save_hand(storage, &record).await.unwrap();
+            }
+            let mut others = hand.clone();
+            others.hand_id = "other".into();
+            for (seat, name) in others.seats.iter_mut().zip(["carol", "dave"]) {
+                seat.player = name.to_string();
+            }
+            history::save_hand(storage, &others).await.unwrap();
+
+            // The newest page first, each page oldest first like every export
+            let first = export_player_hands(storage, &[], 1, ExportFormat::Ohh, "alice").await.unwrap();
+            let ids: Vec<String> = first
+                .split("\n\n")
+                .map(|ohh| serde_json::from_str::<serde_json::Value>(ohh).unwrap()["ohh"]["game_number"].as_str().unwrap().to_string())
+                .collect();
+            assert_eq!(ids.len(), MAX_EXPORT_HANDS);
+            assert_eq!(ids.first().map(String::as_str), Some("h5"));
+            assert_eq!(ids.last().map(String::as_str), Some(format!("h{}", MAX_EXPORT_HANDS + 4).as_str()));
+            assert!(first.len() < protocol::framing::MAX_FRAME_LEN / 4, "a full page is {} bytes", first.len());
+
+            let second = export_player_hands(storage, &[], 2, ExportFormat::PokerStars, "alice").await.unwrap();
+            assert_eq!(second.matches("PokerStars Hand #").count(), 5);
+            assert!(second.starts_with("PokerStars Hand #h0:"));
+            assert!(export_player_hands(storage, &[], 3, ExportFormat::PokerStars, "alice").await.unwrap().is_empty());
+            let carol = export_player_hands(storage, &[], 1, ExportFormat::PokerStars, "carol").await.unwrap();
+            assert!(carol.starts_with("PokerStars Hand #other:"));
+
+            // Naming too many hands is refused
+            let hand_ids: Vec<String> = (0..=MAX_EXPORT_HANDS).map(|n| format!("h{}", n)).collect();
+            let ServerEvent::Error { message } = handle_export_command(storage, None, &hand_ids, 1, "alice").await else {
+                panic!("expected the export to be refused");
+            };
+            assert!(message.contains("At most"), "{}", message);
+        }
     }
 }
@@ -0,0 +1,227 @@
+//! # In-Memory Storage
+//!
+//! A `Storage` backend that keeps everything in process memory.
+//!
+//! Nothing is written to disk, so all players, stats and hand history are lost when the
+//! server stops. It lets the server and its tests run without a MongoDB instance.
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use async_trait::async_trait;
+use mongodb::bson::{doc, Bson, Document};
+use crate::storage::*;
+
+
+/// Storage backend holding all documents in memory.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    /// Player documents in insertion order.
+    players: Mutex<Vec<Document>>,
+    /// The live game state document.
+    game_state: Mutex<Option<Document>>,
+    /// The lobby document.
+    lobby: Mutex<Option<Document>>,
+    /// Hand history records, oldest first.
+    history: Mutex<Vec<Document>>,
+    /// Players' results, oldest first.
+    results: Mutex<Vec<Document>>,
+}
+
+impl MemoryStorage {
+    /// Creates an empty in-memory store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `update` on the named player, returning `false` if there is no such player.
+    fn update_player(&self, name: &str, update: impl FnOnce(&mut Document)) -> bool {
+        let mut players = self.players.lock().unwrap();
+        match players.iter_mut().find(|p| p.get_str("name") == Ok(name)) {
+            Some(player) => {
+                update(player);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    fn backend_name(&self) -> &'static str {
+        "memory"
+    }
+
+    async fn find_player(&self, name: &str) -> StorageResult<Option<Document>> {
+        let players = self.players.lock().unwrap();
+        Ok(players.iter().find(|p| p.get_str("name") == Ok(name)).cloned())
+    }
+
+    async fn insert_player(&self, player: Document) -> StorageResult<()> {
+        let name = player.get_str("name").unwrap_or("").to_string();
+        let mut players = self.players.lock().unwrap();
+        if players.iter().any(|p| p.get_str("name") == Ok(name.as_str())) {
+            return Err(StorageError::Duplicate(name));
+        }
+        players.push(player);
+        Ok(())
+    }
+
+    async fn set_player_fields(&self, name: &str, fields: Document) -> StorageResult<bool> {
+        Ok(self.update_player(name, |player| apply_set(player, &fields)))
+    }
+
+    async fn increment_player_fields(&self, name: &str, amounts: Document) -> StorageResult<bool> {
+        Ok(self.update_player(name, |player| apply_inc(player, &amounts)))
+    }
+
+    async fn list_players(&self) -> StorageResult<Vec<Document>> {
+        Ok(self.players.lock().unwrap().clone())
+    }
+
+    async fn reset_game_state(&self, state: Document) -> StorageResult<()> {
+        *self.game_state.lock().unwrap() = Some(state);
+        Ok(())
+    }
+
+    async fn game_state(&self) -> StorageResult<Option<Document>> {
+        Ok(self.game_state.lock().unwrap().clone())
+    }
+
+    async fn set_game_state_field(&self, key: &str, value: Bson) -> StorageResult<()> {
+        if let Some(state) = self.game_state.lock().unwrap().as_mut() {
+            state.insert(key, value);
+        }
+        Ok(())
+    }
+
+    async fn push_hand_event(&self, event: Document) -> StorageResult<()> {
+        if let Some(state) = self.game_state.lock().unwrap().as_mut() {
+            match state.get_array_mut("events") {
+                Ok(events) => events.push(event.into()),
+                Err(_) => {
+                    state.insert("events", vec![event]);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn save_lobby(&self, lobby: Document) -> StorageResult<()> {
+        *self.lobby.lock().unwrap() = Some(lobby);
+        Ok(())
+    }
+
+    async fn lobby(&self) -> StorageResult<Option<Document>> {
+        Ok(self.lobby.lock().unwrap().clone())
+    }
+
+    async fn insert_history(&self, record: Document) -> StorageResult<()> {
+        let hand_id = record.get_str("hand_id").unwrap_or("").to_string();
+        let mut history = self.history.lock().unwrap();
+        if history.iter().any(|h| h.get_str("hand_id") == Ok(hand_id.as_str())) {
+            return Err(StorageError::Duplicate(hand_id));
+        }
+        history.push(record);
+        Ok(())
+    }
+
+    async fn find_history(&self, hand_id: &str) -> StorageResult<Option<Document>> {
+        let history = self.history.lock().unwrap();
+        Ok(history.iter().find(|h| h.get_str("hand_id") == Ok(hand_id)).cloned())
+    }
+
+    async fn list_history(&self) -> StorageResult<Vec<Document>> {
+        Ok(self.history.lock().unwrap().clone())
+    }
+
+    async fn list_player_history(&self, player: &str, from: Option<i64>, to: Option<i64>) -> StorageResult<Vec<Document>> {
+        let mut history: Vec<Document> = self
+            .history
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|h| {
+                let seats = h.get_array("seats").map(|seats| seats.as_slice()).unwrap_or_default();
+                seats.iter().any(|seat| seat.as_document().and_then(|seat| seat.get_str("player").ok()) == Some(player))
+            })
+            .filter(|h| {
+                let timestamp = h.get_i64("timestamp").unwrap_or(0);
+                from.is_none_or(|from| timestamp >= from) && to.is_none_or(|to| timestamp <= to)
+            })
+            .cloned()
+            .collect();
+        history.sort_by_key(|h| h.get_i64("timestamp").unwrap_or(0));
+        Ok(history)
+    }
+
+    async fn page_player_history(&self, player: &str, skip: usize, limit: usize) -> StorageResult<Vec<Document>> {
+        let mut history: Vec<Document> = self
+            .history
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .filter(|h| {
+                let seats = h.get_array("seats").map(|seats| seats.as_slice()).unwrap_or_default();
+                seats.iter().any(|seat| seat.as_document().and_then(|seat| seat.get_str("player").ok()) == Some(player))
+            })
+            .cloned()
+            .collect();
+        history.sort_by_key(|h| std::cmp::Reverse(h.get_i64("timestamp").unwrap_or(0)));
+        Ok(history.into_iter().skip(skip).take(limit).collect())
+    }
+
+    async fn insert_result(&self, record: Document) -> StorageResult<()> {
+        self.results.lock().unwrap().push(record);
+        Ok(())
+    }
+
+    async fn list_results(&self, player: &str, from: Option<i64>, to: Option<i64>) -> StorageResult<Vec<Document>> {
+        let mut results: Vec<Document> = self
+            .results
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|r| r.get_str("player") == Ok(player))
+            .filter(|r| {
+                let timestamp = r.get_i64("timestamp").unwrap_or(0);
+                from.is_none_or(|from| timestamp >= from) && to.is_none_or(|to| timestamp <= to)
+            })
+            .cloned()
+            .collect();
+        results.sort_by_key(|r| r.get_i64("timestamp").unwrap_or(0));
+        Ok(results)
+    }
+
+    async fn result_totals(&self, variant: Option<&str>, from: Option<i64>) -> StorageResult<Vec<Document>> {
+        let mut totals: BTreeMap<String, Document> = BTreeMap::new();
+        let results = self.results.lock().unwrap();
+        let counted = results
+            .iter()
+            .filter(|r| variant.is_none_or(|variant| r.get_str("variant") == Ok(variant)))
+            .filter(|r| from.is_none_or(|from| r.get_i64("timestamp").unwrap_or(0) >= from));
+        for result in counted {
+            let player = result.get_str("player").unwrap_or("");
+            let won = result.get_bool("won").unwrap_or(false);
+            let money_win = result.get_i32("money_win").unwrap_or(0) as i64;
+            let total = totals.entry(player.to_string()).or_insert_with(|| doc! {
+                "player": player,
+                "games_played": 0_i64,
+                "wins": 0_i64,
+                "money_win": 0_i64,
+                "money_lost": 0_i64,
+                "biggest_pot": 0_i64,
+            });
+            apply_inc(total, &doc! {
+                "games_played": 1_i64,
+                "wins": won as i64,
+                "money_win": money_win,
+                "money_lost": result.get_i32("money_lost").unwrap_or(0) as i64,
+            });
+            if won && money_win > total.get_i64("biggest_pot").unwrap_or(0) {
+                total.insert("biggest_pot", money_win);
+            }
+        }
+        Ok(totals.into_values().collect())
+    }
+}
@@ -0,0 +1,394 @@
+//! # Metrics
+//!
+//! Exposes what the dealer is doing in the Prometheus text format, on
+//! `http://127.0.0.1:<port>/metrics` (port 8082 by default, see the `[metrics]`
+//! config section). The listener only binds to localhost, so the numbers are for
+//! the operator and a local Prometheus, not for players.
+//!
+//! Metrics:
+//! - `dealer_connected_clients`: clients connected over TCP or WebSocket.
+//! - `dealer_active_tables`: tables with a hand in progress.
+//! - `dealer_hands_dealt_total{variant}`: hands dealt.
+//! - `dealer_action_latency_seconds{action}`: how long players take to bet or swap
+//!   once it is their turn.
+//! - `dealer_storage_call_duration_seconds{backend,operation}` and
+//!   `dealer_storage_errors_total{backend,operation}`: every storage call, timed by
+//!   `MeteredStorage`.
+//! - `dealer_pot_size_chips{variant}`: the pot of each hand when it ends.
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write as _,
+    future::Future,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use async_trait::async_trait;
+use mongodb::bson::{Bson, Document};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use crate::storage::{Storage, StorageResult};
+use crate::ClientInfo;
+
+/// The dealer's metrics.
+pub static METRICS: Metrics = Metrics::new();
+
+/// Longest request head the endpoint reads.
+const MAX_REQUEST_LEN: usize = 8 * 1024;
+
+/// How long a scrape may take to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Bucket bounds for how long players take to act, in seconds.
+const ACTION_BUCKETS: &[f64] = &[0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0];
+
+/// Bucket bounds for storage calls, in seconds.
+const STORAGE_BUCKETS: &[f64] = &[0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0];
+
+/// Bucket bounds for pot sizes, in chips.
+const POT_BUCKETS: &[f64] = &[10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0, 5000.0];
+
+/// Observations sorted into buckets, as in a Prometheus histogram.
+#[derive(Debug)]
+struct Histogram {
+    /// Observations at or below each bound (not cumulative).
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+/// Histograms of one metric, by their labels.
+#[derive(Debug)]
+struct HistogramFamily {
+    bounds: &'static [f64],
+    /// Rendered labels (e.g. `variant="5card"`) -> histogram.
+    series: BTreeMap<String, Histogram>,
+}
+
+impl HistogramFamily {
+    const fn new(bounds: &'static [f64]) -> Self {
+        Self { bounds, series: BTreeMap::new() }
+    }
+
+    fn observe(&mut self, labels: String, value: f64) {
+        let bounds = self.bounds;
+        let histogram = self.series.entry(labels).or_insert_with(|| Histogram {
+            counts: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        });
+        if let Some(bucket) = bounds.iter().position(|bound| value <= *bound) {
+            histogram.counts[bucket] += 1;
+        }
+        histogram.sum += value;
+        histogram.count += 1;
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        for (labels, histogram) in &self.series {
+            let mut cumulative = 0;
+            for (bound, count) in self.bounds.iter().zip(&histogram.counts) {
+                cumulative += count;
+                let _ = writeln!(out, "{}_bucket{{{},le=\"{}\"}} {}", name, labels, bound, cumulative);
+            }
+            let _ = writeln!(out, "{}_bucket{{{},le=\"+Inf\"}} {}", name, labels, histogram.count);
+            let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, histogram.sum);
+            let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, histogram.count);
+        }
+    }
+}
+
+/// Counters of one metric, by their labels.
+fn render_counters(out: &mut String, name: &str, help: &str, counters: &BTreeMap<String, u64>) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    for (labels, value) in counters {
+        let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+    }
+}
+
+fn render_gauge(out: &mut String, name: &str, help: &str, value: i64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+/// Renders label pairs, e.g. `backend="sqlite",operation="find_player"`.
+fn labels(pairs: &[(&str, &str)]) -> String {
+    pairs
+        .iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, value.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Everything the dealer counts.
+#[derive(Debug)]
+pub struct Metrics {
+    active_tables: AtomicI64,
+    hands_dealt: Mutex<BTreeMap<String, u64>>,
+    action_latency: Mutex<HistogramFamily>,
+    storage_latency: Mutex<HistogramFamily>,
+    storage_errors: Mutex<BTreeMap<String, u64>>,
+    pot_sizes: Mutex<HistogramFamily>,
+}
+
+impl Metrics {
+    /// Creates an empty set of metrics.
+    pub const fn new() -> Self {
+        Self {
+            active_tables: AtomicI64::new(0),
+            hands_dealt: Mutex::new(BTreeMap::new()),
+            action_latency: Mutex::new(HistogramFamily::new(ACTION_BUCKETS)),
+            storage_latency: Mutex::new(HistogramFamily::new(STORAGE_BUCKETS)),
+            storage_errors: Mutex::new(BTreeMap::new()),
+            pot_sizes: Mutex::new(HistogramFamily::new(POT_BUCKETS)),
+        }
+    }
+
+    /// Counts a table as active until the returned guard is dropped, which also
+    /// happens when its game task is aborted.
+    pub fn table_opened(&'static self) -> ActiveTable {
+        self.active_tables.fetch_add(1, Ordering::Relaxed);
+        ActiveTable { metrics: self }
+    }
+
+    /// Counts a hand dealt.
+    pub fn hand_dealt(&self, variant: &str) {
+        *self.hands_dealt.lock().unwrap().entry(labels(&[("variant", variant)])).or_insert(0) += 1;
+    }
+
+    /// Records how long a player took to act.
+    ///
+    /// # Arguments
+    /// * `action` - `"bet"` or `"swap"`.
+    /// * `elapsed` - Time from their turn starting to their answer.
+    pub fn observe_action(&self, action: &str, elapsed: Duration) {
+        self.action_latency.lock().unwrap().observe(labels(&[("action", action)]), elapsed.as_secs_f64());
+    }
+
+    /// Records a storage call, and counts it as an error if it failed.
+    pub fn observe_storage_call(&self, backend: &str, operation: &str, elapsed: Duration, failed: bool) {
+        let labels = labels(&[("backend", backend), ("operation", operation)]);
+        if failed {
+            *self.storage_errors.lock().unwrap().entry(labels.clone()).or_insert(0) += 1;
+        }
+        self.storage_latency.lock().unwrap().observe(labels, elapsed.as_secs_f64());
+    }
+
+    /// Records the pot of a hand that has ended.
+    pub fn observe_pot(&self, variant: &str, pot: i32) {
+        self.pot_sizes.lock().unwrap().observe(labels(&[("variant", variant)]), pot as f64);
+    }
+
+    /// Renders every metric in the Prometheus text format.
+    ///
+    /// # Arguments
+    /// * `connected_clients` - Clients connected right now.
+    pub fn render(&self, connected_clients: usize) -> String {
+        let mut out = String::new();
+        render_gauge(&mut out, "dealer_connected_clients", "Clients connected over TCP or WebSocket.", connected_clients as i64);
+        render_gauge(
+            &mut out,
+            "dealer_active_tables",
+            "Tables with a hand in progress.",
+            self.active_tables.load(Ordering::Relaxed),
+        );
+        render_counters(&mut out, "dealer_hands_dealt_total", "Hands dealt, by variant.", &self.hands_dealt.lock().unwrap());
+        self.action_latency.lock().unwrap().render(
+            &mut out,
+            "dealer_action_latency_seconds",
+            "Time players take to bet or swap once it is their turn.",
+        );
+        self.storage_latency.lock().unwrap().render(
+            &mut out,
+            "dealer_storage_call_duration_seconds",
+            "Time taken by storage calls, by backend and operation.",
+        );
+        render_counters(
+            &mut out,
+            "dealer_storage_errors_total",
+            "Storage calls that failed, by backend and operation.",
+            &self.storage_errors.lock().unwrap(),
+        );
+        self.pot_sizes.lock().unwrap().render(&mut out, "dealer_pot_size_chips", "Pot of each hand when it ends, in chips.");
+        out
+    }
+}
+
+/// A table counted in `dealer_active_tables` while it is held.
+pub struct ActiveTable {
+    metrics: &'static Metrics,
+}
+
+impl Drop for ActiveTable {
+    fn drop(&mut self) {
+        self.metrics.active_tables.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Serves the metrics endpoint for as long as the server runs.
+///
+/// # Arguments
+/// * `listener` - The bound localhost listening socket.
+/// * `clients` - Shared list of connected clients, counted on each scrape.
+pub async fn serve_metrics(listener: TcpListener, clients: Arc<Mutex<HashMap<SocketAddr, ClientInfo>>>) {
+    loop {
+        let (socket, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                eprintln!("[Metrics] Error accepting connection: {}", e);
+                continue;
+            }
+        };
+        let clients = Arc::clone(&clients);
+        tokio::spawn(async move {
+            if let Err(e) = answer_scrape(socket, &clients).await {
+                eprintln!("[Metrics] Failed to answer {}: {}", addr, e);
+            }
+        });
+    }
+}
+
+/// Reads one HTTP request and answers it: the metrics for `GET /metrics`, and
+/// `404` for anything else.
+async fn answer_scrape(mut socket: TcpStream, clients: &Mutex<HashMap<SocketAddr, ClientInfo>>) -> std::io::Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < MAX_REQUEST_LEN {
+        let n = match tokio::time::timeout(REQUEST_TIMEOUT, socket.read(&mut buf)).await {
+            Ok(read) => read?,
+            Err(_) => return Ok(()),
+        };
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+
+    let head = String::from_utf8_lossy(&request);
+    let mut request_line = head.lines().next().unwrap_or("").split_whitespace();
+    let (status, content_type, body) = match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let connected = clients.lock().unwrap().len();
+            ("200 OK", "text/plain; version=0.0.4", METRICS.render(connected))
+        }
+        _ => ("404 Not Found", "text/plain", "Not found. Metrics are at /metrics.\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    socket.write_all(response.as_bytes()).await?;
+    socket.shutdown().await
+}
+
+/// A storage backend whose calls are timed and counted in `METRICS`.
+pub struct MeteredStorage {
+    inner: Arc<dyn Storage>,
+}
+
+impl MeteredStorage {
+    /// Wraps a backend so its calls are measured.
+    pub fn wrap(inner: Arc<dyn Storage>) -> Arc<dyn Storage> {
+        Arc::new(Self { inner })
+    }
+
+    async fn timed<T>(&self, operation: &str, call: impl Future<Output = StorageResult<T>>) -> StorageResult<T> {
+        let started = Instant::now();
+        let result = call.await;
+        METRICS.observe_storage_call(self.inner.backend_name(), operation, started.elapsed(), result.is_err());
+        result
+    }
+}
+
+#[async_trait]
+impl Storage for MeteredStorage {
+    fn backend_name(&self) -> &'static str {
+        self.inner.backend_name()
+    }
+
+    async fn find_player(&self, name: &str) -> StorageResult<Option<Document>> {
+        self.timed("find_player", self.inner.find_player(name)).await
+    }
+
+    async fn insert_player(&self, player: Document) -> StorageResult<()> {
+        self.timed("insert_player", self.inner.insert_player(player)).await
+    }
+
+    async fn set_player_fields(&self, name: &str, fields: Document) -> StorageResult<bool> {
+        self.timed("set_player_fields", self.inner.set_player_fields(name, fields)).await
+    }
+
+    async fn increment_player_fields(&self, name: &str, amounts: Document) -> StorageResult<bool> {
+        self.timed("increment_player_fields", self.inner.increment_player_fields(name, amounts)).await
+    }
+
+    async fn list_players(&self) -> StorageResult<Vec<Document>> {
+        self.timed("list_players", self.inner.list_players()).await
+    }
+
+    async fn reset_game_state(&self, state: Document) -> StorageResult<()> {
+        self.timed("reset_game_state", self.inner.reset_game_state(state)).await
+    }
+
+    async fn game_state(&self) -> StorageResult<Option<Document>> {
+        self.timed("game_state", self.inner.game_state()).await
+    }
+
+    async fn set_game_state_field(&self, key: &str, value: Bson) -> StorageResult<()> {
+        self.timed("set_game_state_field", self.inner.set_game_state_field(key, value)).await
+    }
+
+    async fn push_hand_event(&self, event: Document) -> StorageResult<()> {
+        self.timed("push_hand_event", self.inner.push_hand_event(event)).await
+    }
+
+    async fn save_lobby(&self, lobby: Document) -> StorageResult<()> {
+        self.timed("save_lobby", self.inner.save_lobby(lobby)).await
+    }
+
+    async fn lobby(&self) -> StorageResult<Option<Document>> {
+        self.timed("lobby", self.inner.lobby()).await
+    }
+
+    async fn insert_history(&self, record: Document) -> StorageResult<()> {
+        self.timed("insert_history", self.inner.insert_history(record)).await
+    }
+
+    async fn find_history(&self, hand_id: &str) -> StorageResult<Option<Document>> {
+        self.timed("find_history", self.inner.find_history(hand_id)).await
+    }
+
+    async fn list_history(&self) -> StorageResult<Vec<Document>> {
+        self.timed("list_history", self.inner.list_history()).await
+    }
+
+    async fn list_player_history(&self, player: &str, from: Option<i64>, to: Option<i64>) -> StorageResult<Vec<Document>> {
+        self.timed("list_player_history", self.inner.list_player_history(player, from, to)).await
+    }
+
+    async fn page_player_history(&self, player: &str, skip: usize, limit: usize) -> StorageResult<Vec<Document>> {
+        self.timed("page_player_history", self.inner.page_player_history(player, skip, limit)).await
+    }
+
+    async fn insert_result(&self, record: Document) -> StorageResult<()> {
+        self.timed("insert_result", self.inner.insert_result(record)).await
+    }
+
+    async fn list_results(&self, player: &str, from: Option<i64>, to: Option<i64>) -> StorageResult<Vec<Document>> {
+        self.timed("list_results", self.inner.list_results(player, from, to)).await
+    }
+
+    async fn result_totals(&self, variant: Option<&str>, from: Option<i64>) -> StorageResult<Vec<Document>> {
+        self.timed("result_totals", self.inner.result_totals(variant, from)).await
+    }
+}
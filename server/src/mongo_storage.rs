@@ -0,0 +1,283 @@
+//! # MongoDB Storage
+//!
+//! The `Storage` backend backed by the MongoDB `dealer` database.
+//!
+//! Collections used:
+//! - `players`: Stores player data and stats, keyed by a unique `name`.
+//! - `lobbies`: Stores game lobby information (document with `_id = 1`).
+//! - `games`: Stores the active game state (document with `_id = 1`).
+//! - `history`: Stores completed hands, keyed by a unique `hand_id`, and indexed
+//!   by the players seated and when the hand started.
+//! - `results`: Stores each player's result in each hand, with when it happened,
+//!   indexed by player and time, and by variant and time for the leaderboards.
+use async_trait::async_trait;
+use futures_util::stream::StreamExt;
+use mongodb::{
+    bson::{doc, Bson, Document},
+    error::{ErrorKind, WriteFailure},
+    options::{ClientOptions, IndexOptions, ServerApi, ServerApiVersion},
+    Client, Collection, IndexModel,
+};
+use crate::storage::*;
+
+
+/// Storage backend using MongoDB collections.
+#[derive(Debug, Clone)]
+pub struct MongoStorage {
+    players: Collection<Document>,
+    lobbies: Collection<Document>,
+    games: Collection<Document>,
+    history: Collection<Document>,
+    results: Collection<Document>,
+}
+
+impl MongoStorage {
+    /// Connects to MongoDB and opens the collections of the given database.
+    ///
+    /// # Arguments
+    /// * `uri` - MongoDB connection string (e.g. `"mongodb://localhost:27017"`).
+    /// * `database` - Name of the database holding the collections.
+    ///
+    /// # Errors
+    /// Returns an error if the connection options cannot be parsed or the client fails to start.
+    pub async fn connect(uri: &str, database: &str) -> mongodb::error::Result<Self> {
+        let mut client_options = ClientOptions::parse(uri).await?;
+        let server_api = ServerApi::builder().version(ServerApiVersion::V1).build();
+        client_options.server_api = Some(server_api);
+
+        let client = Client::with_options(client_options)?;
+        let db = client.database(database);
+
+        Ok(Self {
+            players: db.collection("players"),
+            lobbies: db.collection("lobbies"),
+            games: db.collection("games"),
+            history: db.collection("history"),
+            results: db.collection("results"),
+        })
+    }
+
+    /// Creates the indexes the lookups by player, variant and time use, and the
+    /// unique indexes that keep one account per name and one copy of each hand.
+    /// Indexes that already exist are left as they are, so this is safe on every
+    /// start.
+    pub async fn create_indexes(&self) -> StorageResult<()> {
+        let unique = IndexOptions::builder().unique(true).build();
+        let by_name = IndexModel::builder().keys(doc! { "name": 1 }).options(unique.clone()).build();
+        self.players.create_index(by_name).await?;
+        let by_hand_id = IndexModel::builder().keys(doc! { "hand_id": 1 }).options(unique).build();
+        self.history.create_index(by_hand_id).await?;
+        let by_seat_and_time = IndexModel::builder().keys(doc! { "seats.player": 1, "timestamp": 1 }).build();
+        self.history.create_index(by_seat_and_time).await?;
+        let by_player_and_time = IndexModel::builder().keys(doc! { "player": 1, "timestamp": 1 }).build();
+        self.results.create_index(by_player_and_time).await?;
+        let by_variant_and_time = IndexModel::builder().keys(doc! { "variant": 1, "timestamp": 1 }).build();
+        self.results.create_index(by_variant_and_time).await?;
+        let by_time = IndexModel::builder().keys(doc! { "timestamp": 1 }).build();
+        self.results.create_index(by_time).await?;
+        Ok(())
+    }
+
+    /// Collects every document matched by `filter` in a collection.
+    async fn find_all(collection: &Collection<Document>, filter: Document) -> StorageResult<Vec<Document>> {
+        let mut cursor = collection.find(filter).await?;
+        let mut docs = Vec::new();
+        while let Some(result) = cursor.next().await {
+            docs.push(result?);
+        }
+        Ok(docs)
+    }
+}
+
+#[async_trait]
+impl Storage for MongoStorage {
+    fn backend_name(&self) -> &'static str {
+        "mongodb"
+    }
+
+    async fn find_player(&self, name: &str) -> StorageResult<Option<Document>> {
+        Ok(self.players.find_one(doc! { "name": name }).await?)
+    }
+
+    async fn insert_player(&self, player: Document) -> StorageResult<()> {
+        let name = player.get_str("name").unwrap_or("").to_string();
+        match self.players.insert_one(player).await {
+            Ok(_) => Ok(()),
+            Err(e) if is_duplicate_key(&e) => Err(StorageError::Duplicate(name)),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn set_player_fields(&self, name: &str, fields: Document) -> StorageResult<bool> {
+        let result = self
+            .players
+            .update_one(doc! { "name": name }, doc! { "$set": fields })
+            .await?;
+        Ok(result.matched_count > 0)
+    }
+
+    async fn increment_player_fields(&self, name: &str, amounts: Document) -> StorageResult<bool> {
+        let result = self
+            .players
+            .update_one(doc! { "name": name }, doc! { "$inc": amounts })
+            .await?;
+        Ok(result.matched_count > 0)
+    }
+
+    async fn list_players(&self) -> StorageResult<Vec<Document>> {
+        Self::find_all(&self.players, doc! {}).await
+    }
+
+    async fn reset_game_state(&self, state: Document) -> StorageResult<()> {
+        let deleted = self.games.delete_many(doc! {}).await?;
+        println!("[DB] Cleared games collection ({} docs deleted)", deleted.deleted_count);
+
+        self.games.insert_one(state).await?;
+        Ok(())
+    }
+
+    async fn game_state(&self) -> StorageResult<Option<Document>> {
+        Ok(self.games.find_one(doc! { "_id": 1 }).await?)
+    }
+
+    async fn set_game_state_field(&self, key: &str, value: Bson) -> StorageResult<()> {
+        self.games
+            .update_one(doc! { "_id": 1 }, doc! { "$set": { key: value } })
+            .await?;
+        Ok(())
+    }
+
+    async fn push_hand_event(&self, event: Document) -> StorageResult<()> {
+        self.games
+            .update_one(doc! { "_id": 1 }, doc! { "$push": { "events": event } })
+            .await?;
+        Ok(())
+    }
+
+    async fn save_lobby(&self, mut lobby: Document) -> StorageResult<()> {
+        lobby.insert("_id", 1);
+        self.lobbies
+            .replace_one(doc! { "_id": 1 }, lobby)
+            .upsert(true)
+            .await?;
+        Ok(())
+    }
+
+    async fn lobby(&self) -> StorageResult<Option<Document>> {
+        Ok(self.lobbies.find_one(doc! { "_id": 1 }).await?)
+    }
+
+    async fn insert_history(&self, record: Document) -> StorageResult<()> {
+        let hand_id = record.get_str("hand_id").unwrap_or("").to_string();
+        match self.history.insert_one(record).await {
+            Ok(_) => Ok(()),
+            Err(e) if is_duplicate_key(&e) => Err(StorageError::Duplicate(hand_id)),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn find_history(&self, hand_id: &str) -> StorageResult<Option<Document>> {
+        Ok(self.history.find_one(doc! { "hand_id": hand_id }).await?)
+    }
+
+    async fn list_history(&self) -> StorageResult<Vec<Document>> {
+        Self::find_all(&self.history, doc! {}).await
+    }
+
+    async fn list_player_history(&self, player: &str, from: Option<i64>, to: Option<i64>) -> StorageResult<Vec<Document>> {
+        let mut timestamp = Document::new();
+        if let Some(from) = from {
+            timestamp.insert("$gte", from);
+        }
+        if let Some(to) = to {
+            timestamp.insert("$lte", to);
+        }
+        let mut filter = doc! { "seats.player": player };
+        if !timestamp.is_empty() {
+            filter.insert("timestamp", timestamp);
+        }
+        let mut history = Self::find_all(&self.history, filter).await?;
+        history.sort_by_key(|h| h.get_i64("timestamp").unwrap_or(0));
+        Ok(history)
+    }
+
+    async fn page_player_history(&self, player: &str, skip: usize, limit: usize) -> StorageResult<Vec<Document>> {
+        let mut cursor = self
+            .history
+            .find(doc! { "seats.player": player })
+            .sort(doc! { "timestamp": -1, "_id": -1 })
+            .skip(skip as u64)
+            .limit(limit as i64)
+            .await?;
+        let mut docs = Vec::new();
+        while let Some(result) = cursor.next().await {
+            docs.push(result?);
+        }
+        Ok(docs)
+    }
+
+    async fn insert_result(&self, record: Document) -> StorageResult<()> {
+        self.results.insert_one(record).await?;
+        Ok(())
+    }
+
+    async fn list_results(&self, player: &str, from: Option<i64>, to: Option<i64>) -> StorageResult<Vec<Document>> {
+        let mut timestamp = Document::new();
+        if let Some(from) = from {
+            timestamp.insert("$gte", from);
+        }
+        if let Some(to) = to {
+            timestamp.insert("$lte", to);
+        }
+        let mut filter = doc! { "player": player };
+        if !timestamp.is_empty() {
+            filter.insert("timestamp", timestamp);
+        }
+        let mut results = Self::find_all(&self.results, filter).await?;
+        results.sort_by_key(|r| r.get_i64("timestamp").unwrap_or(0));
+        Ok(results)
+    }
+
+    async fn result_totals(&self, variant: Option<&str>, from: Option<i64>) -> StorageResult<Vec<Document>> {
+        let mut filter = Document::new();
+        if let Some(variant) = variant {
+            filter.insert("variant", variant);
+        }
+        if let Some(from) = from {
+            filter.insert("timestamp", doc! { "$gte": from });
+        }
+        let pipeline = vec![
+            doc! { "$match": filter },
+            doc! { "$group": {
+                "_id": "$player",
+                "games_played": { "$sum": 1_i64 },
+                "wins": { "$sum": { "$cond": ["$won", 1_i64, 0_i64] } },
+                "money_win": { "$sum": { "$toLong": "$money_win" } },
+                "money_lost": { "$sum": { "$toLong": "$money_lost" } },
+                "biggest_pot": { "$max": { "$cond": ["$won", { "$toLong": "$money_win" }, 0_i64] } },
+            } },
+            doc! { "$project": {
+                "_id": 0,
+                "player": "$_id",
+                "games_played": 1,
+                "wins": 1,
+                "money_win": 1,
+                "money_lost": 1,
+                "biggest_pot": 1,
+            } },
+            doc! { "$sort": { "player": 1 } },
+        ];
+        let mut cursor = self.results.aggregate(pipeline).await?;
+        let mut totals = Vec::new();
+        while let Some(total) = cursor.next().await {
+            totals.push(total?);
+        }
+        Ok(totals)
+    }
+}
+
+/// Whether an insert failed because a unique index already holds its key.
+fn is_duplicate_key(error: &mongodb::error::Error) -> bool {
+    const DUPLICATE_KEY: i32 = 11000;
+    matches!(&*error.kind, ErrorKind::Write(WriteFailure::WriteError(e)) if e.code == DUPLICATE_KEY)
+}
@@ -0,0 +1,279 @@
+//! # Rate Limiting
+//!
+//! Flood protection for the dispatcher, configured by the `[limits]` section.
+//!
+//! - Every connection, and every account over all of its connections, has a token
+//!   bucket: `message_burst` messages at once, refilled at `messages_per_second`.
+//! - Failed logins are counted per address, and per account over every address,
+//!   over a sliding window of `login_window_secs`.
+//!
+//! Flooding bans the address, and the account if the connection is logged in, for
+//! `ban_secs`. The dispatcher disconnects the offending connection and turns away
+//! anything else from that address or account until the ban expires; a banned
+//! account cannot `login` or `bind` from a fresh address either.
+//!
+//! Too many failed logins from an address ban the address. Too many for one
+//! account, however many addresses they are spread over, stop password logins to
+//! it for `ban_secs`. That is not a ban: anyone can type the account's name, so
+//! its owner's sessions that are already signed in carry on.
+//!
+//! Every `SWEEP_INTERVAL` the limiter forgets whatever no longer limits anyone
+//! (refilled buckets, failures out of the window and expired bans), so addresses
+//! and usernames that are sprayed at it do not pile up.
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+use crate::config::LimitsConfig;
+
+/// How often state that no longer limits anyone is dropped.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Allows `capacity` events at once, refilled continuously at `rate` per second.
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn full(capacity: f64, now: Instant) -> Self {
+        Self { tokens: capacity, last: now }
+    }
+
+    /// Whether the bucket would have refilled by `now`.
+    fn is_full(&self, capacity: f64, rate: f64, now: Instant) -> bool {
+        self.tokens + now.saturating_duration_since(self.last).as_secs_f64() * rate >= capacity
+    }
+
+    /// Takes a token if one is available.
+    fn try_take(&mut self, capacity: f64, rate: f64, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(capacity);
+        self.last = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Message and login limits for every connection and account.
+#[derive(Debug)]
+pub struct RateLimiter {
+    config: LimitsConfig,
+    connections: HashMap<SocketAddr, TokenBucket>,
+    accounts: HashMap<String, TokenBucket>,
+    /// Recent failed logins per address, and per account named.
+    address_failures: HashMap<IpAddr, Vec<Instant>>,
+    account_failures: HashMap<String, Vec<Instant>>,
+    /// Ban expiry times.
+    banned_addresses: HashMap<IpAddr, Instant>,
+    banned_accounts: HashMap<String, Instant>,
+    /// Accounts that may not log in with a password until the given time.
+    locked_accounts: HashMap<String, Instant>,
+    last_sweep: Instant,
+}
+
+impl RateLimiter {
+    /// Creates a limiter with no history.
+    pub fn new(config: LimitsConfig) -> Self {
+        Self {
+            config,
+            connections: HashMap::new(),
+            accounts: HashMap::new(),
+            address_failures: HashMap::new(),
+            account_failures: HashMap::new(),
+            banned_addresses: HashMap::new(),
+            banned_accounts: HashMap::new(),
+            locked_accounts: HashMap::new(),
+            last_sweep: Instant::now(),
+        }
+    }
+
+    /// Checks a message from a connection against the bans and message limits.
+    ///
+    /// # Arguments
+    /// * `addr` - The connection the message came from.
+    /// * `account` - The user the connection is bound to, if any.
+    /// * `now` - The current time.
+    ///
+    /// # Returns
+    /// An error message if the connection should be disconnected. Going over a
+    /// limit also bans the address and account.
+    pub fn check_message(&mut self, addr: &SocketAddr, account: Option<&str>, now: Instant) -> Result<(), String> {
+        if now.saturating_duration_since(self.last_sweep) >= SWEEP_INTERVAL {
+            self.sweep(now);
+        }
+        if let Some(remaining) = self.ban_remaining(addr, account, now) {
+            return Err(banned_message(remaining));
+        }
+
+        let capacity = self.config.message_burst as f64;
+        let rate = self.config.messages_per_second;
+        let connection_ok = self
+            .connections
+            .entry(*addr)
+            .or_insert_with(|| TokenBucket::full(capacity, now))
+            .try_take(capacity, rate, now);
+        let account_ok = match account {
+            Some(account) => self
+                .accounts
+                .entry(account.to_string())
+                .or_insert_with(|| TokenBucket::full(capacity, now))
+                .try_take(capacity, rate, now),
+            None => true,
+        };
+
+        if connection_ok && account_ok {
+            return Ok(());
+        }
+        println!("[Limits] {} ({}) is sending too many messages", addr, account.unwrap_or("not logged in"));
+        self.ban_address(addr.ip(), now);
+        if let Some(account) = account {
+            self.ban_account(account, now);
+        }
+        Err(format!("Too many messages. {}", banned_message(self.ban_duration())))
+    }
+
+    /// How long until an account may log in again, if it is banned.
+    pub fn account_ban_remaining(&mut self, account: &str, now: Instant) -> Option<Duration> {
+        let until = *self.banned_accounts.get(account)?;
+        if until <= now {
+            self.banned_accounts.remove(account);
+            return None;
+        }
+        Some(until - now)
+    }
+
+    /// Checks a password login before the password is verified.
+    ///
+    /// # Arguments
+    /// * `account` - The account the connection is logging in as.
+    /// * `now` - The current time.
+    ///
+    /// # Returns
+    /// An error message if the account is banned, or has had too many failed
+    /// logins to try another.
+    pub fn check_login(&mut self, account: &str, now: Instant) -> Result<(), String> {
+        if let Some(remaining) = self.account_ban_remaining(account, now) {
+            return Err(banned_message(remaining));
+        }
+        match self.locked_accounts.get(account) {
+            Some(until) if *until > now => Err(format!(
+                "Too many failed logins for {}. Try again in {} seconds.",
+                account,
+                (*until - now).as_secs().max(1)
+            )),
+            Some(_) => {
+                self.locked_accounts.remove(account);
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Records a failed login.
+    ///
+    /// # Arguments
+    /// * `addr` - The connection that tried to log in.
+    /// * `account` - The account it tried to log in as.
+    /// * `now` - The current time.
+    ///
+    /// # Returns
+    /// An error message if the connection should be disconnected: its address has
+    /// failed too often, and is now banned.
+    pub fn record_login_failure(&mut self, addr: &SocketAddr, account: &str, now: Instant) -> Result<(), String> {
+        let window = Duration::from_secs(self.config.login_window_secs);
+        let failures = self.account_failures.entry(account.to_string()).or_default();
+        failures.retain(|at| now.saturating_duration_since(*at) < window);
+        failures.push(now);
+        if failures.len() > self.config.account_login_attempts as usize {
+            println!("[Limits] Too many failed logins for {}", account);
+            self.account_failures.remove(account);
+            self.locked_accounts.insert(account.to_string(), now + self.ban_duration());
+        }
+
+        let failures = self.address_failures.entry(addr.ip()).or_default();
+        failures.retain(|at| now.saturating_duration_since(*at) < window);
+        failures.push(now);
+
+        if failures.len() > self.config.login_attempts as usize {
+            println!("[Limits] Too many failed logins from {}", addr.ip());
+            self.address_failures.remove(&addr.ip());
+            self.ban_address(addr.ip(), now);
+            return Err(format!("Too many failed logins. {}", banned_message(self.ban_duration())));
+        }
+        Ok(())
+    }
+
+    /// Forgets a closed connection's message count. Bans stay in place.
+    pub fn forget(&mut self, addr: &SocketAddr) {
+        self.connections.remove(addr);
+    }
+
+    /// Drops buckets that have refilled, failed logins out of the window and
+    /// expired bans. None of them limit anyone any more.
+    fn sweep(&mut self, now: Instant) {
+        let capacity = self.config.message_burst as f64;
+        let rate = self.config.messages_per_second;
+        let window = Duration::from_secs(self.config.login_window_secs);
+        let recent = |failures: &mut Vec<Instant>| {
+            failures.retain(|at| now.saturating_duration_since(*at) < window);
+            !failures.is_empty()
+        };
+
+        self.connections.retain(|_, bucket| !bucket.is_full(capacity, rate, now));
+        self.accounts.retain(|_, bucket| !bucket.is_full(capacity, rate, now));
+        self.address_failures.retain(|_, failures| recent(failures));
+        self.account_failures.retain(|_, failures| recent(failures));
+        self.banned_addresses.retain(|_, until| *until > now);
+        self.banned_accounts.retain(|_, until| *until > now);
+        self.locked_accounts.retain(|_, until| *until > now);
+        self.last_sweep = now;
+    }
+
+    /// How many addresses, connections and accounts the limiter holds anything for.
+    #[cfg(test)]
+    pub fn tracked(&self) -> usize {
+        self.connections.len()
+            + self.accounts.len()
+            + self.address_failures.len()
+            + self.account_failures.len()
+            + self.banned_addresses.len()
+            + self.banned_accounts.len()
+            + self.locked_accounts.len()
+    }
+
+    fn ban_duration(&self) -> Duration {
+        Duration::from_secs(self.config.ban_secs)
+    }
+
+    fn ban_address(&mut self, ip: IpAddr, now: Instant) {
+        self.banned_addresses.insert(ip, now + self.ban_duration());
+    }
+
+    fn ban_account(&mut self, account: &str, now: Instant) {
+        self.banned_accounts.insert(account.to_string(), now + self.ban_duration());
+    }
+
+    /// Time left on a ban covering this address or account, dropping expired bans.
+    fn ban_remaining(&mut self, addr: &SocketAddr, account: Option<&str>, now: Instant) -> Option<Duration> {
+        let address = match self.banned_addresses.get(&addr.ip()) {
+            Some(until) if *until > now => Some(*until - now),
+            Some(_) => {
+                self.banned_addresses.remove(&addr.ip());
+                None
+            }
+            None => None,
+        };
+        let account = account.and_then(|account| self.account_ban_remaining(account, now));
+        address.max(account)
+    }
+}
+
+/// Tells a banned client when to come back.
+pub fn banned_message(remaining: Duration) -> String {
+    format!("You are temporarily banned. Try again in {} seconds.", remaining.as_secs().max(1))
+}
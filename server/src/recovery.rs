@@ -0,0 +1,237 @@
+//! # Crash Recovery
+//!
+//! Lets a hand survive a server crash or restart.
+//!
+//! While a hand is played, the game state keeps a `live_hand` document with
+//! everything needed to play it again: the seat order, the shuffle seed and the
+//! deck order it produces, every bet and swap the game has taken, and the turn it
+//! is waiting on. The field is cleared when the hand ends or is voided.
+//!
+//! If the server starts and finds a `live_hand`, it resumes that hand instead of
+//! starting a new game:
+//!
+//! 1. Stats and chips already written for the hand are taken back
+//!    (`db::refund_folded_charges`), since playing it again writes them again.
+//! 2. The players are seated with no connection, and hold their seats for the
+//!    usual grace period (see `departures.rs`), reported for a placeholder
+//!    address (`0.0.0.0:<seat>`).
+//! 3. The game runs again with the stored hand's `Replay`, which `spawn_game`
+//!    hands to the variant and on to `play_hand`. The deck is shuffled from the
+//!    same seed, so the same cards are dealt, the hand keeps its ID, and each bet
+//!    and swap is answered from the recorded actions until it reaches the pending
+//!    turn. From there it is played as normal.
+//!
+//! Players who log in again take back their seat and get a snapshot of the hand.
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use mongodb::bson;
+use serde::{Deserialize, Serialize};
+use crate::deck::Deck;
+use crate::storage::{Storage, StorageResult};
+
+/// Game state field holding the hand in progress.
+const LIVE_HAND: &str = "live_hand";
+
+/// What is stored about the hand in progress.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LiveHand {
+    /// ID of the hand, as in its history record.
+    pub hand_id: String,
+    /// The game variant being played.
+    pub variant: String,
+    /// Players in seat order.
+    pub players: Vec<String>,
+    /// Seed the deck is shuffled from, as 16 hex digits.
+    pub shuffle_seed: String,
+    /// The deck order the seed produces, top card first.
+    pub deck: Vec<String>,
+    /// Every bet and swap the game has taken, in order.
+    pub actions: Vec<TurnAction>,
+    /// The turn the game is waiting on, if any.
+    pub pending_turn: Option<PendingTurn>,
+}
+
+/// A bet or swap the game has taken.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum TurnAction {
+    /// Chips added, `0` to check or `-1` to fold.
+    Bet { player: String, amount: i32 },
+    /// Comma-separated indices of the cards replaced.
+    Swap { player: String, indices: String },
+}
+
+/// A turn the game is waiting on.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PendingTurn {
+    pub player: String,
+    /// `"bet"` or `"swap"`.
+    pub action: String,
+}
+
+/// Recorded actions still to be played again.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Replay {
+    hand_id: String,
+    shuffle_seed: u64,
+    actions: VecDeque<TurnAction>,
+}
+
+impl Replay {
+    /// Prepares to play a stored hand again.
+    ///
+    /// # Returns
+    /// `None` if the stored shuffle seed is not valid.
+    pub fn new(hand: &LiveHand) -> Option<Self> {
+        Some(Self {
+            hand_id: hand.hand_id.clone(),
+            shuffle_seed: u64::from_str_radix(&hand.shuffle_seed, 16).ok()?,
+            actions: hand.actions.iter().cloned().collect(),
+        })
+    }
+
+    /// ID of the hand being resumed, which it keeps.
+    pub fn hand_id(&self) -> &str {
+        &self.hand_id
+    }
+
+    /// Seed the hand's deck was shuffled from.
+    pub fn shuffle_seed(&self) -> u64 {
+        self.shuffle_seed
+    }
+
+    /// Takes the next recorded action if it is this player's bet.
+    pub fn next_bet(&mut self, player: &str) -> Option<i32> {
+        match self.actions.front() {
+            Some(TurnAction::Bet { player: p, amount }) if p == player => {
+                let amount = *amount;
+                self.actions.pop_front();
+                Some(amount)
+            }
+            _ => None,
+        }
+    }
+
+    /// Takes the next recorded action if it is this player's swap.
+    pub fn next_swap(&mut self, player: &str) -> Option<String> {
+        match self.actions.front() {
+            Some(TurnAction::Swap { player: p, indices }) if p == player => {
+                let indices = indices.clone();
+                self.actions.pop_front();
+                Some(indices)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether every recorded action has been played again.
+    pub fn is_done(&self) -> bool {
+        self.actions.is_empty()
+    }
+}
+
+/// Loads the hand that was in progress when the server stopped, if any.
+pub async fn load_live_hand(storage: &dyn Storage) -> StorageResult<Option<LiveHand>> {
+    let Some(state) = storage.game_state().await? else {
+        return Ok(None);
+    };
+    match state.get(LIVE_HAND) {
+        Some(bson::Bson::Document(hand)) => Ok(Some(bson::from_document(hand.clone())?)),
+        _ => Ok(None),
+    }
+}
+
+/// The seed for a hand's deck: the resumed hand's, or a random one for a new hand.
+pub fn shuffle_seed(replay: Option<&Replay>) -> u64 {
+    replay.map_or_else(rand::random, Replay::shuffle_seed)
+}
+
+/// Answers a turn from the replay, if the recorded action is next, and ends the
+/// replay once it has caught up with the recorded actions or the game asks for
+/// something else.
+///
+/// # Arguments
+/// * `replay` - The hand's replay; `None` once it has ended, or for a new hand.
+/// * `next` - Takes the action, e.g. `|replay| replay.next_bet(player)`.
+///
+/// # Returns
+/// The recorded action, or `None` if the player has to be asked.
+pub fn replayed<T>(replay: &mut Option<Replay>, next: impl FnOnce(&mut Replay) -> Option<T>) -> Option<T> {
+    let current = replay.as_mut()?;
+    let action = next(current);
+    if action.is_none() || current.is_done() {
+        if !current.is_done() {
+            eprintln!("[Recovery] The game no longer matches the recorded actions; playing on from here");
+        }
+        println!("[Recovery] Caught up with hand {}", current.hand_id);
+        *replay = None;
+    }
+    action
+}
+
+/// Saves a newly started hand as the live hand. A resumed hand is already stored.
+///
+/// # Arguments
+/// * `storage` - The server's storage backend.
+/// * `hand_id` - ID of the hand.
+/// * `variant` - The game variant being played.
+/// * `players` - Players in seat order.
+/// * `shuffle_seed` - Seed the deck is shuffled from.
+pub async fn begin_live_hand(
+    storage: &dyn Storage,
+    hand_id: &str,
+    variant: &str,
+    players: &[String],
+    shuffle_seed: u64,
+) -> StorageResult<()> {
+    let hand = LiveHand {
+        hand_id: hand_id.to_string(),
+        variant: variant.to_string(),
+        players: players.to_vec(),
+        shuffle_seed: format!("{:016x}", shuffle_seed),
+        deck: Deck::with_seed(shuffle_seed).cards.iter().rev().map(|card| card.to_string()).collect(),
+        actions: Vec::new(),
+        pending_turn: None,
+    };
+    storage.set_game_state_field(LIVE_HAND, bson::to_bson(&hand)?).await
+}
+
+/// Records the turn the game is now waiting on.
+pub async fn set_pending_turn(storage: &dyn Storage, player: &str, action: &str) -> StorageResult<()> {
+    update_live_hand(storage, |hand| {
+        hand.pending_turn = Some(PendingTurn {
+            player: player.to_string(),
+            action: action.to_string(),
+        });
+    })
+    .await
+}
+
+/// Records an action the game has taken, ending the pending turn.
+pub async fn record_action(storage: &dyn Storage, action: TurnAction) -> StorageResult<()> {
+    update_live_hand(storage, |hand| {
+        hand.actions.push(action);
+        hand.pending_turn = None;
+    })
+    .await
+}
+
+/// Clears the live hand once it has ended or been voided.
+pub async fn end_live_hand(storage: &dyn Storage) -> StorageResult<()> {
+    storage.set_game_state_field(LIVE_HAND, bson::Bson::Null).await
+}
+
+/// Changes the live hand, if there is one.
+async fn update_live_hand(storage: &dyn Storage, change: impl FnOnce(&mut LiveHand)) -> StorageResult<()> {
+    let Some(mut hand) = load_live_hand(storage).await? else {
+        return Ok(());
+    };
+    change(&mut hand);
+    storage.set_game_state_field(LIVE_HAND, bson::to_bson(&hand)?).await
+}
+
+/// The connection a resumed player's grace period is reported for, since they
+/// have none yet: a placeholder address that no real client has.
+pub fn placeholder_addr(seat: usize) -> SocketAddr {
+    SocketAddr::from(([0, 0, 0, 0], seat as u16))
+}
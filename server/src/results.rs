@@ -0,0 +1,177 @@
+//! # Results
+//!
+//! Keeps a timestamped record of each player's result in each hand, so stats can
+//! be asked for over a date range and a player's bankroll drawn over time.
+//!
+//! The player document's totals (`wins`, `money_win`, ...) are running `$inc`
+//! updates with no time attached, so they only answer "all time". Each result
+//! recorded here carries the same amounts, with the same signs, as the updates
+//! the hand makes for that player: one from `db::update_game_results` for a
+//! player still in at the end, or one from `db::update_players_folded` when the
+//! round they folded in ends. Adding up a player's results from the same hands
+//! gives the same totals, so a range can be answered from the results alone.
+//!
+//! Results are written once a hand has a winner. A voided hand never gets one, so
+//! there is nothing to take back.
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+use mongodb::bson;
+use serde::{Deserialize, Serialize};
+use crate::five_card_draw::Player;
+use crate::storage::*;
+use protocol::{BankrollPoint, PlayerStats, VariantStats};
+
+/// One player's result in one hand.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResultRecord {
+    pub player: String,
+    pub hand_id: String,
+    /// When the hand ended, in seconds since the Unix epoch.
+    pub timestamp: i64,
+    /// Game variant (`"5card"`, `"7card"` or `"texas"`).
+    pub variant: String,
+    pub won: bool,
+    /// Chips won from the pot.
+    pub money_win: i32,
+    /// Chips put in, as a negative amount like the player's `money_lost` total.
+    pub money_lost: i32,
+}
+
+impl ResultRecord {
+    /// Chips won minus chips put in.
+    pub fn net(&self) -> i32 {
+        self.money_win + self.money_lost
+    }
+}
+
+/// One player's results added up, as `Storage::result_totals` returns them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResultTotals {
+    pub player: String,
+    pub games_played: i64,
+    pub wins: i64,
+    /// Chips won from pots.
+    pub money_win: i64,
+    /// Chips put in, as a negative amount.
+    pub money_lost: i64,
+    /// Most chips won in one hand, 0 if the player never won.
+    pub biggest_pot: i64,
+}
+
+/// Records the result of a finished hand for every player in it.
+///
+/// # Arguments
+/// * `storage` - The server's storage backend.
+/// * `variant` - The game variant played.
+/// * `hand_id` - ID of the hand.
+/// * `winner` - The player who won the pot.
+/// * `players` - Every player dealt in, folded or not.
+/// * `pot` - Total pot paid to the winner.
+///
+/// # Returns
+/// Storage operation result.
+pub async fn record_results(
+    storage: &dyn Storage,
+    variant: &str,
+    hand_id: &str,
+    winner: &str,
+    players: &[Player],
+    pot: i32,
+) -> StorageResult<()> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64;
+    for player in players {
+        let won = player.id == winner;
+        let record = ResultRecord {
+            player: player.id.clone(),
+            hand_id: hand_id.to_string(),
+            timestamp,
+            variant: variant.to_string(),
+            won,
+            money_win: if won { pot } else { 0 },
+            money_lost: -player.money_lost,
+        };
+        storage.insert_result(bson::to_document(&record)?).await?;
+    }
+    Ok(())
+}
+
+/// Reads a player's results, oldest first.
+///
+/// # Arguments
+/// * `storage` - The server's storage backend.
+/// * `player` - The player's name.
+/// * `from` - Only results at or after this time, if given.
+/// * `to` - Only results at or before this time, if given.
+pub async fn load_results(
+    storage: &dyn Storage,
+    player: &str,
+    from: Option<i64>,
+    to: Option<i64>,
+) -> StorageResult<Vec<ResultRecord>> {
+    let docs = storage.list_results(player, from, to).await?;
+    Ok(docs.into_iter().map(bson::from_document).collect::<Result<_, _>>()?)
+}
+
+/// Reads every player's totals, ordered by name, for the leaderboards.
+///
+/// # Arguments
+/// * `storage` - The server's storage backend.
+/// * `variant` - Only results in this variant, if given.
+/// * `from` - Only results at or after this time, if given.
+pub async fn load_result_totals(
+    storage: &dyn Storage,
+    variant: Option<&str>,
+    from: Option<i64>,
+) -> StorageResult<Vec<ResultTotals>> {
+    let docs = storage.result_totals(variant, from).await?;
+    Ok(docs.into_iter().map(bson::from_document).collect::<Result<_, _>>()?)
+}
+
+/// Replaces the totals in `stats`, overall and per variant, with the sums of `results`.
+pub fn apply_totals(stats: &mut PlayerStats, results: &[ResultRecord]) {
+    let mut variants: BTreeMap<String, VariantStats> = BTreeMap::new();
+    let (mut wins, mut losses, mut money_win, mut money_lost) = (0, 0, 0, 0);
+    for result in results {
+        let totals = variants.entry(result.variant.clone()).or_default();
+        totals.games_played += 1;
+        if result.won {
+            totals.wins += 1;
+            wins += 1;
+        } else {
+            totals.losses += 1;
+            losses += 1;
+        }
+        totals.money_win += result.money_win;
+        totals.money_lost += result.money_lost;
+        money_win += result.money_win;
+        money_lost += result.money_lost;
+    }
+
+    stats.wins = wins;
+    stats.losses = losses;
+    stats.games_played = results.len() as i32;
+    stats.money_win = money_win;
+    stats.money_lost = money_lost;
+    stats.variants = variants;
+}
+
+/// A player's bankroll after each result.
+///
+/// # Arguments
+/// * `results` - The player's results, oldest first, from their first hand.
+/// * `from` - Only return points at or after this time, if given. Earlier results
+///   still count towards the bankroll.
+///
+/// # Returns
+/// The running total of the player's net chips, oldest first.
+pub fn bankroll_series(results: &[ResultRecord], from: Option<i64>) -> Vec<BankrollPoint> {
+    let mut bankroll = 0;
+    results
+        .iter()
+        .map(|result| {
+            bankroll += result.net();
+            BankrollPoint { timestamp: result.timestamp, bankroll }
+        })
+        .filter(|point| from.is_none_or(|from| point.timestamp >= from))
+        .collect()
+}
@@ -0,0 +1,133 @@
+//! # Sessions
+//!
+//! Tracks who is logged in on which connection.
+//!
+//! A successful login issues a random session token. The client then opens its
+//! persistent connection and sends a `bind` command with the token, which ties
+//! that socket to the token's user. Every later command on the socket runs as the
+//! bound user, whatever `username` the message claims.
+//!
+//! Each user has at most one live token: logging in again revokes the old one,
+//! which also signs out any socket bound to it.
+//!
+//! Seats follow the session rather than the socket: they are kept by username, and
+//! whatever is sent to a seat goes to the connections bound to that user's session
+//! (`connections_for`). When a seated player binds a new connection, for example
+//! after their Wi-Fi drops, the table reaches them there with no further steps.
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use protocol::ClientCommand;
+
+/// Live session tokens and the connections bound to them.
+#[derive(Debug, Default)]
+pub struct Sessions {
+    /// Token -> username.
+    tokens: HashMap<String, String>,
+    /// Username -> that user's current token.
+    user_tokens: HashMap<String, String>,
+    /// Connection -> token it is bound to.
+    bindings: HashMap<SocketAddr, String>,
+}
+
+impl Sessions {
+    /// Issues a new session token for a user who has just logged in, revoking
+    /// their previous one.
+    ///
+    /// # Arguments
+    /// * `username` - The authenticated user.
+    ///
+    /// # Returns
+    /// The new token, 64 hex digits.
+    pub fn issue(&mut self, username: &str) -> String {
+        let mut bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut bytes);
+        let token: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+
+        if let Some(old) = self.user_tokens.insert(username.to_string(), token.clone()) {
+            self.tokens.remove(&old);
+        }
+        self.tokens.insert(token.clone(), username.to_string());
+        println!("[Session] Issued session for {}", username);
+        token
+    }
+
+    /// Binds a connection to a session token.
+    ///
+    /// # Arguments
+    /// * `addr` - The client's connection.
+    /// * `token` - Token from the `"bind"` command.
+    ///
+    /// # Returns
+    /// The user the connection now acts as, or `None` if the token is unknown.
+    pub fn bind(&mut self, addr: SocketAddr, token: &str) -> Option<String> {
+        let username = self.tokens.get(token)?.clone();
+        self.bindings.insert(addr, token.to_string());
+        println!("[Session] {} bound to {}", addr, username);
+        Some(username)
+    }
+
+    /// The user a token belongs to, if it is live.
+    pub fn owner(&self, token: &str) -> Option<String> {
+        self.tokens.get(token).cloned()
+    }
+
+    /// Forgets a connection that has closed. The session itself stays live, so the
+    /// user can bind a new connection with the same token.
+    pub fn unbind(&mut self, addr: &SocketAddr) {
+        self.bindings.remove(addr);
+    }
+
+    /// The user a connection is bound to, if its session is still live.
+    pub fn user_for(&self, addr: &SocketAddr) -> Option<String> {
+        let token = self.bindings.get(addr)?;
+        self.tokens.get(token).cloned()
+    }
+
+    /// The connections bound to a user's live session.
+    pub fn connections_for(&self, username: &str) -> Vec<SocketAddr> {
+        let Some(token) = self.user_tokens.get(username) else {
+            return Vec::new();
+        };
+        self.bindings
+            .iter()
+            .filter(|(_, bound)| *bound == token)
+            .map(|(addr, _)| *addr)
+            .collect()
+    }
+
+    /// Signs a user out everywhere by revoking their token.
+    ///
+    /// # Returns
+    /// The connections that were bound to it, which now have no user.
+    pub fn revoke(&mut self, username: &str) -> Vec<SocketAddr> {
+        let connections = self.connections_for(username);
+        if let Some(token) = self.user_tokens.remove(username) {
+            self.tokens.remove(&token);
+            println!("[Session] Revoked session for {}", username);
+        }
+        for addr in &connections {
+            self.bindings.remove(addr);
+        }
+        connections
+    }
+}
+
+/// Checks that a command does not claim to be someone other than the bound user.
+///
+/// # Arguments
+/// * `command` - The parsed command.
+/// * `bound_user` - The user the connection is bound to.
+///
+/// # Returns
+/// An error message if the command names a different `username`.
+pub fn check_identity(command: &ClientCommand, bound_user: &str) -> Result<(), String> {
+    match command.claimed_username() {
+        Some(claimed) if claimed != bound_user => Err(format!(
+            "Not authorized: you are logged in as {}, not {}.",
+            bound_user, claimed
+        )),
+        _ => Ok(()),
+    }
+}
@@ -1,14 +1,18 @@
 // src/poker/seven_card_stud.rs
-use crate::deck::{Card, Deck};
+use crate::deck::Deck;
 use crate::five_card_draw::{Player, evaluate_hand};
 use crate::texas_holdem::best_hand_from_seven;
+use serde::{Deserialize, Serialize};
 
 /// Represents a game of Seven Card Stud poker.
+#[derive(Debug, Serialize, Deserialize)]
 pub struct SevenCardStudGame {
     /// List of all players who started.
     pub players: Vec<Player>,
     /// The deck of cards used.
     pub deck: Deck,
+    /// Seed the deck is shuffled from when the cards are dealt.
+    pub shuffle_seed: u64,
     /// total chips in the pot.
     pub pot: i32,
     /// Players active in the game.
@@ -25,6 +29,7 @@ impl SevenCardStudGame {
     /// * `player_ids` - A vector of player identifiers.
     pub fn new(player_ids: Vec<String>) -> Self {
         let deck = Deck::new();
+        let shuffle_seed = crate::recovery::shuffle_seed(None);
         let current_players: Vec<Player> = player_ids
             .into_iter()
             .map(|id| Player {
@@ -40,6 +45,7 @@ impl SevenCardStudGame {
         Self {
             players: Vec::new(),
             deck,
+            shuffle_seed,
             pot: 0,
             current_players,
             current_bet: 0,
@@ -48,7 +54,7 @@ impl SevenCardStudGame {
 
     /// Deals Third Street: 2 face-down cards and 1 face-up card to each player.
     pub fn deal_third_street(&mut self) {
-        self.deck = Deck::new();
+        self.deck = Deck::with_seed(self.shuffle_seed);
         for player in &mut self.current_players {
             player.hand.clear();
             player.folded = false;
@@ -101,11 +107,11 @@ impl SevenCardStudGame {
             .filter(|player| !player.folded && player.hand.len() >= 4)
             .map(|player| {
                 // Only evaluate 3rd and 4th cards (face-up)
-                let face_up = vec![player.hand[2].clone(), player.hand[3].clone()];
+                let face_up = vec![player.hand[2], player.hand[3]];
                 let score = evaluate_hand(&face_up);
                 (player.id.clone(), score)
             })
-            .max_by_key(|&(_, ref score)| score.clone())
+            .max_by_key(|(_, score)| score.clone())
             .map(|(id, _)| id)
     }
 
@@ -129,9 +135,9 @@ impl SevenCardStudGame {
             .filter(|player| !player.folded && player.hand.len() >= 5)
             .map(|player| {
                 let face_up = vec![
-                    player.hand[2].clone(),
-                    player.hand[3].clone(),
-                    player.hand[4].clone(),
+                    player.hand[2],
+                    player.hand[3],
+                    player.hand[4],
                 ];
                 let score = evaluate_hand(&face_up);
                 (player.id.clone(), score)
@@ -160,10 +166,10 @@ impl SevenCardStudGame {
             .filter(|player| !player.folded && player.hand.len() >= 6)
             .map(|player| {
                 let face_up = vec![
-                    player.hand[2].clone(),
-                    player.hand[3].clone(),
-                    player.hand[4].clone(),
-                    player.hand[5].clone(),
+                    player.hand[2],
+                    player.hand[3],
+                    player.hand[4],
+                    player.hand[5],
                 ];
                 let score = evaluate_hand(&face_up);
                 (player.id.clone(), score)
@@ -0,0 +1,108 @@
+//! # Shutdown
+//!
+//! Stops the dealer without losing anyone's chips.
+//!
+//! Ctrl+C, `SIGTERM` or the admin console's `shutdown` command starts a shutdown:
+//!
+//! 1. No new game starts; players who try to join are told the server is going down.
+//! 2. A hand in progress plays on for up to `[shutdown] hand_timeout_secs`, and
+//!    writes its stats and history as usual when it ends. A hand still going at the
+//!    deadline is stopped and voided: every bet in it is refunded (see
+//!    `db::void_hand`) and it is left out of the history. A hand stopped after it
+//!    has paid out (see `db::settle_hand`) is not voided.
+//! 3. Every client is told the server is going down and disconnected, and the
+//!    server exits.
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::task::JoinHandle;
+use protocol::ServerEvent;
+use crate::admin::AdminCommand;
+use crate::comms::{broadcast_to_game_players, disconnect_client};
+use crate::db::void_hand;
+use crate::recovery::load_live_hand;
+use crate::storage::Storage;
+use crate::ClientInfo;
+
+/// How long to give connections to deliver their last messages before the server exits.
+const CLOSE_GRACE: Duration = Duration::from_millis(500);
+
+/// Turns Ctrl+C and `SIGTERM` into `shutdown` commands for the dispatcher.
+pub fn forward_signals(admin_tx: UnboundedSender<AdminCommand>) {
+    tokio::spawn(async move {
+        #[cfg(unix)]
+        let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM");
+        loop {
+            #[cfg(unix)]
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = terminate.recv() => {}
+            }
+            #[cfg(not(unix))]
+            let _ = tokio::signal::ctrl_c().await;
+
+            println!("[Shutdown] Received a shutdown signal");
+            if admin_tx.send(AdminCommand::Shutdown).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+/// Lets the hand in progress finish, or voids it once `hand_timeout` has passed.
+///
+/// # Arguments
+/// * `game` - The game task, if a game has started.
+/// * `storage` - The server's storage backend.
+/// * `clients` - Shared list of connected clients.
+/// * `hand_timeout` - How long the hand may play on.
+pub async fn finish_hand(
+    game: Option<JoinHandle<()>>,
+    storage: Arc<dyn Storage>,
+    clients: Arc<Mutex<HashMap<SocketAddr, ClientInfo>>>,
+    hand_timeout: Duration,
+) {
+    let Some(mut game) = game.filter(|game| !game.is_finished()) else {
+        println!("[Shutdown] No hand in progress");
+        return;
+    };
+
+    println!("[Shutdown] Waiting up to {} seconds for the hand to finish", hand_timeout.as_secs());
+    broadcast_to_game_players(
+        &clients,
+        &ServerEvent::info(format!(
+            "The server is shutting down after this hand. If it has not finished in {} seconds, it will be voided and all bets refunded.",
+            hand_timeout.as_secs()
+        )),
+    );
+    if tokio::time::timeout(hand_timeout, &mut game).await.is_ok() {
+        println!("[Shutdown] The hand finished");
+        return;
+    }
+
+    game.abort();
+    let _ = game.await;
+    if let Ok(None) = load_live_hand(&*storage).await {
+        println!("[Shutdown] The hand had already been paid out");
+        return;
+    }
+    match void_hand(&*storage).await {
+        Ok(refunded) => println!("[Shutdown] Voided the hand; refunded folded bets of {:?}", refunded),
+        Err(e) => eprintln!("[DB] Failed to void the hand: {}", e),
+    }
+    broadcast_to_game_players(&clients, &ServerEvent::info("The hand was voided and all bets refunded."));
+}
+
+/// Tells every client the server is going down and closes their connections.
+pub async fn close_connections(clients: &Arc<Mutex<HashMap<SocketAddr, ClientInfo>>>) {
+    let addrs: Vec<SocketAddr> = clients.lock().unwrap().keys().cloned().collect();
+    for addr in addrs {
+        disconnect_client(clients, &addr, &ServerEvent::info("The server is shutting down."));
+    }
+    tokio::time::sleep(CLOSE_GRACE).await;
+}
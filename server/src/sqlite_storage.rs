@@ -0,0 +1,515 @@
+//! # SQLite Storage
+//!
+//! A `Storage` backend that keeps everything in a single SQLite file, so small home
+//! games can run the dealer without installing MongoDB.
+//!
+//! Each record is stored as a BSON blob next to the columns used to look it up,
+//! which keeps documents identical to the ones the MongoDB backend stores.
+//!
+//! Tables:
+//! - `players`: Player accounts and stats, keyed by name.
+//! - `games`: The active game state (row with `id = 1`), apart from its `events`.
+//! - `hand_events`: The current hand's events, in order, one per row.
+//! - `lobbies`: The current lobby (row with `id = 1`).
+//! - `history`: Completed hands, oldest first, with their `hand_id`.
+//! - `history_seats`: Who sat in each hand and when it started, to look up a player's hands.
+//! - `results`: Each player's result in each hand, with the player and when it happened.
+//!
+//! The schema version is kept in SQLite's `user_version` pragma. On open, every
+//! migration newer than that version is applied in order, along with any backfill
+//! that fills in its new tables or columns from the stored documents.
+//!
+//! rusqlite blocks on file I/O, so every call runs on tokio's blocking thread pool
+//! (`spawn_blocking`) rather than on the task that asked for it.
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use async_trait::async_trait;
+use mongodb::bson::{doc, Bson, Document};
+use rusqlite::{params, Connection, OptionalExtension};
+use crate::storage::*;
+
+/// Schema migrations, applied in order. Entry `n` upgrades the schema to version `n + 1`.
+///
+/// Never edit a migration that has shipped; append a new one instead.
+pub(crate) const MIGRATIONS: &[&str] = &[
+    // 1: initial schema
+    "CREATE TABLE players (
+        name TEXT PRIMARY KEY,
+        doc  BLOB NOT NULL
+    );
+    CREATE TABLE games (
+        id  INTEGER PRIMARY KEY,
+        doc BLOB NOT NULL
+    );
+    CREATE TABLE lobbies (
+        id  INTEGER PRIMARY KEY,
+        doc BLOB NOT NULL
+    );
+    CREATE TABLE history (
+        id  INTEGER PRIMARY KEY AUTOINCREMENT,
+        doc BLOB NOT NULL
+    );",
+    // 2: look up hands by ID
+    "ALTER TABLE history ADD COLUMN hand_id TEXT;
+    CREATE UNIQUE INDEX history_hand_id ON history (hand_id);",
+    // 3: timestamped results, looked up by player and time
+    "CREATE TABLE results (
+        id        INTEGER PRIMARY KEY AUTOINCREMENT,
+        player    TEXT NOT NULL,
+        timestamp INTEGER NOT NULL,
+        doc       BLOB NOT NULL
+    );
+    CREATE INDEX results_player_timestamp ON results (player, timestamp);",
+    // 4: results looked up by variant and time, for leaderboards
+    "ALTER TABLE results ADD COLUMN variant TEXT;
+    CREATE INDEX results_variant_timestamp ON results (variant, timestamp);
+    CREATE INDEX results_timestamp ON results (timestamp);",
+    // 5: hands looked up by the players dealt in and time
+    "CREATE TABLE history_seats (
+        history_id INTEGER NOT NULL REFERENCES history (id),
+        player     TEXT NOT NULL,
+        timestamp  INTEGER NOT NULL
+    );
+    CREATE INDEX history_seats_player_timestamp ON history_seats (player, timestamp);",
+    // 6: the current hand's events, one row each, so each is appended on its own
+    "CREATE TABLE hand_events (
+        id  INTEGER PRIMARY KEY AUTOINCREMENT,
+        doc BLOB NOT NULL
+    );",
+    // 7: each result's outcome and chips, added up per player for leaderboards
+    "ALTER TABLE results ADD COLUMN won INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE results ADD COLUMN money_win INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE results ADD COLUMN money_lost INTEGER NOT NULL DEFAULT 0;",
+];
+
+/// Fills in what a migration's SQL cannot, such as columns read from the stored
+/// BSON documents. Each runs in the same transaction, right after its migration.
+const BACKFILLS: &[(usize, Backfill)] = &[(5, backfill_history_seats), (7, backfill_result_columns)];
+
+/// Fills in a migration's new data on the migration's transaction.
+type Backfill = fn(&Connection) -> StorageResult<()>;
+
+/// Game state field kept in the `hand_events` table rather than in the `games`
+/// row, so appending an event does not rewrite the ones before it.
+const EVENTS: &str = "events";
+
+
+/// Storage backend using an SQLite database file.
+#[derive(Debug)]
+pub struct SqliteStorage {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStorage {
+    /// Opens (or creates) the database file and brings its schema up to date.
+    ///
+    /// # Arguments
+    /// * `path` - Location of the database file.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be opened or a migration fails.
+    pub fn open(path: impl AsRef<Path>) -> StorageResult<Self> {
+        Self::with_connection(Connection::open(path)?)
+    }
+
+    /// Opens a private in-memory SQLite database, mainly for tests.
+    #[allow(dead_code)]
+    pub fn open_in_memory() -> StorageResult<Self> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(mut conn: Connection) -> StorageResult<Self> {
+        migrate(&mut conn)?;
+        Ok(Self { conn: Arc::new(Mutex::new(conn)) })
+    }
+
+    /// Returns the schema version of the open database.
+    #[allow(dead_code)]
+    pub fn schema_version(&self) -> StorageResult<usize> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.query_row("PRAGMA user_version", [], |row| row.get(0))?)
+    }
+
+    /// Runs a call on the connection on the blocking thread pool, so a slow disk
+    /// holds up only that call and not the tokio worker it came from.
+    async fn call<T, F>(&self, call: F) -> StorageResult<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> StorageResult<T> + Send + 'static,
+    {
+        let conn = Arc::clone(&self.conn);
+        tokio::task::spawn_blocking(move || call(&mut conn.lock().unwrap()))
+            .await
+            .expect("SQLite call panicked")
+    }
+}
+
+/// Applies every migration the database has not seen yet.
+fn migrate(conn: &mut Connection) -> StorageResult<()> {
+    let current: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+    for (index, sql) in MIGRATIONS.iter().enumerate().skip(current) {
+        let version = index + 1;
+        let tx = conn.transaction()?;
+        tx.execute_batch(sql)?;
+        for (_, backfill) in BACKFILLS.iter().filter(|(migration, _)| *migration == version) {
+            backfill(&tx)?;
+        }
+        tx.pragma_update(None, "user_version", version)?;
+        tx.commit()?;
+        println!("[DB] Applied SQLite migration {}", version);
+    }
+
+    Ok(())
+}
+
+/// Migration 5: adds the seats of the hands already in the history.
+fn backfill_history_seats(conn: &Connection) -> StorageResult<()> {
+    let rows: Vec<(i64, Vec<u8>)> = conn
+        .prepare("SELECT id, doc FROM history ORDER BY id")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+    for (history_id, blob) in &rows {
+        insert_seats(conn, *history_id, &decode(blob)?)?;
+    }
+    println!("[DB] Indexed the seats of {} stored hand(s)", rows.len());
+    Ok(())
+}
+
+/// Migration 7: copies each stored result's variant, outcome and chips into its
+/// columns. Results stored before migration 4 get their variant here too.
+fn backfill_result_columns(conn: &Connection) -> StorageResult<()> {
+    let rows: Vec<(i64, Vec<u8>)> = conn
+        .prepare("SELECT id, doc FROM results ORDER BY id")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+    for (id, blob) in &rows {
+        let record = decode(blob)?;
+        conn.execute(
+            "UPDATE results SET variant = ?1, won = ?2, money_win = ?3, money_lost = ?4 WHERE id = ?5",
+            params![
+                record.get_str("variant").unwrap_or(""),
+                record.get_bool("won").unwrap_or(false),
+                record.get_i32("money_win").unwrap_or(0),
+                record.get_i32("money_lost").unwrap_or(0),
+                id,
+            ],
+        )?;
+    }
+    println!("[DB] Filled in the totals of {} stored result(s)", rows.len());
+    Ok(())
+}
+
+/// Adds a history record's seats to `history_seats`, so the hand can be looked up
+/// by player and time.
+fn insert_seats(conn: &Connection, history_id: i64, record: &Document) -> StorageResult<()> {
+    let timestamp = record.get_i64("timestamp").unwrap_or(0);
+    let seats = record.get_array("seats").map(|seats| seats.as_slice()).unwrap_or_default();
+    for player in seats.iter().filter_map(|seat| seat.as_document()?.get_str("player").ok()) {
+        conn.execute(
+            "INSERT INTO history_seats (history_id, player, timestamp) VALUES (?1, ?2, ?3)",
+            params![history_id, player, timestamp],
+        )?;
+    }
+    Ok(())
+}
+
+/// Reads, modifies and writes back a player document in one transaction.
+///
+/// # Returns
+/// `false` if there is no such player.
+fn update_player(conn: &mut Connection, name: &str, update: impl FnOnce(&mut Document)) -> StorageResult<bool> {
+    let tx = conn.transaction()?;
+
+    let blob: Option<Vec<u8>> = tx
+        .query_row("SELECT doc FROM players WHERE name = ?1", [name], |row| row.get(0))
+        .optional()?;
+    let Some(blob) = blob else {
+        return Ok(false);
+    };
+
+    let mut player = decode(&blob)?;
+    update(&mut player);
+    tx.execute("UPDATE players SET doc = ?1 WHERE name = ?2", params![encode(&player)?, name])?;
+    tx.commit()?;
+    Ok(true)
+}
+
+/// Reads the document stored in the row with `id = 1` of a single-row table.
+fn single_row(conn: &Connection, table: &str) -> StorageResult<Option<Document>> {
+    let blob: Option<Vec<u8>> = conn
+        .query_row(&format!("SELECT doc FROM {} WHERE id = 1", table), [], |row| row.get(0))
+        .optional()?;
+    blob.map(|b| decode(&b)).transpose()
+}
+
+/// Replaces the document stored in the row with `id = 1` of a single-row table.
+fn replace_single_row(conn: &Connection, table: &str, doc: &Document) -> StorageResult<()> {
+    conn.execute(
+        &format!("INSERT OR REPLACE INTO {} (id, doc) VALUES (1, ?1)", table),
+        [encode(doc)?],
+    )?;
+    Ok(())
+}
+
+/// Replaces the stored hand events with the documents in an `events` array.
+fn replace_events(conn: &Connection, events: Option<Bson>) -> StorageResult<()> {
+    conn.execute("DELETE FROM hand_events", [])?;
+    let Some(Bson::Array(events)) = events else {
+        return Ok(());
+    };
+    for event in events.iter().filter_map(Bson::as_document) {
+        conn.execute("INSERT INTO hand_events (doc) VALUES (?1)", [encode(event)?])?;
+    }
+    Ok(())
+}
+
+/// Reads the documents in the first column of every row a query returns.
+fn query_docs(conn: &Connection, sql: &str, params: impl rusqlite::Params) -> StorageResult<Vec<Document>> {
+    let mut stmt = conn.prepare(sql)?;
+    let blobs = stmt
+        .query_map(params, |row| row.get::<_, Vec<u8>>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    blobs.iter().map(|b| decode(b)).collect()
+}
+
+/// Reads a row of `result_totals` into a document.
+fn total_row(row: &rusqlite::Row) -> rusqlite::Result<Document> {
+    Ok(doc! {
+        "player": row.get::<_, String>(0)?,
+        "games_played": row.get::<_, i64>(1)?,
+        "wins": row.get::<_, i64>(2)?,
+        "money_win": row.get::<_, i64>(3)?,
+        "money_lost": row.get::<_, i64>(4)?,
+        "biggest_pot": row.get::<_, i64>(5)?,
+    })
+}
+
+fn encode(doc: &Document) -> StorageResult<Vec<u8>> {
+    let mut bytes = Vec::new();
+    doc.to_writer(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn decode(bytes: &[u8]) -> StorageResult<Document> {
+    Ok(Document::from_reader(bytes)?)
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    fn backend_name(&self) -> &'static str {
+        "sqlite"
+    }
+
+    async fn find_player(&self, name: &str) -> StorageResult<Option<Document>> {
+        let name = name.to_string();
+        self.call(move |conn| {
+            let blob: Option<Vec<u8>> = conn
+                .query_row("SELECT doc FROM players WHERE name = ?1", [name], |row| row.get(0))
+                .optional()?;
+            blob.map(|b| decode(&b)).transpose()
+        })
+        .await
+    }
+
+    async fn insert_player(&self, player: Document) -> StorageResult<()> {
+        self.call(move |conn| {
+            let name = player.get_str("name").unwrap_or("").to_string();
+            let inserted = conn.execute(
+                "INSERT OR IGNORE INTO players (name, doc) VALUES (?1, ?2)",
+                params![name, encode(&player)?],
+            )?;
+            if inserted == 0 {
+                return Err(StorageError::Duplicate(name));
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn set_player_fields(&self, name: &str, fields: Document) -> StorageResult<bool> {
+        let name = name.to_string();
+        self.call(move |conn| update_player(conn, &name, |player| apply_set(player, &fields))).await
+    }
+
+    async fn increment_player_fields(&self, name: &str, amounts: Document) -> StorageResult<bool> {
+        let name = name.to_string();
+        self.call(move |conn| update_player(conn, &name, |player| apply_inc(player, &amounts))).await
+    }
+
+    async fn list_players(&self) -> StorageResult<Vec<Document>> {
+        self.call(|conn| query_docs(conn, "SELECT doc FROM players ORDER BY rowid", [])).await
+    }
+
+    async fn reset_game_state(&self, mut state: Document) -> StorageResult<()> {
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            replace_events(&tx, state.remove(EVENTS))?;
+            replace_single_row(&tx, "games", &state)?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn game_state(&self) -> StorageResult<Option<Document>> {
+        self.call(|conn| {
+            let Some(mut state) = single_row(conn, "games")? else {
+                return Ok(None);
+            };
+            state.insert(EVENTS, query_docs(conn, "SELECT doc FROM hand_events ORDER BY id", [])?);
+            Ok(Some(state))
+        })
+        .await
+    }
+
+    async fn set_game_state_field(&self, key: &str, value: Bson) -> StorageResult<()> {
+        let key = key.to_string();
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            if let Some(mut state) = single_row(&tx, "games")? {
+                if key == EVENTS {
+                    replace_events(&tx, Some(value))?;
+                } else {
+                    state.insert(key, value);
+                    replace_single_row(&tx, "games", &state)?;
+                }
+            }
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn push_hand_event(&self, event: Document) -> StorageResult<()> {
+        self.call(move |conn| {
+            let tx = conn.transaction()?;
+            let started = tx.query_row("SELECT 1 FROM games WHERE id = 1", [], |_| Ok(())).optional()?;
+            if started.is_some() {
+                tx.execute("INSERT INTO hand_events (doc) VALUES (?1)", [encode(&event)?])?;
+            }
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn save_lobby(&self, lobby: Document) -> StorageResult<()> {
+        self.call(move |conn| replace_single_row(conn, "lobbies", &lobby)).await
+    }
+
+    async fn lobby(&self) -> StorageResult<Option<Document>> {
+        self.call(|conn| single_row(conn, "lobbies")).await
+    }
+
+    async fn insert_history(&self, record: Document) -> StorageResult<()> {
+        self.call(move |conn| {
+            let hand_id = record.get_str("hand_id").unwrap_or("").to_string();
+            let tx = conn.transaction()?;
+            let inserted = tx.execute(
+                "INSERT OR IGNORE INTO history (hand_id, doc) VALUES (?1, ?2)",
+                params![hand_id, encode(&record)?],
+            )?;
+            if inserted == 0 {
+                return Err(StorageError::Duplicate(hand_id));
+            }
+            insert_seats(&tx, tx.last_insert_rowid(), &record)?;
+            tx.commit()?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn find_history(&self, hand_id: &str) -> StorageResult<Option<Document>> {
+        let hand_id = hand_id.to_string();
+        self.call(move |conn| {
+            let blob: Option<Vec<u8>> = conn
+                .query_row("SELECT doc FROM history WHERE hand_id = ?1", [hand_id], |row| row.get(0))
+                .optional()?;
+            blob.map(|b| decode(&b)).transpose()
+        })
+        .await
+    }
+
+    async fn list_history(&self) -> StorageResult<Vec<Document>> {
+        self.call(|conn| query_docs(conn, "SELECT doc FROM history ORDER BY id", [])).await
+    }
+
+    async fn list_player_history(&self, player: &str, from: Option<i64>, to: Option<i64>) -> StorageResult<Vec<Document>> {
+        let player = player.to_string();
+        self.call(move |conn| {
+            query_docs(
+                conn,
+                "SELECT history.doc FROM history_seats JOIN history ON history.id = history_seats.history_id
+                 WHERE history_seats.player = ?1 AND history_seats.timestamp >= ?2 AND history_seats.timestamp <= ?3
+                 ORDER BY history_seats.timestamp, history.id",
+                params![player, from.unwrap_or(i64::MIN), to.unwrap_or(i64::MAX)],
+            )
+        })
+        .await
+    }
+
+    async fn page_player_history(&self, player: &str, skip: usize, limit: usize) -> StorageResult<Vec<Document>> {
+        let player = player.to_string();
+        self.call(move |conn| {
+            query_docs(
+                conn,
+                "SELECT history.doc FROM history_seats JOIN history ON history.id = history_seats.history_id
+                 WHERE history_seats.player = ?1
+                 ORDER BY history_seats.timestamp DESC, history.id DESC LIMIT ?2 OFFSET ?3",
+                params![player, limit as i64, skip as i64],
+            )
+        })
+        .await
+    }
+
+    async fn insert_result(&self, record: Document) -> StorageResult<()> {
+        self.call(move |conn| {
+            let player = record.get_str("player").unwrap_or("");
+            let variant = record.get_str("variant").unwrap_or("");
+            let timestamp = record.get_i64("timestamp").unwrap_or(0);
+            let won = record.get_bool("won").unwrap_or(false);
+            let money_win = record.get_i32("money_win").unwrap_or(0);
+            let money_lost = record.get_i32("money_lost").unwrap_or(0);
+            conn.execute(
+                "INSERT INTO results (player, variant, timestamp, won, money_win, money_lost, doc)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![player, variant, timestamp, won, money_win, money_lost, encode(&record)?],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn list_results(&self, player: &str, from: Option<i64>, to: Option<i64>) -> StorageResult<Vec<Document>> {
+        let player = player.to_string();
+        self.call(move |conn| {
+            query_docs(
+                conn,
+                "SELECT doc FROM results WHERE player = ?1 AND timestamp >= ?2 AND timestamp <= ?3 ORDER BY timestamp, id",
+                params![player, from.unwrap_or(i64::MIN), to.unwrap_or(i64::MAX)],
+            )
+        })
+        .await
+    }
+
+    async fn result_totals(&self, variant: Option<&str>, from: Option<i64>) -> StorageResult<Vec<Document>> {
+        let variant = variant.map(str::to_string);
+        let from = from.unwrap_or(i64::MIN);
+        self.call(move |conn| {
+            let filter = if variant.is_some() { "variant = ?2 AND timestamp >= ?1" } else { "timestamp >= ?1" };
+            let mut stmt = conn.prepare(&format!(
+                "SELECT player, COUNT(*), SUM(won), SUM(money_win), SUM(money_lost),
+                        MAX(CASE WHEN won THEN money_win ELSE 0 END)
+                 FROM results WHERE {} GROUP BY player ORDER BY player",
+                filter
+            ))?;
+            let totals = match variant {
+                Some(variant) => stmt.query_map(params![from, variant], total_row)?.collect::<Result<_, _>>()?,
+                None => stmt.query_map(params![from], total_row)?.collect::<Result<_, _>>()?,
+            };
+            Ok(totals)
+        })
+        .await
+    }
+}
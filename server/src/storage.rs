@@ -0,0 +1,263 @@
+//! # Storage Abstraction
+//!
+//! This module defines the `Storage` trait that every part of the server uses to
+//! persist players, stats, the live game state, the lobby and hand history.
+//!
+//! Backends:
+//! - `MongoStorage` (see `mongo_storage.rs`): the MongoDB `dealer` database.
+//! - `SqliteStorage` (see `sqlite_storage.rs`): a single SQLite file, no database server needed.
+//! - `MemoryStorage` (see `memory_storage.rs`): an in-process store used by tests and demos.
+//!
+//! Records are exchanged as BSON `Document`s so that the shape of a player or game
+//! state document is the same no matter which backend stores it.
+use std::fmt;
+use std::sync::Arc;
+use async_trait::async_trait;
+use mongodb::bson::{self, Bson, Document};
+use crate::config::{StorageBackend, StorageConfig};
+use crate::memory_storage::MemoryStorage;
+use crate::mongo_storage::MongoStorage;
+use crate::sqlite_storage::SqliteStorage;
+
+
+/// Errors reported by a storage backend.
+#[derive(Debug)]
+pub enum StorageError {
+    /// The MongoDB driver returned an error.
+    Mongo(mongodb::error::Error),
+    /// SQLite returned an error.
+    Sqlite(rusqlite::Error),
+    /// A record could not be converted to or from BSON.
+    Bson(String),
+    /// A record with the same key already exists.
+    Duplicate(String),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Mongo(e) => write!(f, "MongoDB error: {}", e),
+            StorageError::Sqlite(e) => write!(f, "SQLite error: {}", e),
+            StorageError::Bson(e) => write!(f, "BSON conversion error: {}", e),
+            StorageError::Duplicate(key) => write!(f, "Record '{}' already exists", key),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<mongodb::error::Error> for StorageError {
+    fn from(e: mongodb::error::Error) -> Self {
+        StorageError::Mongo(e)
+    }
+}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(e: rusqlite::Error) -> Self {
+        StorageError::Sqlite(e)
+    }
+}
+
+impl From<bson::ser::Error> for StorageError {
+    fn from(e: bson::ser::Error) -> Self {
+        StorageError::Bson(e.to_string())
+    }
+}
+
+impl From<bson::de::Error> for StorageError {
+    fn from(e: bson::de::Error) -> Self {
+        StorageError::Bson(e.to_string())
+    }
+}
+
+/// Result type returned by all storage operations.
+pub type StorageResult<T> = Result<T, StorageError>;
+
+/// Persistence operations needed by the server.
+///
+/// Player documents are keyed by their `"name"` field. The live game state is a
+/// single document (the one spectators see), and so is the lobby. Hand history
+/// records are appended once a hand is over and are keyed by their `"hand_id"`.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Short backend name used in log messages (e.g. `"mongodb"`).
+    fn backend_name(&self) -> &'static str;
+
+    /// Finds the player document with the given name.
+    async fn find_player(&self, name: &str) -> StorageResult<Option<Document>>;
+
+    /// Inserts a new player document. The document must contain a `"name"` field.
+    async fn insert_player(&self, player: Document) -> StorageResult<()>;
+
+    /// Sets the given fields on a player document (like MongoDB `$set`).
+    ///
+    /// # Returns
+    /// `true` if a player with that name exists.
+    async fn set_player_fields(&self, name: &str, fields: Document) -> StorageResult<bool>;
+
+    /// Adds the given amounts to numeric fields of a player document (like MongoDB `$inc`).
+    ///
+    /// Field names may be dotted paths such as `"stats.wins"`.
+    ///
+    /// # Returns
+    /// `true` if a player with that name exists.
+    async fn increment_player_fields(&self, name: &str, amounts: Document) -> StorageResult<bool>;
+
+    /// Returns every player document, in insertion order.
+    async fn list_players(&self) -> StorageResult<Vec<Document>>;
+
+    /// Replaces the live game state with the given document.
+    async fn reset_game_state(&self, state: Document) -> StorageResult<()>;
+
+    /// Returns the live game state, if it has been initialized.
+    async fn game_state(&self) -> StorageResult<Option<Document>>;
+
+    /// Sets a single field on the live game state.
+    async fn set_game_state_field(&self, key: &str, value: Bson) -> StorageResult<()>;
+
+    /// Appends an event of the current hand to the live game state's `events`
+    /// array (like MongoDB `$push`), without writing the events before it again.
+    async fn push_hand_event(&self, event: Document) -> StorageResult<()>;
+
+    /// Replaces the lobby document (variant, seat count and players who are ready).
+    async fn save_lobby(&self, lobby: Document) -> StorageResult<()>;
+
+    /// Returns the lobby document, if one has been saved.
+    async fn lobby(&self) -> StorageResult<Option<Document>>;
+
+    /// Appends a completed hand to the history. The record must contain a `"hand_id"` field.
+    async fn insert_history(&self, record: Document) -> StorageResult<()>;
+
+    /// Finds the history record of the hand with the given ID.
+    async fn find_history(&self, hand_id: &str) -> StorageResult<Option<Document>>;
+
+    /// Returns every hand history record, oldest first.
+    async fn list_history(&self) -> StorageResult<Vec<Document>>;
+
+    /// Returns the history records of the hands a player was dealt into, oldest first.
+    ///
+    /// # Arguments
+    /// * `player` - The player's name, as in the record's `"seats"`.
+    /// * `from` - Only hands started at or after this time, if given.
+    /// * `to` - Only hands started at or before this time, if given.
+    async fn list_player_history(&self, player: &str, from: Option<i64>, to: Option<i64>) -> StorageResult<Vec<Document>>;
+
+    /// Returns a page of the history records of the hands a player was dealt
+    /// into, newest first.
+    ///
+    /// # Arguments
+    /// * `player` - The player's name, as in the record's `"seats"`.
+    /// * `skip` - Newer hands to pass over.
+    /// * `limit` - Most records returned.
+    async fn page_player_history(&self, player: &str, skip: usize, limit: usize) -> StorageResult<Vec<Document>>;
+
+    /// Appends a player's result in a hand. The record must contain `"player"`,
+    /// `"variant"` and `"timestamp"` (seconds since the Unix epoch, as an `i64`) fields.
+    async fn insert_result(&self, record: Document) -> StorageResult<()>;
+
+    /// Returns a player's results, oldest first.
+    ///
+    /// # Arguments
+    /// * `player` - The player's name.
+    /// * `from` - Only results at or after this time, if given.
+    /// * `to` - Only results at or before this time, if given.
+    async fn list_results(&self, player: &str, from: Option<i64>, to: Option<i64>) -> StorageResult<Vec<Document>>;
+
+    /// Adds up every player's results, one document per player, ordered by name.
+    ///
+    /// Each document has the `"player"`, how many results they have
+    /// (`"games_played"`) and how many they won (`"wins"`), the sums of their
+    /// `"money_win"` and `"money_lost"`, and the most they won in one hand
+    /// (`"biggest_pot"`, 0 if they never won).
+    ///
+    /// # Arguments
+    /// * `variant` - Only results in this variant, if given.
+    /// * `from` - Only results at or after this time, if given.
+    async fn result_totals(&self, variant: Option<&str>, from: Option<i64>) -> StorageResult<Vec<Document>>;
+}
+
+/// Opens the storage backend selected in the server configuration.
+///
+/// # Errors
+/// Returns an error if the backend cannot be reached or initialized.
+pub async fn open_storage(config: &StorageConfig) -> StorageResult<Arc<dyn Storage>> {
+    match config.backend {
+        StorageBackend::MongoDb => {
+            let storage = MongoStorage::connect(&config.mongodb_uri, &config.mongodb_database).await?;
+            storage.create_indexes().await?;
+            Ok(Arc::new(storage))
+        }
+        StorageBackend::Sqlite => Ok(Arc::new(SqliteStorage::open(&config.sqlite_path)?)),
+        StorageBackend::Memory => Ok(Arc::new(MemoryStorage::new())),
+    }
+}
+
+/// Applies `$set`-style updates to a document in place.
+///
+/// Dotted field names (e.g. `"stats.wins"`) create or update nested documents.
+pub fn apply_set(target: &mut Document, fields: &Document) {
+    for (path, value) in fields {
+        let (parent, key) = nested_parent(target, path);
+        parent.insert(key, value.clone());
+    }
+}
+
+/// Applies `$inc`-style updates to a document in place.
+///
+/// Missing fields are created with the increment as their value. Integer widths
+/// follow MongoDB: two `Int32` values stay `Int32` unless the sum overflows.
+pub fn apply_inc(target: &mut Document, amounts: &Document) {
+    for (path, delta) in amounts {
+        let (parent, key) = nested_parent(target, path);
+        let updated = match parent.get(key) {
+            Some(current) => add_numbers(current, delta),
+            None => delta.clone(),
+        };
+        parent.insert(key, updated);
+    }
+}
+
+/// Walks a dotted path and returns the document holding its last segment.
+fn nested_parent<'a, 'p>(target: &'a mut Document, path: &'p str) -> (&'a mut Document, &'p str) {
+    match path.split_once('.') {
+        Some((head, rest)) => {
+            if !matches!(target.get(head), Some(Bson::Document(_))) {
+                target.insert(head, Document::new());
+            }
+            let child = target.get_document_mut(head).unwrap();
+            nested_parent(child, rest)
+        }
+        None => (target, path),
+    }
+}
+
+/// Adds two BSON numbers, widening the result when needed.
+fn add_numbers(current: &Bson, delta: &Bson) -> Bson {
+    match (current, delta) {
+        (Bson::Int32(a), Bson::Int32(b)) => match a.checked_add(*b) {
+            Some(sum) => Bson::Int32(sum),
+            None => Bson::Int64(*a as i64 + *b as i64),
+        },
+        (Bson::Double(a), _) => Bson::Double(a + as_f64(delta)),
+        (_, Bson::Double(b)) => Bson::Double(as_f64(current) + b),
+        _ => Bson::Int64(as_i64(current) + as_i64(delta)),
+    }
+}
+
+fn as_i64(value: &Bson) -> i64 {
+    match value {
+        Bson::Int32(v) => *v as i64,
+        Bson::Int64(v) => *v,
+        Bson::Double(v) => *v as i64,
+        _ => 0,
+    }
+}
+
+fn as_f64(value: &Bson) -> f64 {
+    match value {
+        Bson::Int32(v) => *v as f64,
+        Bson::Int64(v) => *v as f64,
+        Bson::Double(v) => *v,
+        _ => 0.0,
+    }
+}
@@ -6,28 +6,242 @@
 //! - Dealing cards (hole cards, community cards: flop, turn, river)
 //! - Four rounds of betting
 //! - Showdown and determining winner
-//! - Broadcasting game state to players
+//! - Emitting each step as a hand event, which is broadcast to players, stored and
+//!   recorded in the hand history (see `hand_events.rs`)
 //! - Updating the database with game results
 //!
+//! The hand is the state machine `TexasHoldemHand` (see `hand_machine.rs`):
+//!
+//! ```text
+//! Blinds -> Deal(preflop) -> Betting(preflop) -> Deal(flop) -> Betting(flop)
+//!        -> Deal(turn) -> Betting(turn) -> Deal(river) -> Betting(river) -> Showdown -> Over
+//! ```
+//!
+//! Before the flop there are two rounds: everyone matches the big blind, then the
+//! big blind may check to end the betting or raise it.
+//!
 //! Note: All player interactions are done via broadcasting JSON messages
 //! and waiting for players to update their actions in the MongoDB database.
 
 use std::{
     collections::HashMap,
     sync::{Arc, Mutex},
-    thread,
-    time::Duration,
 };
-use mongodb::{
-    bson::Document,
-    Collection,
-    bson::doc
-};
-use serde_json::json;
-use crate::comms::*;
+use serde::{Deserialize, Serialize};
+use crate::hand_events::*;
+use crate::hand_machine::*;
+use crate::history::ActionKind;
+use crate::recovery::Replay;
+use crate::storage::Storage;
 use crate::*;
+use crate::five_card_draw::Player;
 use crate::texas_holdem::*;
 
+const SMALL_BLIND: i32 = 2;
+const BIG_BLIND: i32 = 4;
+
+/// A hand of Texas Hold'em.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TexasHoldemHand {
+    game: TexasHoldemGame,
+    phase: Phase,
+    round: BettingRound,
+    turn: Option<Turn>,
+    small_blind: String,
+    big_blind: String,
+    /// The table's message as last sent, so a prompt is only sent when it changes.
+    info: Option<String>,
+    winner: Option<String>,
+}
+
+impl TexasHoldemHand {
+    /// Sets up a hand, before the blinds. The first two players post them.
+    ///
+    /// # Arguments
+    /// * `player_names` - Players in seat order.
+    /// * `shuffle_seed` - Seed the deck is shuffled from.
+    pub fn new(player_names: Vec<String>, shuffle_seed: u64) -> Self {
+        let small_blind = player_names.first().cloned().unwrap_or_default();
+        let big_blind = player_names.get(1).cloned().unwrap_or_default();
+        let mut game = TexasHoldemGame::new(player_names);
+        game.shuffle_seed = shuffle_seed;
+        Self {
+            game,
+            phase: Phase::Blinds,
+            round: BettingRound::open(0),
+            turn: None,
+            small_blind,
+            big_blind,
+            info: None,
+            winner: None,
+        }
+    }
+
+    /// What the player due to bet is told before the flop.
+    fn preflop_prompt(&self, player: &str) -> String {
+        let blinds = format!("{} is small blind, {} is big blind", self.small_blind, self.big_blind);
+        match self.round.rule() {
+            BetRule::MatchBlind => format!("Match the big blind, enter the amount of the big blind or -1 fold\n{}", blinds),
+            BetRule::BlindOption { pot_before, .. } if self.game.pot == *pot_before && player == self.big_blind => {
+                format!("You are the big blind, call 0 now to end the betting round or raise the bet\n{}", blinds)
+            }
+            _ => blinds,
+        }
+    }
+
+    /// Ends a round of betting: the hand is over if one player is left.
+    fn end_betting(&mut self, next: Phase) -> Vec<HandEvent> {
+        if let Some(winner) = last_player_standing(&self.game.current_players) {
+            self.winner = Some(winner.clone());
+            self.phase = Phase::Over;
+            return vec![HandEvent::HandOver { winner }];
+        }
+        self.phase = next;
+        Vec::new()
+    }
+}
+
+impl HandMachine for TexasHoldemHand {
+    fn phase(&self) -> Phase {
+        self.phase
+    }
+
+    fn turn(&self) -> Option<&Turn> {
+        self.turn.as_ref()
+    }
+
+    fn step(&mut self) -> Vec<HandEvent> {
+        if self.turn.is_some() {
+            return Vec::new();
+        }
+        match self.phase {
+            Phase::Blinds => {
+                for (seat, blind) in [(0, SMALL_BLIND), (1, BIG_BLIND)] {
+                    if let Some(player) = self.game.current_players.get_mut(seat) {
+                        player.bet_amount = blind;
+                        player.money_lost += blind;
+                        self.game.pot += blind;
+                    }
+                }
+                self.game.current_bet = BIG_BLIND;
+
+                let message = format!(
+                    "{} posted small blind ({} chips), {} posted big blind ({} chips).",
+                    self.small_blind, SMALL_BLIND, self.big_blind, BIG_BLIND
+                );
+                self.info = Some(message.clone());
+                self.phase = Phase::Deal(Street::Preflop);
+                vec![
+                    HandEvent::Street { name: Street::Preflop.name().to_string() },
+                    HandEvent::Posted { player: self.small_blind.clone(), kind: ActionKind::SmallBlind, amount: SMALL_BLIND },
+                    HandEvent::Posted { player: self.big_blind.clone(), kind: ActionKind::BigBlind, amount: BIG_BLIND },
+                    HandEvent::Info { message },
+                ]
+            }
+            Phase::Deal(Street::Preflop) => {
+                self.game.deal_hole_cards();
+                // Betting to match the big blind, which is already in
+                self.round = BettingRound::new(2, Some(self.big_blind.clone()), BetRule::MatchBlind);
+                self.phase = Phase::Betting(Street::Preflop);
+                vec![HandEvent::Dealt { cards: dealt_cards(&self.game.current_players, 0) }]
+            }
+            Phase::Deal(street) => {
+                let board_before = self.game.community_cards.len();
+                match street {
+                    Street::Flop => self.game.deal_flop(),
+                    Street::Turn => self.game.deal_turn(),
+                    _ => self.game.deal_river(),
+                }
+                for player in &mut self.game.current_players {
+                    player.bet_amount = 0;
+                }
+                self.game.current_bet = -2;
+                self.round = BettingRound::open(0);
+                self.phase = Phase::Betting(street);
+                vec![
+                    HandEvent::Street { name: street.name().to_string() },
+                    HandEvent::Board { cards: card_names(&self.game.community_cards[board_before..]) },
+                ]
+            }
+            Phase::Betting(street) => match self.round.next_to_act(&self.game.current_players) {
+                Some(player) => {
+                    let mut events = Vec::new();
+                    if street == Street::Preflop {
+                        let prompt = self.preflop_prompt(&player);
+                        if self.info.as_ref() != Some(&prompt) {
+                            self.info = Some(prompt.clone());
+                            events.push(HandEvent::Info { message: prompt });
+                        }
+                    }
+                    self.turn = Some(Turn::Bet(player.clone()));
+                    events.push(HandEvent::ToAct { player });
+                    events
+                }
+                None => match (street, self.round.rule()) {
+                    (Street::Preflop, BetRule::MatchBlind) => {
+                        // Betting if the big blind wants to raise
+                        let seat = self.game.current_players.iter().position(|p| p.id == self.big_blind).unwrap_or(0);
+                        let rule = BetRule::BlindOption {
+                            big_blind: self.big_blind.clone(),
+                            pot_before: self.game.pot,
+                            checked: false,
+                        };
+                        self.round = BettingRound::new(seat, None, rule);
+                        Vec::new()
+                    }
+                    (Street::Preflop, _) => self.end_betting(Phase::Deal(Street::Flop)),
+                    (Street::Flop, _) => self.end_betting(Phase::Deal(Street::Turn)),
+                    (Street::Turn, _) => self.end_betting(Phase::Deal(Street::River)),
+                    _ => self.end_betting(Phase::Showdown),
+                },
+            },
+            Phase::Showdown => {
+                self.winner = self.game.showdown();
+                self.phase = Phase::Over;
+                self.winner.iter().map(|winner| HandEvent::Showdown { winner: winner.clone() }).collect()
+            }
+            Phase::Antes | Phase::Draw | Phase::Over => Vec::new(),
+        }
+    }
+
+    fn bet(&mut self, player: &str, amount: i32) -> Result<Vec<HandEvent>, String> {
+        if self.turn != Some(Turn::Bet(player.to_string())) {
+            return Ok(Vec::new());
+        }
+        self.turn = None;
+        let seats = Seats {
+            current: &mut self.game.current_players,
+            folded: &mut self.game.players,
+            pot: &mut self.game.pot,
+            current_bet: &mut self.game.current_bet,
+        };
+        let event = self.round.bet(seats, player, amount)?;
+        // The bet or fold is now the table's message
+        self.info = None;
+        Ok(vec![event])
+    }
+
+    fn shuffle_seed(&self) -> u64 {
+        self.game.shuffle_seed
+    }
+
+    fn active_players(&self) -> &[Player] {
+        &self.game.current_players
+    }
+
+    fn folded(&self) -> &[Player] {
+        &self.game.players
+    }
+
+    fn pot(&self) -> i32 {
+        self.game.pot
+    }
+
+    fn winner(&self) -> Option<&str> {
+        self.winner.as_deref()
+    }
+}
+
 /// Runs a full game of Texas Hold'em.
 ///
 /// # Flow:
@@ -44,14 +258,15 @@ use crate::texas_holdem::*;
 /// 7. Determines winner at showdown or when only one player remains.
 /// 8. Updates the game result in the database.
 /// 9. Sends final game result to all players.
+/// 10. Records the hand in the hand history.
+///
+/// Each step is a transition of a `TexasHoldemHand`, played through `play_hand`.
 ///
 /// # Arguments
 /// * `clients` - shared list of connected clients.
 /// * `player_names` - List of players in the game.
-/// * `players_collection` - MongoDB collection for player data.
-/// * `lobbies_collection` - MongoDB collection for lobby data.
-/// * `games_collection` - MongoDB collection for ongoing games.
-/// * `history_collection` - MongoDB collection for game history.
+/// * `storage` - Storage backend for player data and the live game state.
+/// * `replay` - The stored hand to play again after a crash, if any (see `recovery.rs`).
 ///
 /// # Notes
 /// - Communication is asynchronous: the server sends messages and waits for player responses.
@@ -70,846 +285,14 @@ use crate::texas_holdem::*;
 pub async fn run_texas_game(
     clients: Arc<Mutex<HashMap<std::net::SocketAddr, ClientInfo>>>,
     player_names: Vec<String>,
-    players_collection: Arc<Collection<Document>>,
-    lobbies_collection: Arc<Collection<Document>>,
-    games_collection: Arc<Collection<Document>>,
-    history_collection: Arc<Collection<Document>>,
-
+    storage: Arc<dyn Storage>,
+    replay: Option<Replay>,
 ) {
     let variant = GAME_VARIANT.get().unwrap();
-    broadcast_to_game_players(&clients, "Started the game: {}");
-
-    let mut poker_game:TexasHoldemGame = TexasHoldemGame::new(player_names.clone());
-    // poker_game.current_players = poker_game.players.clone();
+    let hand = TexasHoldemHand::new(player_names.clone(), recovery::shuffle_seed(replay.as_ref()));
 
     println!("[Game] Created PokerGame for variant: {}", variant);
-    println!("[Game] Players: {:?}", poker_game.current_players.iter().map(|p| &p.id).collect::<Vec<_>>());
-
-
-
-    // Collecting small and big blind
-    let small_blind_index = 0;
-    let big_blind_index = 1;
-    let small_blind = 2;
-    let big_blind = 4;
-
-    let small_blind_player_id = poker_game.current_players[small_blind_index].id.clone();
-    let big_blind_player_id = poker_game.current_players[big_blind_index].id.clone();
-
-    if let Some(sb_player) = poker_game.current_players.get_mut(small_blind_index) {
-        sb_player.bet_amount = small_blind;
-        sb_player.money_lost += small_blind as i32;
-        poker_game.pot += small_blind;
-    }
-    if let Some(bb_player) = poker_game.current_players.get_mut(big_blind_index) {
-        bb_player.bet_amount = big_blind;
-        bb_player.money_lost += big_blind as i32;
-        poker_game.pot += big_blind;
-    }
-
-    poker_game.current_bet = big_blind;
-    
-    let blind_info = json!({
-        "info": format!(
-            "{} posted small blind ({} chips), {} posted big blind ({} chips).",
-            small_blind_player_id, small_blind,
-            big_blind_player_id, big_blind
-        ),
-        "pot": poker_game.pot
-    });
-    broadcast_to_game_players(&clients, &blind_info.to_string());
-
-    update_game_state_field(&games_collection, "info", format!(
-        "{} posted small blind ({} chips), {} posted big blind ({} chips).",
-        small_blind_player_id, small_blind,
-        big_blind_player_id, big_blind).into()).await.unwrap();
-    update_game_state_field(&games_collection, "pot", poker_game.pot.clone().into()).await.unwrap();
-    
-    println!(
-        "[Blinds] {} (SB) posts {}, {} (BB) posts {}. Pot = {}",
-        small_blind_player_id, small_blind,
-        big_blind_player_id, big_blind,
-        poker_game.pot
-    );
-
-    thread::sleep(Duration::from_secs(3));
-
-
-
-    // // Deal initial hole cards (2)
-    poker_game.deal_hole_cards();
-    let mut hands_map: HashMap<String, Vec<String>> = HashMap::new();
-    for player in &poker_game.current_players {
-        let cards = player.hand.iter().map(|card| format!("{}", card)).collect::<Vec<_>>();
-
-        hands_map.insert(player.id.clone(), cards.clone());
-        println!(" - {}: {}", player.id, cards.join(", "));
-    }
-
-    let mut message = json!({
-        "cards": hands_map
-    });
-    
-    let json_str = message.to_string();
-    println!("JSON to send: {}", json_str);
-    broadcast_to_game_players(&clients, json_str.as_str());
-
-    let hands_value = serde_json::to_value(&hands_map).unwrap();
-    update_game_state_field(&games_collection, "cards", hands_value).await.unwrap();
-
- 
-
-    //  pre flop (NO COMMUNITY CARDS) JUST A BETTING ROUND
-    // First round of betting
-    // betting to match the big blind
-    let mut first_highest_bet = big_blind_player_id.clone();
-    let mut player_bet_index:i32 = 2;
-
-    loop {
-        if player_bet_index >= poker_game.current_players.len() as i32{
-            player_bet_index = 0;
-        }
-
-        let player_id = poker_game.current_players[player_bet_index as usize].id.clone();
-
-        if player_id == first_highest_bet || poker_game.current_players.len() == 1  {
-            break;
-        }
-
-        loop {
-            let mut bet_amounts_map: HashMap<String, i32> = HashMap::new();
-            for p in &poker_game.current_players {
-                bet_amounts_map.insert(p.id.clone(), p.bet_amount);
-            }
-
-
-            message = json!({
-                "cards": hands_map,
-                "bet": player_id.clone(),
-                "pot": poker_game.pot,
-                "round current bet": poker_game.current_bet,
-                "info": format!("Match the big blind, enter the amount of the big blind or -1 fold\n
-                {} is small blind, {} is big blind", small_blind_player_id, big_blind_player_id),
-                "player bet amount": bet_amounts_map,
-            });
-
-            let json_str = message.to_string();
-            println!("JSON to send: {}", json_str);
-            broadcast_to_game_players(&clients, json_str.as_str());
-
-            update_game_state_field(&games_collection, "pot", poker_game.pot.into()).await.unwrap();
-            update_game_state_field(&games_collection, "round current bet", poker_game.current_bet.into()).await.unwrap();
-            let bet_amounts_value = serde_json::to_value(&bet_amounts_map).unwrap();
-            update_game_state_field(&games_collection, "player current bets", bet_amounts_value).await.unwrap();
-            let hands_value = serde_json::to_value(&hands_map).unwrap();
-            update_game_state_field(&games_collection, "cards", hands_value).await.unwrap();
-            
-            let filter = doc! { "name": &player_id.clone() };
-            let update = doc! { "$set": { "bet_turn": true } };
-            let _ = players_collection.update_one(filter.clone(), update).await;
-            
-            // Wait till the bet is made
-            loop {
-                // Sleep to avoid hammering the DB
-                thread::sleep(Duration::from_millis(300));
-                
-                // Fetch the latest value
-                if let Ok(Some(doc)) = players_collection.find_one(filter.clone()).await {
-                    if let Some(bet_turn) = doc.get_bool("bet_turn").ok() {
-                        if !bet_turn {
-                            println!("{} has completed their bet.", player_id);
-                            break;
-                        }
-                    }
-                }
-            }
-            
-            if let Some(bet_amount) = get_player_bet(&players_collection, &player_id).await {
-                if let Some(player) = poker_game.current_players.iter_mut().find(|p| p.id == player_id) {
-                    if bet_amount == -1 {
-                        println!("{} folds.", player_id.clone());
-                        if let Some(player) = poker_game.current_players.iter_mut().find(|p| p.id == player_id) {
-                            poker_game.players.push(player.clone());
-                        }
-                        poker_game.current_players.remove(player_bet_index as usize);
-                        player_bet_index -= 1;
-                        send_to_player_by_id(&clients, player_id.as_str(), "You folded");
-                        update_game_state_field(&games_collection, "info", format!("Player {} folded", player_id).into()).await.unwrap();
-                        break;
-                    }
-
-                    if player.bet_amount + bet_amount == poker_game.current_bet {
-                        player.bet_amount += bet_amount;
-                        poker_game.pot += bet_amount;
-                        update_game_state_field(&games_collection, "info", format!("Player {} raised the bet by {}", player_id, bet_amount).into()).await.unwrap();
-                        break;
-                    }
-                }  
-
-            }        
-        }
-        player_bet_index += 1;
-    }
-
-    // Betting if the dealer wants to raise
-    let mut first_highest_bet = "none".to_string();
-    let mut player_bet_index = poker_game
-                                                .current_players
-                                                .iter()
-                                                .position(|p| p.id == big_blind_player_id).unwrap() as i32;
-    let mut big_blind_check = false;
-    let pre_round_pot = poker_game.pot.clone();
-
-    loop {
-        if big_blind_check {
-            break;
-        }
-
-        if player_bet_index >= poker_game.current_players.len() as i32{
-            player_bet_index = 0;
-        }
-
-        let player_id = poker_game.current_players[player_bet_index as usize].id.clone();
-
-        if player_id == first_highest_bet || poker_game.current_players.len() == 1  {
-            break;
-        }
-
-        loop {
-            let mut bet_amounts_map: HashMap<String, i32> = HashMap::new();
-            for p in &poker_game.current_players {
-                bet_amounts_map.insert(p.id.clone(), p.bet_amount);
-            }
-
-
-            if poker_game.pot == pre_round_pot && player_id == big_blind_player_id {
-                message = json!({
-                    "cards": hands_map,
-                    "bet": player_id.clone(),
-                    "pot": poker_game.pot,
-                    "round current bet": poker_game.current_bet,
-                    "info": format!("You are the big blind, call 0 now to end the betting round or raise the bet\n
-                            {} is small blind, {} is big blind", small_blind_player_id, big_blind_player_id),
-                    "player bet amount": bet_amounts_map,
-                });
-            }else {
-                message = json!({
-                    "cards": hands_map,
-                    "bet": player_id.clone(),
-                    "pot": poker_game.pot,
-                    "round current bet": poker_game.current_bet,
-                    "info": format!("{} is small blind, {} is big blind", small_blind_player_id, big_blind_player_id),
-                    "player bet amount": bet_amounts_map,
-                });
-            }
-
-            let json_str = message.to_string();
-            println!("JSON to send: {}", json_str);
-            broadcast_to_game_players(&clients, json_str.as_str());
-
-            update_game_state_field(&games_collection, "pot", poker_game.pot.into()).await.unwrap();
-            update_game_state_field(&games_collection, "round current bet", poker_game.current_bet.into()).await.unwrap();
-            let bet_amounts_value = serde_json::to_value(&bet_amounts_map).unwrap();
-            update_game_state_field(&games_collection, "player current bets", bet_amounts_value).await.unwrap();
-            let hands_value = serde_json::to_value(&hands_map).unwrap();
-            update_game_state_field(&games_collection, "cards", hands_value).await.unwrap();
-            
-            let filter = doc! { "name": &player_id.clone() };
-            let update = doc! { "$set": { "bet_turn": true } };
-            let _ = players_collection.update_one(filter.clone(), update).await;
-            
-            // Wait till the bet is made
-            loop {
-                // Sleep to avoid hammering the DB
-                thread::sleep(Duration::from_millis(300));
-                
-                // Fetch the latest value
-                if let Ok(Some(doc)) = players_collection.find_one(filter.clone()).await {
-                    if let Some(bet_turn) = doc.get_bool("bet_turn").ok() {
-                        if !bet_turn {
-                            println!("{} has completed their bet.", player_id);
-                            break;
-                        }
-                    }
-                }
-            }
-            
-            if let Some(bet_amount) = get_player_bet(&players_collection, &player_id).await {
-                if let Some(player) = poker_game.current_players.iter_mut().find(|p| p.id == player_id) {
-                    if player.id == big_blind_player_id && poker_game.pot == pre_round_pot {
-                        if !big_blind_check {
-                            if bet_amount == 0 {
-                                big_blind_check = true;
-                                break;
-                            }
-                        }
-                    }
-
-                    if bet_amount == -1 {
-                        println!("{} folds.", player_id.clone());
-                        if let Some(player) = poker_game.current_players.iter_mut().find(|p| p.id == player_id) {
-                            poker_game.players.push(player.clone());
-                        }
-    
-                        poker_game.current_players.remove(player_bet_index as usize);
-                        player_bet_index -= 1;
-                        send_to_player_by_id(&clients, player_id.as_str(), "You folded");
-                        update_game_state_field(&games_collection, "info", format!("Player {} folded", player_id).into()).await.unwrap();
-                        break;
-                    }
-
-                    if player.bet_amount + bet_amount >= poker_game.current_bet {
-                        if player.bet_amount + bet_amount > poker_game.current_bet {
-                            first_highest_bet = player.id.clone();
-                            if poker_game.current_bet < 0 {
-                                poker_game.current_bet = 0;
-                            }
-                            poker_game.current_bet = player.bet_amount + bet_amount;
-                        }
-                        player.money_lost += bet_amount;
-                        player.bet_amount += bet_amount;
-                        poker_game.pot += bet_amount;
-                        update_game_state_field(&games_collection, "info", format!("Player {} raised the bet by {}", player_id, bet_amount).into()).await.unwrap();
-                        break;
-                    }
-                }  
-
-            }        
-        }
-        player_bet_index += 1;
-    }
-
-
-
-    // After 1st round of betting
-    update_players_folded(&players_collection, &poker_game.players.clone()).await.unwrap();
-
-    if poker_game.current_players.len() == 1 {
-        let winner_id = poker_game.current_players[0].id.clone();
-        broadcast_to_game_players(&clients, format!("Game is over, winner is {}", winner_id).as_str());
-        update_game_state_field(&games_collection, "winner", format!("Game is over, winner is {}", winner_id).as_str().into()).await.unwrap();
-
-        println!("[Game] Winner determined: {}", winner_id);
-        
-        // Update database with results
-        if let Err(e) = db::update_game_results(
-            &players_collection,
-            &winner_id,
-            &poker_game.current_players.clone(),
-            poker_game.pot,
-        ).await {
-            eprintln!("Failed to update game results: {}", e);
-        }
-        
-        return;
-    }
-
-
-
-
-    // flop: 3 community cards
-    poker_game.deal_flop();
-
-    let community_cards: Vec<String> = poker_game
-        .community_cards
-        .iter()
-        .map(|card| format!("{}", card))
-        .collect();
-    println!("Community Cards: {}", community_cards.join(", "));
-
-    let message = json!({
-        "community": community_cards,
-        "cards": hands_map
-    });
-
-    let json_str = message.to_string();
-    println!("JSON to send: {}", json_str);
-    broadcast_to_game_players(&clients, json_str.as_str());
-
-    let hands_value = serde_json::to_value(&hands_map).unwrap();
-    update_game_state_field(&games_collection, "cards", hands_value).await.unwrap();
-    let community_value = serde_json::to_value(&community_cards).unwrap();
-    update_game_state_field(&games_collection, "community", community_value).await.unwrap();
-
-
-
-    // 2nd beting
-    for player in &mut poker_game.current_players {
-        player.bet_amount = 0;
-    }
-
-    poker_game.current_bet = -2;
-    let mut first_highest_bet = "none".to_string();
-    let mut player_bet_index:i32 = 0;
-    
-    loop {
-
-        if player_bet_index >= poker_game.current_players.len() as i32{
-            player_bet_index = 0;
-        }
-
-        let player_id = poker_game.current_players[player_bet_index as usize].id.clone();
-
-        if player_id == first_highest_bet || poker_game.current_players.len() == 1 {
-            break;
-        }
-
-        loop {
-            let mut bet_amounts_map: HashMap<String, i32> = HashMap::new();
-            for p in &poker_game.current_players {
-                bet_amounts_map.insert(p.id.clone(), p.bet_amount);
-            }
-
-
-            let message = json!({
-                "community": community_cards,
-                "cards": hands_map,
-                "bet": player_id.clone(),
-                "pot": poker_game.pot,
-                "round current bet": poker_game.current_bet,
-                "player bet amount": bet_amounts_map,
-            });
-            
-            let json_str = message.to_string();
-            println!("JSON to send: {}", json_str);
-            broadcast_to_game_players(&clients, json_str.as_str());
-
-            update_game_state_field(&games_collection, "pot", poker_game.pot.into()).await.unwrap();
-            update_game_state_field(&games_collection, "round current bet", poker_game.current_bet.into()).await.unwrap();
-            let bet_amounts_value = serde_json::to_value(&bet_amounts_map).unwrap();
-            update_game_state_field(&games_collection, "player current bets", bet_amounts_value).await.unwrap();
-            let hands_value = serde_json::to_value(&hands_map).unwrap();
-            update_game_state_field(&games_collection, "cards", hands_value).await.unwrap();
-            
-            let filter = doc! { "name": &player_id.clone() };
-            let update = doc! { "$set": { "bet_turn": true } };
-            let _ = players_collection.update_one(filter.clone(), update).await;
-            
-            // Wait till the bet is made
-            loop {
-                // Sleep to avoid hammering the DB
-                thread::sleep(Duration::from_millis(300));
-                
-                // Fetch the latest value
-                if let Ok(Some(doc)) = players_collection.find_one(filter.clone()).await {
-                    if let Some(bet_turn) = doc.get_bool("bet_turn").ok() {
-                        if !bet_turn {
-                            println!("{} has completed their bet.", player_id);
-                            break;
-                        }
-                    }
-                }
-            }
-            
-            if let Some(bet_amount) = get_player_bet(&players_collection, &player_id).await {
-                if let Some(player) = poker_game.current_players.iter_mut().find(|p| p.id == player_id) {
-                    if bet_amount == -1 {
-                        println!("{} folds.", player_id.clone());
-                        if let Some(player) = poker_game.current_players.iter_mut().find(|p| p.id == player_id) {
-                            poker_game.players.push(player.clone());
-                        }
-                        poker_game.current_players.remove(player_bet_index as usize);
-                        player_bet_index -= 1;
-                        send_to_player_by_id(&clients, player_id.as_str(), "You folded");
-                        update_game_state_field(&games_collection, "info", format!("Player {} folded", player_id).into()).await.unwrap();
-                        break;
-                    }
-
-                    if player.bet_amount + bet_amount >= poker_game.current_bet {
-                        if player.bet_amount + bet_amount > poker_game.current_bet {
-                            first_highest_bet = player.id.clone();
-                            if poker_game.current_bet < 0 {
-                                poker_game.current_bet = 0;
-                            }
-                            poker_game.current_bet = player.bet_amount + bet_amount;
-                        }
-                        player.money_lost += bet_amount;
-                        player.bet_amount += bet_amount;
-                        poker_game.pot += bet_amount;
-                        update_game_state_field(&games_collection, "info", format!("Player {} raised the bet by {}", player_id, bet_amount).into()).await.unwrap();
-                        break;
-                    }
-                }
-            }        
-        }
-        player_bet_index += 1;
-
-    }
-
-
-
-    // After 2nd round of betting
-    update_players_folded(&players_collection, &poker_game.players.clone()).await.unwrap();
-
-    if poker_game.current_players.len() == 1 {
-        let winner_id = poker_game.current_players[0].id.clone();
-        broadcast_to_game_players(&clients, format!("Game is over, winner is {}", winner_id).as_str());
-        update_game_state_field(&games_collection, "winner", format!("Game is over, winner is {}", winner_id).as_str().into()).await.unwrap();
-
-        println!("[Game] Winner determined: {}", winner_id);
-        
-        // Update database with results
-        if let Err(e) = db::update_game_results(
-            &players_collection,
-            &winner_id,
-            &poker_game.current_players.clone(),
-            poker_game.pot,
-        ).await {
-            eprintln!("Failed to update game results: {}", e);
-        }
-        
-        return;
-    }
-
-
-
-    // turn: 4 community cards
-    poker_game.deal_turn();
-
-    let community_cards: Vec<String> = poker_game
-        .community_cards
-        .iter()
-        .map(|card| format!("{}", card))
-        .collect();
-    println!("Community Cards: {}", community_cards.join(", "));
-
-    let message = json!({
-        "community": community_cards,
-        "cards": hands_map,
-        
-    });
-
-    let json_str = message.to_string();
-    println!("JSON to send: {}", json_str);
-    broadcast_to_game_players(&clients, json_str.as_str());
-
-    let hands_value = serde_json::to_value(&hands_map).unwrap();
-    update_game_state_field(&games_collection, "cards", hands_value).await.unwrap();
-    let community_value = serde_json::to_value(&community_cards).unwrap();
-    update_game_state_field(&games_collection, "community", community_value).await.unwrap();
-
-
-
-    // third round of betting
-    // Resetting bet for all players to 0
-    for player in &mut poker_game.current_players {
-        player.bet_amount = 0;
-    }
-
-    poker_game.current_bet = -2;
-    let mut first_highest_bet = "none".to_string();
-    let mut player_bet_index:i32 = 0;
-
-    loop {
-        if player_bet_index >= poker_game.current_players.len() as i32{
-            player_bet_index = 0;
-        }
-
-        let player_id = poker_game.current_players[player_bet_index as usize].id.clone();
-
-        if player_id == first_highest_bet || poker_game.current_players.len() == 1 {
-            break;
-        }
-
-        loop {
-            let mut bet_amounts_map: HashMap<String, i32> = HashMap::new();
-            for p in &poker_game.current_players {
-                bet_amounts_map.insert(p.id.clone(), p.bet_amount);
-            }
-
-
-            let message = json!({
-                "cards": hands_map,
-                "bet": player_id.clone(),
-                "community": community_cards,
-                "pot": poker_game.pot,
-                "round current bet": poker_game.current_bet,
-                "player bet amount": bet_amounts_map,
-            });
-            
-            let json_str = message.to_string();
-            println!("JSON to send: {}", json_str);
-            broadcast_to_game_players(&clients, json_str.as_str());
-
-            update_game_state_field(&games_collection, "pot", poker_game.pot.into()).await.unwrap();
-            update_game_state_field(&games_collection, "round current bet", poker_game.current_bet.into()).await.unwrap();
-            let bet_amounts_value = serde_json::to_value(&bet_amounts_map).unwrap();
-            update_game_state_field(&games_collection, "player current bets", bet_amounts_value).await.unwrap();
-            let hands_value = serde_json::to_value(&hands_map).unwrap();
-            update_game_state_field(&games_collection, "cards", hands_value).await.unwrap();
-            
-            let filter = doc! { "name": &player_id.clone() };
-            let update = doc! { "$set": { "bet_turn": true } };
-            let _ = players_collection.update_one(filter.clone(), update).await;
-            
-            // Wait till the bet is made
-            loop {
-                // Sleep to avoid hammering the DB
-                thread::sleep(Duration::from_millis(300));
-                
-                // Fetch the latest value
-                if let Ok(Some(doc)) = players_collection.find_one(filter.clone()).await {
-                    if let Some(bet_turn) = doc.get_bool("bet_turn").ok() {
-                        if !bet_turn {
-                            println!("{} has completed their bet.", player_id);
-                            break;
-                        }
-                    }
-                }
-            }
-            
-            if let Some(bet_amount) = get_player_bet(&players_collection, &player_id).await {
-                if let Some(player) = poker_game.current_players.iter_mut().find(|p| p.id == player_id) {
-                    if bet_amount == -1 {
-                        println!("{} folds.", player_id.clone());
-                        if let Some(player) = poker_game.current_players.iter_mut().find(|p| p.id == player_id) {
-                            poker_game.players.push(player.clone());
-                        }
-                        poker_game.current_players.remove(player_bet_index as usize);
-                        player_bet_index -= 1;
-                        send_to_player_by_id(&clients, player_id.as_str(), "You folded");
-                        update_game_state_field(&games_collection, "info", format!("Player {} folded", player_id).into()).await.unwrap();
-                        break;
-                    }
-
-                    if player.bet_amount + bet_amount >= poker_game.current_bet {
-                        if player.bet_amount + bet_amount > poker_game.current_bet {
-                            first_highest_bet = player.id.clone();
-                            if poker_game.current_bet < 0 {
-                                poker_game.current_bet = 0;
-                            }
-                            poker_game.current_bet = player.bet_amount + bet_amount;
-                        }
-                        player.bet_amount += bet_amount;
-                        player.money_lost += bet_amount;
-                        poker_game.pot += bet_amount;
-                        update_game_state_field(&games_collection, "info", format!("Player {} raised the bet by {}", player_id, bet_amount).into()).await.unwrap();
-                        break;
-                    }
-                }
-            }        
-        }
-        player_bet_index += 1;
-    }
-
-
-
-    // After 3rd round of betting
-    update_players_folded(&players_collection, &poker_game.players.clone()).await.unwrap();
-
-    if poker_game.current_players.len() == 1 {
-        let winner_id = poker_game.current_players[0].id.clone();
-        broadcast_to_game_players(&clients, format!("Game is over, winner is {}", winner_id).as_str());
-        update_game_state_field(&games_collection, "winner", format!("Game is over, winner is {}", winner_id).as_str().into()).await.unwrap();
-
-        println!("[Game] Winner determined: {}", winner_id);
-        
-        // Update database with results
-        if let Err(e) = db::update_game_results(
-            &players_collection,
-            &winner_id,
-            &poker_game.current_players.clone(),
-            poker_game.pot,
-        ).await {
-            eprintln!("Failed to update game results: {}", e);
-        }
-        
-        return;
-    }
-    
-
-
-    // turn: 4 community cards
-    poker_game.deal_river();
-
-    let community_cards: Vec<String> = poker_game
-        .community_cards
-        .iter()
-        .map(|card| format!("{}", card))
-        .collect();
-    println!("Community Cards: {}", community_cards.join(", "));
-
-    let message = json!({
-        "community": community_cards,
-        "cards": hands_map
-    });
-
-    let json_str = message.to_string();
-    println!("JSON to send: {}", json_str);
-    broadcast_to_game_players(&clients, json_str.as_str());
-
-    let hands_value = serde_json::to_value(&hands_map).unwrap();
-    update_game_state_field(&games_collection, "cards", hands_value).await.unwrap();
-    let community_value = serde_json::to_value(&community_cards).unwrap();
-    update_game_state_field(&games_collection, "community", community_value).await.unwrap();
- 
-   
-
-    // 4th betting round
-    // Resetting bet for all players to 0
-    for player in &mut poker_game.current_players {
-        player.bet_amount = 0;
-    }
-
-    poker_game.current_bet = -2;
-    let mut first_highest_bet = "none".to_string();
-    let mut player_bet_index:i32 = 0;
-
-    loop {
-        if player_bet_index >= poker_game.current_players.len() as i32{
-            player_bet_index = 0;
-        }
-
-        let player_id = poker_game.current_players[player_bet_index as usize].id.clone();
-
-        if player_id == first_highest_bet || poker_game.current_players.len() == 1 {
-            break;
-        }
-
-        loop {
-            let mut bet_amounts_map: HashMap<String, i32> = HashMap::new();
-            for p in &poker_game.current_players {
-                bet_amounts_map.insert(p.id.clone(), p.bet_amount);
-            }
-
-
-            let message = json!({
-                "cards": hands_map,
-                "bet": player_id.clone(),
-                "community": community_cards,
-                "pot": poker_game.pot,
-                "round current bet": poker_game.current_bet,
-                "player bet amount": bet_amounts_map,        
-            });
-            
-            let json_str = message.to_string();
-            println!("JSON to send: {}", json_str);
-            broadcast_to_game_players(&clients, json_str.as_str());
-
-            update_game_state_field(&games_collection, "pot", poker_game.pot.into()).await.unwrap();
-            update_game_state_field(&games_collection, "round current bet", poker_game.current_bet.into()).await.unwrap();
-            let bet_amounts_value = serde_json::to_value(&bet_amounts_map).unwrap();
-            update_game_state_field(&games_collection, "player current bets", bet_amounts_value).await.unwrap();
-            let hands_value = serde_json::to_value(&hands_map).unwrap();
-            update_game_state_field(&games_collection, "cards", hands_value).await.unwrap();
-            
-            let filter = doc! { "name": &player_id.clone() };
-            let update = doc! { "$set": { "bet_turn": true } };
-            let _ = players_collection.update_one(filter.clone(), update).await;
-            
-            // Wait till the bet is made
-            loop {
-                // Sleep to avoid hammering the DB
-                thread::sleep(Duration::from_millis(300));
-                
-                // Fetch the latest value
-                if let Ok(Some(doc)) = players_collection.find_one(filter.clone()).await {
-                    if let Some(bet_turn) = doc.get_bool("bet_turn").ok() {
-                        if !bet_turn {
-                            println!("{} has completed their bet.", player_id);
-                            break;
-                        }
-                    }
-                }
-            }
-            
-            if let Some(bet_amount) = get_player_bet(&players_collection, &player_id).await {
-                if let Some(player) = poker_game.current_players.iter_mut().find(|p| p.id == player_id) {
-                    if bet_amount == -1 {
-                        println!("{} folds.", player_id.clone());
-                        if let Some(player) = poker_game.current_players.iter_mut().find(|p| p.id == player_id) {
-                            poker_game.players.push(player.clone());
-                        }
-                        poker_game.current_players.remove(player_bet_index as usize);
-                        player_bet_index -= 1;
-                        send_to_player_by_id(&clients, player_id.as_str(), "You folded");
-                        update_game_state_field(&games_collection, "info", format!("Player {} folded", player_id).into()).await.unwrap();
-                        break;
-                    }
-
-                    if player.bet_amount + bet_amount >= poker_game.current_bet {
-                        if player.bet_amount + bet_amount > poker_game.current_bet {
-                            first_highest_bet = player.id.clone();
-                            if poker_game.current_bet < 0 {
-                                poker_game.current_bet = 0;
-                            }
-                            poker_game.current_bet = player.bet_amount + bet_amount;
-                        }
-                        player.bet_amount += bet_amount;
-                        poker_game.pot += bet_amount;
-                        player.money_lost += bet_amount;
-                        update_game_state_field(&games_collection, "info", format!("Player {} raised the bet by {}", player_id, bet_amount).into()).await.unwrap();
-                        break;
-                    }
-                }
-                
-            }        
-        }
-        player_bet_index += 1;
-    }
-
-
-
-    // After 4th round of betting
-    update_players_folded(&players_collection, &poker_game.players.clone()).await.unwrap();
-
-    if poker_game.current_players.len() == 1 {
-        let winner_id = poker_game.current_players[0].id.clone();
-        broadcast_to_game_players(&clients, format!("Game is over, winner is {}", winner_id).as_str());
-        update_game_state_field(&games_collection, "winner", format!("Game is over, winner is {}", winner_id).as_str().into()).await.unwrap();
-
-        println!("[Game] Winner determined: {}", winner_id);
-        
-        // Update database with results
-        if let Err(e) = db::update_game_results(
-            &players_collection,
-            &winner_id,
-            &poker_game.current_players.clone(),
-            poker_game.pot,
-        ).await {
-            eprintln!("Failed to update game results: {}", e);
-        }
-        
-        return;
-    }
-
-
-    
-    // Showdown
-    if let Some(winner) = poker_game.showdown() {
-        println!("[Game] Winner determined: {}", winner);
-        update_game_state_field(&games_collection, "winner", format!("Game is over, winner is {}", winner).as_str().into()).await.unwrap();
-        
-        // Create a showdown JSON message to send to all players.
-        let showdown_msg = json!({
-            "winner": winner,
-            "showdown": hands_map,
-            "pot": poker_game.pot,
-            "community": community_cards
-        })
-        .to_string();
-    
-        broadcast_to_game_players(&clients, showdown_msg.as_str());
-    }
-
-
-
-    // Updating results after game
-    if let Some(winner) = poker_game.showdown() {
-        println!("[Game] Winner determined: {}", winner);
-        
-        // Update database with results
-        if let Err(e) = db::update_game_results(
-            &players_collection,
-            &winner,
-            &poker_game.current_players.clone(),
-            poker_game.pot,
-        ).await {
-            eprintln!("Failed to update game results: {}", e);
-        }
-    }
+    println!("[Game] Players: {:?}", hand.active_players().iter().map(|p| &p.id).collect::<Vec<_>>());
 
-    // Simulate game running
-    thread::sleep(Duration::from_secs(5));
-    println!("[Game] Game finished.");
-}
\ No newline at end of file
+    play_hand(hand, clients, &player_names, storage, replay).await;
+}
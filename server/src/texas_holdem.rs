@@ -2,14 +2,17 @@
 use crate::deck::{Card, Deck};
 use crate::five_card_draw::{Player, evaluate_hand};
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 
 /// Represents a Texas Hold'em poker game.
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct TexasHoldemGame {
     /// List of all players who started
     pub players: Vec<Player>,
     /// Deck of cards used
     pub deck: Deck,
+    /// Seed the deck is shuffled from when the cards are dealt.
+    pub shuffle_seed: u64,
     /// Total pot size for the current game.
     pub pot: i32,
     /// The current highest bet in the round.
@@ -32,6 +35,7 @@ impl TexasHoldemGame {
     /// A new instance of `TexasHoldemGame`.
     pub fn new(player_ids: Vec<String>) -> Self {
         let deck = Deck::new();
+        let shuffle_seed = crate::recovery::shuffle_seed(None);
         let current_players: Vec<Player> = player_ids
             .into_iter()
             .map(|id| Player {
@@ -47,6 +51,7 @@ impl TexasHoldemGame {
         Self {
             players: Vec::new(),
             deck,
+            shuffle_seed,
             pot: 0,
             current_bet: 0,
             current_players,
@@ -56,7 +61,7 @@ impl TexasHoldemGame {
 
     /// Deals hole cards to each player (2 cards each).
     pub fn deal_hole_cards(&mut self) {
-        self.deck = Deck::new();
+        self.deck = Deck::with_seed(self.shuffle_seed);
         for player in &mut self.current_players {
             player.hand.clear();
             player.folded = false;
@@ -0,0 +1,110 @@
+//! # TLS
+//!
+//! Optional TLS for client connections, enabled with the `[tls]` section of the
+//! config file.
+//!
+//! The certificate and private key are read from the configured PEM files. If
+//! neither file exists, a self-signed certificate is generated and saved there, so
+//! it stays the same across restarts and clients that pinned it keep working.
+//! The generated key is readable by its owner only; a key supplied in the config
+//! should be protected the same way (`chmod 600`).
+//!
+//! At startup the certificate's SHA-256 fingerprint is printed. Players can enter
+//! it in the client to pin the dealer, or let the client trust it on first use.
+use std::fs;
+use std::io::{BufReader, Write};
+use std::path::Path;
+use std::sync::Arc;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::ServerConfig;
+use sha2::{Digest, Sha256};
+use crate::config::TlsConfig;
+
+/// Builds the TLS settings for the listener, generating a certificate if needed.
+///
+/// # Arguments
+/// * `config` - The `[tls]` section of the server config.
+///
+/// # Returns
+/// The rustls server config, or a message saying what went wrong.
+pub fn server_config(config: &TlsConfig) -> Result<Arc<ServerConfig>, String> {
+    let cert_exists = Path::new(&config.cert_path).exists();
+    let key_exists = Path::new(&config.key_path).exists();
+    match (cert_exists, key_exists) {
+        (false, false) => generate_self_signed(&config.cert_path, &config.key_path)?,
+        (true, false) => return Err(format!("TLS key file {} not found", config.key_path)),
+        (false, true) => return Err(format!("TLS certificate file {} not found", config.cert_path)),
+        (true, true) => {}
+    }
+
+    let certs = load_certs(&config.cert_path)?;
+    let key = load_key(&config.key_path)?;
+    if let Some(cert) = certs.first() {
+        println!("[TLS] Certificate fingerprint (SHA-256): {}", fingerprint(cert));
+    }
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let server_config = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(|e| format!("TLS setup failed: {}", e))?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("Invalid TLS certificate or key: {}", e))?;
+    Ok(Arc::new(server_config))
+}
+
+/// SHA-256 fingerprint of a DER certificate, as colon-separated hex pairs.
+pub fn fingerprint(cert: &[u8]) -> String {
+    Sha256::digest(cert)
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// Generates a self-signed certificate for `localhost` and writes it and its key
+/// as PEM files.
+fn generate_self_signed(cert_path: &str, key_path: &str) -> Result<(), String> {
+    let mut names = vec!["localhost".to_string()];
+    if let Some(ip) = crate::get_local_ip() {
+        names.push(ip);
+    }
+    let certified = rcgen::generate_simple_self_signed(names)
+        .map_err(|e| format!("Failed to generate TLS certificate: {}", e))?;
+
+    fs::write(cert_path, certified.cert.pem())
+        .map_err(|e| format!("Failed to write {}: {}", cert_path, e))?;
+    write_private(key_path, certified.key_pair.serialize_pem().as_bytes())
+        .map_err(|e| format!("Failed to write {}: {}", key_path, e))?;
+    println!("[TLS] Generated self-signed certificate {} and key {}", cert_path, key_path);
+    Ok(())
+}
+
+/// Writes a file only its owner can read, for the private key. A new file is
+/// created with mode `0o600` on unix, instead of the umask's usual world-readable
+/// default.
+fn write_private(path: &str, contents: &[u8]) -> std::io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(contents)
+}
+
+fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, String> {
+    let file = fs::File::open(path).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Failed to read certificates from {}: {}", path, e))?;
+    if certs.is_empty() {
+        return Err(format!("No certificates found in {}", path));
+    }
+    Ok(certs)
+}
+
+fn load_key(path: &str) -> Result<PrivateKeyDer<'static>, String> {
+    let file = fs::File::open(path).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .map_err(|e| format!("Failed to read private key from {}: {}", path, e))?
+        .ok_or_else(|| format!("No private key found in {}", path))
+}
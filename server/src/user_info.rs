@@ -1,19 +1,104 @@
 //! # User Info Handling
 //!
 //! This module manages user registration and login functionalities.
-//! It connects to the MongoDB `players_collection` and processes incoming JSON data
-//! to register new players or authenticate existing ones.
+//! It reads and writes player documents through the server's `Storage` backend and
+//! processes incoming JSON data to register new players or authenticate existing ones.
+//!
+//! Passwords are stored as salted Argon2 hashes in PHC string format
+//! (`$argon2id$v=19$...`). Accounts created before hashing was introduced still
+//! hold the plain password; it is replaced with a hash the next time that user
+//! logs in.
+//!
+//! Argon2 is slow on purpose, so hashing and checking run on tokio's blocking
+//! thread pool (`spawn_blocking`), and the dispatcher runs each registration and
+//! login in a task of its own, so they never hold up the tasks serving the table.
 //!
 //! Functions:
 //! - `handle_registration`: Register a new player.
 //! - `handle_login`: Authenticate an existing player.
+//! - `hash_password`: Hash a password for storage.
+//! - `verify_password`: Check a password against a stored hash.
+//! - `redact_secrets`: Hide the password and session token in a message before logging it.
+use std::sync::Mutex;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use rand::rngs::OsRng;
 use serde_json::Value;
 use mongodb::bson::doc;
-use mongodb::{
-    bson::Document,
-    options::{ClientOptions, ServerApi, ServerApiVersion},
-    Client, Collection,
-};
+use protocol::ServerEvent;
+use crate::session::Sessions;
+use crate::storage::{Storage, StorageError};
+
+/// Hashes a password with Argon2id and a random salt, on the blocking thread pool.
+///
+/// # Arguments
+/// * `password` - The plain password.
+///
+/// # Returns
+/// The hash as a PHC string, which includes the salt and parameters.
+pub async fn hash_password(password: &str) -> Result<String, String> {
+    let password = password.to_string();
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|e| format!("Password hashing failed: {}", e))
+    })
+    .await
+    .map_err(|e| format!("Password hashing failed: {}", e))?
+}
+
+/// Checks a password against a stored hash, on the blocking thread pool.
+///
+/// # Arguments
+/// * `password` - The password to check.
+/// * `stored` - The PHC string from the player document.
+///
+/// # Returns
+/// `true` if the password matches. A malformed hash never matches.
+pub async fn verify_password(password: &str, stored: &str) -> bool {
+    let (password, stored) = (password.to_string(), stored.to_string());
+    tokio::task::spawn_blocking(move || match PasswordHash::new(&stored) {
+        Ok(hash) => Argon2::default().verify_password(password.as_bytes(), &hash).is_ok(),
+        Err(_) => false,
+    })
+    .await
+    .unwrap_or(false)
+}
+
+/// Fields of a message that must never reach the logs.
+const SECRET_FIELDS: &[&str] = &["password", "token"];
+
+/// Masks the password and session token in a message so it can be logged.
+///
+/// Used on messages from clients (`login`, `register`, `bind`), since either
+/// secret is enough to take over the account. Replies are never logged in full
+/// (see `comms::describe_reply`).
+///
+/// # Arguments
+/// * `message` - Raw message text.
+///
+/// # Returns
+/// The message with any `"password"` or `"token"` value replaced by `"***"`.
+pub fn redact_secrets(message: &str) -> String {
+    match serde_json::from_str::<Value>(message) {
+        Ok(mut json) if SECRET_FIELDS.iter().any(|field| json.get(field).is_some()) => {
+            for field in SECRET_FIELDS {
+                if json.get(field).is_some() {
+                    json[field] = Value::from("***");
+                }
+            }
+            json.to_string()
+        }
+        _ => message.to_string(),
+    }
+}
+
+/// Whether a stored password is a hash rather than a legacy plain password.
+fn is_hashed(stored: &str) -> bool {
+    stored.starts_with("$argon2")
+}
 
 /// Handles new user registration.
 ///
@@ -25,23 +110,24 @@ use mongodb::{
 /// - If the JSON is invalid, returns an error message.
 /// - If either the username or password is missing, returns an error.
 /// - If the username already exists in the database, returns an error.
-/// - If all checks pass, creates a new player document in the `players_collection`.
+/// - If all checks pass, creates a new player document in storage with the
+///   password hashed.
 ///
 /// # Arguments
-/// * `players_collection` - MongoDB collection for storing player documents.
+/// * `storage` - The server's storage backend.
 /// * `data` - JSON string containing registration info.
 ///
 /// # Returns
-/// A string message indicating success or why it failed.
+/// An `Info` event on success, or an `Error` saying why it failed.
 ///
 /// # Example Input JSON
 /// ```json
 /// { "username": "player1", "password": "secret" }
 /// ```
-pub async fn handle_registration(players_collection: &Collection<Document>, data: &str) -> String {
+pub async fn handle_registration(storage: &dyn Storage, data: &str) -> ServerEvent {
     let parsed: Result<Value, _> = serde_json::from_str(data);
     if parsed.is_err() {
-        return "Invalid JSON format.".to_string();
+        return ServerEvent::error("Invalid JSON format.");
     }
 
     let json = parsed.unwrap();
@@ -49,21 +135,23 @@ pub async fn handle_registration(players_collection: &Collection<Document>, data
     let password = json.get("password").and_then(Value::as_str).unwrap_or("");
 
     if username.is_empty() || password.is_empty() {
-        return "Username and password required.".to_string();
+        return ServerEvent::error("Username and password required.");
     }
 
-    let existing = players_collection
-        .find_one(doc! { "name": username })
-        .await
-        .unwrap();
-
-    if existing.is_some() {
-        return format!("Username '{}' already exists.", username);
+    match storage.find_player(username).await {
+        Ok(Some(_)) => return ServerEvent::error(format!("Username '{}' already exists.", username)),
+        Ok(None) => {}
+        Err(e) => return ServerEvent::error(format!("Database error: {}", e)),
     }
 
+    let password_hash = match hash_password(password).await {
+        Ok(hash) => hash,
+        Err(e) => return ServerEvent::error(e),
+    };
+
     let player_doc = doc! {
         "name": username,
-        "password": password,
+        "password": password_hash,
         "wins": 0,
         "losses": 0,
         "games_played": 0,
@@ -78,8 +166,13 @@ pub async fn handle_registration(players_collection: &Collection<Document>, data
         "swap_turn": false
     };
 
-    let _ = players_collection.insert_one(player_doc).await;
-    format!("Player '{}' registered successfully.", username)
+    match storage.insert_player(player_doc).await {
+        Ok(()) => {}
+        // Someone registered the same name since the check above
+        Err(StorageError::Duplicate(_)) => return ServerEvent::error(format!("Username '{}' already exists.", username)),
+        Err(e) => return ServerEvent::error(format!("Database error: {}", e)),
+    }
+    ServerEvent::info(format!("Player '{}' registered successfully.", username))
 }
 
 /// Handles user login/authentication.
@@ -91,24 +184,27 @@ pub async fn handle_registration(players_collection: &Collection<Document>, data
 /// # Behavior:
 /// - If the JSON is invalid, returns an error message.
 /// - If either the username or password is missing, returns error.
-/// - If the username exists and password matches, returns welcome message.
+/// - If the username exists and password matches, issues a session token and
+///   returns a `LoggedIn` event carrying it.
+///   A plain password left over from before hashing is upgraded to a hash.
 /// - If the password is incorrect or user does not exist, returns error.
 ///
 /// # Arguments
-/// * `players_collection` - MongoDB collection containing player documents.
+/// * `storage` - The server's storage backend.
+/// * `sessions` - Live sessions; a new one is issued on success.
 /// * `data` - JSON string containing login info.
 ///
 /// # Returns
-/// A string message indicating success or the reason for failure.
+/// `LoggedIn` on success, or an `Error` giving the reason for failure.
 ///
 /// # Example Input JSON
 /// ```json
 /// { "username": "player1", "password": "secret" }
 /// ```
-pub async fn handle_login(players_collection: &Collection<Document>, data: &str) -> String {
+pub async fn handle_login(storage: &dyn Storage, sessions: &Mutex<Sessions>, data: &str) -> ServerEvent {
     let parsed: Result<Value, _> = serde_json::from_str(data);
     if parsed.is_err() {
-        return "Invalid JSON format.".to_string();
+        return ServerEvent::error("Invalid JSON format.");
     }
 
     let json = parsed.unwrap();
@@ -116,18 +212,39 @@ pub async fn handle_login(players_collection: &Collection<Document>, data: &str)
     let password = json.get("password").and_then(Value::as_str).unwrap_or("");
 
     if username.is_empty() || password.is_empty() {
-        return "Username and password required.".to_string();
+        return ServerEvent::error("Username and password required.");
     }
 
-    let filter = doc! { "name": username };
-    if let Some(player_doc) = players_collection.find_one(filter).await.unwrap() {
-        if let Some(stored_pass) = player_doc.get_str("password").ok() {
-            if stored_pass == password {
-                return format!("Welcome, {}! You are now in the waiting room.", username);
+    match storage.find_player(username).await {
+        Ok(Some(player_doc)) => {
+            let stored_pass = player_doc.get_str("password").unwrap_or("");
+            if is_hashed(stored_pass) {
+                if verify_password(password, stored_pass).await {
+                    return login_success(sessions, username);
+                }
+            } else if !stored_pass.is_empty() && stored_pass == password {
+                // Legacy plain password: replace it with a hash now that we know it.
+                match hash_password(password).await {
+                    Ok(hash) => match storage.set_player_fields(username, doc! { "password": hash }).await {
+                        Ok(_) => println!("[Auth] Upgraded password for {} to a hash", username),
+                        Err(e) => eprintln!("[Auth] Failed to upgrade password for {}: {}", username, e),
+                    },
+                    Err(e) => eprintln!("[Auth] {}", e),
+                }
+                return login_success(sessions, username);
             }
+            ServerEvent::error("Invalid password.")
         }
-        return "Invalid password.".to_string();
+        Ok(None) => ServerEvent::error("No such user found."),
+        Err(e) => ServerEvent::error(format!("Database error: {}", e)),
     }
+}
 
-    "No such user found.".to_string()
-}
\ No newline at end of file
+/// Issues a session for a user who logged in and builds the login reply.
+fn login_success(sessions: &Mutex<Sessions>, username: &str) -> ServerEvent {
+    ServerEvent::LoggedIn {
+        username: username.to_string(),
+        token: sessions.lock().unwrap().issue(username),
+        message: format!("Welcome, {}! You are now in the waiting room.", username),
+    }
+}
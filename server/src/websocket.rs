@@ -0,0 +1,153 @@
+//! # WebSocket Gateway
+//!
+//! Lets browser and other web clients play without the native client.
+//!
+//! A second listener (port 8081 by default, see the `[websocket]` config section)
+//! accepts WebSocket connections. Each text message carries one JSON command or
+//! event, exactly as inside a TCP frame, so WebSocket clients go through the same
+//! handshake, sessions and dispatcher in `main.rs` as everyone else. With TLS
+//! enabled the listener speaks `wss://` using the same certificate.
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use futures_util::{SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio_rustls::TlsAcceptor;
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+use protocol::framing::{HANDSHAKE_FRAME_LEN, MAX_FRAME_LEN};
+use crate::comms::{describe_reply, event_kind, register_client, ClientEvent};
+use crate::user_info::redact_secrets;
+use crate::ClientInfo;
+
+/// Accepts WebSocket clients for as long as the server runs.
+///
+/// # Arguments
+/// * `listener` - The bound WebSocket listening socket.
+/// * `clients` - Shared list of connected clients.
+/// * `server_tx` - Sender to forward client messages to the server.
+/// * `tls` - The TLS acceptor, or `None` for plain `ws://`.
+pub async fn accept_websocket_clients(
+    listener: TcpListener,
+    clients: Arc<Mutex<HashMap<std::net::SocketAddr, ClientInfo>>>,
+    server_tx: UnboundedSender<(std::net::SocketAddr, ClientEvent)>,
+    tls: Option<TlsAcceptor>,
+) {
+    loop {
+        let (socket, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                eprintln!("[WebSocket] Error accepting client: {}", e);
+                continue;
+            }
+        };
+        println!("[WebSocket] Client connected: {}", addr);
+
+        let clients = Arc::clone(&clients);
+        let server_tx = server_tx.clone();
+        let tls = tls.clone();
+        tokio::spawn(async move {
+            match tls {
+                Some(acceptor) => match acceptor.accept(socket).await {
+                    Ok(stream) => upgrade(stream, addr, clients, server_tx).await,
+                    Err(e) => eprintln!("[TLS] Failed to start session with {}: {}", addr, e),
+                },
+                None => upgrade(socket, addr, clients, server_tx).await,
+            }
+        });
+    }
+}
+
+/// Completes the WebSocket opening handshake, then serves the client.
+///
+/// The client is only registered once the handshake succeeds, so stray HTTP
+/// requests never show up as players.
+async fn upgrade<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    addr: std::net::SocketAddr,
+    clients: Arc<Mutex<HashMap<std::net::SocketAddr, ClientInfo>>>,
+    server_tx: UnboundedSender<(std::net::SocketAddr, ClientEvent)>,
+) {
+    let config = WebSocketConfig::default()
+        .max_message_size(Some(MAX_FRAME_LEN))
+        .max_frame_size(Some(MAX_FRAME_LEN));
+    match tokio_tungstenite::accept_async_with_config(stream, Some(config)).await {
+        Ok(ws) => {
+            let client_rx = register_client(&clients, addr);
+            handle_websocket_client(ws, addr, server_tx, client_rx).await;
+        }
+        Err(e) => eprintln!("[WebSocket] Handshake with {} failed: {}", addr, e),
+    }
+}
+
+/// Handles communication with a single WebSocket client.
+///
+/// Works like `comms::handle_client`, with one text message per command or event
+/// instead of length-prefixed frames. Messages over `HANDSHAKE_FRAME_LEN` bytes
+/// before the dealer's `welcome` close the connection. Reports the disconnect to
+/// the server when the connection ends.
+///
+/// # Arguments
+/// * `ws` - The upgraded WebSocket connection.
+/// * `addr` - The network address of the client.
+/// * `server_tx` - Sender to forward client messages to the server.
+/// * `client_rx` - Receiver to get messages from the server for this client.
+pub async fn handle_websocket_client<S: AsyncRead + AsyncWrite + Unpin>(
+    ws: WebSocketStream<S>,
+    addr: std::net::SocketAddr,
+    server_tx: UnboundedSender<(std::net::SocketAddr, ClientEvent)>,
+    mut client_rx: UnboundedReceiver<String>,
+) {
+    let (mut sink, mut stream) = ws.split();
+    let mut greeted = false;
+
+    loop {
+        tokio::select! {
+            incoming = stream.next() => {
+                match incoming {
+                    Some(Ok(Message::Text(text))) if !greeted && text.len() > HANDSHAKE_FRAME_LEN => {
+                        eprintln!("[WebSocket] {} sent {} bytes before the handshake; disconnecting", addr, text.len());
+                        break;
+                    }
+                    Some(Ok(Message::Text(text))) => {
+                        println!("[WebSocket] Received message from {}: {}", addr, redact_secrets(&text));
+                        if let Err(e) = server_tx.send((addr, ClientEvent::Message(text.to_string()))) {
+                            eprintln!("[WebSocket] Failed to forward message from {}: {}", addr, e);
+                        }
+                    }
+                    Some(Ok(Message::Close(_))) | None => {
+                        println!("[WebSocket] Client {} closed the connection", addr);
+                        break;
+                    }
+                    // Pings are answered by the library; binary data is not part of the protocol
+                    Some(Ok(Message::Binary(_))) => {
+                        eprintln!("[WebSocket] Ignoring binary message from {}", addr);
+                    }
+                    Some(Ok(_)) => {}
+                    Some(Err(e)) => {
+                        println!("[WebSocket] Client {} disconnected (read error): {}", addr, e);
+                        break;
+                    }
+                }
+            }
+            reply = client_rx.recv() => {
+                let Some(reply) = reply else {
+                    println!("[WebSocket] Channel to {} closed", addr);
+                    break;
+                };
+                println!("[WebSocket] Sending reply to {}: {}", addr, describe_reply(&reply));
+                greeted = greeted || event_kind(&reply) == "welcome";
+                if let Err(e) = sink.send(Message::text(reply)).await {
+                    eprintln!("[WebSocket] Failed to send reply to {}: {}", addr, e);
+                    break;
+                }
+            }
+        }
+    }
+
+    let _ = server_tx.send((addr, ClientEvent::Disconnected));
+}